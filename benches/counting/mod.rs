@@ -0,0 +1,27 @@
+//! An allocator counting the allocations made by each thread, shared by the benchmarks.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// An allocator counting the allocations made by each thread, so that the work of other threads
+/// (e.g. that of a client) is not counted.
+pub struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Gets the number of allocations made by the current thread so far.
+pub fn allocations() -> usize {
+    ALLOCATIONS.with(|n| n.get())
+}
@@ -0,0 +1,56 @@
+//! Compares the allocations made for each message parsed into an owned `Message` against those
+//! made for a borrowed `MessageRef`. Run with `cargo bench --bench parse`.
+extern crate irc;
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use irc::proto::{Message, MessageRef};
+
+mod counting;
+
+use counting::{allocations, CountingAlloc};
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+const ROUNDS: usize = 20_000;
+
+const LINES: &[&str] = &[
+    ":ada!ada@host.example PRIVMSG #rust :The quick brown fox jumps over the lazy dog.\r\n",
+    "@time=2019-01-01T00:00:00.000Z;account=ada :ada!ada@host.example JOIN #rust ada :Ada\r\n",
+    ":irc.example.net 353 bench = #rust :@ada +grace bench alan\r\n",
+    ":ada!ada@host.example MODE #rust +ov grace alan\r\n",
+    "PING :irc.example.net\r\n",
+];
+
+/// Runs the given parse function over each line, printing the allocations and time taken.
+fn bench<F: FnMut(&str) -> usize>(name: &str, mut parse: F) {
+    let before = allocations();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for line in LINES {
+            black_box(parse(line));
+        }
+    }
+    let elapsed = start.elapsed();
+    let messages = (ROUNDS * LINES.len()) as u64;
+    let allocations = allocations() - before;
+    println!(
+        "{:<12} {:>6.2} allocations/message {:>8} ns/message",
+        name,
+        allocations as f64 / messages as f64,
+        (elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos())) / messages,
+    );
+}
+
+fn main() {
+    bench("Message", |line| {
+        let message: Message = line.parse().unwrap();
+        message.prefix.map_or(0, |p| p.len())
+    });
+    bench("MessageRef", |line| {
+        let message = MessageRef::parse(line).unwrap();
+        message.prefix().map_or(0, |p| p.len())
+    });
+}
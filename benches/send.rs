@@ -0,0 +1,48 @@
+//! Measures the allocations made on the calling thread for each message sent in a tight loop.
+//! Run with `cargo bench --bench send`.
+extern crate irc;
+
+use std::default::Default;
+use std::time::Instant;
+
+use irc::client::prelude::*;
+
+mod counting;
+
+use counting::{allocations, CountingAlloc};
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+const MESSAGES: usize = 100_000;
+
+/// Runs the given send function for each message, printing the allocations and time taken.
+fn bench<F: FnMut(&IrcClient)>(name: &str, mut send: F) {
+    let client = IrcClient::from_config(Config {
+        nickname: Some("bench".to_owned()),
+        server: Some("irc.test.net".to_owned()),
+        use_mock_connection: Some(true),
+        ..Default::default()
+    }).unwrap();
+    let before = allocations();
+    let start = Instant::now();
+    for _ in 0..MESSAGES {
+        send(&client);
+    }
+    let elapsed = start.elapsed();
+    let allocations = allocations() - before;
+    println!(
+        "{:<16} {:>6.2} allocations/message {:>8} ns/message",
+        name,
+        allocations as f64 / MESSAGES as f64,
+        (elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos())) / MESSAGES as u64,
+    );
+}
+
+fn main() {
+    let text = "The quick brown fox jumps over the lazy dog.";
+    bench("send_privmsg", |client| client.send_privmsg("#bench", text).unwrap());
+    bench("send", |client| {
+        client.send(Command::PRIVMSG("#bench".to_owned(), text.to_owned())).unwrap()
+    });
+}
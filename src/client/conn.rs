@@ -1,22 +1,34 @@
 //! A module providing IRC connections for use by `IrcServer`s.
 use std::fs::File;
 use std::fmt;
-use std::io::Read;
+use std::io::{self, Read};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use encoding::EncoderTrap;
 use encoding::label::encoding_from_whatwg_label;
-use futures::{Async, Poll, Future, Sink, StartSend, Stream};
+use futures::{future, Async, Poll, Future, Sink, StartSend, Stream};
+use futures::future::{Either, Loop};
+use futures::sync::oneshot;
 use native_tls::{Certificate, TlsConnector, Identity};
 use tokio_codec::Decoder;
-use tokio_core::reactor::Handle;
+use tokio_core::reactor::{Handle, Remote};
 use tokio_core::net::{TcpStream, TcpStreamNew};
+use tokio_io;
 use tokio_mockstream::MockStream;
 use tokio_tls::{self, TlsStream};
+#[cfg(unix)]
+use tokio_uds::UnixStream;
 
 use error;
-use client::data::Config;
+use client::data::{Config, Resolver};
+use client::data::metrics::Metrics;
+use client::data::sts;
+use client::test_util::MockTransport;
 use client::transport::{IrcTransport, LogView, Logged};
-use proto::{IrcCodec, Message};
+use proto::{Command, IrcCodec, Message, Response};
 
 /// An IRC connection used internally by `IrcServer`.
 pub enum Connection {
@@ -26,6 +38,11 @@ pub enum Connection {
     Secured(IrcTransport<TlsStream<TcpStream>>),
     #[doc(hidden)]
     Mock(Logged<MockStream>),
+    #[doc(hidden)]
+    Scripted(IrcTransport<MockTransport>),
+    #[doc(hidden)]
+    #[cfg(unix)]
+    Unix(IrcTransport<UnixStream>),
 }
 
 impl fmt::Debug for Connection {
@@ -37,6 +54,9 @@ impl fmt::Debug for Connection {
                 Connection::Unsecured(_) => "Connection::Unsecured(...)",
                 Connection::Secured(_) => "Connection::Secured(...)",
                 Connection::Mock(_) => "Connection::Mock(...)",
+                Connection::Scripted(_) => "Connection::Scripted(...)",
+                #[cfg(unix)]
+                Connection::Unix(_) => "Connection::Unix(...)",
             }
         )
     }
@@ -45,14 +65,42 @@ impl fmt::Debug for Connection {
 /// A convenient type alias representing the `TlsStream` future.
 type TlsFuture = Box<Future<Error = error::IrcError, Item = TlsStream<TcpStream>> + Send>;
 
+/// A convenient type alias representing the `TcpStream` future.
+type TcpFuture = Box<Future<Error = error::IrcError, Item = TcpStream> + Send>;
+
+/// A convenient type alias representing the `UnixStream` future.
+#[cfg(unix)]
+type UnixFuture = Box<Future<Error = error::IrcError, Item = UnixStream>>;
+
+/// A convenient type alias representing a future connecting to one of the configured servers,
+/// yielding the stream along with the host that accepted the connection.
+type FailoverFuture = Box<Future<Error = error::IrcError, Item = (TcpStream, String)> + Send>;
+
+/// A convenient type alias representing a future connecting to one of several addresses.
+type StreamFuture = Box<Future<Error = io::Error, Item = TcpStream> + Send>;
+
+/// A convenient type alias representing a future resolving a host to its socket addresses.
+type ResolveFuture = Box<Future<Error = io::Error, Item = Vec<SocketAddr>> + Send>;
+
+/// A server's host and port.
+type Server = (String, u16);
+
+/// The server that most recently accepted a connection, shared between a client and the clients
+/// that replace it when reconnecting, so that reconnects go straight to a server known to be
+/// reachable.
+pub(crate) type LastServer = Arc<Mutex<Option<Server>>>;
+
 /// A future representing an eventual `Connection`.
 pub enum ConnectionFuture<'a> {
     #[doc(hidden)]
-    Unsecured(&'a Config, TcpStreamNew),
+    Unsecured(&'a Config, TcpFuture),
     #[doc(hidden)]
     Secured(&'a Config, TlsFuture),
     #[doc(hidden)]
     Mock(&'a Config),
+    #[doc(hidden)]
+    #[cfg(unix)]
+    Unix(&'a Config, UnixFuture),
 }
 
 impl<'a> fmt::Debug for ConnectionFuture<'a> {
@@ -64,11 +112,15 @@ impl<'a> fmt::Debug for ConnectionFuture<'a> {
                 ConnectionFuture::Unsecured(_, _) => "ConnectionFuture::Unsecured",
                 ConnectionFuture::Secured(_, _) => "ConnectionFuture::Secured",
                 ConnectionFuture::Mock(_) => "ConnectionFuture::Mock",
+                #[cfg(unix)]
+                ConnectionFuture::Unix(_, _) => "ConnectionFuture::Unix",
             },
             match *self {
                 ConnectionFuture::Unsecured(cfg, _) |
                 ConnectionFuture::Secured(cfg, _) |
                 ConnectionFuture::Mock(cfg) => cfg,
+                #[cfg(unix)]
+                ConnectionFuture::Unix(cfg, _) => cfg,
             }
         )
     }
@@ -82,75 +134,78 @@ impl<'a> Future for ConnectionFuture<'a> {
         match *self {
             ConnectionFuture::Unsecured(config, ref mut inner) => {
                 let stream = try_ready!(inner.poll());
-                let framed = IrcCodec::new(config.encoding())?.framed(stream);
-                let transport = IrcTransport::new(config, framed);
-
-                Ok(Async::Ready(Connection::Unsecured(transport)))
+                Ok(Async::Ready(Connection::unsecured(config, stream)?))
             }
             ConnectionFuture::Secured(config, ref mut inner) => {
                 let stream = try_ready!(inner.poll());
-                let framed = IrcCodec::new(config.encoding())?.framed(stream);
-                let transport = IrcTransport::new(config, framed);
-
-                Ok(Async::Ready(Connection::Secured(transport)))
+                Ok(Async::Ready(Connection::secured(config, stream)?))
             }
-            ConnectionFuture::Mock(config) => {
-                let enc: error::Result<_> = encoding_from_whatwg_label(
-                    config.encoding()
-                ).ok_or_else(|| error::IrcError::UnknownCodec {
-                    codec: config.encoding().to_owned(),
-                });
-                let encoding = enc?;
-                let init_str = config.mock_initial_value();
-                let initial: error::Result<_> = {
-                    encoding.encode(init_str, EncoderTrap::Replace).map_err(|data| {
-                        error::IrcError::CodecFailed {
-                            codec: encoding.name(),
-                            data: data.into_owned(),
-                        }
-                    })
-                };
-
-                let stream = MockStream::new(&initial?);
-                let framed = IrcCodec::new(config.encoding())?.framed(stream);
-                let transport = IrcTransport::new(config, framed);
-
-                Ok(Async::Ready(Connection::Mock(Logged::wrap(transport))))
+            ConnectionFuture::Mock(config) => Ok(Async::Ready(Connection::mock(config)?)),
+            #[cfg(unix)]
+            ConnectionFuture::Unix(config, ref mut inner) => {
+                let stream = try_ready!(inner.poll());
+                Ok(Async::Ready(Connection::unix(config, stream)?))
             }
         }
     }
 }
 
+/// A future representing an eventual `Connection` that owns its configuration, so that it is not
+/// tied to the lifetime of a borrowed one.
+pub type OwnedConnectionFuture = Box<Future<Error = error::IrcError, Item = Connection>>;
+
 impl Connection {
     /// Creates a new `Connection` using the specified `Config` and `Handle`.
     pub fn new<'a>(config: &'a Config, handle: &Handle) -> error::Result<ConnectionFuture<'a>> {
+        Connection::with_last_server(config, handle, &LastServer::default())
+    }
+
+    /// Creates a new `Connection` using the specified `Config` and `Handle`, trying the server
+    /// that last accepted a connection first and recording the one that accepts this connection.
+    pub(crate) fn with_last_server<'a>(
+        config: &'a Config, handle: &Handle, last: &LastServer
+    ) -> error::Result<ConnectionFuture<'a>> {
         if config.use_mock_connection() {
             Ok(ConnectionFuture::Mock(config))
+        } else if let Some(path) = config.unix_socket_path() {
+            Connection::connect_unix(config, path)
         } else if config.use_ssl() {
-            let domain = format!("{}", config.server()?);
-            info!("Connecting via SSL to {}.", domain);
-            let mut builder = TlsConnector::builder();
-            if let Some(cert_path) = config.cert_path() {
-                let mut file = File::open(cert_path)?;
-                let mut cert_data = vec![];
-                file.read_to_end(&mut cert_data)?;
-                let cert = Certificate::from_der(&cert_data)?;
-                builder.add_root_certificate(cert);
-                info!("Added {} to trusted certificates.", cert_path);
-            }
-            if let Some(client_cert_path) = config.client_cert_path() {
-                let client_cert_pass = config.client_cert_pass();
-                let mut file = File::open(client_cert_path)?;
-                let mut client_cert_data = vec![];
-                file.read_to_end(&mut client_cert_data)?;
-                let pkcs12_archive = Identity::from_pkcs12(&client_cert_data, &client_cert_pass)?;
-                builder.identity(pkcs12_archive);
-                info!("Using {} for client certificate authentication.", client_cert_path);
-            }
-            let connector: tokio_tls::TlsConnector = builder.build()?.into();
-            let stream = Box::new(TcpStream::connect(&config.socket_addr()?, handle).map_err(|e| {
-                let res: error::IrcError = e.into();
-                res
+            info!("Connecting via SSL to {}.", config.server()?);
+            let connector = Connection::tls_connector(config)?;
+            let stream = Box::new(Connection::connect(config, handle, last)?.and_then(
+                move |(socket, domain)| connector.connect(&domain, socket).map_err(|e| e.into())
+            ));
+            Ok(ConnectionFuture::Secured(config, stream))
+        } else if config.use_starttls() {
+            info!("Connecting via STARTTLS to {}.", config.server()?);
+            let connector = Connection::tls_connector(config)?;
+            let stream = Box::new(Connection::connect(config, handle, last)?.and_then(
+                |(socket, domain)| {
+                    tokio_io::io::write_all(socket, &b"STARTTLS\r\n"[..])
+                        .map(|(socket, _)| (socket, domain))
+                        .map_err(|e| e.into())
+                }
+            ).and_then(|(socket, domain)| {
+                StartTls::new(socket).map(|socket| (socket, domain))
+            }).and_then(move |(socket, domain)| {
+                connector.connect(&domain, socket).map_err(
+                    |e| e.into(),
+                )
+            }));
+            Ok(ConnectionFuture::Secured(config, stream))
+        } else if let Some(port) = sts::upgrade_port(config) {
+            let domain = config.server()?.to_owned();
+            info!("Connecting via SSL to {} on port {} due to its STS policy.", domain, port);
+            let connector = Connection::tls_connector(config)?;
+            let addrs = Connection::resolve_async(config.resolver().cloned(), &domain, port);
+            let (keepalive_ms, nodelay) = (config.tcp_keepalive_ms(), config.tcp_nodelay());
+            let (server, remote) = (domain.clone(), handle.remote().clone());
+            let socket = addrs.and_then(move |addrs| Connection::connect_addrs(addrs, &remote));
+            let stream = Box::new(socket.map_err(move |e| {
+                error::IrcError::ConnectionFailed { server: server, port: port, cause: e }
+            }).and_then(move |socket| {
+                Connection::set_socket_options(&socket, keepalive_ms, nodelay).map(|_| socket)
+                    .map_err(|e| e.into())
             }).and_then(move |socket| {
                 connector.connect(&domain, socket).map_err(
                     |e| e.into(),
@@ -161,11 +216,247 @@ impl Connection {
             info!("Connecting to {}.", config.server()?);
             Ok(ConnectionFuture::Unsecured(
                 config,
-                TcpStream::connect(&config.socket_addr()?, handle),
+                Box::new(Connection::connect(config, handle, last)?.map(|(socket, _)| socket)),
             ))
         }
     }
 
+    /// Creates a new `Connection` using the specified `Config` and `Handle`, taking ownership of
+    /// the configuration.
+    pub fn new_owned(config: Config, handle: &Handle) -> error::Result<OwnedConnectionFuture> {
+        let future = Connection::new(&config, handle)?;
+        Ok(match future {
+            ConnectionFuture::Unsecured(_, inner) => Box::new(inner.and_then(move |stream| {
+                Connection::unsecured(&config, stream)
+            })),
+            ConnectionFuture::Secured(_, inner) => Box::new(inner.and_then(move |stream| {
+                Connection::secured(&config, stream)
+            })),
+            ConnectionFuture::Mock(_) => Box::new(future::result(Connection::mock(&config))),
+            #[cfg(unix)]
+            ConnectionFuture::Unix(_, inner) => Box::new(inner.and_then(move |stream| {
+                Connection::unix(&config, stream)
+            })),
+        })
+    }
+
+    /// Starts connecting to the Unix domain socket at the given path.
+    #[cfg(unix)]
+    fn connect_unix<'a>(config: &'a Config, path: &str) -> error::Result<ConnectionFuture<'a>> {
+        info!("Connecting to Unix socket {}.", path);
+        Ok(ConnectionFuture::Unix(
+            config,
+            Box::new(UnixStream::connect(path).map_err(|e| e.into())),
+        ))
+    }
+
+    /// Fails to connect, as Unix domain sockets are only supported on Unix platforms.
+    #[cfg(not(unix))]
+    fn connect_unix<'a>(config: &'a Config, _: &str) -> error::Result<ConnectionFuture<'a>> {
+        Err(error::IrcError::InvalidConfig {
+            path: config.path(),
+            cause: error::ConfigError::InvalidField {
+                field: "unix_socket_path",
+                reason: "is only supported on Unix platforms",
+            },
+        })
+    }
+
+    /// Wraps a connected Unix domain socket in a transport.
+    #[cfg(unix)]
+    fn unix(config: &Config, stream: UnixStream) -> error::Result<Connection> {
+        let framed = Connection::codec(config)?.framed(stream);
+        Ok(Connection::Unix(IrcTransport::new(config, framed)))
+    }
+
+    /// Wraps a connected plaintext stream in a transport.
+    fn unsecured(config: &Config, stream: TcpStream) -> error::Result<Connection> {
+        let framed = Connection::codec(config)?.framed(stream);
+        Ok(Connection::Unsecured(IrcTransport::new(config, framed)))
+    }
+
+    /// Wraps a connected TLS stream in a transport.
+    fn secured(config: &Config, stream: TlsStream<TcpStream>) -> error::Result<Connection> {
+        let framed = Connection::codec(config)?.framed(stream);
+        Ok(Connection::Secured(IrcTransport::new(config, framed)))
+    }
+
+    /// Creates a mock connection from which the configured `mock_initial_value` is read.
+    fn mock(config: &Config) -> error::Result<Connection> {
+        let enc: error::Result<_> = encoding_from_whatwg_label(
+            config.encoding()
+        ).ok_or_else(|| error::IrcError::UnknownCodec {
+            codec: config.encoding().to_owned(),
+        });
+        let encoding = enc?;
+        let init_str = config.mock_initial_value();
+        let initial: error::Result<_> = {
+            encoding.encode(init_str, EncoderTrap::Replace).map_err(|data| {
+                error::IrcError::CodecFailed {
+                    codec: encoding.name(),
+                    data: data.into_owned(),
+                }
+            })
+        };
+
+        let stream = MockStream::new(&initial?);
+        let framed = Connection::codec(config)?.framed(stream);
+        let transport = IrcTransport::new(config, framed);
+
+        Ok(Connection::Mock(Logged::wrap(transport)))
+    }
+
+    /// Creates the codec for a connection with the encoding and decoding options in the
+    /// configuration.
+    pub(crate) fn codec(config: &Config) -> error::Result<IrcCodec> {
+        Ok(IrcCodec::with_decode_lossy(config.encoding(), config.decode_lossy())?
+            .sanitize_incoming(config.sanitize_incoming()))
+    }
+
+    /// Connects to the first of the configured servers that accepts a TCP connection, trying the
+    /// server that last accepted a connection first.
+    fn connect(
+        config: &Config, handle: &Handle, last: &LastServer
+    ) -> error::Result<FailoverFuture> {
+        let mut servers: Vec<_> = config.servers()?.into_iter().map(|(host, port)| {
+            (host.to_owned(), port)
+        }).collect();
+        let preferred = last.lock().unwrap().clone();
+        if let Some(i) = preferred.and_then(|last| servers.iter().position(|s| *s == last)) {
+            let server = servers.remove(i);
+            servers.insert(0, server);
+        }
+
+        // Unlike the handle, the remote can be sent along with the future to the event loop.
+        let remote = handle.remote().clone();
+        let last = last.clone();
+        let resolver = config.resolver().cloned();
+        let attempts = future::loop_fn((servers.into_iter(), None), move |(mut servers, err)| {
+            let (host, port) = match servers.next() {
+                Some(server) => server,
+                None => return Either::A(future::err(match err {
+                    Some((server, port, cause)) => error::IrcError::ConnectionFailed {
+                        server: server, port: port, cause: cause,
+                    },
+                    None => io::Error::new(io::ErrorKind::NotFound, "no servers to connect to")
+                        .into(),
+                })),
+            };
+            debug!("Trying {}:{}.", host, port);
+            let remote = remote.clone();
+            let last = last.clone();
+            let addrs = Connection::resolve_async(resolver.clone(), &host, port);
+            Either::B(addrs.and_then(move |addrs| {
+                Connection::connect_addrs(addrs, &remote)
+            }).then(move |res| match res {
+                Ok(socket) => {
+                    *last.lock().unwrap() = Some((host.clone(), port));
+                    Ok(Loop::Break((socket, host)))
+                }
+                Err(e) => {
+                    warn!("Failed to connect to {}:{}: {}", host, port, e);
+                    Ok(Loop::Continue((servers, Some((host, port, e)))))
+                }
+            }))
+        });
+        let (keepalive_ms, nodelay) = (config.tcp_keepalive_ms(), config.tcp_nodelay());
+        Ok(Box::new(attempts.and_then(move |(socket, host)| {
+            Connection::set_socket_options(&socket, keepalive_ms, nodelay).map(|_| (socket, host))
+                .map_err(|e| e.into())
+        })))
+    }
+
+    /// Applies the configured TCP keepalive and nodelay options to a connected socket.
+    fn set_socket_options(
+        socket: &TcpStream, keepalive_ms: Option<u32>, nodelay: bool
+    ) -> io::Result<()> {
+        socket.set_keepalive(keepalive_ms.map(|ms| Duration::from_millis(u64::from(ms))))?;
+        socket.set_nodelay(nodelay)
+    }
+
+    /// Resolves the given host and port to its socket addresses, using the custom resolver if one
+    /// is configured and the system resolver otherwise. This fails if there are no addresses.
+    fn resolve(resolver: Option<&Resolver>, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let addrs: Vec<_> = match resolver {
+            Some(resolver) => resolver.resolve(host, port)?,
+            None => (host, port).to_socket_addrs()?.collect(),
+        };
+        if addrs.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "host has no addresses"));
+        }
+        Ok(addrs)
+    }
+
+    /// Resolves the given host and port like `resolve`, but on a separate thread so that a slow
+    /// lookup does not stall the event loop.
+    fn resolve_async(resolver: Option<Resolver>, host: &str, port: u16) -> ResolveFuture {
+        let (tx, rx) = oneshot::channel();
+        let host = host.to_owned();
+        let spawned = thread::Builder::new().name("irc-resolver".to_owned()).spawn(move || {
+            let _ = tx.send(Connection::resolve(resolver.as_ref(), &host, port));
+        });
+        if let Err(e) = spawned {
+            return Box::new(future::err(e));
+        }
+        Box::new(rx.then(|res| match res {
+            Ok(addrs) => addrs,
+            Err(_) => Err(io::Error::new(io::ErrorKind::Interrupted, "resolver thread panicked")),
+        }))
+    }
+
+    /// Connects to the first of the given addresses that accepts a TCP connection, trying each in
+    /// turn, and failing with the error from the last one if none do.
+    fn connect_addrs(addrs: Vec<SocketAddr>, remote: &Remote) -> StreamFuture {
+        let mut addrs = addrs.into_iter();
+        let mut attempts: StreamFuture = match addrs.next() {
+            Some(addr) => Box::new(Connection::connect_addr(&addr, remote)),
+            None => Box::new(future::err(
+                io::Error::new(io::ErrorKind::NotFound, "host has no addresses")
+            )),
+        };
+        for addr in addrs {
+            let remote = remote.clone();
+            attempts = Box::new(attempts.or_else(move |e| {
+                debug!("Failed to connect, trying {} next: {}", addr, e);
+                Connection::connect_addr(&addr, &remote)
+            }));
+        }
+        attempts
+    }
+
+    /// Starts connecting to the given address on the event loop of the given remote.
+    fn connect_addr(addr: &SocketAddr, remote: &Remote) -> TcpStreamNew {
+        match remote.handle() {
+            Some(handle) => TcpStream::connect(addr, &handle),
+            // Outside of the remote's event loop, the default reactor is used instead.
+            None => TcpStream::connect2(addr),
+        }
+    }
+
+    /// Creates a TLS connector trusting the certificates and using the client certificate
+    /// specified in the configuration.
+    fn tls_connector(config: &Config) -> error::Result<tokio_tls::TlsConnector> {
+        let mut builder = TlsConnector::builder();
+        if let Some(cert_path) = config.cert_path() {
+            let mut file = File::open(cert_path)?;
+            let mut cert_data = vec![];
+            file.read_to_end(&mut cert_data)?;
+            let cert = Certificate::from_der(&cert_data)?;
+            builder.add_root_certificate(cert);
+            info!("Added {} to trusted certificates.", cert_path);
+        }
+        if let Some(client_cert_path) = config.client_cert_path() {
+            let client_cert_pass = config.client_cert_pass();
+            let mut file = File::open(client_cert_path)?;
+            let mut client_cert_data = vec![];
+            file.read_to_end(&mut client_cert_data)?;
+            let pkcs12_archive = Identity::from_pkcs12(&client_cert_data, &client_cert_pass)?;
+            builder.identity(pkcs12_archive);
+            info!("Using {} for client certificate authentication.", client_cert_path);
+        }
+        Ok(builder.build()?.into())
+    }
+
     /// Gets a view of the internal logging if and only if this connection is using a mock stream.
     /// Otherwise, this will always return `None`. This is used for unit testing.
     pub fn log_view(&self) -> Option<LogView> {
@@ -174,6 +465,77 @@ impl Connection {
             _ => None,
         }
     }
+
+    /// Gets the counters for the traffic over this connection.
+    pub(crate) fn metrics(&self) -> Arc<Metrics> {
+        match *self {
+            Connection::Unsecured(ref inner) => inner.metrics(),
+            Connection::Secured(ref inner) => inner.metrics(),
+            Connection::Mock(ref inner) => inner.metrics(),
+            Connection::Scripted(ref inner) => inner.metrics(),
+            #[cfg(unix)]
+            Connection::Unix(ref inner) => inner.metrics(),
+        }
+    }
+}
+
+/// A future that waits for the server's reply to a STARTTLS request, resolving to the socket once
+/// the server is ready for the TLS handshake. Lines are read a byte at a time so that nothing
+/// belonging to the handshake is consumed.
+struct StartTls {
+    socket: Option<TcpStream>,
+    line: Vec<u8>,
+}
+
+impl StartTls {
+    fn new(socket: TcpStream) -> StartTls {
+        StartTls {
+            socket: Some(socket),
+            line: vec![],
+        }
+    }
+}
+
+impl Future for StartTls {
+    type Item = TcpStream;
+    type Error = error::IrcError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let mut byte = [0; 1];
+            let read = match self.socket.as_mut().expect("polled StartTls after completion")
+                .read(&mut byte)
+            {
+                Ok(read) => read,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(e) => return Err(e.into()),
+            };
+            if read == 0 {
+                return Err(error::IrcError::StartTlsFailed {
+                    reason: "connection closed by server".to_owned(),
+                });
+            }
+            if byte[0] != b'\n' {
+                self.line.push(byte[0]);
+                continue;
+            }
+            let line = String::from_utf8_lossy(&self.line).into_owned();
+            self.line.clear();
+            // Anything other than the reply to STARTTLS (e.g. notices) is ignored.
+            match line.parse::<Message>().map(|msg| msg.command) {
+                Ok(Command::Response(Response::RPL_STARTTLS, _, _)) => {
+                    return Ok(Async::Ready(self.socket.take().unwrap()))
+                }
+                Ok(Command::Response(Response::ERR_STARTTLS, _, suffix)) |
+                Ok(Command::Response(Response::ERR_UNKNOWNCOMMAND, _, suffix)) => {
+                    return Err(error::IrcError::StartTlsFailed {
+                        reason: suffix.unwrap_or_else(|| line.trim_end().to_owned()),
+                    })
+                }
+                _ => (),
+            }
+        }
+    }
 }
 
 impl Stream for Connection {
@@ -185,6 +547,9 @@ impl Stream for Connection {
             Connection::Unsecured(ref mut inner) => inner.poll(),
             Connection::Secured(ref mut inner) => inner.poll(),
             Connection::Mock(ref mut inner) => inner.poll(),
+            Connection::Scripted(ref mut inner) => inner.poll(),
+            #[cfg(unix)]
+            Connection::Unix(ref mut inner) => inner.poll(),
         }
     }
 }
@@ -198,6 +563,9 @@ impl Sink for Connection {
             Connection::Unsecured(ref mut inner) => inner.start_send(item),
             Connection::Secured(ref mut inner) => inner.start_send(item),
             Connection::Mock(ref mut inner) => inner.start_send(item),
+            Connection::Scripted(ref mut inner) => inner.start_send(item),
+            #[cfg(unix)]
+            Connection::Unix(ref mut inner) => inner.start_send(item),
         }
     }
 
@@ -206,6 +574,341 @@ impl Sink for Connection {
             Connection::Unsecured(ref mut inner) => inner.poll_complete(),
             Connection::Secured(ref mut inner) => inner.poll_complete(),
             Connection::Mock(ref mut inner) => inner.poll_complete(),
+            Connection::Scripted(ref mut inner) => inner.poll_complete(),
+            #[cfg(unix)]
+            Connection::Unix(ref mut inner) => inner.poll_complete(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::default::Default;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    #[cfg(unix)]
+    use std::os::unix::net::UnixListener;
+    use std::process;
+    use std::thread;
+    use std::time::Duration;
+
+    use failure::Fail;
+    use futures::{Future, Sink, Stream};
+    use native_tls::{Identity, TlsAcceptor};
+    use tokio_core::reactor::Core;
+
+    use super::{Connection, LastServer};
+    use error::IrcError;
+    use client::data::{Config, Resolver};
+    use client::data::sts;
+    use proto::Command::PRIVMSG;
+
+    fn starttls_config(port: u16) -> Config {
+        Config {
+            nickname: Some("test".to_owned()),
+            server: Some("127.0.0.1".to_owned()),
+            port: Some(port),
+            use_starttls: Some(true),
+            cert_path: Some("tests/fixtures/localhost.der".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn read_line<R: BufRead>(reader: &mut R) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        line
+    }
+
+    #[test]
+    fn starttls_upgrade() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut starttls = [0; 10];
+            socket.read_exact(&mut starttls).unwrap();
+            assert_eq!(&starttls[..], b"STARTTLS\r\n");
+            socket.write_all(b":irc.test.net NOTICE * :Looking up your hostname\r\n").unwrap();
+            socket.write_all(b":irc.test.net 670 * :STARTTLS successful\r\n").unwrap();
+
+            let mut p12 = vec![];
+            File::open("tests/fixtures/localhost.p12").unwrap().read_to_end(&mut p12).unwrap();
+            let acceptor = TlsAcceptor::new(Identity::from_pkcs12(&p12, "password").unwrap());
+            let mut tls = BufReader::new(acceptor.unwrap().accept(socket).unwrap());
+            let line = read_line(&mut tls);
+            tls.get_mut().write_all(b":irc.test.net 001 test :Welcome\r\n").unwrap();
+            line
+        });
+
+        let config = starttls_config(port);
+        let mut reactor = Core::new().unwrap();
+        let handle = reactor.handle();
+        let conn = reactor.run(Connection::new(&config, &handle).unwrap()).unwrap();
+        let conn = reactor.run(conn.send(PRIVMSG("#test".to_owned(), "hi".to_owned()).into()))
+            .unwrap();
+        let (msg, _) = reactor.run(conn.into_future().map_err(|(e, _)| e)).unwrap();
+
+        assert_eq!(server.join().unwrap(), "PRIVMSG #test :hi\r\n");
+        assert_eq!(msg.unwrap().to_string(), ":irc.test.net 001 test :Welcome\r\n");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn unix_socket_ping() {
+        let path = env::temp_dir().join(format!("irc-unix-conn-test-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            socket.write_all(b"PING :irc.test.net\r\n").unwrap();
+            read_line(&mut BufReader::new(socket))
+        });
+
+        let config = Config {
+            nickname: Some("test".to_owned()),
+            unix_socket_path: Some(path.to_str().unwrap().to_owned()),
+            ..Default::default()
+        };
+        let mut reactor = Core::new().unwrap();
+        let handle = reactor.handle();
+        let conn = reactor.run(Connection::new(&config, &handle).unwrap()).unwrap();
+        let (msg, _) = reactor.run(conn.into_future().map_err(|(e, _)| e)).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(msg.unwrap().to_string(), "PING :irc.test.net\r\n");
+        assert_eq!(server.join().unwrap(), "PONG :irc.test.net\r\n");
+    }
+
+    #[test]
+    fn sts_cached_policy_connects_with_tls() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut p12 = vec![];
+            File::open("tests/fixtures/localhost.p12").unwrap().read_to_end(&mut p12).unwrap();
+            let acceptor = TlsAcceptor::new(Identity::from_pkcs12(&p12, "password").unwrap());
+            let mut tls = BufReader::new(acceptor.unwrap().accept(socket).unwrap());
+            read_line(&mut tls)
+        });
+
+        let path = env::temp_dir().join(format!("irc-sts-conn-test-{}", process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        sts::store(&path, "127.0.0.1", port, 300).unwrap();
+        let config = Config {
+            use_starttls: None,
+            // Nothing listens on the plaintext port, so only the cached TLS port can succeed.
+            port: Some(1),
+            sts_cache: Some(path.clone()),
+            ..starttls_config(port)
+        };
+        let mut reactor = Core::new().unwrap();
+        let handle = reactor.handle();
+        let conn = reactor.run(Connection::new(&config, &handle).unwrap()).unwrap();
+        reactor.run(conn.send(PRIVMSG("#test".to_owned(), "hi".to_owned()).into())).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(server.join().unwrap(), "PRIVMSG #test :hi\r\n");
+    }
+
+    #[test]
+    fn failover_to_alt_server() {
+        // Reserve a port that refuses connections by binding and then releasing it.
+        let primary_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let alt = TcpListener::bind("127.0.0.1:0").unwrap();
+        let alt_port = alt.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut lines = vec![];
+            for _ in 0..2 {
+                let (socket, _) = alt.accept().unwrap();
+                lines.push(read_line(&mut BufReader::new(socket)));
+            }
+            lines
+        });
+
+        let config = Config {
+            nickname: Some("test".to_owned()),
+            server: Some("127.0.0.1".to_owned()),
+            port: Some(primary_port),
+            alt_servers: Some(vec![format!("127.0.0.1:{}", alt_port)]),
+            ..Default::default()
+        };
+        let mut reactor = Core::new().unwrap();
+        let handle = reactor.handle();
+        let last = LastServer::default();
+        let conn = reactor.run(Connection::with_last_server(&config, &handle, &last).unwrap());
+        reactor.run(conn.unwrap().send(PRIVMSG("#test".to_owned(), "hi".to_owned()).into()))
+            .unwrap();
+        assert_eq!(*last.lock().unwrap(), Some(("127.0.0.1".to_owned(), alt_port)));
+
+        // Once the primary is back, reconnects still go to the server that last accepted.
+        let primary = TcpListener::bind(("127.0.0.1", primary_port)).unwrap();
+        primary.set_nonblocking(true).unwrap();
+        let conn = reactor.run(Connection::with_last_server(&config, &handle, &last).unwrap());
+        reactor.run(conn.unwrap().send(PRIVMSG("#test".to_owned(), "again".to_owned()).into()))
+            .unwrap();
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec!["PRIVMSG #test :hi\r\n", "PRIVMSG #test :again\r\n"]
+        );
+        assert!(primary.accept().is_err());
+
+        // Unrelated connections don't share the server that last accepted.
+        let conn = reactor.run(Connection::new(&config, &handle).unwrap()).unwrap();
+        drop(conn);
+        assert!(primary.accept().is_ok());
+    }
+
+    #[test]
+    fn connection_failed() {
+        // Reserve ports that refuse connections by binding and then releasing them.
+        let primary_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let alt_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let config = Config {
+            nickname: Some("test".to_owned()),
+            server: Some("127.0.0.1".to_owned()),
+            port: Some(primary_port),
+            alt_servers: Some(vec![format!("127.0.0.1:{}", alt_port)]),
+            ..Default::default()
+        };
+        let mut reactor = Core::new().unwrap();
+        let handle = reactor.handle();
+        let err = match reactor.run(Connection::new(&config, &handle).unwrap()) {
+            Err(err) => err,
+            Ok(_) => panic!("expected ConnectionFailed, but connected"),
+        };
+        // The underlying error is chained as the cause.
+        assert!(err.cause().and_then(|c| c.downcast_ref::<io::Error>()).is_some());
+        match err {
+            IrcError::ConnectionFailed { server, port, cause } => {
+                assert_eq!((&server[..], port), ("127.0.0.1", alt_port));
+                assert_eq!(cause.kind(), io::ErrorKind::ConnectionRefused);
+            }
+            err => panic!("expected ConnectionFailed, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn custom_resolver() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            read_line(&mut BufReader::new(socket))
+        });
+
+        let config = Config {
+            nickname: Some("test".to_owned()),
+            server: Some("irc.example.invalid".to_owned()),
+            port: Some(port),
+            resolver: Some(Resolver::new(|host, port| {
+                assert_eq!(host, "irc.example.invalid");
+                Ok(vec![SocketAddr::from(([127, 0, 0, 1], port))])
+            })),
+            ..Default::default()
+        };
+        let mut reactor = Core::new().unwrap();
+        let handle = reactor.handle();
+        let conn = reactor.run(Connection::new(&config, &handle).unwrap()).unwrap();
+        reactor.run(conn.send(PRIVMSG("#test".to_owned(), "hi".to_owned()).into())).unwrap();
+        assert_eq!(server.join().unwrap(), "PRIVMSG #test :hi\r\n");
+    }
+
+    #[test]
+    fn resolves_off_event_loop() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || listener.accept().map(|_| ()));
+
+        let event_loop = thread::current().id();
+        let config = Config {
+            nickname: Some("test".to_owned()),
+            server: Some("irc.example.invalid".to_owned()),
+            port: Some(port),
+            resolver: Some(Resolver::new(move |_, port| {
+                assert_ne!(thread::current().id(), event_loop);
+                Ok(vec![SocketAddr::from(([127, 0, 0, 1], port))])
+            })),
+            ..Default::default()
+        };
+        let mut reactor = Core::new().unwrap();
+        let handle = reactor.handle();
+        reactor.run(Connection::new(&config, &handle).unwrap()).unwrap();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn fallback_to_next_address() {
+        // Reserve a port that refuses connections by binding and then releasing it.
+        let refused_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            read_line(&mut BufReader::new(socket))
+        });
+
+        let config = Config {
+            nickname: Some("test".to_owned()),
+            server: Some("irc.example.invalid".to_owned()),
+            port: Some(port),
+            resolver: Some(Resolver::new(move |_, port| Ok(vec![
+                SocketAddr::from(([127, 0, 0, 1], refused_port)),
+                SocketAddr::from(([127, 0, 0, 1], port)),
+            ]))),
+            ..Default::default()
+        };
+        let mut reactor = Core::new().unwrap();
+        let handle = reactor.handle();
+        let conn = reactor.run(Connection::new(&config, &handle).unwrap()).unwrap();
+        reactor.run(conn.send(PRIVMSG("#test".to_owned(), "hi".to_owned()).into())).unwrap();
+        assert_eq!(server.join().unwrap(), "PRIVMSG #test :hi\r\n");
+    }
+
+    #[test]
+    fn socket_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = Config {
+            nickname: Some("test".to_owned()),
+            server: Some("127.0.0.1".to_owned()),
+            port: Some(port),
+            tcp_keepalive_ms: Some(30000),
+            tcp_nodelay: Some(true),
+            ..Default::default()
+        };
+        let mut reactor = Core::new().unwrap();
+        let handle = reactor.handle();
+        let last = LastServer::default();
+        let conn = Connection::connect(&config, &handle, &last).unwrap();
+        let (socket, _) = reactor.run(conn).unwrap();
+        assert!(socket.nodelay().unwrap());
+        assert_eq!(socket.keepalive().unwrap(), Some(Duration::from_secs(30)));
+        drop(listener);
+    }
+
+    #[test]
+    fn starttls_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(socket);
+            assert_eq!(read_line(&mut reader), "STARTTLS\r\n");
+            reader.get_mut().write_all(b":irc.test.net 691 * :STARTTLS failed\r\n").unwrap();
+        });
+
+        let config = starttls_config(port);
+        let mut reactor = Core::new().unwrap();
+        let handle = reactor.handle();
+        match reactor.run(Connection::new(&config, &handle).unwrap()) {
+            Err(IrcError::StartTlsFailed { reason }) => assert_eq!(reason, "STARTTLS failed"),
+            res => panic!("expected StartTlsFailed, got {:?}", res),
         }
+        server.join().unwrap();
     }
 }
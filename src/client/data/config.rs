@@ -1,11 +1,15 @@
 //! JSON configuration files using serde
 use std::borrow::ToOwned;
 use std::collections::HashMap;
+use std::env;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::path::{Path, PathBuf};
 
+use encoding::label::encoding_from_whatwg_label;
+
 #[cfg(feature = "json")]
 use serde_json;
 #[cfg(feature = "yaml")]
@@ -13,6 +17,7 @@ use serde_yaml;
 #[cfg(feature = "toml")]
 use toml;
 
+use client::data::Resolver;
 #[cfg(feature = "toml")]
 use error::TomlError;
 use error::{ConfigError, Result};
@@ -63,6 +68,20 @@ use error::IrcError::InvalidConfig;
 /// let config = Config::load("config.toml").unwrap();
 /// # }
 /// ```
+///
+/// # Environment variables
+///
+/// When loading a configuration, references to environment variables written as `${VAR}` in any
+/// string field are replaced with the variable's value. This makes it possible to keep secrets,
+/// such as `password`, out of configuration files:
+///
+/// ```toml
+/// nickname = "test"
+/// server = "irc.example.com"
+/// password = "${IRC_PASSWORD}"
+/// ```
+///
+/// Loading fails with `ConfigError::UnsetEnvVar` if a referenced variable is not set.
 #[derive(Clone, Deserialize, Serialize, Default, PartialEq, Debug)]
 pub struct Config {
     /// A list of the owners of the client by nickname (for bots).
@@ -71,44 +90,110 @@ pub struct Config {
     pub nickname: Option<String>,
     /// The client's NICKSERV password.
     pub nick_password: Option<String>,
+    /// The SASL mechanism to authenticate with, either `PLAIN` to use `sasl_account` and
+    /// `nick_password`, or `EXTERNAL` to use the client certificate. This defaults to `PLAIN`.
+    pub sasl_mechanism: Option<SaslMechanism>,
+    /// The account name to authenticate as with SASL `PLAIN`, if it differs from the nickname.
+    pub sasl_account: Option<String>,
     /// Alternative nicknames for the client, if the default is taken.
     pub alt_nicks: Option<Vec<String>>,
+    /// Whether or not to truncate nicknames to the server's advertised `NICKLEN` once it is
+    /// known, both when sending `NICK` and when determining the current nickname. Before then, a
+    /// nickname that the server refuses as erroneous is retried truncated to the 9 characters
+    /// allowed by RFC 2812.
+    pub truncate_nick: Option<bool>,
     /// The client's username.
     pub username: Option<String>,
     /// The client's real name.
     pub realname: Option<String>,
+    /// The mode field sent in the USER command during registration. Per RFC 2812, this is a
+    /// bitmask where `8` requests user mode `+i` and `4` requests `+w`.
+    pub user_mode_flags: Option<String>,
     /// The server to connect to.
     pub server: Option<String>,
     /// The port to connect on.
     pub port: Option<u16>,
-    /// The password to connect to the server.
+    /// Alternative servers to try in order if connecting to the primary server fails, each given
+    /// as `host:port` or just `host` to use the configured port.
+    pub alt_servers: Option<Vec<String>>,
+    /// The password to connect to the server. This is sent as is, so it may take whatever form
+    /// the server expects, e.g. `user/network:password` for ZNC or `account:password` for
+    /// networks that accept it in place of SASL.
     pub password: Option<String>,
     /// Whether or not to use SSL.
     /// Clients will automatically panic if this is enabled without SSL support.
     pub use_ssl: Option<bool>,
+    /// Whether or not to upgrade a plaintext connection to TLS with STARTTLS before registering.
+    /// This has no effect if `use_ssl` is enabled.
+    pub use_starttls: Option<bool>,
+    /// The path to a file in which to remember Strict Transport Security policies advertised by
+    /// servers. When a plaintext connection is configured for a server with a cached policy, the
+    /// client will connect with TLS on the cached port instead.
+    pub sts_cache: Option<String>,
+    /// The path to a Unix domain socket to connect to in place of a TCP connection to `server`,
+    /// e.g. for a local bouncer. This is only supported on Unix platforms, and cannot be combined
+    /// with TLS.
+    pub unix_socket_path: Option<String>,
+    /// A custom resolver for the hostnames of the configured servers, used in place of the system
+    /// resolver. This can only be set programmatically, and is never loaded from a file.
+    #[serde(skip)]
+    pub resolver: Option<Resolver>,
+    /// The idle time in milliseconds before the operating system starts sending TCP keepalive
+    /// probes on the connection, which lets dead peers be detected even when the client is idle.
+    /// Most systems only support whole seconds. Keepalives are disabled if this is not specified.
+    pub tcp_keepalive_ms: Option<u32>,
+    /// Whether or not to disable Nagle's algorithm on the connection, sending messages
+    /// immediately instead of coalescing small writes.
+    pub tcp_nodelay: Option<bool>,
     /// The path to the SSL certificate for this server in DER format.
     pub cert_path: Option<String>,
     /// The path to a SSL certificate to use for CertFP client authentication in DER format.
     pub client_cert_path: Option<String>,
     /// The password for the certificate to use in CertFP authentication.
     pub client_cert_pass: Option<String>,
-    /// The encoding type used for this connection.
-    /// This is typically UTF-8, but could be something else.
+    /// The encoding type used for this connection, given as a WHATWG encoding label (e.g.
+    /// `UTF-8` or `ISO-8859-1`). This is typically UTF-8, but could be something else.
     pub encoding: Option<String>,
-    /// A list of channels to join on connection.
+    /// Whether invalid byte sequences in received lines should be replaced with U+FFFD rather
+    /// than producing an error. This defaults to true so that a single bad message doesn't break
+    /// the stream.
+    pub decode_lossy: Option<bool>,
+    /// Whether carriage returns and line feeds embedded in received messages should be stripped,
+    /// so that they cannot be passed on by anything echoing the text of messages. They are
+    /// preserved when this is not specified.
+    pub sanitize_incoming: Option<bool>,
+    /// A list of channels to join on connection. These are joined once registration is complete,
+    /// i.e. after the end of the MOTD.
     pub channels: Option<Vec<String>>,
     /// User modes to set on connect. Example: "+RB -x"
     pub umodes: Option<String>,
+    /// Whether the client should identify itself as a bot by setting the user mode the server
+    /// advertises in its `BOT` token on connect. Servers that do so mark messages from bots with
+    /// the `bot` tag.
+    pub is_bot: Option<bool>,
     /// The text that'll be sent in response to CTCP USERINFO requests.
     pub user_info: Option<String>,
     /// The text that'll be sent in response to CTCP VERSION requests.
     pub version: Option<String>,
     /// The text that'll be sent in response to CTCP SOURCE requests.
     pub source: Option<String>,
+    /// The message sent when quitting if `send_quit` is called with an empty message.
+    pub quit_message: Option<String>,
+    /// The amount of time in milliseconds without sending any messages after which the client
+    /// marks itself as away with `auto_away_message`, as desktop clients do. The client marks
+    /// itself as back as soon as it sends another message. Idleness is checked whenever a message
+    /// is received, which happens at least every `ping_time` seconds. The client is never marked
+    /// as away automatically if this is not specified.
+    pub auto_away_after_ms: Option<u32>,
+    /// The away message used when the client marks itself as away after `auto_away_after_ms`.
+    pub auto_away_message: Option<String>,
     /// The amount of inactivity in seconds before the client will ping the server.
     pub ping_time: Option<u32>,
     /// The amount of time in seconds for a client to reconnect due to no ping response.
     pub ping_timeout: Option<u32>,
+    /// The amount of time in seconds to wait for the server to complete registration before
+    /// giving up.
+    pub registration_timeout: Option<u32>,
     /// The length in seconds of a rolling window for message throttling. If more than
     /// `max_messages_in_burst` messages are sent within `burst_window_length` seconds, additional
     /// messages will be delayed automatically as appropriate. In particular, in the past
@@ -118,6 +203,35 @@ pub struct Config {
     /// The maximum number of messages that can be sent in a burst window before they'll be delayed.
     /// Messages are automatically delayed as appropriate.
     pub max_messages_in_burst: Option<u32>,
+    /// The maximum number of `PRIVMSG`s and `NOTICE`s to any one target (channel or user) that
+    /// can be sent in a burst window. Further messages to that target are held back, in order,
+    /// while messages to other targets are sent ahead of them, so that one busy target doesn't
+    /// starve the rest. Messages are only limited globally if this is not specified.
+    pub max_messages_per_target_burst: Option<u32>,
+    /// The maximum number of messages that can be waiting to be sent at once. When the queue is
+    /// full, `outgoing_overflow_policy` determines what happens to further messages. The queue is
+    /// unbounded if this is not specified.
+    pub outgoing_queue_max: Option<u32>,
+    /// What to do with messages sent while the outgoing queue is full. This can be `block` to wait
+    /// for room in the queue, `drop_oldest` to discard the oldest queued message, `drop_newest` to
+    /// discard the message being sent, or `error` to fail the send with an error. `PONG`, `QUIT`,
+    /// `CAP`, and `AUTHENTICATE` are always queued, as holding them back would break the
+    /// connection.
+    pub outgoing_overflow_policy: Option<OverflowPolicy>,
+    /// The length in milliseconds of a window during which a `PRIVMSG` with the same target and
+    /// text as one already sent is silently dropped instead of sent. This guards against bots
+    /// stuck in a loop sending the same message. Duplicates are never dropped if this is not
+    /// specified.
+    pub dedupe_window_ms: Option<u32>,
+    /// Whether to fail sending messages that exceed the line length limit of 512 bytes with
+    /// `MessageTooLong`, rather than sending them for the server to truncate or reject.
+    pub reject_long_messages: Option<bool>,
+    /// Whether `send_privmsg` and `send_notice` should split text that would exceed the line
+    /// length limit, once relayed by the server with the client's prefix, into several messages.
+    /// Text is only split between characters, and never within what is likely a single grapheme
+    /// cluster (e.g. a sequence of emoji joined with zero width joiners, a letter and its
+    /// combining marks, or a flag) unless such a cluster alone exceeds the limit.
+    pub split_long_messages: Option<bool>,
     /// Whether the client should use NickServ GHOST to reclaim its primary nickname if it is in
     /// use. This has no effect if `nick_password` is not set.
     pub should_ghost: Option<bool>,
@@ -126,6 +240,41 @@ pub struct Config {
     /// E.g. `["RECOVER", "RELEASE"]` means `RECOVER nick pass` and `RELEASE nick pass` will be sent
     /// in that order.
     pub ghost_sequence: Option<Vec<String>>,
+    /// Whether the client should automatically rejoin a channel after being kicked from it.
+    pub rejoin_on_kick: Option<bool>,
+    /// The delay in milliseconds before rejoining a channel after being kicked. Repeated kicks
+    /// from the same channel double this delay each time, and the client will give up rejoining
+    /// after three consecutive kicks until the channel has left it alone for five minutes.
+    pub rejoin_delay_ms: Option<u32>,
+    /// The delay in milliseconds before a
+    /// [`ReconnectingIter`](../struct.ReconnectingIter.html) retries a connection that failed
+    /// before the server welcomed the client. Each consecutive failure doubles this delay up to
+    /// `max_reconnect_delay_ms`, and a random part of up to half of it is taken off each time so
+    /// that many clients do not reconnect all at once.
+    pub reconnect_delay_ms: Option<u32>,
+    /// The longest delay in milliseconds between reconnection attempts.
+    pub max_reconnect_delay_ms: Option<u32>,
+    /// Whether or not to send a `WHO` for each channel joined, so that the hosts and accounts of
+    /// the users in it are known immediately. `WHOX` is used when the server supports it.
+    pub who_on_join: Option<bool>,
+    /// The time in seconds during which repeated `whois_throttled` calls for the same nickname
+    /// are ignored.
+    pub whois_throttle_secs: Option<u32>,
+    /// Whether or not to collect the reactions sent with `+draft/react` for each message id, so
+    /// that they can be looked up with `IrcClient::reactions`.
+    pub track_reactions: Option<bool>,
+    /// The maximum number of messages whose reactions are tracked. Beyond this, the reactions to
+    /// the message that was first reacted to the longest ago are forgotten.
+    pub max_tracked_reactions: Option<u32>,
+    /// The maximum number of channels whose users are tracked, to bound the memory used by
+    /// tracking on large networks. Users are not tracked in channels joined beyond this, which is
+    /// reported to the callback set with `IrcClient::on_tracking_limit`. Channels are tracked
+    /// without limit if this is not specified.
+    pub max_tracked_channels: Option<u32>,
+    /// The maximum number of users tracked in any one channel. Tracking is disabled for a channel
+    /// once it has more users than this, which is reported to the callback set with
+    /// `IrcClient::on_tracking_limit`. Users are tracked without limit if this is not specified.
+    pub max_tracked_users_per_channel: Option<u32>,
     /// Whether or not to use a fake connection for testing purposes. You probably will never want
     /// to enable this, but it is used in unit testing for the `irc` crate.
     pub use_mock_connection: Option<bool>,
@@ -133,8 +282,13 @@ pub struct Config {
     /// set this, but it is used in unit testing for the `irc` crate.
     pub mock_initial_value: Option<String>,
 
-    /// A mapping of channel names to keys for join-on-connect.
+    /// A mapping of channel names to keys for join-on-connect. Channels listed in `channels` with a
+    /// key here will be joined using that key.
     pub channel_keys: Option<HashMap<String, String>>,
+    /// A mapping of the standard names of services (i.e. `NickServ`, `ChanServ`, and `MemoServ`)
+    /// to the nicknames they use on this network, for networks where they differ. Services not
+    /// listed here are messaged by their standard names.
+    pub service_names: Option<HashMap<String, String>>,
     /// A map of additional options to be stored in config.
     pub options: Option<HashMap<String, String>>,
 
@@ -147,12 +301,18 @@ pub struct Config {
 }
 
 impl Config {
+    /// Creates a `ConfigBuilder` for assembling a configuration with chained method calls.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
     fn with_path<P: AsRef<Path>>(mut self, path: P) -> Config {
         self.path = Some(path.as_ref().to_owned());
         self
     }
 
-    fn path(&self) -> String {
+    /// Gets the path that the configuration was loaded from for use in errors, or `<none>`.
+    pub(crate) fn path(&self) -> String {
         self.path.as_ref().map(|buf| buf.to_string_lossy().into_owned()).unwrap_or_else(|| {
             "<none>".to_owned()
         })
@@ -182,11 +342,79 @@ impl Config {
             }),
         };
 
-        res.map(|config| {
-            config.with_path(path)
+        res.and_then(|config| {
+            config.with_path(path).expand_env()
         })
     }
 
+    /// Expands references to environment variables, written as `${VAR}`, in the string fields of
+    /// this configuration. This fails if any referenced variable is not set.
+    fn expand_env(mut self) -> Result<Config> {
+        let path = self.path();
+        let err = |var| InvalidConfig {
+            path: path.clone(),
+            cause: ConfigError::UnsetEnvVar { var: var },
+        };
+        {
+            let fields = vec![
+                &mut self.nickname, &mut self.nick_password, &mut self.sasl_account,
+                &mut self.username,
+                &mut self.realname, &mut self.user_mode_flags, &mut self.server,
+                &mut self.password, &mut self.sts_cache, &mut self.unix_socket_path,
+                &mut self.cert_path,
+                &mut self.client_cert_path, &mut self.client_cert_pass, &mut self.encoding,
+                &mut self.umodes, &mut self.user_info, &mut self.version, &mut self.source,
+                &mut self.quit_message, &mut self.auto_away_message,
+            ];
+            for field in fields.into_iter().filter_map(|f| f.as_mut()) {
+                *field = expand_env_vars(field).map_err(&err)?;
+            }
+        }
+        {
+            let lists = vec![
+                &mut self.owners, &mut self.alt_nicks, &mut self.alt_servers,
+                &mut self.channels, &mut self.ghost_sequence,
+            ];
+            for value in lists.into_iter().filter_map(|l| l.as_mut()).flat_map(|l| l.iter_mut()) {
+                *value = expand_env_vars(value).map_err(&err)?;
+            }
+        }
+        {
+            let maps = vec![&mut self.channel_keys, &mut self.service_names, &mut self.options];
+            for value in maps.into_iter().filter_map(|m| m.as_mut()).flat_map(|m| m.values_mut()) {
+                *value = expand_env_vars(value).map_err(&err)?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Loads a configuration from a string of TOML, e.g. one embedded in the program or injected
+    /// through the environment. This requires the `toml` feature, which is enabled by default.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::Config;
+    /// # fn main() {
+    /// # #[cfg(feature = "toml")] {
+    /// let config = Config::from_toml_str(r#"
+    ///     nickname = "test"
+    ///     server = "irc.example.com"
+    /// "#).unwrap();
+    /// assert_eq!(config.server().unwrap(), "irc.example.com");
+    /// # }
+    /// # }
+    /// ```
+    pub fn from_toml_str(data: &str) -> Result<Config> {
+        Config::load_toml(&"<none>", data).and_then(|config| config.expand_env())
+    }
+
+    /// Loads a configuration from a string of JSON, e.g. one fetched over the network. This
+    /// requires the `json` feature.
+    pub fn from_json_str(data: &str) -> Result<Config> {
+        Config::load_json(&"<none>", data).and_then(|config| config.expand_env())
+    }
+
     #[cfg(feature = "json")]
     fn load_json<P: AsRef<Path>>(path: &P, data: &str) -> Result<Config> {
         serde_json::from_str(&data[..]).map_err(|e| {
@@ -333,6 +561,88 @@ impl Config {
         })
     }
 
+    /// Checks the configuration for values that are invalid or contradict each other, so that
+    /// these are reported clearly before attempting to connect. The offending field is named in
+    /// the resulting `ConfigError::InvalidField`. Fields that are merely ineffective, such as a
+    /// certificate for a connection without TLS, are only logged as warnings.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::Config;
+    /// # fn main() {
+    /// let config = Config {
+    ///     nickname: Some("test".to_owned()),
+    ///     server: Some("irc.example.com".to_owned()),
+    ///     port: Some(0),
+    ///     ..Config::default()
+    /// };
+    /// assert!(config.validate().is_err());
+    /// # }
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        for (field, reason) in self.ineffective_fields() {
+            warn!("Configuration field {} {}.", field, reason);
+        }
+        let invalid = if self.nickname.as_ref().map_or(false, |s| s.is_empty()) {
+            Some(("nickname", "must not be empty"))
+        } else if self.server.as_ref().map_or(false, |s| s.is_empty()) {
+            Some(("server", "must not be empty"))
+        } else if self.alt_servers.iter().flat_map(|v| v.iter()).any(|s| s.is_empty()) {
+            Some(("alt_servers", "must not contain empty entries"))
+        } else if self.unix_socket_path() == Some("") {
+            Some(("unix_socket_path", "must not be empty"))
+        } else if self.unix_socket_path.is_some() && cfg!(not(unix)) {
+            Some(("unix_socket_path", "is only supported on Unix platforms"))
+        } else if self.unix_socket_path.is_some() && (self.use_ssl() || self.use_starttls()) {
+            Some(("unix_socket_path", "cannot be combined with use_ssl or use_starttls"))
+        } else if self.port == Some(0) {
+            Some(("port", "must not be 0"))
+        } else if self.tcp_keepalive_ms.unwrap_or(1000) < 1000 {
+            Some(("tcp_keepalive_ms", "must be at least 1000"))
+        } else if encoding_from_whatwg_label(self.encoding()).is_none() {
+            Some(("encoding", "is not a known encoding label"))
+        } else if self.max_messages_in_burst == Some(0) {
+            Some(("max_messages_in_burst", "must be at least 1"))
+        } else if self.max_messages_per_target_burst == Some(0) {
+            Some(("max_messages_per_target_burst", "must be at least 1"))
+        } else if self.max_tracked_reactions == Some(0) {
+            Some(("max_tracked_reactions", "must be at least 1"))
+        } else if self.max_tracked_users_per_channel == Some(0) {
+            Some(("max_tracked_users_per_channel", "must be at least 1"))
+        } else if self.outgoing_queue_max == Some(0) {
+            Some(("outgoing_queue_max", "must be at least 1"))
+        } else {
+            None
+        };
+        match invalid {
+            Some((field, reason)) => Err(InvalidConfig {
+                path: self.path(),
+                cause: ConfigError::InvalidField { field: field, reason: reason },
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Gets the fields that are set but have no effect given the rest of the configuration, along
+    /// with the reason why.
+    fn ineffective_fields(&self) -> Vec<(&'static str, &'static str)> {
+        let tls = self.use_ssl() || self.use_starttls() || self.sts_cache.is_some();
+        let mut fields = vec![];
+        if self.cert_path.is_some() && !tls {
+            fields.push(("cert_path", "has no effect without use_ssl, use_starttls, or sts_cache"));
+        }
+        if self.client_cert_path.is_some() && !tls {
+            fields.push((
+                "client_cert_path", "has no effect without use_ssl, use_starttls, or sts_cache"
+            ));
+        }
+        if self.client_cert_pass.is_some() && self.client_cert_path.is_none() {
+            fields.push(("client_cert_pass", "has no effect without client_cert_path"));
+        }
+        fields
+    }
+
     /// Determines whether or not the nickname provided is the owner of the bot.
     pub fn is_owner(&self, nickname: &str) -> bool {
         self.owners
@@ -357,6 +667,21 @@ impl Config {
         self.nick_password.as_ref().map_or("", |s| &s[..])
     }
 
+    /// Gets the SASL mechanism specified in the configuration.
+    /// This defaults to `PLAIN` when not specified.
+    pub fn sasl_mechanism(&self) -> SaslMechanism {
+        self.sasl_mechanism.unwrap_or(SaslMechanism::Plain)
+    }
+
+    /// Gets the account name to authenticate as with SASL `PLAIN`.
+    /// This defaults to the user's nickname when not specified.
+    pub fn sasl_account(&self) -> Result<&str> {
+        match self.sasl_account {
+            Some(ref account) => Ok(account),
+            None => self.nickname(),
+        }
+    }
+
     /// Gets the alternate nicknames specified in the configuration.
     /// This defaults to an empty vector when not specified.
     pub fn alternate_nicknames(&self) -> Vec<&str> {
@@ -365,6 +690,12 @@ impl Config {
         })
     }
 
+    /// Gets whether or not to truncate nicknames to the server's `NICKLEN`.
+    /// This defaults to false when not specified.
+    pub fn truncate_nick(&self) -> bool {
+        self.truncate_nick.as_ref().cloned().unwrap_or(false)
+    }
+
 
     /// Gets the username specified in the configuration.
     /// This defaults to the user's nickname when not specified.
@@ -378,6 +709,12 @@ impl Config {
         self.realname.as_ref().map_or(self.nickname().unwrap_or("irc"), |s| &s)
     }
 
+    /// Gets the mode field to send in the USER command during registration.
+    /// This defaults to `0` when not specified.
+    pub fn user_mode_flags(&self) -> &str {
+        self.user_mode_flags.as_ref().map_or("0", |s| &s[..])
+    }
+
     /// Gets the address of the server specified in the configuration.
     pub fn server(&self) -> Result<&str> {
         self.server.as_ref().map(|s| &s[..]).ok_or_else(|| {
@@ -398,6 +735,20 @@ impl Config {
         })
     }
 
+    /// Gets every server to try connecting to in order, as pairs of host and port. This is the
+    /// primary server followed by any alternative servers.
+    pub fn servers(&self) -> Result<Vec<(&str, u16)>> {
+        let mut servers = vec![(self.server()?, self.port())];
+        for alt in self.alt_servers.iter().flat_map(|v| v.iter()) {
+            let mut parts = alt.rsplitn(2, ':');
+            servers.push(match (parts.next().and_then(|p| p.parse().ok()), parts.next()) {
+                (Some(port), Some(host)) if !host.is_empty() => (host, port),
+                _ => (&alt[..], self.port()),
+            });
+        }
+        Ok(servers)
+    }
+
     /// Gets the server and port as a `SocketAddr`.
     /// This panics when server is not specified or the address is malformed.
     pub fn socket_addr(&self) -> Result<SocketAddr> {
@@ -418,6 +769,38 @@ impl Config {
         self.use_ssl.as_ref().cloned().unwrap_or(false)
     }
 
+    /// Gets whether or not to upgrade the connection with STARTTLS.
+    /// This defaults to false when not specified.
+    pub fn use_starttls(&self) -> bool {
+        self.use_starttls.as_ref().cloned().unwrap_or(false)
+    }
+
+    /// Gets the path to the STS policy cache if specified.
+    pub fn sts_cache(&self) -> Option<&str> {
+        self.sts_cache.as_ref().map(|s| &s[..])
+    }
+
+    /// Gets the path to the Unix domain socket to connect to if specified.
+    pub fn unix_socket_path(&self) -> Option<&str> {
+        self.unix_socket_path.as_ref().map(|s| &s[..])
+    }
+
+    /// Gets the custom resolver for server hostnames if specified.
+    pub fn resolver(&self) -> Option<&Resolver> {
+        self.resolver.as_ref()
+    }
+
+    /// Gets the idle time in milliseconds before TCP keepalive probes are sent if specified.
+    pub fn tcp_keepalive_ms(&self) -> Option<u32> {
+        self.tcp_keepalive_ms
+    }
+
+    /// Gets whether or not to disable Nagle's algorithm on the connection.
+    /// This defaults to false when not specified.
+    pub fn tcp_nodelay(&self) -> bool {
+        self.tcp_nodelay.as_ref().cloned().unwrap_or(false)
+    }
+
     /// Gets the path to the SSL certificate in DER format if specified.
     pub fn cert_path(&self) -> Option<&str> {
         self.cert_path.as_ref().map(|s| &s[..])
@@ -433,12 +816,24 @@ impl Config {
         self.client_cert_pass.as_ref().map_or("", |s| &s[..])
     }
 
-    /// Gets the encoding to use for this connection. This requires the encode feature to work.
+    /// Gets the encoding to use for this connection.
     /// This defaults to UTF-8 when not specified.
     pub fn encoding(&self) -> &str {
         self.encoding.as_ref().map_or("UTF-8", |s| &s)
     }
 
+    /// Gets whether or not invalid byte sequences should be replaced when decoding.
+    /// This defaults to true when not specified.
+    pub fn decode_lossy(&self) -> bool {
+        self.decode_lossy.as_ref().cloned().unwrap_or(true)
+    }
+
+    /// Gets whether or not line breaks embedded in received messages should be stripped.
+    /// This defaults to false when not specified.
+    pub fn sanitize_incoming(&self) -> bool {
+        self.sanitize_incoming.as_ref().cloned().unwrap_or(false)
+    }
+
     /// Gets the channels to join upon connection.
     /// This defaults to an empty vector if it's not specified.
     pub fn channels(&self) -> Vec<&str> {
@@ -447,7 +842,6 @@ impl Config {
         })
     }
 
-
     /// Gets the key for the specified channel if it exists in the configuration.
     pub fn channel_key(&self, chan: &str) -> Option<&str> {
         self.channel_keys.as_ref().and_then(|m| {
@@ -455,12 +849,24 @@ impl Config {
         })
     }
 
+    /// Gets the nickname used on this network by the service with the given standard name, e.g.
+    /// `NickServ`. This defaults to the standard name when not specified.
+    pub fn service_name<'a>(&'a self, service: &'a str) -> &'a str {
+        self.service_names.as_ref().and_then(|m| m.get(service)).map_or(service, |s| &s[..])
+    }
+
     /// Gets the user modes to set on connect specified in the configuration.
     /// This defaults to an empty string when not specified.
     pub fn umodes(&self) -> &str {
         self.umodes.as_ref().map_or("", |s| &s[..])
     }
 
+    /// Gets whether the client should identify itself as a bot as specified in the configuration.
+    /// This defaults to false when not specified.
+    pub fn is_bot(&self) -> bool {
+        self.is_bot.as_ref().cloned().unwrap_or(false)
+    }
+
     /// Gets the string to be sent in response to CTCP USERINFO requests.
     /// This defaults to an empty string when not specified.
     pub fn user_info(&self) -> &str {
@@ -474,6 +880,24 @@ impl Config {
         self.version.as_ref().map_or(::VERSION_STR, |s| &s)
     }
 
+    /// Gets the message to quit with when no other message is given.
+    /// This defaults to `Powered by Rust.` when not specified.
+    pub fn quit_message(&self) -> &str {
+        self.quit_message.as_ref().map_or("Powered by Rust.", |s| &s[..])
+    }
+
+    /// Gets the amount of time in milliseconds without sending any messages after which the
+    /// client marks itself as away, if specified.
+    pub fn auto_away_after_ms(&self) -> Option<u32> {
+        self.auto_away_after_ms
+    }
+
+    /// Gets the away message used when the client marks itself as away automatically.
+    /// This defaults to `Idle` when not specified.
+    pub fn auto_away_message(&self) -> &str {
+        self.auto_away_message.as_ref().map_or("Idle", |s| &s[..])
+    }
+
     /// Gets the string to be sent in response to CTCP SOURCE requests.
     /// This defaults to `https://github.com/aatxe/irc` when not specified.
     pub fn source(&self) -> &str {
@@ -496,6 +920,13 @@ impl Config {
         self.ping_timeout.as_ref().cloned().unwrap_or(10)
     }
 
+    /// Gets the amount of time in seconds to wait for the server to welcome the client after
+    /// connecting.
+    /// This defaults to 60 seconds when not specified.
+    pub fn registration_timeout(&self) -> u32 {
+        self.registration_timeout.as_ref().cloned().unwrap_or(60)
+    }
+
     /// The amount of time in seconds to consider a window for burst messages. The message throttling
     /// system maintains the invariant that in the past `burst_window_length` seconds, the maximum
     /// number of messages sent is `max_messages_in_burst`.
@@ -513,6 +944,24 @@ impl Config {
         self.max_messages_in_burst.as_ref().cloned().unwrap_or(15)
     }
 
+    /// Gets the maximum number of messages to any one target that can be sent in a burst window,
+    /// if limited.
+    pub fn max_messages_per_target_burst(&self) -> Option<u32> {
+        self.max_messages_per_target_burst
+    }
+
+    /// Gets the maximum number of messages that can be waiting to be sent at once, if limited.
+    /// The outgoing queue is unbounded when this is not specified.
+    pub fn outgoing_queue_max(&self) -> Option<usize> {
+        self.outgoing_queue_max.map(|max| max as usize)
+    }
+
+    /// Gets what to do with messages sent while the outgoing queue is full.
+    /// This defaults to `block` when not specified.
+    pub fn outgoing_overflow_policy(&self) -> OverflowPolicy {
+        self.outgoing_overflow_policy.unwrap_or(OverflowPolicy::Block)
+    }
+
     /// Gets whether or not to attempt nickname reclamation using NickServ GHOST.
     /// This defaults to false when not specified.
     pub fn should_ghost(&self) -> bool {
@@ -527,6 +976,82 @@ impl Config {
         })
     }
 
+    /// Gets whether or not to automatically rejoin channels after being kicked.
+    /// This defaults to false when not specified.
+    pub fn rejoin_on_kick(&self) -> bool {
+        self.rejoin_on_kick.as_ref().cloned().unwrap_or(false)
+    }
+
+    /// Gets the delay in milliseconds before rejoining a channel after being kicked.
+    /// This defaults to 5000 milliseconds when not specified.
+    pub fn rejoin_delay_ms(&self) -> u32 {
+        self.rejoin_delay_ms.as_ref().cloned().unwrap_or(5000)
+    }
+
+    /// Gets the delay in milliseconds before retrying a connection that failed before
+    /// registration. This defaults to 1000 milliseconds when not specified.
+    pub fn reconnect_delay_ms(&self) -> u32 {
+        self.reconnect_delay_ms.as_ref().cloned().unwrap_or(1000)
+    }
+
+    /// Gets the longest delay in milliseconds between reconnection attempts.
+    /// This defaults to 300000 milliseconds (five minutes) when not specified.
+    pub fn max_reconnect_delay_ms(&self) -> u32 {
+        self.max_reconnect_delay_ms.as_ref().cloned().unwrap_or(300_000)
+    }
+
+    /// Gets the time in seconds during which repeated throttled `WHOIS` requests are ignored.
+    /// This defaults to 60s when not specified.
+    pub fn whois_throttle_secs(&self) -> u32 {
+        self.whois_throttle_secs.as_ref().cloned().unwrap_or(60)
+    }
+
+    /// Gets whether or not to collect the reactions to each message.
+    /// This defaults to false when not specified.
+    pub fn track_reactions(&self) -> bool {
+        self.track_reactions.as_ref().cloned().unwrap_or(false)
+    }
+
+    /// Gets the maximum number of messages whose reactions are tracked.
+    /// This defaults to 1000 messages when not specified.
+    pub fn max_tracked_reactions(&self) -> usize {
+        self.max_tracked_reactions.map_or(1000, |max| max as usize)
+    }
+
+    /// Gets the maximum number of channels whose users are tracked, if limited.
+    pub fn max_tracked_channels(&self) -> Option<usize> {
+        self.max_tracked_channels.map(|max| max as usize)
+    }
+
+    /// Gets the maximum number of users tracked in any one channel, if limited.
+    pub fn max_tracked_users_per_channel(&self) -> Option<usize> {
+        self.max_tracked_users_per_channel.map(|max| max as usize)
+    }
+
+    /// Gets the length in milliseconds of the window during which duplicate messages are dropped,
+    /// if specified.
+    pub fn dedupe_window_ms(&self) -> Option<u32> {
+        self.dedupe_window_ms
+    }
+
+    /// Gets whether or not to fail sending messages over the line length limit.
+    /// This defaults to false when not specified.
+    pub fn reject_long_messages(&self) -> bool {
+        self.reject_long_messages.as_ref().cloned().unwrap_or(false)
+    }
+
+    /// Gets whether or not to split text sent with `send_privmsg` and `send_notice` that would
+    /// exceed the line length limit. This defaults to false when not specified.
+    pub fn split_long_messages(&self) -> bool {
+        self.split_long_messages.as_ref().cloned().unwrap_or(false)
+    }
+
+    /// Gets whether or not to send a `WHO` for each channel joined.
+    /// This defaults to false when not specified.
+    pub fn who_on_join(&self) -> bool {
+        self.who_on_join.as_ref().cloned().unwrap_or(false)
+    }
+
     /// Looks up the specified string in the options map.
     pub fn get_option(&self, option: &str) -> Option<&str> {
         self.options.as_ref().and_then(|o| {
@@ -548,6 +1073,145 @@ impl Config {
     }
 }
 
+/// A builder for assembling a `Config` with chained method calls, as an alternative to struct
+/// update syntax. Any field not set on the builder is left unspecified, and so takes its default.
+///
+/// # Example
+/// ```
+/// # extern crate irc;
+/// # use irc::client::prelude::Config;
+/// # fn main() {
+/// let config = Config::builder()
+///     .nickname("test")
+///     .server("irc.example.com")
+///     .port(6697)
+///     .use_tls(true)
+///     .add_channel("#rust")
+///     .build()
+///     .unwrap();
+/// assert_eq!(config.channels(), vec!["#rust"]);
+/// # }
+/// ```
+#[derive(Clone, Default, Debug)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Sets the client's nickname.
+    pub fn nickname<S: Into<String>>(mut self, nickname: S) -> ConfigBuilder {
+        self.config.nickname = Some(nickname.into());
+        self
+    }
+
+    /// Sets the server to connect to.
+    pub fn server<S: Into<String>>(mut self, server: S) -> ConfigBuilder {
+        self.config.server = Some(server.into());
+        self
+    }
+
+    /// Sets the port to connect on.
+    pub fn port(mut self, port: u16) -> ConfigBuilder {
+        self.config.port = Some(port);
+        self
+    }
+
+    /// Sets whether or not to connect with TLS.
+    pub fn use_tls(mut self, use_tls: bool) -> ConfigBuilder {
+        self.config.use_ssl = Some(use_tls);
+        self
+    }
+
+    /// Sets the channels to join on connection, replacing any added so far.
+    pub fn channels<I, S>(mut self, channels: I) -> ConfigBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.channels = Some(channels.into_iter().map(|c| c.into()).collect());
+        self
+    }
+
+    /// Adds a channel to join on connection.
+    pub fn add_channel<S: Into<String>>(mut self, channel: S) -> ConfigBuilder {
+        self.config.channels.get_or_insert_with(Vec::new).push(channel.into());
+        self
+    }
+
+    /// Sets a custom resolver for the hostnames of the configured servers.
+    pub fn resolver<F>(mut self, resolve: F) -> ConfigBuilder
+    where
+        F: Fn(&str, u16) -> io::Result<Vec<SocketAddr>> + Send + Sync + 'static,
+    {
+        self.config.resolver = Some(Resolver::new(resolve));
+        self
+    }
+
+    /// Builds the configuration, checking it with
+    /// [`Config::validate`](struct.Config.html#method.validate).
+    pub fn build(self) -> Result<Config> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// What to do with messages sent while the outgoing queue is full, as configured by
+/// `outgoing_overflow_policy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Wait for room in the queue. Clients that send their messages on the same event loop that
+    /// handles them, such as those made by `IrcClient::new_future` or `IrcReactor`, cannot wait
+    /// for it to drain, and drop the message as with `DropNewest` instead.
+    Block,
+    /// Discard the oldest queued message to make room.
+    DropOldest,
+    /// Discard the message being sent.
+    DropNewest,
+    /// Fail the send with `OutgoingQueueFull`.
+    Error,
+}
+
+/// A SASL mechanism to authenticate with, as configured by `sasl_mechanism`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum SaslMechanism {
+    /// Authenticate with an account name and password.
+    Plain,
+    /// Authenticate with the client certificate presented when connecting.
+    External,
+}
+
+impl SaslMechanism {
+    /// Gets the name of this mechanism as sent in `AUTHENTICATE`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::External => "EXTERNAL",
+        }
+    }
+}
+
+/// Expands references to environment variables, written as `${VAR}`, in the given value. A `$`
+/// that does not begin a complete reference is kept as is. If a referenced variable is not set,
+/// its name is returned as the error.
+fn expand_env_vars(value: &str) -> ::std::result::Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let var = &rest[start + 2..end];
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&env::var(var).map_err(|_| var.to_owned())?);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
 #[cfg(test)]
 mod test {
     use std::collections::HashMap;
@@ -555,36 +1219,79 @@ mod test {
     #[cfg(feature = "json")]
     use std::path::Path;
 
+    #[cfg(feature = "toml")]
+    use std::env;
+
     use super::Config;
+    #[cfg(feature = "toml")]
+    use super::{OverflowPolicy, SaslMechanism};
+    use error::{ConfigError, IrcError};
+    #[cfg(feature = "toml")]
+    use error::IrcError::InvalidConfig;
 
     fn test_config() -> Config {
         Config {
             owners: Some(vec![format!("test")]),
             nickname: Some(format!("test")),
             nick_password: None,
+            sasl_mechanism: None,
+            sasl_account: None,
             alt_nicks: None,
+            truncate_nick: None,
             username: Some(format!("test")),
             realname: Some(format!("test")),
+            user_mode_flags: None,
             password: Some(String::new()),
             umodes: Some(format!("+BR")),
+            is_bot: None,
             server: Some(format!("irc.test.net")),
             port: Some(6667),
+            alt_servers: None,
             use_ssl: Some(false),
+            use_starttls: None,
+            sts_cache: None,
+            unix_socket_path: None,
+            resolver: None,
+            tcp_keepalive_ms: None,
+            tcp_nodelay: None,
             cert_path: None,
             client_cert_path: None,
             client_cert_pass: None,
             encoding: Some(format!("UTF-8")),
+            decode_lossy: None,
+            sanitize_incoming: None,
             channels: Some(vec![format!("#test"), format!("#test2")]),
             channel_keys: None,
+            service_names: None,
             user_info: None,
             version: None,
             source: None,
+            quit_message: None,
+            auto_away_after_ms: None,
+            auto_away_message: None,
             ping_time: None,
             ping_timeout: None,
+            registration_timeout: None,
             burst_window_length: None,
             max_messages_in_burst: None,
+            max_messages_per_target_burst: None,
+            outgoing_queue_max: None,
+            outgoing_overflow_policy: None,
             should_ghost: None,
             ghost_sequence: None,
+            rejoin_on_kick: None,
+            rejoin_delay_ms: None,
+            reconnect_delay_ms: None,
+            max_reconnect_delay_ms: None,
+            who_on_join: None,
+            whois_throttle_secs: None,
+            track_reactions: None,
+            max_tracked_reactions: None,
+            max_tracked_channels: None,
+            max_tracked_users_per_channel: None,
+            dedupe_window_ms: None,
+            reject_long_messages: None,
+            split_long_messages: None,
             options: Some(HashMap::new()),
             use_mock_connection: None,
             mock_initial_value: None,
@@ -611,6 +1318,205 @@ mod test {
         assert_eq!(Config::load("client_config.yaml").unwrap(), test_config().with_path("client_config.yaml"));
     }
 
+    #[test]
+    #[cfg(feature = "toml")]
+    fn from_toml_str() {
+        let config = Config::from_toml_str("nickname = \"test\"\nserver = \"irc.test.net\"\n");
+        assert_eq!(config.unwrap(), Config {
+            nickname: Some(format!("test")),
+            server: Some(format!("irc.test.net")),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn from_json_str() {
+        let config = Config::from_json_str(r#"{"nickname": "test", "server": "irc.test.net"}"#);
+        assert_eq!(config.unwrap(), Config {
+            nickname: Some(format!("test")),
+            server: Some(format!("irc.test.net")),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn from_toml_str_invalid() {
+        match Config::from_toml_str("nickname = ") {
+            Err(InvalidConfig { path, .. }) => assert_eq!(path, "<none>"),
+            res => panic!("expected InvalidConfig, got {:?}", res),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn overflow_policy_from_toml_str() {
+        let config = Config::from_toml_str("outgoing_overflow_policy = \"drop_oldest\"").unwrap();
+        assert_eq!(config.outgoing_overflow_policy(), OverflowPolicy::DropOldest);
+        assert_eq!(Config::default().outgoing_overflow_policy(), OverflowPolicy::Block);
+        match Config::from_toml_str("outgoing_overflow_policy = \"drop_all\"") {
+            Err(InvalidConfig { cause: ConfigError::InvalidToml(_), .. }) => (),
+            res => panic!("expected InvalidToml, got {:?}", res),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn sasl_from_toml_str() {
+        let config = Config::from_toml_str(
+            "nickname = \"test\"\nsasl_mechanism = \"EXTERNAL\"\nsasl_account = \"account\"\n"
+        ).unwrap();
+        assert_eq!(config.sasl_mechanism(), SaslMechanism::External);
+        assert_eq!(config.sasl_account().unwrap(), "account");
+        let config = Config::from_toml_str("nickname = \"test\"").unwrap();
+        assert_eq!(config.sasl_mechanism(), SaslMechanism::Plain);
+        assert_eq!(config.sasl_account().unwrap(), "test");
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn expand_env_in_password() {
+        env::set_var("IRC_TEST_PASS", "hunter2");
+        let config = Config::from_toml_str(
+            "nickname = \"test\"\npassword = \"${IRC_TEST_PASS}\"\n\
+             nick_password = \"pre-${IRC_TEST_PASS}-post $HOME ${\"\n"
+        ).unwrap();
+        assert_eq!(config.password(), "hunter2");
+        assert_eq!(config.nick_password(), "pre-hunter2-post $HOME ${");
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn expand_env_in_connection_settings() {
+        env::set_var("IRC_TEST_HOST", "irc.example.com");
+        let config = Config::from_toml_str(
+            "alt_servers = [\"${IRC_TEST_HOST}:6697\"]\n\
+             unix_socket_path = \"/run/${IRC_TEST_HOST}.sock\"\n\
+             [service_names]\nNickServ = \"NickServ@services.${IRC_TEST_HOST}\"\n"
+        ).unwrap();
+        assert_eq!(config.alt_servers, Some(vec!["irc.example.com:6697".to_owned()]));
+        assert_eq!(config.unix_socket_path, Some("/run/irc.example.com.sock".to_owned()));
+        assert_eq!(
+            config.service_names.unwrap()["NickServ"], "NickServ@services.irc.example.com"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn expand_env_unset() {
+        env::remove_var("IRC_TEST_UNSET");
+        match Config::from_toml_str("password = \"${IRC_TEST_UNSET}\"") {
+            Err(InvalidConfig { cause: ConfigError::UnsetEnvVar { var }, .. }) => {
+                assert_eq!(var, "IRC_TEST_UNSET")
+            }
+            res => panic!("expected UnsetEnvVar, got {:?}", res),
+        }
+    }
+
+    fn assert_invalid_field(config: Config, expected: &str) {
+        match config.validate() {
+            Err(IrcError::InvalidConfig { cause: ConfigError::InvalidField { field, .. }, .. }) => {
+                assert_eq!(field, expected)
+            }
+            res => panic!("expected InvalidField for {}, got {:?}", expected, res),
+        }
+    }
+
+    #[test]
+    fn validate() {
+        assert!(test_config().validate().is_ok());
+        assert_invalid_field(Config { server: Some(String::new()), ..test_config() }, "server");
+        assert_invalid_field(Config { port: Some(0), ..test_config() }, "port");
+        assert_invalid_field(
+            Config { unix_socket_path: Some(String::new()), ..test_config() }, "unix_socket_path"
+        );
+        assert_invalid_field(
+            Config {
+                unix_socket_path: Some("/run/ircd.sock".to_owned()),
+                use_ssl: Some(true),
+                ..test_config()
+            },
+            "unix_socket_path"
+        );
+        assert_invalid_field(
+            Config { tcp_keepalive_ms: Some(500), ..test_config() }, "tcp_keepalive_ms"
+        );
+        assert_invalid_field(
+            Config { encoding: Some(format!("bogus")), ..test_config() }, "encoding"
+        );
+        assert_invalid_field(
+            Config { max_messages_per_target_burst: Some(0), ..test_config() },
+            "max_messages_per_target_burst"
+        );
+        assert!(Config {
+            cert_path: Some(format!("cert.der")),
+            use_ssl: Some(true),
+            ..test_config()
+        }.validate().is_ok());
+    }
+
+    #[test]
+    fn ineffective_fields() {
+        let config = Config {
+            cert_path: Some(format!("cert.der")),
+            client_cert_pass: Some(format!("pass")),
+            ..test_config()
+        };
+        // Such configurations loaded fine before these fields were checked, and so still do.
+        assert!(config.validate().is_ok());
+        let fields: Vec<_> = config.ineffective_fields().into_iter().map(|f| f.0).collect();
+        assert_eq!(fields, vec!["cert_path", "client_cert_pass"]);
+        let config = Config { use_ssl: Some(true), ..config };
+        let fields: Vec<_> = config.ineffective_fields().into_iter().map(|f| f.0).collect();
+        assert_eq!(fields, vec!["client_cert_pass"]);
+    }
+
+    #[test]
+    fn builder() {
+        let config = Config::builder()
+            .nickname("test")
+            .server("irc.test.net")
+            .port(6697)
+            .use_tls(true)
+            .channels(vec!["#test"])
+            .add_channel("#test2")
+            .build()
+            .unwrap();
+        assert_eq!(config, Config {
+            nickname: Some(format!("test")),
+            server: Some(format!("irc.test.net")),
+            port: Some(6697),
+            use_ssl: Some(true),
+            channels: Some(vec![format!("#test"), format!("#test2")]),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn builder_validates() {
+        match Config::builder().nickname("test").port(0).build() {
+            Err(IrcError::InvalidConfig { cause: ConfigError::InvalidField { field, .. }, .. }) => {
+                assert_eq!(field, "port")
+            }
+            res => panic!("expected InvalidField, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn servers() {
+        let cfg = Config {
+            server: Some(format!("irc.test.net")),
+            port: Some(6697),
+            alt_servers: Some(vec![format!("irc2.test.net:7000"), format!("irc3.test.net")]),
+            ..Default::default()
+        };
+        assert_eq!(
+            cfg.servers().unwrap(),
+            vec![("irc.test.net", 6697), ("irc2.test.net", 7000), ("irc3.test.net", 6697)]
+        );
+    }
+
     #[test]
     fn is_owner() {
         let cfg = Config {
@@ -0,0 +1,110 @@
+//! Data for tracking channel list modes, i.e. bans, ban exceptions, and invite exceptions.
+use proto::{ChannelMode, Response};
+
+/// A channel mode that holds a list of masks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ListMode {
+    /// The ban list (`+b`).
+    Ban,
+    /// The ban exception list (`+e`).
+    Exception,
+    /// The invite exception list (`+I`).
+    InviteException,
+}
+
+impl ListMode {
+    /// Gets the channel mode corresponding to this list.
+    pub fn channel_mode(&self) -> ChannelMode {
+        match *self {
+            ListMode::Ban => ChannelMode::Ban,
+            ListMode::Exception => ChannelMode::Exception,
+            ListMode::InviteException => ChannelMode::InviteException,
+        }
+    }
+
+    /// Gets the list mode and whether the reply ends the list for the given response, if the
+    /// response is part of a list reply at all.
+    pub(crate) fn from_response(resp: Response) -> Option<(ListMode, bool)> {
+        match resp {
+            Response::RPL_BANLIST => Some((ListMode::Ban, false)),
+            Response::RPL_ENDOFBANLIST => Some((ListMode::Ban, true)),
+            Response::RPL_EXCEPTLIST => Some((ListMode::Exception, false)),
+            Response::RPL_ENDOFEXCEPTLIST => Some((ListMode::Exception, true)),
+            Response::RPL_INVITELIST => Some((ListMode::InviteException, false)),
+            Response::RPL_ENDOFINVITELIST => Some((ListMode::InviteException, true)),
+            _ => None,
+        }
+    }
+}
+
+/// An entry in one of a channel's lists.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListEntry {
+    /// The mask on the list.
+    mask: String,
+    /// Who set the entry, if the server reported it.
+    set_by: Option<String>,
+    /// When the entry was set as a Unix timestamp, if the server reported it.
+    set_at: Option<u64>,
+}
+
+impl ListEntry {
+    /// Creates a new list entry for the given mask.
+    pub fn new(mask: &str, set_by: Option<&str>, set_at: Option<u64>) -> ListEntry {
+        ListEntry {
+            mask: mask.to_owned(),
+            set_by: set_by.map(|s| s.to_owned()),
+            set_at: set_at,
+        }
+    }
+
+    /// Parses a list entry from the arguments of a list reply, which take the form
+    /// `<client> <channel> <mask> [<who> <set-ts>]`.
+    #[cfg(not(feature = "nochanlists"))]
+    pub(crate) fn from_args(args: &[String]) -> Option<ListEntry> {
+        args.get(2).map(|mask| ListEntry {
+            mask: mask.clone(),
+            set_by: args.get(3).cloned(),
+            set_at: args.get(4).and_then(|ts| ts.parse().ok()),
+        })
+    }
+
+    /// Gets the mask on the list.
+    pub fn mask(&self) -> &str {
+        &self.mask
+    }
+
+    /// Gets who set the entry, if known.
+    pub fn set_by(&self) -> Option<&str> {
+        self.set_by.as_ref().map(|s| &s[..])
+    }
+
+    /// Gets when the entry was set as a Unix timestamp, if known.
+    pub fn set_at(&self) -> Option<u64> {
+        self.set_at
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "nochanlists"))]
+    use super::ListEntry;
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn from_args() {
+        let args: Vec<_> = vec!["test", "#test", "*!*@host", "op!op@host", "1500000000"]
+            .into_iter()
+            .map(|s| s.to_owned())
+            .collect();
+        assert_eq!(
+            ListEntry::from_args(&args),
+            Some(ListEntry::new("*!*@host", Some("op!op@host"), Some(1500000000)))
+        );
+        assert_eq!(
+            ListEntry::from_args(&args[..3]),
+            Some(ListEntry::new("*!*@host", None, None))
+        );
+        assert_eq!(ListEntry::from_args(&args[..2]), None);
+    }
+}
@@ -0,0 +1,100 @@
+//! Counters for the traffic over a connection, e.g. for exporting to a monitoring system.
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A snapshot of the counters for the traffic of a client, taken with
+/// [`IrcClient::metrics`](../../struct.IrcClient.html#method.metrics). Byte counts are of messages
+/// as serialized by this crate, including tags and the trailing CRLF.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionMetrics {
+    /// The number of messages written to the connection.
+    pub messages_sent: usize,
+    /// The number of messages read from the connection.
+    pub messages_received: usize,
+    /// The number of bytes written to the connection.
+    pub bytes_sent: usize,
+    /// The number of bytes read from the connection.
+    pub bytes_received: usize,
+    /// The number of times the client has reconnected, carrying over the counters of the previous
+    /// connections.
+    pub reconnects: usize,
+    /// The number of messages held back by rate limiting before being sent.
+    pub rate_limit_deferrals: usize,
+}
+
+/// The live counters shared between a transport and its client. Each counter is updated on its
+/// own, so a snapshot taken while messages are in flight may be slightly inconsistent.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    messages_sent: AtomicUsize,
+    messages_received: AtomicUsize,
+    bytes_sent: AtomicUsize,
+    bytes_received: AtomicUsize,
+    reconnects: AtomicUsize,
+    rate_limit_deferrals: AtomicUsize,
+}
+
+impl Metrics {
+    /// Counts a message of the given length in bytes as written to the connection.
+    pub(crate) fn message_sent(&self, len: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(len, Ordering::Relaxed);
+    }
+
+    /// Counts a message of the given length in bytes as read from the connection.
+    pub(crate) fn message_received(&self, len: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(len, Ordering::Relaxed);
+    }
+
+    /// Counts a message as held back by rate limiting.
+    pub(crate) fn message_deferred(&self) {
+        self.rate_limit_deferrals.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Carries over the counters of the connection that this one replaced, counting a reconnect.
+    pub(crate) fn inherit(&self, old: &Metrics) {
+        let old = old.snapshot();
+        self.messages_sent.fetch_add(old.messages_sent, Ordering::Relaxed);
+        self.messages_received.fetch_add(old.messages_received, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(old.bytes_sent, Ordering::Relaxed);
+        self.bytes_received.fetch_add(old.bytes_received, Ordering::Relaxed);
+        self.reconnects.fetch_add(old.reconnects + 1, Ordering::Relaxed);
+        self.rate_limit_deferrals.fetch_add(old.rate_limit_deferrals, Ordering::Relaxed);
+    }
+
+    /// Takes a snapshot of the current counters.
+    pub(crate) fn snapshot(&self) -> ConnectionMetrics {
+        ConnectionMetrics {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            rate_limit_deferrals: self.rate_limit_deferrals.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ConnectionMetrics, Metrics};
+
+    #[test]
+    fn inherit() {
+        let old = Metrics::default();
+        old.message_sent(10);
+        old.message_received(20);
+        old.message_deferred();
+        let new = Metrics::default();
+        new.message_sent(5);
+        new.inherit(&old);
+        assert_eq!(new.snapshot(), ConnectionMetrics {
+            messages_sent: 2,
+            messages_received: 1,
+            bytes_sent: 15,
+            bytes_received: 20,
+            reconnects: 1,
+            rate_limit_deferrals: 1,
+        });
+    }
+}
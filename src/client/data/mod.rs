@@ -1,7 +1,17 @@
 //! Data related to IRC functionality.
 
-pub use client::data::config::Config;
-pub use client::data::user::{AccessLevel, User};
+pub use client::data::config::{Config, ConfigBuilder, OverflowPolicy, SaslMechanism};
+pub use client::data::list::{ListEntry, ListMode};
+pub use client::data::metrics::ConnectionMetrics;
+pub use client::data::resolver::Resolver;
+pub use client::data::sts::StsPolicy;
+pub use client::data::user::{AccessLevel, User, UserInfo};
+pub use client::data::whois::WhoisReply;
 
 pub mod config;
+pub mod list;
+pub mod metrics;
+pub mod resolver;
+pub mod sts;
 pub mod user;
+pub mod whois;
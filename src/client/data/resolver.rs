@@ -0,0 +1,59 @@
+//! A hook for overriding how the hostnames of servers are resolved.
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// A custom resolver for the hostnames of servers, used in place of the system resolver when set
+/// in a [`Config`](../config/struct.Config.html). This is useful for split-horizon DNS, for
+/// routing through a custom resolver, or for pointing a client at a local server in tests.
+///
+/// # Example
+/// ```
+/// # extern crate irc;
+/// # use std::default::Default;
+/// # use std::net::SocketAddr;
+/// use irc::client::data::{Config, Resolver};
+///
+/// # fn main() {
+/// let config = Config {
+///     server: Some("irc.example.com".to_owned()),
+///     resolver: Some(Resolver::new(|_, port| Ok(vec![SocketAddr::from(([127, 0, 0, 1], port))]))),
+///     ..Default::default()
+/// };
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Resolver {
+    resolve: Arc<ResolveFn>,
+}
+
+/// A function mapping a hostname and port to the addresses to try.
+type ResolveFn = Fn(&str, u16) -> io::Result<Vec<SocketAddr>> + Send + Sync;
+
+impl Resolver {
+    /// Creates a resolver from a function mapping a hostname and port to the addresses to try.
+    pub fn new<F>(resolve: F) -> Resolver
+    where
+        F: Fn(&str, u16) -> io::Result<Vec<SocketAddr>> + Send + Sync + 'static,
+    {
+        Resolver { resolve: Arc::new(resolve) }
+    }
+
+    /// Resolves the given hostname and port to the addresses to try, in order.
+    pub fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        (self.resolve)(host, port)
+    }
+}
+
+impl fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Resolver(...)")
+    }
+}
+
+impl PartialEq for Resolver {
+    fn eq(&self, other: &Resolver) -> bool {
+        Arc::ptr_eq(&self.resolve, &other.resolve)
+    }
+}
@@ -0,0 +1,185 @@
+//! Data for the IRCv3 Strict Transport Security (`sts`) capability.
+//!
+//! Servers advertise an STS policy as the value of the `sts` capability. Over plaintext, the
+//! policy tells clients which port to reconnect to with TLS. Over TLS, it tells clients how long
+//! to keep connecting with TLS. See the [specification](https://ircv3.net/specs/extensions/sts)
+//! for details.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use client::data::Config;
+
+/// A Strict Transport Security policy advertised by a server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StsPolicy {
+    /// The port on which the server accepts TLS connections.
+    port: Option<u16>,
+    /// How long in seconds clients should keep connecting to the server with TLS.
+    duration: Option<u64>,
+    /// Whether the server consents to being preloaded into clients.
+    preload: bool,
+}
+
+impl StsPolicy {
+    /// Finds and parses the STS policy in the capabilities of a `CAP LS` reply, if the server
+    /// advertised one. Policies that fail to parse are ignored.
+    pub fn from_cap_ls(caps: &str) -> Option<StsPolicy> {
+        caps.split_whitespace()
+            .find(|cap| cap.starts_with("sts="))
+            .and_then(|cap| cap[4..].parse().ok())
+    }
+
+    /// Gets the port on which the server accepts TLS connections, if specified.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// Gets how long in seconds clients should keep connecting with TLS, if specified.
+    pub fn duration(&self) -> Option<u64> {
+        self.duration
+    }
+
+    /// Gets whether the server consents to being preloaded into clients.
+    pub fn preload(&self) -> bool {
+        self.preload
+    }
+}
+
+impl FromStr for StsPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<StsPolicy, ()> {
+        let mut policy = StsPolicy {
+            port: None,
+            duration: None,
+            preload: false,
+        };
+        for key in s.split(',') {
+            let mut kv = key.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some("port"), Some(port)) => policy.port = Some(port.parse().map_err(|_| ())?),
+                (Some("duration"), Some(dur)) => {
+                    policy.duration = Some(dur.parse().map_err(|_| ())?)
+                }
+                (Some("preload"), _) => policy.preload = true,
+                // Unknown keys must be ignored for forward compatibility.
+                _ => (),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// Gets the current Unix timestamp.
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Reads the STS cache at the given path, mapping hosts to their TLS port and policy expiry.
+/// A missing cache is treated as empty, and malformed lines are skipped.
+fn read_cache(path: &str) -> io::Result<HashMap<String, (u16, u64)>> {
+    let mut data = String::new();
+    match File::open(path) {
+        Ok(mut file) => file.read_to_string(&mut data)?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(data.lines().filter_map(|line| {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        match (fields.first(), fields.get(1).and_then(|p| p.parse().ok()),
+               fields.get(2).and_then(|e| e.parse().ok())) {
+            (Some(host), Some(port), Some(expiry)) => Some((host.to_string(), (port, expiry))),
+            _ => None,
+        }
+    }).collect())
+}
+
+/// Records a policy seen over TLS on the given port in the STS cache at the given path. A
+/// duration of zero removes the host from the cache.
+pub(crate) fn store(path: &str, host: &str, port: u16, duration: u64) -> io::Result<()> {
+    let mut cache = read_cache(path)?;
+    if duration == 0 {
+        cache.remove(host);
+    } else {
+        cache.insert(host.to_owned(), (port, now().saturating_add(duration)));
+    }
+    let mut hosts: Vec<_> = cache.into_iter().collect();
+    hosts.sort();
+    let mut file = File::create(path)?;
+    for (host, (port, expiry)) in hosts {
+        writeln!(file, "{} {} {}", host, port, expiry)?;
+    }
+    Ok(())
+}
+
+/// Gets the TLS port to use for a plaintext connection made with the given configuration, if the
+/// STS cache holds an unexpired policy for its server.
+pub(crate) fn upgrade_port(config: &Config) -> Option<u16> {
+    if config.use_ssl() || config.use_starttls() {
+        return None;
+    }
+    let path = config.sts_cache()?;
+    let host = config.server().ok()?;
+    match read_cache(path) {
+        Ok(cache) => cache.get(host).and_then(|&(port, expiry)| {
+            if expiry > now() { Some(port) } else { None }
+        }),
+        Err(e) => {
+            warn!("Failed to read STS cache {}: {}", path, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::default::Default;
+    use std::env;
+    use std::fs;
+
+    use super::{store, upgrade_port, StsPolicy};
+    use client::data::Config;
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!(
+            "port=6697,duration=300,preload".parse(),
+            Ok(StsPolicy { port: Some(6697), duration: Some(300), preload: true })
+        );
+        assert_eq!(
+            "duration=0,foo=bar".parse(),
+            Ok(StsPolicy { port: None, duration: Some(0), preload: false })
+        );
+        assert_eq!("port=abc".parse::<StsPolicy>(), Err(()));
+    }
+
+    #[test]
+    fn policy_from_cap_ls() {
+        let policy = StsPolicy::from_cap_ls("multi-prefix sts=port=6697,duration=300 sasl");
+        assert_eq!(policy.as_ref().and_then(|p| p.port()), Some(6697));
+        assert_eq!(policy.as_ref().and_then(|p| p.duration()), Some(300));
+        assert_eq!(StsPolicy::from_cap_ls("multi-prefix sasl"), None);
+        assert_eq!(StsPolicy::from_cap_ls("sts=port=oops"), None);
+    }
+
+    #[test]
+    fn cache_round_trip() {
+        let path = env::temp_dir().join(format!("irc-sts-test-{}", ::std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let config = Config {
+            server: Some("irc.test.net".to_owned()),
+            sts_cache: Some(path.clone()),
+            ..Default::default()
+        };
+        assert_eq!(upgrade_port(&config), None);
+        store(&path, "irc.test.net", 6697, 300).unwrap();
+        assert_eq!(upgrade_port(&config), Some(6697));
+        assert_eq!(upgrade_port(&Config { use_ssl: Some(true), ..config.clone() }), None);
+        store(&path, "irc.test.net", 6697, 0).unwrap();
+        assert_eq!(upgrade_port(&config), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -5,6 +5,7 @@ use std::cmp::Ordering::{Less, Equal, Greater};
 use std::str::FromStr;
 
 use proto::{Mode, ChannelMode};
+pub(crate) use proto::casemap::{fold_nickname, nicknames_eq};
 
 /// IRC User data.
 #[derive(Clone, Debug)]
@@ -19,6 +20,10 @@ pub struct User {
     highest_access_level: AccessLevel,
     /// All of the user's current access levels.
     access_levels: Vec<AccessLevel>,
+    /// The user's account name, if they're known to be logged in.
+    account: Option<String>,
+    /// Whether the user is known to be away.
+    away: bool,
 }
 
 impl User {
@@ -48,6 +53,8 @@ impl User {
                 }
                 max
             },
+            account: None,
+            away: false,
         }
     }
 
@@ -56,6 +63,17 @@ impl User {
         &self.nickname
     }
 
+    /// Changes the nickname of the user, preserving everything else that is known about them.
+    #[cfg(not(feature = "nochanlists"))]
+    pub(crate) fn set_nickname(&mut self, nickname: &str) {
+        self.nickname = nickname.to_owned();
+    }
+
+    /// Determines whether this user has the given nickname, using RFC 1459 casemapping.
+    pub(crate) fn is_nickname(&self, nickname: &str) -> bool {
+        nicknames_eq(&self.nickname, nickname)
+    }
+
     /// Gets the username of the user, if it's known.
     /// This requires the IRCv3.2 extension `userhost-in-name`.
     pub fn get_username(&self) -> Option<&str> {
@@ -68,6 +86,37 @@ impl User {
         self.hostname.as_ref().map(|s| &s[..])
     }
 
+    /// Gets the account name of the user, if they're known to be logged in.
+    /// This requires the IRCv3.1 extension `extended-join`.
+    pub fn get_account(&self) -> Option<&str> {
+        self.account.as_ref().map(|s| &s[..])
+    }
+
+    /// Gets whether the user is known to be away.
+    /// This requires the IRCv3.1 extension `away-notify`.
+    pub fn is_away(&self) -> bool {
+        self.away
+    }
+
+    /// Sets the account name of the user, with `None` indicating that they're logged out.
+    #[cfg(not(feature = "nochanlists"))]
+    pub(crate) fn set_account(&mut self, account: Option<&str>) {
+        self.account = account.map(|s| s.to_owned());
+    }
+
+    /// Sets the username and hostname of the user.
+    #[cfg(not(feature = "nochanlists"))]
+    pub(crate) fn set_userhost(&mut self, username: &str, hostname: &str) {
+        self.username = Some(username.to_owned());
+        self.hostname = Some(hostname.to_owned());
+    }
+
+    /// Sets whether the user is away.
+    #[cfg(not(feature = "nochanlists"))]
+    pub(crate) fn set_away(&mut self, away: bool) {
+        self.away = away;
+    }
+
     /// Gets the user's highest access level.
     pub fn highest_access_level(&self) -> AccessLevel {
         self.highest_access_level
@@ -78,6 +127,18 @@ impl User {
         self.access_levels.clone()
     }
 
+    /// Gets the nickname of the user prefixed with the symbol of their highest-ranking access
+    /// level, where `prefix` is the value of the `PREFIX` token advertised by the server (e.g.
+    /// `(qaohv)~&@%+`) listing the membership modes and their symbols from highest to lowest rank.
+    /// The nickname is returned as is if the user has none of the given modes.
+    pub fn display_nickname(&self, prefix: &str) -> String {
+        let modes: Vec<_> = self.access_levels.iter().filter_map(AccessLevel::mode).collect();
+        match prefix_modes(prefix).into_iter().find(|&(mode, _)| modes.contains(&mode)) {
+            Some((_, symbol)) => format!("{}{}", symbol, self.nickname),
+            None => self.nickname.clone(),
+        }
+    }
+
     /// Updates the user's access level.
     pub fn update_access_level(&mut self, mode: &Mode<ChannelMode>) {
         match *mode {
@@ -129,6 +190,85 @@ impl PartialEq for User {
     }
 }
 
+/// A point-in-time snapshot of what is known about a user across all tracked channels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserInfo {
+    /// The user's nickname.
+    nickname: String,
+    /// The user's username.
+    username: Option<String>,
+    /// The user's hostname.
+    hostname: Option<String>,
+    /// The user's account name.
+    account: Option<String>,
+    /// Whether the user is known to be away.
+    away: bool,
+    /// The tracked channels the user is in.
+    channels: Vec<String>,
+}
+
+impl UserInfo {
+    /// Creates a new snapshot from a user seen in the given channel.
+    #[cfg(not(feature = "nochanlists"))]
+    pub(crate) fn new(channel: &str, user: &User) -> UserInfo {
+        UserInfo {
+            nickname: user.nickname.clone(),
+            username: user.username.clone(),
+            hostname: user.hostname.clone(),
+            account: user.account.clone(),
+            away: user.away,
+            channels: vec![channel.to_owned()],
+        }
+    }
+
+    /// Merges what is known about the same user from another channel into this snapshot.
+    #[cfg(not(feature = "nochanlists"))]
+    pub(crate) fn merge(&mut self, channel: &str, user: &User) {
+        if self.username.is_none() {
+            self.username = user.username.clone();
+        }
+        if self.hostname.is_none() {
+            self.hostname = user.hostname.clone();
+        }
+        if self.account.is_none() {
+            self.account = user.account.clone();
+        }
+        self.away |= user.away;
+        self.channels.push(channel.to_owned());
+        self.channels.sort();
+    }
+
+    /// Gets the nickname of the user.
+    pub fn get_nickname(&self) -> &str {
+        &self.nickname
+    }
+
+    /// Gets the username of the user, if it's known.
+    pub fn get_username(&self) -> Option<&str> {
+        self.username.as_ref().map(|s| &s[..])
+    }
+
+    /// Gets the hostname of the user, if it's known.
+    pub fn get_hostname(&self) -> Option<&str> {
+        self.hostname.as_ref().map(|s| &s[..])
+    }
+
+    /// Gets the account name of the user, if they're known to be logged in.
+    pub fn get_account(&self) -> Option<&str> {
+        self.account.as_ref().map(|s| &s[..])
+    }
+
+    /// Gets whether the user is known to be away.
+    pub fn is_away(&self) -> bool {
+        self.away
+    }
+
+    /// Gets the tracked channels that the user is in, sorted by name.
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+}
+
 /// The user's access level.
 #[derive(Copy, PartialEq, Clone, Debug)]
 pub enum AccessLevel {
@@ -146,6 +286,39 @@ pub enum AccessLevel {
     Member,
 }
 
+impl AccessLevel {
+    /// Gets the channel mode letter granting this access level, e.g. `o` for operators. Normal
+    /// members have no mode.
+    pub fn mode(&self) -> Option<char> {
+        match *self {
+            AccessLevel::Owner => Some('q'),
+            AccessLevel::Admin => Some('a'),
+            AccessLevel::Oper => Some('o'),
+            AccessLevel::HalfOp => Some('h'),
+            AccessLevel::Voice => Some('v'),
+            AccessLevel::Member => None,
+        }
+    }
+
+    /// Gets the membership prefix symbol for this access level according to the value of the
+    /// `PREFIX` token advertised by the server, e.g. `@` for operators given `(ohv)@%+`. Normal
+    /// members and access levels whose mode the server does not list have no symbol.
+    pub fn symbol(&self, prefix: &str) -> Option<char> {
+        let mode = self.mode()?;
+        prefix_modes(prefix).into_iter().find(|&(m, _)| m == mode).map(|(_, symbol)| symbol)
+    }
+}
+
+/// Pairs the membership modes in the value of a `PREFIX` token with their symbols, from highest to
+/// lowest rank. A malformed token lists no modes.
+fn prefix_modes(prefix: &str) -> Vec<(char, char)> {
+    // PREFIX=(<modes>)<symbols>
+    match (prefix.starts_with('('), prefix.find(')')) {
+        (true, Some(end)) => prefix[1..end].chars().zip(prefix[end + 1..].chars()).collect(),
+        _ => vec![],
+    }
+}
+
 impl PartialOrd for AccessLevel {
     fn partial_cmp(&self, other: &AccessLevel) -> Option<Ordering> {
         if self == other {
@@ -226,6 +399,8 @@ impl Iterator for AccessLevelIterator {
 #[cfg(test)]
 mod test {
     use super::{AccessLevel, User};
+    #[cfg(not(feature = "nochanlists"))]
+    use super::UserInfo;
     use super::AccessLevel::*;
     use proto::ChannelMode as M;
     use proto::Mode::*;
@@ -241,6 +416,28 @@ mod test {
         assert!("".parse::<AccessLevel>().is_err());
     }
 
+    #[test]
+    fn display_nickname() {
+        let user = User::new("+@ada");
+        assert_eq!(user.display_nickname("(qaohv)~&@%+"), "@ada");
+        // The rank of each prefix comes from the given order.
+        assert_eq!(user.display_nickname("(vo)+@"), "+ada");
+        assert_eq!(user.display_nickname("(h)%"), "ada");
+        assert_eq!(User::new("ada").display_nickname("(qaohv)~&@%+"), "ada");
+        // The symbol of each mode comes from the server.
+        assert_eq!(User::new("~ada").display_nickname("(qaohv)*!@%+"), "*ada");
+        assert_eq!(user.display_nickname("~&@%+"), "ada");
+    }
+
+    #[test]
+    fn access_level_symbol() {
+        assert_eq!(Admin.symbol("(qaohv)~&@%+"), Some('&'));
+        assert_eq!(Admin.symbol("(qaohv)~!@%+"), Some('!'));
+        assert_eq!(Voice.symbol("(o)@"), None);
+        assert_eq!(Member.symbol("(qaohv)~&@%+"), None);
+        assert_eq!(Oper.mode(), Some('o'));
+    }
+
     #[test]
     fn create_user() {
         let user = User::new("~owner");
@@ -250,6 +447,8 @@ mod test {
             hostname: None,
             highest_access_level: Owner,
             access_levels: vec![Owner, Member],
+            account: None,
+            away: false,
         };
         assert_eq!(user, exp);
         assert_eq!(user.highest_access_level, exp.highest_access_level);
@@ -265,6 +464,8 @@ mod test {
             hostname: None,
             highest_access_level: Owner,
             access_levels: vec![Owner, Admin, Voice, Member],
+            account: None,
+            away: false,
         };
         assert_eq!(user, exp);
         assert_eq!(user.highest_access_level, exp.highest_access_level);
@@ -294,6 +495,38 @@ mod test {
         assert_eq!(user.get_hostname(), None);
     }
 
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn set_nickname() {
+        let mut user = User::new("@user!username@hostname");
+        user.set_nickname("other");
+        assert_eq!(user.get_nickname(), "other");
+        assert_eq!(user.get_username(), Some("username"));
+        assert_eq!(user.highest_access_level(), Oper);
+    }
+
+    #[test]
+    fn nickname_casemapping() {
+        assert!(User::new("+Test").is_nickname("test"));
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn merge_user_info() {
+        let mut away = User::new("user");
+        away.set_away(true);
+        let mut info = UserInfo::new("#test2", &away);
+        let mut user = User::new("user!username@hostname");
+        user.set_account(Some("account"));
+        info.merge("#test", &user);
+        assert_eq!(info.get_nickname(), "user");
+        assert_eq!(info.get_username(), Some("username"));
+        assert_eq!(info.get_hostname(), Some("hostname"));
+        assert_eq!(info.get_account(), Some("account"));
+        assert!(info.is_away());
+        assert_eq!(info.channels(), &["#test".to_owned(), "#test2".to_owned()][..]);
+    }
+
     #[test]
     fn access_level() {
         let user = User::new("~owner");
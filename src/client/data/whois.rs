@@ -0,0 +1,184 @@
+//! Data for assembling the many numerics of a reply to `WHOIS`.
+use proto::Response;
+
+/// The numerics that make up a reply to `WHOIS`.
+const WHOIS_RESPONSES: &[Response] = &[
+    Response::RPL_WHOISUSER, Response::RPL_WHOISSERVER, Response::RPL_WHOISOPERATOR,
+    Response::RPL_WHOISIDLE, Response::RPL_WHOISCHANNELS, Response::RPL_WHOISACCOUNT,
+    Response::RPL_AWAY, Response::RPL_ENDOFWHOIS,
+];
+
+/// The information about a user gathered from the numerics of a reply to `WHOIS`, which ends with
+/// `RPL_ENDOFWHOIS`. Fields are `None` (or empty) if the server did not send the corresponding
+/// numeric.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WhoisReply {
+    /// The nickname of the user.
+    nickname: String,
+    /// The username of the user from `RPL_WHOISUSER`.
+    username: Option<String>,
+    /// The hostname of the user from `RPL_WHOISUSER`.
+    hostname: Option<String>,
+    /// The real name of the user from `RPL_WHOISUSER`.
+    realname: Option<String>,
+    /// The server the user is connected to from `RPL_WHOISSERVER`.
+    server: Option<String>,
+    /// The description of the server the user is connected to from `RPL_WHOISSERVER`.
+    server_info: Option<String>,
+    /// Whether the user is an IRC operator, from `RPL_WHOISOPERATOR`.
+    operator: bool,
+    /// How long in seconds the user has been idle from `RPL_WHOISIDLE`.
+    idle_secs: Option<u64>,
+    /// When the user connected as a Unix timestamp from `RPL_WHOISIDLE`.
+    signon: Option<u64>,
+    /// The channels the user is in, with their membership prefixes, from `RPL_WHOISCHANNELS`.
+    channels: Vec<String>,
+    /// The account the user is logged in to from `RPL_WHOISACCOUNT`.
+    account: Option<String>,
+    /// The away message of the user from `RPL_AWAY`.
+    away_message: Option<String>,
+}
+
+impl WhoisReply {
+    /// Creates a new, empty reply for the given nickname.
+    pub fn new(nickname: &str) -> WhoisReply {
+        WhoisReply {
+            nickname: nickname.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Determines whether the given response is one of the numerics of a `WHOIS` reply.
+    pub(crate) fn is_whois_response(resp: Response) -> bool {
+        WHOIS_RESPONSES.contains(&resp)
+    }
+
+    /// Updates the reply with the arguments and suffix of the given numeric, which take the form
+    /// `<client> <nick> ...`.
+    pub(crate) fn update(&mut self, resp: Response, args: &[String], suffix: Option<&str>) {
+        let suffix = suffix.map(|s| s.to_owned());
+        match resp {
+            Response::RPL_WHOISUSER => {
+                // <client> <nick> <user> <host> * :<real name>
+                self.username = args.get(2).cloned();
+                self.hostname = args.get(3).cloned();
+                self.realname = suffix;
+            }
+            Response::RPL_WHOISSERVER => {
+                // <client> <nick> <server> :<server info>
+                self.server = args.get(2).cloned();
+                self.server_info = suffix;
+            }
+            Response::RPL_WHOISOPERATOR => self.operator = true,
+            Response::RPL_WHOISIDLE => {
+                // <client> <nick> <secs> [<signon>] :seconds idle[, signon time]
+                self.idle_secs = args.get(2).and_then(|s| s.parse().ok());
+                self.signon = args.get(3).and_then(|s| s.parse().ok());
+            }
+            Response::RPL_WHOISCHANNELS => {
+                // Servers may split the channels across several replies.
+                let chans = suffix.as_ref().map(|s| &s[..]).unwrap_or("");
+                self.channels.extend(chans.split_whitespace().map(|c| c.to_owned()));
+            }
+            Response::RPL_WHOISACCOUNT => self.account = args.get(2).cloned(),
+            Response::RPL_AWAY => self.away_message = suffix,
+            _ => (),
+        }
+    }
+
+    /// Gets the nickname of the user.
+    pub fn nickname(&self) -> &str {
+        &self.nickname
+    }
+
+    /// Gets the username of the user, if known.
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_ref().map(|s| &s[..])
+    }
+
+    /// Gets the hostname of the user, if known.
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_ref().map(|s| &s[..])
+    }
+
+    /// Gets the real name of the user, if known.
+    pub fn realname(&self) -> Option<&str> {
+        self.realname.as_ref().map(|s| &s[..])
+    }
+
+    /// Gets the server the user is connected to, if known.
+    pub fn server(&self) -> Option<&str> {
+        self.server.as_ref().map(|s| &s[..])
+    }
+
+    /// Gets the description of the server the user is connected to, if known.
+    pub fn server_info(&self) -> Option<&str> {
+        self.server_info.as_ref().map(|s| &s[..])
+    }
+
+    /// Gets whether the user is an IRC operator.
+    pub fn is_operator(&self) -> bool {
+        self.operator
+    }
+
+    /// Gets how long in seconds the user has been idle, if known.
+    pub fn idle_secs(&self) -> Option<u64> {
+        self.idle_secs
+    }
+
+    /// Gets when the user connected as a Unix timestamp, if known.
+    pub fn signon(&self) -> Option<u64> {
+        self.signon
+    }
+
+    /// Gets the channels the user is in that are visible to the client, each with the user's
+    /// membership prefix (e.g. `@#rust`) if any.
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    /// Gets the account the user is logged in to, if known.
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_ref().map(|s| &s[..])
+    }
+
+    /// Gets the away message of the user, if the user is away.
+    pub fn away_message(&self) -> Option<&str> {
+        self.away_message.as_ref().map(|s| &s[..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WhoisReply;
+    use proto::Response;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn update() {
+        let mut reply = WhoisReply::new("ada");
+        reply.update(
+            Response::RPL_WHOISUSER, &args(&["test", "ada", "ada", "host", "*"]), Some("Ada")
+        );
+        reply.update(
+            Response::RPL_WHOISIDLE, &args(&["test", "ada", "42", "1500000000"]),
+            Some("seconds idle, signon time")
+        );
+        reply.update(Response::RPL_WHOISCHANNELS, &args(&["test", "ada"]), Some("@#rust #irc"));
+        reply.update(Response::RPL_WHOISCHANNELS, &args(&["test", "ada"]), Some("+#test"));
+        assert_eq!(reply.username(), Some("ada"));
+        assert_eq!(reply.hostname(), Some("host"));
+        assert_eq!(reply.realname(), Some("Ada"));
+        assert_eq!(reply.idle_secs(), Some(42));
+        assert_eq!(reply.signon(), Some(1500000000));
+        assert_eq!(
+            reply.channels(),
+            &["@#rust".to_owned(), "#irc".to_owned(), "+#test".to_owned()][..]
+        );
+        assert!(!reply.is_operator());
+        assert_eq!(reply.account(), None);
+    }
+}
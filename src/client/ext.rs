@@ -36,17 +36,21 @@
 //! server.identify().unwrap();
 //! # }
 //! ```
+use std::cmp;
 use std::string::ToString;
 
 #[cfg(feature = "ctcp")]
 use chrono::prelude::*;
 
 use error::Result;
-use proto::{Capability, Command, Mode, NegotiationVersion};
-use proto::command::CapSubCommand::{END, LS, REQ};
+use proto::{Capability, ChannelMode, Command, Message, Mode, NegotiationVersion, ValuedCapability};
+use proto::message::Tag;
+use proto::command::CapSubCommand::{END, LIST, LS, REQ};
+use proto::command::MetadataSubCommand;
 use proto::command::Command::*;
 use proto::mode::ModeType;
-use client::Client;
+use client::{Client, MAX_LINE_LEN};
+use client::data::Config;
 
 /// Idiomatic extensions for sending messages to an IRC server as a [`Client`](../trait.Client.html).
 pub trait ClientExt: Client {
@@ -66,23 +70,39 @@ pub trait ClientExt: Client {
         ))
     }
 
-    /// Sends an IRCv3 capabilities request for the specified extensions.
+    /// Sends a request for the list of capabilities currently enabled for this connection. Replies
+    /// are tracked by `IrcClient::negotiated_capabilities`.
+    fn send_cap_list(&self) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.send(CAP(None, LIST, None, None))
+    }
+
+    /// Sends an IRCv3 capabilities request for the specified extensions. Requests that would not
+    /// fit on a single line are split across several `CAP REQ` lines.
     fn send_cap_req(&self, extensions: &[Capability]) -> Result<()>
     where
         Self: Sized,
     {
-        let append = |mut s: String, c| {
-            s.push_str(c);
-            s.push(' ');
-            s
-        };
-        let mut exts = extensions.iter().map(|c| c.as_ref()).fold(
-            String::new(),
-            append,
-        );
-        let len = exts.len() - 1;
-        exts.truncate(len);
-        self.send(CAP(None, REQ, None, Some(exts)))
+        for exts in join_within(extensions.iter().map(|c| c.as_ref()), ' ', MAX_CAP_REQ_LEN) {
+            self.send(CAP(None, REQ, None, Some(exts)))?;
+        }
+        Ok(())
+    }
+
+    /// Sends an IRCv3 capabilities request for the specified extensions, including their values
+    /// where given (e.g. `draft/example=value`). Requests that would not fit on a single line are
+    /// split across several `CAP REQ` lines.
+    fn send_cap_req_values(&self, extensions: &[ValuedCapability]) -> Result<()>
+    where
+        Self: Sized,
+    {
+        let exts: Vec<_> = extensions.iter().map(|c| c.to_string()).collect();
+        for exts in join_within(exts.iter().map(|c| &c[..]), ' ', MAX_CAP_REQ_LEN) {
+            self.send(CAP(None, REQ, None, Some(exts)))?;
+        }
+        Ok(())
     }
 
     /// Sends a CAP END, NICK and USER to identify.
@@ -98,7 +118,7 @@ pub trait ClientExt: Client {
         self.send(NICK(self.config().nickname()?.to_owned()))?;
         self.send(USER(
             self.config().username().to_owned(),
-            "0".to_owned(),
+            self.config().user_mode_flags().to_owned(),
             self.config().real_name().to_owned(),
         ))?;
         Ok(())
@@ -146,7 +166,7 @@ pub trait ClientExt: Client {
         self.send(PONG(msg.to_string(), None))
     }
 
-    /// Joins the specified channel or chanlist.
+    /// Joins the specified channel or chanlist. Joining `0` parts every channel instead.
     fn send_join<S>(&self, chanlist: S) -> Result<()>
     where
         Self: Sized,
@@ -165,6 +185,28 @@ pub trait ClientExt: Client {
         self.send(JOIN(chanlist.to_string(), Some(keylist.to_string()), None))
     }
 
+    /// Joins each of the given channels, with as few `JOIN` commands as fit within the line length
+    /// limit, e.g. to join a long list of channels without any of the commands being truncated.
+    fn send_join_many(&self, chans: &[&str]) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.send_join_many_with_keys(chans, &[])
+    }
+
+    /// Joins each of the given channels using the keys at the same positions in `keys`, with as
+    /// few `JOIN` commands as fit within the line length limit. Channels after the last key are
+    /// joined without one. As in a single `JOIN`, the channels with keys should come first.
+    fn send_join_many_with_keys(&self, chans: &[&str], keys: &[&str]) -> Result<()>
+    where
+        Self: Sized,
+    {
+        for (chanlist, keylist) in join_lines(chans, keys) {
+            self.send(JOIN(chanlist, keylist, None))?;
+        }
+        Ok(())
+    }
+
     /// Parts the specified channel or chanlist.
     fn send_part<S>(&self, chanlist: S) -> Result<()>
     where
@@ -174,6 +216,27 @@ pub trait ClientExt: Client {
         self.send(PART(chanlist.to_string(), None))
     }
 
+    /// Parts every channel the client is currently in, with an optional part message, e.g. for a
+    /// graceful shutdown. The channels are parted with as few `PART` commands as fit within the
+    /// line length limit. This does nothing if channel tracking is disabled via the
+    /// `nochanlists` feature.
+    fn part_all(&self, message: Option<&str>) -> Result<()>
+    where
+        Self: Sized,
+    {
+        let mut chans = self.list_channels().unwrap_or_default();
+        chans.sort();
+        if chans.is_empty() {
+            return Ok(());
+        }
+        // Leave room for ` :<message>` after the channels.
+        let max_len = MAX_PART_LEN.saturating_sub(message.map_or(0, |m| m.len() + 2));
+        for chanlist in join_within(chans.iter().map(|c| &c[..]), ',', max_len) {
+            self.send(PART(chanlist, message.map(|m| m.to_owned())))?;
+        }
+        Ok(())
+    }
+
     /// Attempts to oper up using the specified username and password.
     fn send_oper<S1, S2>(&self, username: S1, password: S2) -> Result<()>
     where
@@ -192,8 +255,14 @@ pub trait ClientExt: Client {
         S2: ToString,
     {
         let message = message.to_string();
+        let max_len = max_text_len(self.config(), "PRIVMSG", &target.to_string());
+        if !message.contains("\r\n") && message.len() <= max_len {
+            return self.send(PRIVMSG(target.to_string(), message));
+        }
         for line in message.split("\r\n") {
-            self.send(PRIVMSG(target.to_string(), line.to_string()))?
+            for piece in split_text(line, max_len) {
+                self.send(PRIVMSG(target.to_string(), piece.to_owned()))?
+            }
         }
         Ok(())
     }
@@ -204,14 +273,118 @@ pub trait ClientExt: Client {
         Self: Sized,
         S1: ToString,
         S2: ToString,
+    {
+        let message = message.to_string();
+        let max_len = max_text_len(self.config(), "NOTICE", &target.to_string());
+        if !message.contains("\r\n") && message.len() <= max_len {
+            return self.send(NOTICE(target.to_string(), message));
+        }
+        for line in message.split("\r\n") {
+            for piece in split_text(line, max_len) {
+                self.send(NOTICE(target.to_string(), piece.to_owned()))?
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the given text in reply to the given message, to the channel it was sent to or
+    /// otherwise to its sender, as with
+    /// [`response_target`](../../proto/message/struct.Message.html#method.response_target).
+    /// If the `message-tags` capability is enabled and the message has a `msgid`, the reply refers
+    /// to it with the `+draft/reply` client tag. Nothing is sent if there is no one to respond to.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// # let client = IrcClient::new("config.toml").unwrap();
+    /// client.for_each_incoming(|message| {
+    ///     if let Command::PRIVMSG(_, ref text) = message.command {
+    ///         if text == "!ping" {
+    ///             client.reply_to(&message, "pong").unwrap();
+    ///         }
+    ///     }
+    /// }).unwrap();
+    /// # }
+    /// ```
+    fn reply_to<S>(&self, msg: &Message, text: S) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+    {
+        let target = match msg.response_target() {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        match msg.msgid() {
+            Some(msgid) if self.has_capability("message-tags") => {
+                self.send_reply(target, msgid, text)
+            }
+            _ => self.send_privmsg(target, text),
+        }
+    }
+
+    /// Sends a message to the specified target as a reply to the message with the given `msgid`,
+    /// using the `+draft/reply` client tag. This requires the `message-tags` capability.
+    fn send_reply<S1, S2, S3>(&self, target: S1, reply_to_msgid: S2, message: S3) -> Result<()>
+    where
+        Self: Sized,
+        S1: ToString,
+        S2: ToString,
+        S3: ToString,
     {
         let message = message.to_string();
         for line in message.split("\r\n") {
-            self.send(NOTICE(target.to_string(), line.to_string()))?
+            self.send(Message {
+                tags: Some(vec![Tag("+draft/reply".to_owned(), Some(reply_to_msgid.to_string()))]),
+                prefix: None,
+                command: PRIVMSG(target.to_string(), line.to_string()),
+            })?
         }
         Ok(())
     }
 
+    /// Reacts to the message with the given `msgid` in the specified target, using the
+    /// `+draft/reply` and `+draft/react` client tags on a `TAGMSG`. This requires the
+    /// `message-tags` capability.
+    fn send_react<S1, S2, S3>(&self, target: S1, reply_to_msgid: S2, emoji: S3) -> Result<()>
+    where
+        Self: Sized,
+        S1: ToString,
+        S2: ToString,
+        S3: ToString,
+    {
+        self.send_tagmsg(target, &[
+            ("+draft/reply".to_owned(), Some(reply_to_msgid.to_string())),
+            ("+draft/react".to_owned(), Some(emoji.to_string())),
+        ])
+    }
+
+    /// Notifies the specified target of the client's typing activity, using the `+typing` client
+    /// tag on a `TAGMSG`. This requires the `message-tags` capability.
+    fn send_typing<S>(&self, target: S, state: TypingState) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+    {
+        self.send_tagmsg(target, &[("+typing".to_owned(), Some(state.as_ref().to_owned()))])
+    }
+
+    /// Sends a `TAGMSG` carrying only the given tags to the specified target. This requires the
+    /// `message-tags` capability.
+    fn send_tagmsg<S>(&self, target: S, tags: &[(String, Option<String>)]) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+    {
+        self.send(Message {
+            tags: Some(tags.iter().map(|&(ref k, ref v)| Tag(k.clone(), v.clone())).collect()),
+            prefix: None,
+            command: TAGMSG(target.to_string()),
+        })
+    }
+
     /// Sets the topic of a channel or requests the current one.
     /// If `topic` is an empty string, it won't be included in the message.
     fn send_topic<S1, S2>(&self, channel: S1, topic: S2) -> Result<()>
@@ -272,6 +445,134 @@ pub trait ClientExt: Client {
         self.send(T::mode(&target.to_string(), modes))
     }
 
+    /// Changes the given modes for the specified target, split across as many `MODE` commands as
+    /// needed to stay within the limit from
+    /// [`Client::max_modes`](../trait.Client.html#method.max_modes), e.g. to give operator status
+    /// to many users at once without the server rejecting the changes.
+    fn send_modes<S, T>(&self, target: S, modes: &[Mode<T>]) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+        T: ModeType,
+    {
+        let target = target.to_string();
+        let max = cmp::max(self.max_modes(), 1) as usize;
+        for modes in modes.chunks(max) {
+            self.send(T::mode(&target, modes))?;
+        }
+        Ok(())
+    }
+
+    /// Requests the ban list (`+b`) of the specified channel.
+    fn send_ban_list<S>(&self, channel: S) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+    {
+        self.send_mode(channel, &[Mode::Plus(ChannelMode::Ban, None)])
+    }
+
+    /// Requests the ban exception list (`+e`) of the specified channel.
+    fn send_exception_list<S>(&self, channel: S) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+    {
+        self.send_mode(channel, &[Mode::Plus(ChannelMode::Exception, None)])
+    }
+
+    /// Requests the invite exception list (`+I`) of the specified channel.
+    fn send_invite_list<S>(&self, channel: S) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+    {
+        self.send_mode(channel, &[Mode::Plus(ChannelMode::InviteException, None)])
+    }
+
+    /// Gives channel operator status (`+o`) to the specified nickname in the specified channel.
+    fn send_op<S1, S2>(&self, channel: S1, nick: S2) -> Result<()>
+    where
+        Self: Sized,
+        S1: ToString,
+        S2: ToString,
+    {
+        self.send_mode(channel, &[Mode::Plus(ChannelMode::Oper, Some(nick.to_string()))])
+    }
+
+    /// Takes channel operator status (`-o`) from the specified nickname in the specified channel.
+    fn send_deop<S1, S2>(&self, channel: S1, nick: S2) -> Result<()>
+    where
+        Self: Sized,
+        S1: ToString,
+        S2: ToString,
+    {
+        self.send_mode(channel, &[Mode::Minus(ChannelMode::Oper, Some(nick.to_string()))])
+    }
+
+    /// Gives voice (`+v`) to the specified nickname in the specified channel.
+    fn send_voice<S1, S2>(&self, channel: S1, nick: S2) -> Result<()>
+    where
+        Self: Sized,
+        S1: ToString,
+        S2: ToString,
+    {
+        self.send_mode(channel, &[Mode::Plus(ChannelMode::Voice, Some(nick.to_string()))])
+    }
+
+    /// Takes voice (`-v`) from the specified nickname in the specified channel.
+    fn send_devoice<S1, S2>(&self, channel: S1, nick: S2) -> Result<()>
+    where
+        Self: Sized,
+        S1: ToString,
+        S2: ToString,
+    {
+        self.send_mode(channel, &[Mode::Minus(ChannelMode::Voice, Some(nick.to_string()))])
+    }
+
+    /// Bans the specified mask (`+b`) from the specified channel. Masks can be constructed from the
+    /// hostmask of a user with [`banmask`](../../proto/banmask/fn.banmask.html).
+    fn send_ban<S1, S2>(&self, channel: S1, mask: S2) -> Result<()>
+    where
+        Self: Sized,
+        S1: ToString,
+        S2: ToString,
+    {
+        self.send_mode(channel, &[Mode::Plus(ChannelMode::Ban, Some(mask.to_string()))])
+    }
+
+    /// Removes the ban on the specified mask (`-b`) from the specified channel.
+    fn send_unban<S1, S2>(&self, channel: S1, mask: S2) -> Result<()>
+    where
+        Self: Sized,
+        S1: ToString,
+        S2: ToString,
+    {
+        self.send_mode(channel, &[Mode::Minus(ChannelMode::Ban, Some(mask.to_string()))])
+    }
+
+    /// Bans the specified mask from the specified channel, and then kicks the specified nickname
+    /// from it with a comment, so that the user cannot immediately rejoin.
+    /// If `message` is an empty string, it won't be included in the kick.
+    fn send_kickban<S1, S2, S3, S4>(
+        &self,
+        channel: S1,
+        nick: S2,
+        mask: S3,
+        message: S4,
+    ) -> Result<()>
+    where
+        Self: Sized,
+        S1: ToString,
+        S2: ToString,
+        S3: ToString,
+        S4: ToString,
+    {
+        let channel = channel.to_string();
+        self.send_ban(&channel[..], mask)?;
+        self.send_kick(channel, nick, message)
+    }
+
     /// Changes the mode of the target by force.
     /// If `modeparams` is an empty string, it won't be included in the message.
     fn send_samode<S1, S2, S3>(&self, target: S1, mode: S2, modeparams: S3) -> Result<()>
@@ -313,8 +614,161 @@ pub trait ClientExt: Client {
         self.send(INVITE(nick.to_string(), chan.to_string()))
     }
 
+    /// Adds the given nickname to the client's `WATCH` list, for networks that support `WATCH`
+    /// rather than `MONITOR`. The server then reports when the user comes online or goes
+    /// offline, which can be read with
+    /// [`Message::as_presence`](../../proto/message/struct.Message.html#method.as_presence).
+    fn send_watch_add<S>(&self, nick: S) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+    {
+        self.send(Raw("WATCH".to_owned(), vec![format!("+{}", nick.to_string())], None))
+    }
+
+    /// Removes the given nickname from the client's `WATCH` list.
+    fn send_watch_del<S>(&self, nick: S) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+    {
+        self.send(Raw("WATCH".to_owned(), vec![format!("-{}", nick.to_string())], None))
+    }
+
+    /// Adds the given mask to the client's `SILENCE` list, for networks that support `SILENCE`.
+    /// The server then drops any private messages and notices from users matching the mask before
+    /// they reach the client.
+    fn send_silence_add<S>(&self, mask: S) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+    {
+        self.send(Raw("SILENCE".to_owned(), vec![format!("+{}", mask.to_string())], None))
+    }
+
+    /// Removes the given mask from the client's `SILENCE` list.
+    fn send_silence_del<S>(&self, mask: S) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+    {
+        self.send(Raw("SILENCE".to_owned(), vec![format!("-{}", mask.to_string())], None))
+    }
+
+    /// Requests the client's `SILENCE` list, which is then available from
+    /// [`IrcClient::silence_list`](../struct.IrcClient.html#method.silence_list).
+    fn send_silence_list(&self) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.send(Raw("SILENCE".to_owned(), vec![], None))
+    }
+
+    /// Sends the given command to `NickServ`, under the name configured in `service_names`.
+    fn nickserv(&self, command: &str) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.send_privmsg(self.config().service_name("NickServ"), command)
+    }
+
+    /// Sends the given command to `ChanServ`, under the name configured in `service_names`.
+    fn chanserv(&self, command: &str) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.send_privmsg(self.config().service_name("ChanServ"), command)
+    }
+
+    /// Sends the given command to `MemoServ`, under the name configured in `service_names`.
+    fn memoserv(&self, command: &str) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.send_privmsg(self.config().service_name("MemoServ"), command)
+    }
+
+    /// Requests the latest `count` messages sent to the given target from the server's history,
+    /// which requires the `draft/chathistory` capability. The messages are returned in a
+    /// `chathistory` batch, which the client reassembles and exposes through
+    /// [`IrcClient::chat_history`](../struct.IrcClient.html#method.chat_history).
+    fn send_chathistory_latest<S>(&self, target: S, count: u32) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+    {
+        self.send(Raw("CHATHISTORY".to_owned(), vec![
+            "LATEST".to_owned(), target.to_string(), "*".to_owned(), count.to_string(),
+        ], None))
+    }
+
+    /// Requests up to `count` messages sent to the given target before the given message
+    /// reference, which takes the form `msgid=<id>` or `timestamp=<time>`. This requires the
+    /// `draft/chathistory` capability.
+    fn send_chathistory_before<S1, S2>(&self, target: S1, msgref: S2, count: u32) -> Result<()>
+    where
+        Self: Sized,
+        S1: ToString,
+        S2: ToString,
+    {
+        self.send(Raw("CHATHISTORY".to_owned(), vec![
+            "BEFORE".to_owned(), target.to_string(), msgref.to_string(), count.to_string(),
+        ], None))
+    }
+
+    /// Requests up to `count` messages sent to the given target after the given message
+    /// reference, which takes the form `msgid=<id>` or `timestamp=<time>`. This requires the
+    /// `draft/chathistory` capability.
+    fn send_chathistory_after<S1, S2>(&self, target: S1, msgref: S2, count: u32) -> Result<()>
+    where
+        Self: Sized,
+        S1: ToString,
+        S2: ToString,
+    {
+        self.send(Raw("CHATHISTORY".to_owned(), vec![
+            "AFTER".to_owned(), target.to_string(), msgref.to_string(), count.to_string(),
+        ], None))
+    }
+
+    /// Sets the given metadata key of the target (a nickname, a channel, or `*` for the client
+    /// itself) to the given value. This requires the `metadata` capability, and `IrcClient` fails
+    /// with `IrcError::CapabilityNotEnabled` if it has not been enabled.
+    fn send_metadata_set<S1, S2, S3>(&self, target: S1, key: S2, value: S3) -> Result<()>
+    where
+        Self: Sized,
+        S1: ToString,
+        S2: ToString,
+        S3: ToString,
+    {
+        self.send(METADATA(
+            target.to_string(),
+            Some(MetadataSubCommand::SET),
+            Some(vec![key.to_string()]),
+            Some(value.to_string()),
+        ))
+    }
+
+    /// Requests the values of the given metadata keys of the target (a nickname, a channel, or
+    /// `*` for the client itself). The server replies with an `RPL_KEYVALUE` for each key, which
+    /// can be read with
+    /// [`Message::as_metadata`](../../proto/message/struct.Message.html#method.as_metadata).
+    /// This requires the `metadata` capability, and `IrcClient` fails with
+    /// `IrcError::CapabilityNotEnabled` if it has not been enabled.
+    fn send_metadata_get<S>(&self, target: S, keys: &[&str]) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+    {
+        self.send(METADATA(
+            target.to_string(),
+            Some(MetadataSubCommand::GET),
+            Some(keys.iter().map(|key| key.to_string()).collect()),
+            None,
+        ))
+    }
+
     /// Quits the server entirely with a message.
-    /// This defaults to `Powered by Rust.` if none is specified.
+    /// This defaults to the configured `quit_message` if the message is empty.
     fn send_quit<S>(&self, msg: S) -> Result<()>
     where
         Self: Sized,
@@ -322,7 +776,7 @@ pub trait ClientExt: Client {
     {
         let msg = msg.to_string();
         self.send(QUIT(Some(if msg.is_empty() {
-            "Powered by Rust.".to_string()
+            self.config().quit_message().to_owned()
         } else {
             msg
         })))
@@ -340,6 +794,27 @@ pub trait ClientExt: Client {
         self.send_privmsg(target, &format!("\u{001}{}\u{001}", msg.to_string())[..])
     }
 
+    /// Sends a CTCP reply with the given tag and body to the specified target. Replies are sent as
+    /// a `NOTICE`, so that they never trigger automatic replies in turn. An empty body sends the
+    /// tag alone.
+    /// This requires the CTCP feature to be enabled.
+    #[cfg(feature = "ctcp")]
+    fn send_ctcp_reply<S1, S2, S3>(&self, target: S1, tag: S2, body: S3) -> Result<()>
+    where
+        Self: Sized,
+        S1: ToString,
+        S2: ToString,
+        S3: ToString,
+    {
+        let body = body.to_string();
+        let payload = if body.is_empty() {
+            tag.to_string()
+        } else {
+            format!("{} {}", tag.to_string(), body)
+        };
+        self.send_notice(target, &format!("\u{001}{}\u{001}", payload)[..])
+    }
+
     /// Sends an action command to the specified target.
     /// This requires the CTCP feature to be enabled.
     #[cfg(feature = "ctcp")]
@@ -396,7 +871,9 @@ pub trait ClientExt: Client {
         self.send_ctcp(target, "USERINFO")
     }
 
-    /// Sends a finger request to the specified target.
+    /// Sends a CTCP ping to the specified target, stamped with the current time in seconds since
+    /// the Unix epoch. The round-trip time can be computed from the reply with
+    /// `Message::ctcp_ping_latency`, though only to the second.
     /// This requires the CTCP feature to be enabled.
     #[cfg(feature = "ctcp")]
     fn send_ctcp_ping<S>(&self, target: S) -> Result<()>
@@ -408,27 +885,300 @@ pub trait ClientExt: Client {
         self.send_ctcp(target, &format!("PING {}", time.timestamp())[..])
     }
 
-    /// Sends a time request to the specified target.
+    /// Sends a CTCP ping to the specified target like `send_ctcp_ping`, but stamped with the
+    /// current time in milliseconds since the Unix epoch, so that the round-trip time computed
+    /// with `Message::ctcp_ping_latency` is precise to the millisecond.
     /// This requires the CTCP feature to be enabled.
     #[cfg(feature = "ctcp")]
-    fn send_time<S>(&self, target: S) -> Result<()>
+    fn send_ctcp_ping_ms<S>(&self, target: S) -> Result<()>
     where
         Self: Sized,
         S: ToString,
     {
-        self.send_ctcp(target, "TIME")
+        let time = Local::now();
+        let millis = time.timestamp() * 1000 + i64::from(time.timestamp_subsec_millis());
+        self.send_ctcp(target, &format!("PING {}", millis)[..])
     }
-}
+
+    /// Sends a time request to the specified target.
+    /// This requires the CTCP feature to be enabled.
+    #[cfg(feature = "ctcp")]
+    fn send_time<S>(&self, target: S) -> Result<()>
+    where
+        Self: Sized,
+        S: ToString,
+    {
+        self.send_ctcp(target, "TIME")
+    }
+}
 
 impl<C> ClientExt for C where C: Client {}
 
+/// The longest list of capabilities that fits in a `CAP REQ`, i.e. the 512 byte line length limit
+/// less `CAP REQ :` and the line terminator.
+const MAX_CAP_REQ_LEN: usize = 512 - 9 - 2;
+
+/// The longest list of channels that fits in a `PART` without a message, i.e. the 512 byte line
+/// length limit less `PART ` and the line terminator.
+const MAX_PART_LEN: usize = 512 - 5 - 2;
+
+/// The longest list of channels and keys that fits in a `JOIN`, i.e. the 512 byte line length
+/// limit less `JOIN ` and the line terminator.
+const MAX_JOIN_LEN: usize = 512 - 5 - 2;
+
+/// Splits the given channels and the keys at the same positions into as few pairs of chanlists
+/// and keylists as fit within a `JOIN`, keeping each channel in the same command as its key.
+fn join_lines(chans: &[&str], keys: &[&str]) -> Vec<(String, Option<String>)> {
+    let mut lines = vec![];
+    let (mut chanlist, mut keylist) = (String::new(), String::new());
+    for (i, chan) in chans.iter().enumerate() {
+        let key = keys.get(i);
+        let len = chanlist.len() + if keylist.is_empty() { 0 } else { 1 + keylist.len() };
+        let added = 1 + chan.len() + key.map_or(0, |k| 1 + k.len());
+        if !chanlist.is_empty() && len + added > MAX_JOIN_LEN {
+            lines.push((chanlist, keylist));
+            chanlist = String::new();
+            keylist = String::new();
+        }
+        if !chanlist.is_empty() {
+            chanlist.push(',');
+        }
+        chanlist.push_str(chan);
+        if let Some(key) = key {
+            if !keylist.is_empty() {
+                keylist.push(',');
+            }
+            keylist.push_str(key);
+        }
+    }
+    if !chanlist.is_empty() {
+        lines.push((chanlist, keylist));
+    }
+    lines.into_iter().map(|(chanlist, keylist)| {
+        (chanlist, if keylist.is_empty() { None } else { Some(keylist) })
+    }).collect()
+}
+
+/// The longest host assumed in the prefix that the server adds when relaying a message, which is
+/// the longest hostname allowed by [RFC 2812](https://tools.ietf.org/html/rfc2812#section-2.3.1).
+const MAX_RELAYED_HOST_LEN: usize = 63;
+
+/// Gets the longest text that fits in a `PRIVMSG` or `NOTICE` to the given target within the line
+/// length limit, or no limit at all if the configuration does not split long messages. Room is
+/// left for the prefix that the server adds when relaying the message to others, assuming the
+/// longest of the configured nicknames, a username marked with `~` as unverified, and the longest
+/// possible host.
+fn max_text_len(config: &Config, command: &str, target: &str) -> usize {
+    if !config.split_long_messages() {
+        return usize::MAX;
+    }
+    let nicks = config.alternate_nicknames().into_iter().chain(config.nickname().ok());
+    let nick_len = nicks.map(str::len).max().unwrap_or(0);
+    // :<nick>!~<user>@<host> <command> <target> :<text>\r\n
+    let prefix_len = nick_len + config.username().len() + MAX_RELAYED_HOST_LEN + 5;
+    MAX_LINE_LEN.saturating_sub(prefix_len + command.len() + target.len() + 5)
+}
+
+/// Splits the given text into pieces of at most `max_len` bytes. Pieces end on character
+/// boundaries, and are kept from ending within what is likely a single grapheme cluster. A piece
+/// only breaks such a cluster if the cluster alone exceeds `max_len`, and holds at least one
+/// character even if that character alone does.
+///
+/// Grapheme clusters are approximated without the full Unicode tables: characters are kept
+/// together when joined by a zero width joiner, when the latter is a mark, variation selector,
+/// emoji modifier or tag, when they are a pair of regional indicators (i.e. a flag), and when
+/// they are Hangul jamo making up a single syllable.
+fn split_text(text: &str, max_len: usize) -> Vec<&str> {
+    let mut pieces = vec![];
+    let mut rest = text;
+    while rest.len() > max_len {
+        let mut end = max_len;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut cut = end;
+        while cut > 0 && joins_previous(rest, cut) {
+            cut -= 1;
+            while !rest.is_char_boundary(cut) {
+                cut -= 1;
+            }
+        }
+        if cut == 0 {
+            cut = if end > 0 { end } else { rest.chars().next().map_or(0, char::len_utf8) };
+        }
+        pieces.push(&rest[..cut]);
+        rest = &rest[cut..];
+    }
+    if !rest.is_empty() || pieces.is_empty() {
+        pieces.push(rest);
+    }
+    pieces
+}
+
+/// The ranges of characters that extend the grapheme cluster of the character before them: the
+/// generic combining marks, the marks of the Cyrillic, Hebrew, Arabic, Sinhala, Thai, Lao and
+/// Myanmar scripts (both spacing and not), the zero width joiner, variation selectors, emoji
+/// modifiers, and tags. The marks of the Indic scripts from Devanagari to Malayalam are found by
+/// `is_indic_mark` instead.
+const CLUSTER_EXTENDERS: [(char, char); 25] = [
+    ('\u{0300}', '\u{036F}'), ('\u{0483}', '\u{0489}'), ('\u{0591}', '\u{05BD}'),
+    ('\u{05BF}', '\u{05C7}'), ('\u{0610}', '\u{061A}'), ('\u{064B}', '\u{065F}'),
+    ('\u{0670}', '\u{0670}'), ('\u{06D6}', '\u{06ED}'), ('\u{0D81}', '\u{0D83}'),
+    ('\u{0DCA}', '\u{0DDF}'), ('\u{0E31}', '\u{0E31}'), ('\u{0E34}', '\u{0E3A}'),
+    ('\u{0E47}', '\u{0E4E}'), ('\u{0EB1}', '\u{0EB1}'), ('\u{0EB4}', '\u{0EBC}'),
+    ('\u{0EC8}', '\u{0ECD}'), ('\u{102B}', '\u{103E}'), ('\u{1AB0}', '\u{1AFF}'),
+    ('\u{1DC0}', '\u{1DFF}'), ('\u{200D}', '\u{200D}'), ('\u{20D0}', '\u{20FF}'),
+    ('\u{FE00}', '\u{FE0F}'), ('\u{FE20}', '\u{FE2F}'), ('\u{1F3FB}', '\u{1F3FF}'),
+    ('\u{E0020}', '\u{E007F}'),
+];
+
+/// The regional indicator symbols, pairs of which make up the flag of a country.
+const REGIONAL_INDICATORS: (char, char) = ('\u{1F1E6}', '\u{1F1FF}');
+
+/// The kinds of Hangul characters that combine into a single syllable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Jamo {
+    /// A leading consonant.
+    Lead,
+    /// A vowel.
+    Vowel,
+    /// A trailing consonant.
+    Tail,
+    /// A precomposed syllable without a trailing consonant.
+    LeadVowel,
+    /// A precomposed syllable with a trailing consonant.
+    LeadVowelTail,
+}
+
+/// The pairs of Hangul characters that belong to the same syllable, following the rules of
+/// [UAX #29](https://www.unicode.org/reports/tr29/#GB6).
+const JAMO_SEQUENCES: [(Jamo, Jamo); 10] = [
+    (Jamo::Lead, Jamo::Lead), (Jamo::Lead, Jamo::Vowel),
+    (Jamo::Lead, Jamo::LeadVowel), (Jamo::Lead, Jamo::LeadVowelTail),
+    (Jamo::LeadVowel, Jamo::Vowel), (Jamo::Vowel, Jamo::Vowel),
+    (Jamo::LeadVowel, Jamo::Tail), (Jamo::Vowel, Jamo::Tail),
+    (Jamo::LeadVowelTail, Jamo::Tail), (Jamo::Tail, Jamo::Tail),
+];
+
+/// The ranges of conjoining Hangul jamo, by kind.
+const JAMO_RANGES: [(char, char, Jamo); 6] = [
+    ('\u{1100}', '\u{115F}', Jamo::Lead), ('\u{A960}', '\u{A97C}', Jamo::Lead),
+    ('\u{1160}', '\u{11A7}', Jamo::Vowel), ('\u{D7B0}', '\u{D7C6}', Jamo::Vowel),
+    ('\u{11A8}', '\u{11FF}', Jamo::Tail), ('\u{D7CB}', '\u{D7FB}', Jamo::Tail),
+];
+
+/// The precomposed Hangul syllables, every 28th of which lacks a trailing consonant.
+const HANGUL_SYLLABLES: (char, char) = ('\u{AC00}', '\u{D7A3}');
+
+/// Gets the kind of Hangul character that the given character is, if any.
+fn jamo(c: char) -> Option<Jamo> {
+    if in_range(c, HANGUL_SYLLABLES) {
+        let index = c as u32 - HANGUL_SYLLABLES.0 as u32;
+        return Some(match index % 28 {
+            0 => Jamo::LeadVowel,
+            _ => Jamo::LeadVowelTail,
+        });
+    }
+    JAMO_RANGES.iter().find(|&&(lo, hi, _)| in_range(c, (lo, hi))).map(|&(_, _, kind)| kind)
+}
+
+/// Checks whether the given character is a mark in one of the Indic scripts from Devanagari to
+/// Malayalam, whose blocks share the layout of ISCII: the signs before the letters, and the
+/// nukta, vowel signs, virama and length marks after them.
+fn is_indic_mark(c: char) -> bool {
+    let c = c as u32;
+    if !(0x0900..0x0D80).contains(&c) {
+        return false;
+    }
+    let offset = c & 0x7F;
+    offset <= 0x03 || offset == 0x3C || ((0x3E..=0x57).contains(&offset) && offset != 0x50) ||
+        offset == 0x62 || offset == 0x63
+}
+
+/// Checks whether the given character is within the given inclusive range.
+fn in_range(c: char, (lo, hi): (char, char)) -> bool {
+    lo <= c && c <= hi
+}
+
+/// Checks whether the character at the given position in the text likely continues the grapheme
+/// cluster of the character before it.
+fn joins_previous(text: &str, pos: usize) -> bool {
+    let (before, after) = text.split_at(pos);
+    let (prev, next) = match (before.chars().next_back(), after.chars().next()) {
+        (Some(prev), Some(next)) => (prev, next),
+        _ => return false,
+    };
+    if prev == '\u{200D}' || is_indic_mark(next) {
+        return true;
+    }
+    if CLUSTER_EXTENDERS.iter().any(|&range| in_range(next, range)) {
+        return true;
+    }
+    if let (Some(prev), Some(next)) = (jamo(prev), jamo(next)) {
+        return JAMO_SEQUENCES.contains(&(prev, next));
+    }
+    // Regional indicators pair up from the start of a run of them, so the next one completes a
+    // flag if an odd number of them come before it.
+    in_range(next, REGIONAL_INDICATORS) &&
+        before.chars().rev().take_while(|&c| in_range(c, REGIONAL_INDICATORS)).count() % 2 == 1
+}
+
+/// Joins the given items into as few lists as possible with the given separator, without any list
+/// exceeding `max_len` unless a single item does. Items are never split between lists, e.g. as
+/// each server acknowledges or rejects a `CAP REQ` as a whole. At least one list is always
+/// returned.
+fn join_within<'a, I>(items: I, separator: char, max_len: usize) -> Vec<String>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let mut lines = vec![String::new()];
+    for item in items {
+        let len = lines.last().unwrap().len();
+        if len > 0 && len + 1 + item.len() > max_len {
+            lines.push(String::new());
+        }
+        let line = lines.last_mut().unwrap();
+        if !line.is_empty() {
+            line.push(separator);
+        }
+        line.push_str(item);
+    }
+    lines
+}
+
+/// The typing activity sent with [`send_typing`](trait.ClientExt.html#method.send_typing).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypingState {
+    /// The user is actively typing.
+    Active,
+    /// The user has typed something, but has stopped for a while.
+    Paused,
+    /// The user has stopped typing without sending, e.g. by clearing their input.
+    Done,
+}
+
+impl AsRef<str> for TypingState {
+    fn as_ref(&self) -> &str {
+        match *self {
+            TypingState::Active => "active",
+            TypingState::Paused => "paused",
+            TypingState::Done => "done",
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::ClientExt;
+    use std::collections::HashMap;
+
+    use super::{ClientExt, TypingState};
     use client::data::Config;
-    use client::IrcClient;
+    use client::{Client, IrcClient};
+    use error::IrcError;
     use client::test::{get_client_value, test_config};
-    use proto::{ChannelMode, Mode};
+    use client::test_util::MockServer;
+    use proto::{Capability, ChannelMode, Message, Mode, ValuedCapability};
+    use proto::Command::JOIN;
 
     #[test]
     fn identify() {
@@ -441,6 +1191,60 @@ mod test {
         );
     }
 
+    #[test]
+    fn identify_with_user_mode_flags() {
+        let client = IrcClient::from_config(Config {
+            user_mode_flags: Some(format!("8")),
+            ..test_config()
+        }).unwrap();
+        client.identify().unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP END\r\nNICK :test\r\n\
+             USER test 8 * :test\r\n"
+        );
+    }
+
+    #[test]
+    fn send_cap_list() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_cap_list().unwrap();
+        assert_eq!(&get_client_value(client)[..], "CAP LIST\r\n");
+    }
+
+    #[test]
+    fn send_cap_req_chunked() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        let caps: Vec<_> = (0..25)
+            .map(|i| Capability::Unknown(format!("vendor.example/capability-{:02}", i)))
+            .collect();
+        client.send_cap_req(&caps).unwrap();
+        let value = get_client_value(client);
+        let lines: Vec<_> = value.split_terminator("\r\n").collect();
+        assert_eq!(lines.len(), 2);
+        let mut requested = vec![];
+        for line in lines {
+            assert!(line.len() + 2 <= 512);
+            assert!(line.starts_with("CAP REQ :"));
+            requested.extend(line["CAP REQ :".len()..].split(' ').map(|s| s.to_owned()));
+        }
+        let expected: Vec<_> = caps.iter().map(|c| c.as_ref().to_owned()).collect();
+        assert_eq!(requested, expected);
+    }
+
+    #[test]
+    fn send_cap_req_values() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_cap_req_values(&[
+            Capability::MultiPrefix.into(),
+            ValuedCapability::new(Capability::Unknown("draft/example".to_owned()), Some("on")),
+        ]).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP REQ :multi-prefix draft/example=on\r\n"
+        );
+    }
+
     #[test]
     fn identify_with_password() {
         let client = IrcClient::from_config(Config {
@@ -456,6 +1260,20 @@ mod test {
         );
     }
 
+    #[test]
+    fn identify_with_bouncer_password() {
+        let client = IrcClient::from_config(Config {
+            password: Some(format!("user/net:pass")),
+            ..test_config()
+        }).unwrap();
+        client.identify().unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CAP END\r\nPASS :user/net:pass\r\nNICK :test\r\n\
+             USER test 0 * :test\r\n"
+        );
+    }
+
     #[test]
     fn send_pong() {
         let client = IrcClient::from_config(test_config()).unwrap();
@@ -473,6 +1291,57 @@ mod test {
         );
     }
 
+    #[test]
+    fn send_join_many() {
+        let chans: Vec<_> = (0..50).map(|i| format!("#long-channel-name-{:02}", i)).collect();
+        let chans: Vec<_> = chans.iter().map(|c| &c[..]).collect();
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_join_many(&chans).unwrap();
+        let value = get_client_value(client);
+        let lines: Vec<_> = value.split_terminator("\r\n").collect();
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| line.starts_with("JOIN #") && line.len() + 2 <= 512));
+        let joined: Vec<_> = lines.iter().flat_map(|line| line[5..].split(',')).collect();
+        assert_eq!(joined, chans);
+    }
+
+    #[test]
+    fn send_join_many_with_keys() {
+        let chans: Vec<_> = (0..50).map(|i| format!("#long-channel-name-{:02}", i)).collect();
+        let chans: Vec<_> = chans.iter().map(|c| &c[..]).collect();
+        let keys: Vec<_> = (0..30).map(|i| format!("key{:02}", i)).collect();
+        let keys: Vec<_> = keys.iter().map(|k| &k[..]).collect();
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_join_many_with_keys(&chans, &keys).unwrap();
+        let value = get_client_value(client);
+        let lines: Vec<_> = value.split_terminator("\r\n").collect();
+        assert!(lines.len() > 1);
+        let (mut joined, mut keyed) = (vec![], vec![]);
+        for line in &lines {
+            assert!(line.len() + 2 <= 512);
+            match line.parse::<Message>().unwrap().command {
+                JOIN(chanlist, keylist, None) => {
+                    let chanlist: Vec<_> = chanlist.split(',').map(|c| c.to_owned()).collect();
+                    let keylist: Vec<_> = match keylist {
+                        Some(ref keylist) => keylist.split(',').collect(),
+                        None => vec![],
+                    };
+                    // Each key stays with its channel.
+                    for (chan, key) in chanlist.iter().zip(keylist.iter()) {
+                        keyed.push((chan.clone(), key.to_string()));
+                    }
+                    joined.extend(chanlist);
+                }
+                command => panic!("expected a JOIN, got {:?}", command),
+            }
+        }
+        assert_eq!(joined, chans);
+        let expected: Vec<_> = chans.iter().zip(keys.iter()).map(|(c, k)| {
+            (c.to_string(), k.to_string())
+        }).collect();
+        assert_eq!(keyed, expected);
+    }
+
     #[test]
     fn send_part() {
         let client = IrcClient::from_config(test_config()).unwrap();
@@ -480,6 +1349,38 @@ mod test {
         assert_eq!(&get_client_value(client)[..], "PART #test\r\n");
     }
 
+    #[cfg(not(feature = "nochanlists"))]
+    fn joined_server(chans: &[String]) -> MockServer {
+        let server = MockServer::new(test_config()).unwrap();
+        for chan in chans {
+            server.push_incoming(&format!(":irc.test.net 353 test = {} :test ada\r\n", chan));
+        }
+        server.process_incoming(|_| ()).unwrap();
+        server
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn part_all() {
+        let server = joined_server(&["#a".to_owned(), "#b".to_owned(), "#c".to_owned()]);
+        server.client().part_all(Some("Goodbye!")).unwrap();
+        assert_eq!(server.take_outgoing(), vec!["PART #a,#b,#c :Goodbye!"]);
+        assert_eq!(server.client().list_channels(), Some(vec![]));
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn part_all_chunked() {
+        let chans: Vec<_> = (0..100).map(|i| format!("#channel{:03}", i)).collect();
+        let server = joined_server(&chans);
+        server.client().part_all(None).unwrap();
+        let lines = server.take_outgoing();
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| line.len() + 2 <= 512));
+        let parted: Vec<_> = lines.iter().flat_map(|line| line[5..].split(',')).collect();
+        assert_eq!(parted, chans);
+    }
+
     #[test]
     fn send_oper() {
         let client = IrcClient::from_config(test_config()).unwrap();
@@ -497,6 +1398,165 @@ mod test {
         );
     }
 
+    #[test]
+    fn send_privmsg_split() {
+        let server = MockServer::new(Config {
+            split_long_messages: Some(true),
+            ..test_config()
+        }).unwrap();
+        // :test2!~test@<63 bytes of host> PRIVMSG #test :<message>\r\n
+        let max_len = 512 - 77 - 17;
+        server.client().send_privmsg("#test", "a".repeat(max_len)).unwrap();
+        server.client().send_privmsg("#test", "é".repeat(max_len / 2 + 1)).unwrap();
+        let sent = server.take_outgoing();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0].len(), 512 - 77 - 2);
+        assert_eq!(sent[1], format!("PRIVMSG #test :{}", "é".repeat(max_len / 2)));
+        assert_eq!(sent[2], "PRIVMSG #test :é");
+    }
+
+    #[test]
+    fn send_notice_split_keeps_zwj_sequences() {
+        let server = MockServer::new(Config {
+            split_long_messages: Some(true),
+            ..test_config()
+        }).unwrap();
+        // :test2!~test@<63 bytes of host> NOTICE #test :<message>\r\n
+        let max_len = 512 - 77 - 16;
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let padding = "a".repeat(max_len - 6);
+        server.client().send_notice("#test", format!("{}{} ok", padding, family)).unwrap();
+        assert_eq!(server.take_outgoing(), vec![
+            format!("NOTICE #test :{}", padding),
+            format!("NOTICE #test :{} ok", family),
+        ]);
+    }
+
+    #[test]
+    fn split_text() {
+        assert_eq!(super::split_text("", 4), vec![""]);
+        assert_eq!(super::split_text("abcdef", 4), vec!["abcd", "ef"]);
+        assert_eq!(super::split_text("ab\u{E9}", 3), vec!["ab", "\u{E9}"]);
+        // A combining acute accent stays with its letter.
+        assert_eq!(super::split_text("abe\u{301}", 4), vec!["ab", "e\u{301}"]);
+        // A cluster longer than the limit is split anyway, at least one character at a time.
+        assert_eq!(super::split_text("e\u{301}\u{301}", 3), vec!["e\u{301}", "\u{301}"]);
+        assert_eq!(super::split_text("\u{1F600}", 2), vec!["\u{1F600}"]);
+        // Flags are pairs of regional indicators, which are only split between pairs.
+        let flags = "\u{1F1EB}\u{1F1F7}\u{1F1E9}\u{1F1EA}";
+        assert_eq!(super::split_text(flags, 12), vec![&flags[..8], &flags[8..]]);
+        // Conjoining jamo make up a single syllable.
+        let jamo = "\u{1100}\u{1161}\u{11A8}";
+        assert_eq!(super::split_text(&format!("a{}", jamo), 9), vec!["a", jamo]);
+        let syllables = "\u{AC00}\u{11A8}\u{AC01}";
+        assert_eq!(super::split_text(syllables, 6), vec![&syllables[..6], &syllables[6..]]);
+        // Spacing vowel signs stay with their consonant, e.g. in Devanagari.
+        assert_eq!(super::split_text("a\u{0915}\u{093F}", 6), vec!["a", "\u{0915}\u{093F}"]);
+    }
+
+    #[test]
+    fn send_privmsg_split_leaves_room_for_relay_prefix() {
+        let server = MockServer::new(Config {
+            nickname: Some("nick".to_owned()),
+            alt_nicks: Some(vec!["longernick".to_owned()]),
+            username: Some("user".to_owned()),
+            split_long_messages: Some(true),
+            ..test_config()
+        }).unwrap();
+        server.client().send_privmsg("#test", "a".repeat(512)).unwrap();
+        let sent = server.take_outgoing();
+        // :longernick!~user@<63 bytes of host> PRIVMSG #test :<message>\r\n
+        assert_eq!(sent[0].len(), 512 - 2 - (1 + 10 + 2 + 4 + 1 + 63 + 1));
+    }
+
+    #[test]
+    fn reply_to() {
+        let server = MockServer::new(Config {
+            nickname: Some("test".to_owned()),
+            ..Default::default()
+        }).unwrap();
+        let msg: Message = "@msgid=abc123 :ada!ada@host PRIVMSG #test :!ping\r\n".into();
+        server.client().reply_to(&msg, "pong").unwrap();
+        server.push_incoming(":irc.test.net CAP test ACK :message-tags\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        server.client().reply_to(&msg, "pong").unwrap();
+        let msg: Message = ":ada!ada@host PRIVMSG #test :!ping\r\n".into();
+        server.client().reply_to(&msg, "pong").unwrap();
+        assert_eq!(server.take_outgoing(), vec![
+            "PRIVMSG #test :pong".to_owned(),
+            "@+draft/reply=abc123 PRIVMSG #test :pong".to_owned(),
+            "PRIVMSG #test :pong".to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn reply_to_private_message() {
+        let server = MockServer::new(Config {
+            nickname: Some("test".to_owned()),
+            ..Default::default()
+        }).unwrap();
+        server.push_incoming(":irc.test.net CAP test ACK :message-tags\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        let msg: Message = "@msgid=abc123 :ada!ada@host PRIVMSG test :!ping\r\n".into();
+        server.client().reply_to(&msg, "pong").unwrap();
+        let msg: Message = "NOTICE test :No source\r\n".into();
+        server.client().reply_to(&msg, "pong").unwrap();
+        assert_eq!(server.take_outgoing(), vec![
+            "@+draft/reply=abc123 PRIVMSG ada :pong".to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn send_reply() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_reply("#test", "abc123", "Hi, ada!").unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "@+draft/reply=abc123 PRIVMSG #test :Hi, ada!\r\n"
+        );
+    }
+
+    #[test]
+    fn send_react() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_react("#test", "abc123", "👍").unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "@+draft/reply=abc123;+draft/react=👍 TAGMSG #test\r\n"
+        );
+    }
+
+    #[test]
+    fn send_tagmsg() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_tagmsg("#test", &[
+            ("+example/flag".to_owned(), None),
+            ("+example/key".to_owned(), Some("value".to_owned())),
+        ]).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "@+example/flag;+example/key=value TAGMSG #test\r\n"
+        );
+    }
+
+    #[test]
+    fn send_typing() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_typing("#c", TypingState::Active).unwrap();
+        assert_eq!(&get_client_value(client)[..], "@+typing=active TAGMSG #c\r\n");
+    }
+
+    #[test]
+    fn send_typing_to_user() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_typing("ada", TypingState::Paused).unwrap();
+        client.send_typing("ada", TypingState::Done).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "@+typing=paused TAGMSG ada\r\n@+typing=done TAGMSG ada\r\n"
+        );
+    }
+
     #[test]
     fn send_notice() {
         let client = IrcClient::from_config(test_config()).unwrap();
@@ -507,6 +1567,42 @@ mod test {
         );
     }
 
+    #[test]
+    fn send_quit() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_quit("").unwrap();
+        client.send_quit("Bye!").unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "QUIT :Powered by Rust.\r\nQUIT :Bye!\r\n"
+        );
+    }
+
+    #[test]
+    fn send_quit_configured_message() {
+        let client = IrcClient::from_config(Config {
+            quit_message: Some(format!("Brought to you by the test suite.")),
+            ..test_config()
+        }).unwrap();
+        client.send_quit("").unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "QUIT :Brought to you by the test suite.\r\n"
+        );
+    }
+
+    #[test]
+    fn send_list_modes() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_ban_list("#test").unwrap();
+        client.send_exception_list("#test").unwrap();
+        client.send_invite_list("#test").unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "MODE #test +b\r\nMODE #test +e\r\nMODE #test +I\r\n"
+        );
+    }
+
     #[test]
     fn send_topic_no_topic() {
         let client = IrcClient::from_config(test_config()).unwrap();
@@ -566,6 +1662,41 @@ mod test {
         assert_eq!(&get_client_value(client)[..], "MODE #test +o test\r\n");
     }
 
+    #[test]
+    fn send_op() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_op("#test", "ada").unwrap();
+        client.send_deop("#test", "ada").unwrap();
+        client.send_voice("#test", "ada").unwrap();
+        client.send_devoice("#test", "ada").unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "MODE #test +o ada\r\nMODE #test -o ada\r\nMODE #test +v ada\r\n\
+             MODE #test -v ada\r\n"
+        );
+    }
+
+    #[test]
+    fn send_ban() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_ban("#test", "*!*@host").unwrap();
+        client.send_unban("#test", "*!*@host").unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "MODE #test +b *!*@host\r\nMODE #test -b *!*@host\r\n"
+        );
+    }
+
+    #[test]
+    fn send_kickban() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_kickban("#test", "ada", "*!*@host", "Bye.").unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "MODE #test +b *!*@host\r\nKICK #test ada :Bye.\r\n"
+        );
+    }
+
     #[test]
     fn send_samode_no_modeparams() {
         let client = IrcClient::from_config(test_config()).unwrap();
@@ -594,6 +1725,137 @@ mod test {
         assert_eq!(&get_client_value(client)[..], "INVITE test #test\r\n");
     }
 
+    #[test]
+    fn send_watch() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_watch_add("ada").unwrap();
+        client.send_watch_del("ada").unwrap();
+        assert_eq!(&get_client_value(client)[..], "WATCH +ada\r\nWATCH -ada\r\n");
+    }
+
+    #[test]
+    fn send_modes() {
+        let value = ":irc.test.net 005 test MODES=4 :are supported by this server\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        let modes: Vec<_> = (0..10)
+            .map(|i| Mode::Plus(ChannelMode::Oper, Some(format!("user{}", i))))
+            .collect();
+        client.send_modes("#test", &modes).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "MODE #test +o user0 +o user1 +o user2 +o user3\r\n\
+             MODE #test +o user4 +o user5 +o user6 +o user7\r\n\
+             MODE #test +o user8 +o user9\r\n"
+        );
+    }
+
+    #[test]
+    fn send_silence() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_silence_add("*!*@spam.host").unwrap();
+        client.send_silence_del("*!*@spam.host").unwrap();
+        client.send_silence_list().unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "SILENCE +*!*@spam.host\r\nSILENCE -*!*@spam.host\r\nSILENCE\r\n"
+        );
+    }
+
+    #[test]
+    fn services() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.chanserv("OP #chan").unwrap();
+        client.nickserv("INFO test").unwrap();
+        client.memoserv("LIST").unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "PRIVMSG ChanServ :OP #chan\r\nPRIVMSG NickServ :INFO test\r\n\
+             PRIVMSG MemoServ :LIST\r\n"
+        );
+    }
+
+    #[test]
+    fn services_with_custom_names() {
+        let mut names = HashMap::new();
+        names.insert("ChanServ".to_owned(), "Q".to_owned());
+        let client = IrcClient::from_config(Config {
+            service_names: Some(names),
+            ..test_config()
+        }).unwrap();
+        client.chanserv("OP #chan").unwrap();
+        client.nickserv("INFO test").unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "PRIVMSG Q :OP #chan\r\nPRIVMSG NickServ :INFO test\r\n"
+        );
+    }
+
+    fn metadata_client() -> IrcClient {
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(":irc.test.net CAP test ACK :metadata\r\n".to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|_| ()).unwrap();
+        client
+    }
+
+    #[test]
+    fn send_metadata_set() {
+        let client = metadata_client();
+        client.send_metadata_set("*", "avatar", "https://example.com/ada.png").unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "METADATA * SET avatar :https://example.com/ada.png\r\n"
+        );
+    }
+
+    #[test]
+    fn send_metadata_get() {
+        let client = metadata_client();
+        client.send_metadata_get("ada", &["avatar", "url"]).unwrap();
+        assert_eq!(&get_client_value(client)[..], "METADATA ada GET avatar url\r\n");
+    }
+
+    #[test]
+    fn send_metadata_without_capability() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        match client.send_metadata_get("ada", &["avatar"]) {
+            Err(IrcError::CapabilityNotEnabled { capability: Capability::Metadata }) => (),
+            res => panic!("expected CapabilityNotEnabled, got {:?}", res),
+        }
+        assert_eq!(&get_client_value(client)[..], "");
+    }
+
+    #[test]
+    fn send_chathistory_latest() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_chathistory_latest("#test", 50).unwrap();
+        assert_eq!(&get_client_value(client)[..], "CHATHISTORY LATEST #test * 50\r\n");
+    }
+
+    #[test]
+    fn send_chathistory_before() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_chathistory_before("#test", "msgid=abc", 20).unwrap();
+        assert_eq!(&get_client_value(client)[..], "CHATHISTORY BEFORE #test msgid=abc 20\r\n");
+    }
+
+    #[test]
+    fn send_chathistory_after() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_chathistory_after("#test", "timestamp=2019-01-01T00:00:00.000Z", 10).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "CHATHISTORY AFTER #test timestamp=2019-01-01T00:00:00.000Z 10\r\n"
+        );
+    }
+
     #[test]
     #[cfg(feature = "ctcp")]
     fn send_ctcp() {
@@ -605,6 +1867,19 @@ mod test {
         );
     }
 
+    #[test]
+    #[cfg(feature = "ctcp")]
+    fn send_ctcp_reply() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_ctcp_reply("test", "VERSION", "irc:git:Rust").unwrap();
+        client.send_ctcp_reply("test", "SOURCE", "").unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NOTICE test :\u{001}VERSION irc:git:Rust\u{001}\r\n\
+             NOTICE test :\u{001}SOURCE\u{001}\r\n"
+        );
+    }
+
     #[test]
     #[cfg(feature = "ctcp")]
     fn send_action() {
@@ -671,6 +1946,21 @@ mod test {
         assert!(val.ends_with("\u{001}\r\n"));
     }
 
+    #[test]
+    #[cfg(feature = "ctcp")]
+    fn send_ctcp_ping_ms() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_ctcp_ping("test").unwrap();
+        client.send_ctcp_ping_ms("test").unwrap();
+        let val = get_client_value(client);
+        let stamps: Vec<u64> = val.split("\r\n").filter(|line| !line.is_empty()).map(|line| {
+            line.trim_start_matches("PRIVMSG test :\u{001}PING ").trim_end_matches('\u{001}')
+                .parse().unwrap()
+        }).collect();
+        assert_eq!(stamps.len(), 2);
+        assert!(stamps[1] / 1000 >= stamps[0] && stamps[1] / 1000 - stamps[0] <= 1);
+    }
+
     #[test]
     #[cfg(feature = "ctcp")]
     fn send_time() {
@@ -37,7 +37,7 @@
 //! # client.identify().unwrap();
 //! client.for_each_incoming(|irc_msg| {
 //!     if let Command::PRIVMSG(channel, message) = irc_msg.command {
-//!         if message.contains(client.current_nickname()) {
+//!         if message.contains(&client.current_nickname()) {
 //!             client.send_privmsg(&channel, "beep boop").unwrap();
 //!         }
 //!     }
@@ -45,34 +45,131 @@
 //! # }
 //! ```
 
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::mem;
 use std::path::Path;
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[cfg(feature = "ctcp")]
 use chrono::prelude::*;
-use futures::{Async, Poll, Future, Sink, Stream};
-use futures::stream::SplitStream;
-use futures::sync::mpsc;
+use futures::{future, task, Async, AsyncSink, Poll, Future, Sink, Stream};
+use futures::stream::{SplitStream, Wait};
 use futures::sync::oneshot;
-use futures::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use futures::task::Task;
 use tokio_core::reactor::{Core, Handle};
+use tokio_timer::{self, Sleep, Timer};
 
 use error;
-use client::conn::{Connection, ConnectionFuture};
-use client::data::{Config, User};
+use client::conn::{Connection, ConnectionFuture, LastServer};
+use client::data::{Config, ConnectionMetrics, ListEntry, ListMode, OverflowPolicy, User, UserInfo};
+use client::data::WhoisReply;
+use client::data::config::SaslMechanism;
+use client::data::metrics::Metrics;
+use client::data::user::fold_nickname;
+use client::data::user::nicknames_eq;
+use client::data::sts::{self, StsPolicy};
 use client::ext::ClientExt;
-use client::transport::LogView;
-use proto::{ChannelMode, Command, Message, Mode, Response};
-use proto::Command::{JOIN, KICK, NICK, NICKSERV, PART, PRIVMSG, ChannelMODE, QUIT};
+use client::transport::{throttle_key, LogView};
+use client::util::base64_encode;
+use proto::{Capability, CapSubCommand, ChannelJoinError, ChannelMode, ChannelVisibility};
+use proto::{BatchSubCommand, Command, Message, Mode};
+use proto::{is_channel, Response, ValuedCapability};
+use proto::chan::DEFAULT_CHANTYPES;
+use proto::message::Reaction;
+use proto::Command::{ACCOUNT, AWAY, JOIN, KICK, NICK, NICKSERV, PART, PRIVMSG, ChannelMODE, QUIT};
+use proto::Command::{BATCH, NAMES, NOTICE, WHOIS};
 
 pub mod conn;
 pub mod data;
 pub mod ext;
 pub mod prelude;
 pub mod reactor;
+pub mod relay;
+pub mod test_util;
 pub mod transport;
+mod util;
+
+pub use self::relay::relay;
+
+/// The number of consecutive kicks from a channel after which automatic rejoins stop.
+const MAX_REJOIN_ATTEMPTS: u32 = 3;
+
+/// The number of seconds without a kick after which a channel's rejoin backoff is reset.
+const REJOIN_RESET_SECS: u64 = 300;
+
+/// The maximum length of a nickname assumed until the server advertises its own with `NICKLEN`,
+/// as specified by [RFC 2812](https://tools.ietf.org/html/rfc2812#section-1.2.1). Nicknames are
+/// only truncated to it when the server refuses a longer one.
+const DEFAULT_NICK_LEN: usize = 9;
+
+/// The maximum length of a line in bytes, excluding tags, as specified by
+/// [RFC 2812](https://tools.ietf.org/html/rfc2812#section-2.3).
+const MAX_LINE_LEN: usize = 512;
+
+/// The maximum length of the tags of a message sent by a client in bytes, excluding the leading
+/// `@` and the trailing space, as specified by
+/// [IRCv3](https://ircv3.net/specs/extensions/message-tags#size-limit).
+const MAX_TAGS_LEN: usize = 4094;
+
+/// The maximum length of the data in a single `AUTHENTICATE` command, as specified by
+/// [IRCv3](https://ircv3.net/specs/extensions/sasl-3.1#the-authenticate-command).
+const MAX_SASL_CHUNK_LEN: usize = 400;
+
+/// Truncates the given nickname to at most the given length in bytes without splitting a UTF-8
+/// character.
+fn truncate_nickname(nick: &str, mut len: usize) -> &str {
+    if len >= nick.len() {
+        return nick;
+    }
+    while !nick.is_char_boundary(len) {
+        len -= 1;
+    }
+    &nick[..len]
+}
+
+/// Computes the length in bytes of the tags of the given message as sent, i.e. with their values
+/// escaped, excluding the leading `@` and the trailing space.
+fn tags_len(msg: &Message) -> usize {
+    let tags = match msg.tags {
+        Some(ref tags) if !tags.is_empty() => tags,
+        _ => return 0,
+    };
+    let separators = tags.len() - 1;
+    separators + tags.iter().map(|tag| {
+        let value = tag.1.as_ref().map_or(0, |value| {
+            1 + value.chars().map(|c| match c {
+                ';' | ' ' | '\\' | '\r' | '\n' => 2,
+                c => c.len_utf8(),
+            }).sum::<usize>()
+        });
+        tag.0.len() + value
+    }).sum::<usize>()
+}
+
+/// Computes the length in bytes of the given message as sent, including tags.
+fn wire_len(msg: &Message) -> usize {
+    match tags_len(msg) {
+        0 => line_len(msg),
+        tags => tags + 2 + line_len(msg),
+    }
+}
+
+/// Computes the length in bytes of the given message as sent, excluding tags. As this is checked
+/// for every message sent, the most common commands are measured without serializing them.
+fn line_len(msg: &Message) -> usize {
+    let prefix = msg.prefix.as_ref().map_or(0, |p| p.len() + 2);
+    let command = match msg.command {
+        // <command> <target> :<text>
+        PRIVMSG(ref target, ref text) => "PRIVMSG".len() + target.len() + text.len() + 3,
+        NOTICE(ref target, ref text) => "NOTICE".len() + target.len() + text.len() + 3,
+        ref command => String::from(command).len(),
+    };
+    prefix + command + 2
+}
 
 /// Trait extending all IRC streams with `for_each_incoming` convenience function.
 ///
@@ -92,7 +189,7 @@ pub mod transport;
 /// # client.identify().unwrap();
 /// client.stream().for_each_incoming(|irc_msg| {
 ///   match irc_msg.command {
-///     Command::PRIVMSG(channel, message) => if message.contains(client.current_nickname()) {
+///     Command::PRIVMSG(channel, message) => if message.contains(&client.current_nickname()) {
 ///       client.send_privmsg(&channel, "beep boop").unwrap();
 ///     }
 ///     _ => ()
@@ -157,7 +254,7 @@ pub trait Client {
     /// # client.identify().unwrap();
     /// client.for_each_incoming(|irc_msg| {
     ///     if let Command::PRIVMSG(channel, message) = irc_msg.command {
-    ///         if message.contains(client.current_nickname()) {
+    ///         if message.contains(&client.current_nickname()) {
     ///             client.send_privmsg(&channel, "beep boop").unwrap();
     ///         }
     ///     }
@@ -191,6 +288,34 @@ pub trait Client {
     /// # }
     /// ```
     fn list_users(&self, channel: &str) -> Option<Vec<User>>;
+
+    /// Gets the maximum number of mode changes per `MODE` command, as used by
+    /// [`ClientExt::send_modes`](./ext/trait.ClientExt.html#method.send_modes). This defaults to
+    /// 3, as specified by [RFC 2812](https://tools.ietf.org/html/rfc2812#section-3.2.3).
+    fn max_modes(&self) -> i64 {
+        3
+    }
+
+    /// Determines whether the capability with the given name is enabled for this connection, as
+    /// reported in
+    /// [`negotiated_capabilities`](./struct.IrcClient.html#method.negotiated_capabilities).
+    /// Names are compared case-insensitively, and do not include any value (e.g. `sasl` for
+    /// `sasl=PLAIN`). Clients that do not track capabilities report none as enabled.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// # let client = IrcClient::new("config.toml").unwrap();
+    /// if client.has_capability("server-time") {
+    ///     // messages carry the time they were sent in their tags
+    /// }
+    /// # }
+    /// ```
+    fn has_capability(&self, _: &str) -> bool {
+        false
+    }
 }
 
 /// A stream of `Messages` received from an IRC server via an `IrcClient`.
@@ -210,13 +335,265 @@ impl Stream for ClientStream {
     type Error = error::IrcError;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        match try_ready!(self.stream.poll()) {
-            Some(msg) => {
+        self.state.send_due_rejoins()?;
+        match self.stream.poll() {
+            Ok(Async::Ready(Some(msg))) => {
                 self.state.handle_message(&msg)?;
+                ClientState::run_rename_callback(&self.state);
+                self.state.run_limit_callback();
                 Ok(Async::Ready(Some(msg)))
             }
-            None => Ok(Async::Ready(None)),
+            Ok(Async::Ready(None)) => {
+                self.state.set_disconnected();
+                match self.state.take_server_error() {
+                    Some(reason) => Err(error::IrcError::ServerError { reason: reason }),
+                    None => Ok(Async::Ready(None)),
+                }
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => {
+                self.state.set_disconnected();
+                Err(e)
+            }
+        }
+    }
+}
+
+/// The automatic rejoins scheduled by a client after being kicked from channels.
+#[derive(Debug, Default)]
+struct Rejoins {
+    /// The timer for the rejoin delays, created on the first kick.
+    timer: Option<Timer>,
+    /// The channels to rejoin, each with the delay until doing so.
+    pending: Vec<(Sleep, String)>,
+}
+
+/// The stage in the lifecycle of an [`IrcClient`](./struct.IrcClient.html)'s connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The connection ended or failed without the client quitting.
+    Disconnected,
+    /// The client is connected, but has not yet begun to register with the server.
+    Connecting,
+    /// The client has begun to register (e.g. with `identify`), but the server has not yet
+    /// welcomed it with `RPL_WELCOME`.
+    Registering,
+    /// The server has welcomed the client, so that it can join channels and send messages.
+    Registered,
+    /// The connection was lost, and a
+    /// [`ReconnectingIter`](./struct.ReconnectingIter.html) is reconnecting with a new client.
+    /// The old client stays in this state once it has been replaced.
+    Reconnecting,
+    /// The client has quit.
+    Closed,
+}
+
+/// An event yielded by a [`ReconnectingIter`](./struct.ReconnectingIter.html).
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientEvent {
+    /// A message received from the server.
+    Message(Message),
+    /// The connection was lost, and the client has reconnected and identified itself again. Any
+    /// messages sent should now go through the new client.
+    Reconnected,
+}
+
+/// A limit on tracking that a channel exceeded, as reported to the callback set with
+/// [`on_tracking_limit`](./struct.IrcClient.html#method.on_tracking_limit).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackingLimit {
+    /// The channel was joined while the users of `max_tracked_channels` channels were already
+    /// tracked.
+    Channels,
+    /// The channel has more users than `max_tracked_users_per_channel`.
+    UsersPerChannel,
+}
+
+/// A blocking iterator over the messages received by an `IrcClient` that reconnects whenever the
+/// connection ends or fails, rather than ending itself. Upon reconnecting, the new client
+/// identifies itself and rejoins both the configured channels and those joined at runtime (with
+/// the keys they were joined with), and the iterator yields `ClientEvent::Reconnected`.
+///
+/// Errors ending the connection are yielded before reconnecting, as are errors from failed
+/// attempts to reconnect, which are retried on the next call to `next`. Attempts that keep failing
+/// to register back off exponentially, as configured by `reconnect_delay_ms` and
+/// `max_reconnect_delay_ms`. A `StsUpgrade` error is followed by a reconnect with TLS on the port
+/// that the server advertised.
+///
+/// # Example
+/// ```no_run
+/// # extern crate irc;
+/// # use irc::client::prelude::*;
+/// use irc::client::ClientEvent;
+///
+/// # fn main() {
+/// # let client = IrcClient::new("config.toml").unwrap();
+/// client.identify().unwrap();
+/// let mut events = client.iter_reconnecting();
+/// while let Some(event) = events.next() {
+///     match event {
+///         Ok(ClientEvent::Message(message)) => print!("{}", message),
+///         Ok(ClientEvent::Reconnected) => println!("reconnected"),
+///         Err(e) => println!("connection error: {}", e),
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ReconnectingIter {
+    client: IrcClient,
+    messages: Option<Wait<ClientStream>>,
+    failures: u32,
+    sts_port: Option<u16>,
+}
+
+impl ReconnectingIter {
+    /// Gets the client currently in use, which changes upon reconnecting.
+    pub fn client(&self) -> &IrcClient {
+        &self.client
+    }
+
+    /// Connects a new client with the same configuration, carrying over the channels that were
+    /// joined at runtime so that they are rejoined once registration completes.
+    ///
+    /// A connection lost after registration is replaced right away, but each consecutive attempt
+    /// that fails to register first waits for a delay with exponential backoff. If the server
+    /// demanded an upgrade with its STS policy, the new client connects with TLS on the advertised
+    /// port, where the policy is then persisted to the `sts_cache`.
+    fn reconnect(&mut self) -> error::Result<()> {
+        self.client.state.set_connection_state(ConnectionState::Reconnecting);
+        let delay = reconnect_delay(
+            self.client.config().reconnect_delay_ms(),
+            self.client.config().max_reconnect_delay_ms(),
+            self.failures,
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0),
+        );
+        self.failures = self.failures.saturating_add(1);
+        thread::sleep(delay);
+        let mut config = self.client.config().clone();
+        if let Some(port) = self.sts_port {
+            config.use_ssl = Some(true);
+            config.use_starttls = Some(false);
+            config.port = Some(port);
+        }
+        let last = self.client.state.last_server.clone();
+        let client = IrcClient::from_config_with_last_server(config, last)?;
+        self.sts_port = None;
+        client.state.inherit_channels(&self.client.state);
+        client.state.inherit_state_callback(&self.client.state);
+        client.state.metrics.inherit(&self.client.state.metrics);
+        client.identify()?;
+        self.messages = Some(client.stream().wait());
+        self.client = client;
+        Ok(())
+    }
+}
+
+impl Iterator for ReconnectingIter {
+    type Item = error::Result<ClientEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.messages.as_mut().and_then(|messages| messages.next());
+        match next {
+            Some(Ok(message)) => {
+                if self.client.state() == ConnectionState::Registered {
+                    self.failures = 0;
+                }
+                return Some(Ok(ClientEvent::Message(message)));
+            }
+            Some(Err(e)) => {
+                // The connection is unusable, so we reconnect on the next call.
+                if let error::IrcError::StsUpgrade { port } = e {
+                    self.sts_port = Some(port);
+                }
+                self.messages = None;
+                return Some(Err(e));
+            }
+            None => (),
         }
+        Some(self.reconnect().map(|()| ClientEvent::Reconnected))
+    }
+}
+
+/// Computes the delay before reconnecting after the given number of consecutive failures. The base
+/// delay doubles with each failure up to the cap, and up to half of it is taken off using the seed
+/// as a source of randomness.
+fn reconnect_delay(base: u32, cap: u32, failures: u32, seed: u32) -> Duration {
+    if failures == 0 {
+        return Duration::from_millis(0);
+    }
+    let delay = cmp::min(u64::from(base) << cmp::min(failures - 1, 32), u64::from(cap));
+    Duration::from_millis(delay - u64::from(seed) % (delay / 2 + 1))
+}
+
+/// The membership modes and prefixes assumed from highest to lowest rank until the server
+/// advertises `PREFIX`.
+const DEFAULT_PREFIX: &str = "(qaohv)~&@%+";
+
+/// The token used to recognize replies to the `WHOX` queries sent for `who_on_join`.
+const WHOX_TOKEN: &str = "152";
+
+/// The outcome of joining a channel, failing with the reason the server refused the join.
+type JoinOutcome = Result<(), ChannelJoinError>;
+
+/// The outcome of a `WHOIS`, failing with the error reply sent by the server.
+type WhoisOutcome = Result<WhoisReply, Message>;
+
+/// The reactions received to each message id, and the message ids in the order they were first
+/// reacted to.
+type ReactionLog = (HashMap<String, Vec<Reaction>>, VecDeque<String>);
+
+/// A callback run when the server forcibly changes the client's nickname.
+#[derive(Clone)]
+struct RenameCallback(Arc<RenameFn>);
+
+/// A function taking the client along with its old and new nicknames.
+type RenameFn = Fn(&IrcClient, &str, &str) + Send + Sync;
+
+impl fmt::Debug for RenameCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RenameCallback(...)")
+    }
+}
+
+/// A callback run when a channel exceeds one of the limits on tracking.
+#[derive(Clone)]
+struct LimitCallback(Arc<LimitFn>);
+
+/// A function taking the channel and the limit it exceeded.
+type LimitFn = Fn(&str, TrackingLimit) + Send + Sync;
+
+impl fmt::Debug for LimitCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LimitCallback(...)")
+    }
+}
+
+/// A handler for a custom CTCP request, producing the body of the reply.
+#[derive(Clone)]
+struct CtcpHandler(Arc<CtcpFn>);
+
+/// A function taking the arguments of a CTCP request, and returning the body of the reply or
+/// `None` to send no reply.
+type CtcpFn = Fn(&str) -> Option<String> + Send + Sync;
+
+impl fmt::Debug for CtcpHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CtcpHandler(...)")
+    }
+}
+
+/// A callback run on every transition between stages of the connection's lifecycle. This is
+/// shared so that it can be run without holding its lock, letting it use the client freely.
+#[derive(Clone)]
+struct StateCallback(Arc<StateFn>);
+
+/// A function taking the old and new stages of the connection.
+type StateFn = Fn(ConnectionState, ConnectionState) + Send + Sync;
+
+impl fmt::Debug for StateCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StateCallback(...)")
     }
 }
 
@@ -225,14 +602,128 @@ impl Stream for ClientStream {
 struct ClientState {
     /// The configuration used with this connection.
     config: Config,
+    /// The port of the TLS connection in use, or `None` if TLS was not used to connect directly.
+    tls_port: Option<u16>,
     /// A thread-safe map of channels to the list of users in them.
     chanlists: Mutex<HashMap<String, Vec<User>>>,
+    /// The channels in `chanlists` whose users are not tracked, as they exceeded the configured
+    /// `max_tracked_channels` or `max_tracked_users_per_channel`.
+    untracked_channels: Mutex<HashSet<String>>,
+    /// The channels that exceeded a limit on tracking while handling the most recent message.
+    exceeded_limits: Mutex<Vec<(String, TrackingLimit)>>,
+    /// The callback to run when a channel exceeds a limit on tracking.
+    limit_callback: Mutex<Option<LimitCallback>>,
+    /// A thread-safe list of the capabilities most recently reported as enabled by `CAP LIST`.
+    capabilities: Mutex<Vec<Capability>>,
+    /// A thread-safe list of capabilities from a multiline `CAP LIST` reply still being received.
+    pending_capabilities: Mutex<Vec<Capability>>,
+    /// A thread-safe record of the capabilities acknowledged by the most recent `CAP ACK` or
+    /// `CAP NAK`, paired with a condition variable to wake anyone waiting on a reply.
+    cap_reply: (Mutex<Option<CapReply>>, Condvar),
+    /// A thread-safe map of the channels being joined by `join_and_wait` (under RFC 1459
+    /// casemapping) to the outcome of the join once known, paired with a condition variable to
+    /// wake anyone waiting on an outcome.
+    join_replies: (Mutex<HashMap<String, Option<JoinOutcome>>>, Condvar),
+    /// A thread-safe record of the replies awaited by `send_and_await`, paired with a condition
+    /// variable to wake the waiting threads when a reply arrives.
+    awaited_replies: (Mutex<AwaitedReplies>, Condvar),
+    /// A thread-safe record of the `WHOIS` replies being received and awaited by `whois`, paired
+    /// with a condition variable to wake the waiting threads when a reply is complete.
+    whois_replies: (Mutex<WhoisReplies>, Condvar),
+    /// The `NAMES` replies awaited by `names` calls, paired with a condition variable to wake them.
+    names_replies: (Mutex<NamesReplies>, Condvar),
+    /// A thread-safe map of joined channels (under RFC 1459 casemapping) to their visibility as of
+    /// the most recent `RPL_NAMREPLY`.
+    #[cfg(not(feature = "nochanlists"))]
+    visibilities: Mutex<HashMap<String, ChannelVisibility>>,
+    /// A thread-safe map of channels (under RFC 1459 casemapping) to the keys sent when joining
+    /// them, for joins that the server has yet to confirm.
+    pending_channel_keys: Mutex<HashMap<String, String>>,
+    /// A thread-safe map of channels (under RFC 1459 casemapping) to the keys used to join them,
+    /// so that they can be rejoined with the same keys.
+    channel_keys: Mutex<HashMap<String, String>>,
+    /// A thread-safe list of channels joined at runtime by a previous connection, to be rejoined
+    /// once registration completes.
+    rejoin_channels: Mutex<Vec<String>>,
+    /// A thread-safe map of the references of `chathistory` batches still being received to
+    /// their targets and the messages received in them so far.
+    pending_history: Mutex<HashMap<String, (String, Vec<Message>)>>,
+    /// A thread-safe map of targets (under RFC 1459 casemapping) to the messages in the most
+    /// recently completed `chathistory` batch for them.
+    chat_history: Mutex<HashMap<String, Vec<Message>>>,
+    /// A thread-safe map of message ids to the reactions received to them, if `track_reactions`
+    /// is enabled, along with the message ids in the order they were first reacted to.
+    reactions: Mutex<ReactionLog>,
+    /// A thread-safe map of channels and list modes to the most recently completed list.
+    #[cfg(not(feature = "nochanlists"))]
+    lists: Mutex<HashMap<(String, ListMode), Vec<ListEntry>>>,
+    /// A thread-safe map of channels and list modes to lists that are still being received.
+    #[cfg(not(feature = "nochanlists"))]
+    pending_lists: Mutex<HashMap<(String, ListMode), Vec<ListEntry>>>,
+    /// The most recently completed `SILENCE` list of the client, if any.
+    silence_list: Mutex<Option<Vec<String>>>,
+    /// The masks of a `SILENCE` list that is still being received.
+    pending_silence_list: Mutex<Vec<String>>,
     /// A thread-safe index to track the current alternative nickname being used.
     alt_nick_index: RwLock<usize>,
+    /// The nickname the server forcibly changed the client's nickname to, if it is not one of the
+    /// configured nicknames.
+    forced_nickname: RwLock<Option<String>>,
+    /// The nickname most recently requested with `NICK`, used to tell forced renames apart.
+    requested_nickname: Mutex<Option<String>>,
+    /// A forced rename not yet passed to the rename callback, as the old and new nicknames.
+    forced_rename: Mutex<Option<(String, String)>>,
+    /// The reason given in an `ERROR` from the server, reported once the connection ends.
+    server_error: Mutex<Option<String>>,
+    /// Whether a SASL `PLAIN` exchange started by `reauthenticate` is waiting for the server to
+    /// ask for the credentials.
+    sasl_pending: Mutex<bool>,
+    /// The callback to run when the server forcibly changes the client's nickname.
+    rename_callback: Mutex<Option<RenameCallback>>,
+    /// The handlers for custom CTCP requests, keyed by their uppercased tags.
+    ctcp_handlers: Mutex<HashMap<String, CtcpHandler>>,
+    /// The name the server announced for itself in `RPL_WELCOME` or `RPL_MYINFO`.
+    server_name: RwLock<Option<String>>,
+    /// The server software version announced in `RPL_MYINFO`.
+    server_version: RwLock<Option<String>>,
+    /// A thread-safe map of the tokens advertised in `RPL_ISUPPORT` to their values.
+    isupport: RwLock<HashMap<String, String>>,
+    /// A thread-safe map of folded nicknames to when they were last queried with
+    /// `whois_throttled`.
+    whois_requests: Mutex<HashMap<String, Instant>>,
+    /// A thread-safe map of folded targets and texts to when a `PRIVMSG` with them was last sent,
+    /// used to drop duplicates within the configured `dedupe_window_ms`.
+    recent_privmsgs: Mutex<HashMap<(String, String), Instant>>,
+    /// A thread-safe count of the duplicate messages dropped so far.
+    suppressed_privmsgs: Mutex<usize>,
+    /// A thread-safe record of when the most recent message was received.
+    last_activity: Mutex<Instant>,
+    /// A thread-safe record of when the most recent message was sent.
+    last_sent: Mutex<Instant>,
+    /// A thread-safe record of whether and how the client has marked itself as away.
+    away: Mutex<AwayState>,
+    /// A thread-safe record of registration progress, paired with a condition variable to wake
+    /// anyone waiting on registration.
+    registration: (Mutex<Registration>, Condvar),
+    /// The reply with which the server refused registration, if it has done so.
+    registration_refusal: Mutex<Option<Message>>,
+    /// The stage in the lifecycle of the connection.
+    connection_state: RwLock<ConnectionState>,
+    /// The callback to run on every transition between stages of the connection's lifecycle.
+    state_callback: Mutex<Option<StateCallback>>,
+    /// A thread-safe map of channels to the time of and number of consecutive recent kicks from
+    /// them, used to back off from automatic rejoins.
+    kicks: Mutex<HashMap<String, (Instant, u32)>>,
+    /// The automatic rejoins waiting for their delay to pass after a kick.
+    rejoins: Mutex<Rejoins>,
     /// A thread-safe internal IRC stream used for the reading API.
     incoming: Mutex<Option<SplitStream<Connection>>>,
-    /// A thread-safe copy of the outgoing channel.
-    outgoing: UnboundedSender<Message>,
+    /// The queue of messages waiting to be sent.
+    backlog: Arc<Backlog>,
+    /// The server that most recently accepted a connection, tried first when reconnecting.
+    last_server: LastServer,
+    /// The counters for the traffic over the connection.
+    metrics: Arc<Metrics>,
 }
 
 impl<'a> Client for ClientState {
@@ -241,9 +732,33 @@ impl<'a> Client for ClientState {
     }
 
     fn send<M: Into<Message>>(&self, msg: M) -> error::Result<()> where Self: Sized {
-        let msg = msg.into();
-        self.handle_sent_message(&msg)?;
-        Ok(self.outgoing.unbounded_send(msg)?)
+        let mut msg = msg.into();
+        if let NICK(ref mut nick) = msg.command {
+            let len = self.fit_nickname(nick).len();
+            nick.truncate(len);
+        }
+        if let Command::METADATA(..) = msg.command {
+            if !self.capabilities.lock().unwrap().contains(&Capability::Metadata) {
+                return Err(error::IrcError::CapabilityNotEnabled {
+                    capability: Capability::Metadata,
+                });
+            }
+        }
+        let length = tags_len(&msg);
+        if length > MAX_TAGS_LEN {
+            return Err(error::IrcError::TagsTooLong { length: length, max: MAX_TAGS_LEN });
+        }
+        let length = line_len(&msg);
+        if length > MAX_LINE_LEN && self.config.reject_long_messages() {
+            return Err(error::IrcError::MessageTooLong { length: length, max: MAX_LINE_LEN });
+        }
+        if self.is_duplicate_privmsg(&msg) {
+            debug!("Dropping duplicate {}", msg.to_string().trim_end());
+            *self.suppressed_privmsgs.lock().unwrap() += 1;
+            return Ok(());
+        }
+        self.record_sent(&msg)?;
+        self.send_message(msg)
     }
 
     fn stream(&self) -> ClientStream {
@@ -269,6 +784,9 @@ impl<'a> Client for ClientState {
 
     #[cfg(not(feature = "nochanlists"))]
     fn list_users(&self, chan: &str) -> Option<Vec<User>> {
+        if self.untracked_channels.lock().unwrap().contains(chan) {
+            return None;
+        }
         self.chanlists
             .lock()
             .unwrap()
@@ -280,41 +798,307 @@ impl<'a> Client for ClientState {
     fn list_users(&self, _: &str) -> Option<Vec<User>> {
         None
     }
+
+    fn max_modes(&self) -> i64 {
+        self.isupport
+            .read()
+            .unwrap()
+            .get("MODES")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(3)
+    }
+
+    fn has_capability(&self, cap: &str) -> bool {
+        self.capabilities.lock().unwrap().iter().any(|c| c.as_ref().eq_ignore_ascii_case(cap))
+    }
+}
+
+impl Drop for ClientState {
+    fn drop(&mut self) {
+        // Nothing else will be sent, so the sending future can finish once the backlog drains.
+        self.backlog.close();
+    }
 }
 
 impl ClientState {
+    #[cfg(not(feature = "nochanlists"))]
+    fn users(&self) -> Option<Vec<UserInfo>> {
+        let chanlists = self.chanlists.lock().unwrap();
+        let mut chans: Vec<_> = chanlists.iter().collect();
+        chans.sort_by(|a, b| a.0.cmp(b.0));
+        let mut users: HashMap<String, UserInfo> = HashMap::new();
+        for (chan, list) in chans {
+            for user in list {
+                let key = fold_nickname(user.get_nickname());
+                if let Some(info) = users.get_mut(&key) {
+                    info.merge(chan, user);
+                    continue;
+                }
+                users.insert(key, UserInfo::new(chan, user));
+            }
+        }
+        let mut users: Vec<_> = users.into_iter().collect();
+        users.sort_by(|a, b| a.0.cmp(&b.0));
+        Some(users.into_iter().map(|(_, info)| info).collect())
+    }
+
+    #[cfg(feature = "nochanlists")]
+    fn users(&self) -> Option<Vec<UserInfo>> {
+        None
+    }
+
     fn new(
         incoming: SplitStream<Connection>,
-        outgoing: UnboundedSender<Message>,
+        backlog: Arc<Backlog>,
+        metrics: Arc<Metrics>,
+        last_server: LastServer,
         config: Config,
     ) -> ClientState {
         ClientState {
+            tls_port: if config.use_ssl() {
+                Some(config.port())
+            } else {
+                sts::upgrade_port(&config)
+            },
             config: config,
             chanlists: Mutex::new(HashMap::new()),
+            untracked_channels: Mutex::new(HashSet::new()),
+            exceeded_limits: Mutex::new(vec![]),
+            limit_callback: Mutex::new(None),
+            capabilities: Mutex::new(Vec::new()),
+            pending_capabilities: Mutex::new(Vec::new()),
+            cap_reply: (Mutex::new(None), Condvar::new()),
+            join_replies: (Mutex::new(HashMap::new()), Condvar::new()),
+            awaited_replies: (Mutex::new(AwaitedReplies::default()), Condvar::new()),
+            whois_replies: (Mutex::new(WhoisReplies::default()), Condvar::new()),
+            names_replies: (Mutex::new(NamesReplies::default()), Condvar::new()),
+            pending_channel_keys: Mutex::new(HashMap::new()),
+            channel_keys: Mutex::new(HashMap::new()),
+            rejoin_channels: Mutex::new(Vec::new()),
+            pending_history: Mutex::new(HashMap::new()),
+            chat_history: Mutex::new(HashMap::new()),
+            reactions: Mutex::new((HashMap::new(), VecDeque::new())),
+            #[cfg(not(feature = "nochanlists"))]
+            visibilities: Mutex::new(HashMap::new()),
+            #[cfg(not(feature = "nochanlists"))]
+            lists: Mutex::new(HashMap::new()),
+            #[cfg(not(feature = "nochanlists"))]
+            pending_lists: Mutex::new(HashMap::new()),
+            silence_list: Mutex::new(None),
+            pending_silence_list: Mutex::new(vec![]),
             alt_nick_index: RwLock::new(0),
+            forced_nickname: RwLock::new(None),
+            requested_nickname: Mutex::new(None),
+            forced_rename: Mutex::new(None),
+            server_error: Mutex::new(None),
+            sasl_pending: Mutex::new(false),
+            rename_callback: Mutex::new(None),
+            ctcp_handlers: Mutex::new(HashMap::new()),
+            server_name: RwLock::new(None),
+            server_version: RwLock::new(None),
+            isupport: RwLock::new(HashMap::new()),
+            whois_requests: Mutex::new(HashMap::new()),
+            recent_privmsgs: Mutex::new(HashMap::new()),
+            suppressed_privmsgs: Mutex::new(0),
+            last_activity: Mutex::new(Instant::now()),
+            last_sent: Mutex::new(Instant::now()),
+            away: Mutex::new(AwayState::Present),
+            registration: (Mutex::new(Registration::Pending), Condvar::new()),
+            registration_refusal: Mutex::new(None),
+            connection_state: RwLock::new(ConnectionState::Connecting),
+            state_callback: Mutex::new(None),
+            kicks: Mutex::new(HashMap::new()),
+            rejoins: Mutex::new(Rejoins::default()),
             incoming: Mutex::new(Some(incoming)),
-            outgoing: outgoing,
+            backlog: backlog,
+            last_server: last_server,
+            metrics: metrics,
         }
     }
 
     /// Gets the current nickname in use.
-    fn current_nickname(&self) -> &str {
+    fn current_nickname(&self) -> String {
+        if let Some(ref nick) = *self.forced_nickname.read().unwrap() {
+            return nick.clone();
+        }
         let alt_nicks = self.config().alternate_nicknames();
         let index = self.alt_nick_index.read().unwrap();
-        match *index {
+        self.fit_nickname(match *index {
             0 => self.config().nickname().expect(
                 "current_nickname should not be callable if nickname is not defined."
             ),
             i => alt_nicks[i - 1],
+        }).to_owned()
+    }
+
+    /// Truncates the given nickname to the server's `NICKLEN`, if `truncate_nick` is enabled and
+    /// the server has advertised one. Nicknames sent before then, e.g. during registration, are
+    /// left as they are, since the server may well allow more than RFC 2812 does.
+    fn fit_nickname<'a>(&self, nick: &'a str) -> &'a str {
+        if !self.config().truncate_nick() {
+            return nick;
+        }
+        match self.isupport.read().unwrap().get("NICKLEN").and_then(|n| n.parse().ok()) {
+            Some(len) => truncate_nickname(nick, len),
+            None => nick,
+        }
+    }
+
+    /// Gets the nickname refused by the given `ERR_ERRONEOUSNICKNAME` truncated to the limit of 9
+    /// from RFC 2812, if it may have been refused for its length: `truncate_nick` is enabled, the
+    /// server has yet to advertise a `NICKLEN`, and the nickname exceeds that limit.
+    fn refit_nickname(&self, msg: &Message) -> Option<String> {
+        let nick = match msg.command {
+            Command::Response(Response::ERR_ERRONEOUSNICKNAME, ref args, _) => args.get(1)?,
+            _ => return None,
+        };
+        if !self.config().truncate_nick() || self.isupport.read().unwrap().contains_key("NICKLEN") {
+            return None;
+        }
+        let fitted = truncate_nickname(nick, DEFAULT_NICK_LEN);
+        if fitted.len() < nick.len() {
+            Some(fitted.to_owned())
+        } else {
+            None
+        }
+    }
+
+    /// Determines whether the given target is a channel, using the channel prefixes advertised
+    /// in the server's `CHANTYPES` if it has done so.
+    fn is_channel(&self, target: &str) -> bool {
+        match self.isupport.read().unwrap().get("CHANTYPES") {
+            Some(chantypes) => is_channel(target, chantypes),
+            None => is_channel(target, DEFAULT_CHANTYPES),
+        }
+    }
+
+    /// Advances registration to the given stage, waking all threads waiting on registration.
+    /// Registration never moves backwards, so a late `RPL_WELCOME` will not undo completion.
+    fn advance_registration(&self, stage: Registration) {
+        let (ref lock, ref cvar) = self.registration;
+        let mut current = lock.lock().unwrap();
+        if stage > *current {
+            *current = stage;
+            cvar.notify_all();
+        }
+    }
+
+    /// Records the given reply as refusing registration if the server has not yet welcomed us,
+    /// waking all threads waiting on registration.
+    fn refuse_registration(&self, reply: &Message) {
+        let (ref lock, ref cvar) = self.registration;
+        let current = lock.lock().unwrap();
+        if *current == Registration::Pending {
+            *self.registration_refusal.lock().unwrap() = Some(reply.clone());
+            cvar.notify_all();
+        }
+    }
+
+    /// Blocks until registration reaches the given stage or the registration timeout elapses,
+    /// failing early if the server refuses registration.
+    fn wait_for_registration(&self, stage: Registration) -> error::Result<()> {
+        let timeout = Duration::from_secs(u64::from(self.config().registration_timeout()));
+        let start = Instant::now();
+        let (ref lock, ref cvar) = self.registration;
+        let mut current = lock.lock().unwrap();
+        while *current < stage {
+            if let Some(ref reply) = *self.registration_refusal.lock().unwrap() {
+                return Err(error::IrcError::Registration(reply.clone().into()));
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Err(error::IrcError::RegistrationTimeout);
+            }
+            current = cvar.wait_timeout(current, timeout - elapsed).unwrap().0;
+        }
+        Ok(())
+    }
+
+    /// Moves the connection to the stage chosen from its current one, running the state callback
+    /// if the stage changed.
+    fn update_connection_state<F>(&self, next: F)
+    where
+        F: FnOnce(ConnectionState) -> ConnectionState,
+    {
+        let (old, new) = {
+            let mut state = self.connection_state.write().unwrap();
+            let old = *state;
+            *state = next(old);
+            (old, *state)
+        };
+        if old != new {
+            // The lock is released first, so that the callback can use the client.
+            let callback = self.state_callback.lock().unwrap().clone();
+            if let Some(StateCallback(callback)) = callback {
+                callback(old, new);
+            }
+        }
+    }
+
+    /// Sets the stage in the lifecycle of the connection.
+    fn set_connection_state(&self, state: ConnectionState) {
+        self.update_connection_state(|_| state)
+    }
+
+    /// Moves the connection to the given stage, if it is still at one of the stages before it.
+    fn advance_connection_state(&self, from: &[ConnectionState], to: ConnectionState) {
+        self.update_connection_state(|state| if from.contains(&state) { to } else { state })
+    }
+
+    /// Marks the connection as ended, unless the client quit or is already reconnecting.
+    fn set_disconnected(&self) {
+        self.update_connection_state(|state| match state {
+            ConnectionState::Closed | ConnectionState::Reconnecting => state,
+            _ => ConnectionState::Disconnected,
+        })
+    }
+
+    /// Takes the reason given in an `ERROR` from the server, unless the client quit itself, in
+    /// which case the `ERROR` merely confirms the quit.
+    fn take_server_error(&self) -> Option<String> {
+        let reason = self.server_error.lock().unwrap().take();
+        if *self.connection_state.read().unwrap() == ConnectionState::Closed {
+            None
+        } else {
+            reason
         }
     }
 
+    /// Takes on the state callback of a previous connection, so that it sees the transitions of
+    /// the new connection upon reconnecting.
+    fn inherit_state_callback(&self, previous: &ClientState) {
+        let callback = previous.state_callback.lock().unwrap().clone();
+        *self.state_callback.lock().unwrap() = callback;
+    }
+
     /// Handles sent messages internally for basic client functionality.
     fn handle_sent_message(&self, msg: &Message) -> error::Result<()> {
         trace!("[SENT] {}", msg.to_string());
         match msg.command {
-            PART(ref chan, _) => {
-                let _ = self.chanlists.lock().unwrap().remove(chan);
+            Command::CAP(..) | Command::PASS(_) | Command::NICK(_) | Command::USER(..) => {
+                self.advance_connection_state(
+                    &[ConnectionState::Connecting], ConnectionState::Registering
+                )
+            }
+            _ => (),
+        }
+        match msg.command {
+            QUIT(_) => self.set_connection_state(ConnectionState::Closed),
+            NICK(ref nick) => *self.requested_nickname.lock().unwrap() = Some(nick.clone()),
+            JOIN(ref chanlist, _, _) if chanlist == "0" => self.leave_all_channels(),
+            PART(ref chanlist, _) => {
+                for chan in chanlist.split(',') {
+                    let _ = self.chanlists.lock().unwrap().remove(chan);
+                    self.untracked_channels.lock().unwrap().remove(chan);
+                    self.channel_keys.lock().unwrap().remove(&fold_nickname(chan));
+                    self.forget_channel_visibility(chan);
+                }
+            }
+            JOIN(ref chanlist, Some(ref keylist), _) => {
+                let mut pending = self.pending_channel_keys.lock().unwrap();
+                for (chan, key) in chanlist.split(',').zip(keylist.split(',')) {
+                    pending.insert(fold_nickname(chan), key.to_owned());
+                }
             }
             _ => (),
         }
@@ -324,13 +1108,82 @@ impl ClientState {
     /// Handles received messages internally for basic client functionality.
     fn handle_message(&self, msg: &Message) -> error::Result<()> {
         trace!("[RECV] {}", msg.to_string());
+        *self.last_activity.lock().unwrap() = Instant::now();
+        self.update_auto_away(Instant::now())?;
+        if self.handle_history(msg) {
+            return Ok(());
+        }
+        if self.config().track_reactions() {
+            self.handle_reaction(msg);
+        }
+        if let Command::Response(resp, ref args, ref suffix) = msg.command {
+            self.set_awaited_reply(resp, msg);
+            self.handle_whois_reply(resp, args, suffix.as_ref().map(|s| &s[..]), msg);
+            self.handle_names_reply(resp, args, suffix.as_ref().map(|s| &s[..]));
+        }
         match msg.command {
-            JOIN(ref chan, _, _) => self.handle_join(msg.source_nickname().unwrap_or(""), chan),
+            // JOIN 0 parts every channel.
+            JOIN(ref chan, _, _) if chan == "0" &&
+                nicknames_eq(msg.source_nickname().unwrap_or(""), &self.current_nickname()) =>
+            {
+                self.leave_all_channels()
+            }
+            JOIN(ref chan, ref account, ref realname) => {
+                // With extended-join, the account is "*" for users who are not logged in.
+                let account = match (account, realname) {
+                    (&Some(ref account), &Some(_)) if account != "*" => Some(&account[..]),
+                    _ => None,
+                };
+                let src = msg.source_nickname().unwrap_or("");
+                self.handle_join(src, chan, account);
+                if nicknames_eq(src, &self.current_nickname()) {
+                    self.confirm_channel_key(chan);
+                    self.set_join_reply(chan, Ok(()));
+                    if self.config().who_on_join() {
+                        self.send_who_on_join(chan)?;
+                    }
+                }
+            }
+            AWAY(ref away_msg) => {
+                self.handle_away(msg.source_nickname().unwrap_or(""), away_msg.is_some())
+            }
+            ACCOUNT(_) => {
+                let account = msg.as_account().unwrap();
+                self.handle_account(msg.source_nickname().unwrap_or(""), account.as_ref())
+            }
             PART(ref chan, _) => self.handle_part(msg.source_nickname().unwrap_or(""), chan),
-            KICK(ref chan, ref user, _) => self.handle_part(user, chan),
+            KICK(ref chan, ref users, _) => {
+                for user in users.split(',') {
+                    self.handle_part(user, chan);
+                }
+                if msg.is_self_kick(&self.current_nickname()) {
+                    self.forget_channel_visibility(chan);
+                    self.handle_self_kick(chan);
+                }
+            }
             QUIT(_) => self.handle_quit(msg.source_nickname().unwrap_or("")),
+            Command::ERROR(ref reason) => {
+                *self.server_error.lock().unwrap() = Some(reason.clone());
+                self.refuse_registration(msg)
+            }
+            Command::Response(Response::ERR_PASSWDMISMATCH, _, _) |
+            Command::Response(Response::ERR_YOUREBANNEDCREEP, _, _) => {
+                self.refuse_registration(msg)
+            }
+            Command::AUTHENTICATE(ref data) if data == "+" => self.send_sasl_credentials()?,
+            Command::Response(Response::RPL_SASLSUCCESS, _, _) |
+            Command::Response(Response::ERR_SASLFAIL, _, _) |
+            Command::Response(Response::ERR_SASLTOOLONG, _, _) |
+            Command::Response(Response::ERR_SASLABORT, _, _) |
+            Command::Response(Response::ERR_SASLALREADY, _, _) => {
+                *self.sasl_pending.lock().unwrap() = false
+            }
             NICK(ref new_nick) => {
-                self.handle_nick_change(msg.source_nickname().unwrap_or(""), new_nick)
+                let old_nick = msg.source_nickname().unwrap_or("");
+                if nicknames_eq(old_nick, &self.current_nickname()) {
+                    self.handle_own_nick_change(old_nick, new_nick);
+                }
+                self.handle_nick_change(old_nick, new_nick)
             }
             ChannelMODE(ref chan, ref modes) => self.handle_mode(chan, modes),
             PRIVMSG(ref target, ref body) => {
@@ -343,20 +1196,97 @@ impl ClientState {
                         };
                         body[1..end].split(' ').collect()
                     };
-                    if target.starts_with('#') {
+                    if self.is_channel(target) {
                         self.handle_ctcp(target, &tokens)?
                     } else if let Some(user) = msg.source_nickname() {
                         self.handle_ctcp(user, &tokens)?
                     }
                 }
             }
+            Command::CAP(_, CapSubCommand::LS, _, ref caps) => {
+                self.handle_cap_ls(caps.as_ref().map_or("", |s| &s[..]))?
+            }
+            Command::CAP(_, CapSubCommand::LIST, ref more, ref caps) => {
+                // In IRCv3.2, all but the last line of a multiline reply are marked with a "*".
+                let more = more.as_ref().map_or(false, |s| s == "*");
+                self.handle_cap_list(more, caps.as_ref().map_or("", |s| &s[..]))
+            }
+            Command::CAP(_, CapSubCommand::ACK, _, ref caps) => {
+                self.handle_cap_ack(caps.as_ref().map_or("", |s| &s[..]))
+            }
+            Command::CAP(_, CapSubCommand::NAK, _, _) => self.set_cap_reply(Err(msg.clone())),
+            Command::Response(Response::RPL_WELCOME, _, _) => {
+                if let Some(ref prefix) = msg.prefix {
+                    *self.server_name.write().unwrap() = Some(prefix.clone());
+                }
+                self.advance_connection_state(
+                    &[ConnectionState::Connecting, ConnectionState::Registering],
+                    ConnectionState::Registered,
+                );
+                self.advance_registration(Registration::Registered)
+            }
+            Command::Response(Response::RPL_MYINFO, ref args, _) => {
+                // <client> <servername> <version> <available user modes> <available chan modes>
+                if let Some(name) = args.get(1) {
+                    *self.server_name.write().unwrap() = Some(name.clone());
+                }
+                if let Some(version) = args.get(2) {
+                    *self.server_version.write().unwrap() = Some(version.clone());
+                }
+            }
+            Command::Response(Response::RPL_ISUPPORT, ref args, _) => self.handle_isupport(args),
+            Command::Response(Response::RPL_WHOREPLY, ref args, _) => {
+                // <client> <channel> <user> <host> <server> <nick> <flags> :<hopcount> <realname>
+                if args.len() >= 7 {
+                    self.handle_who_reply(&args[1], &args[5], &args[2], &args[3], &args[6], None)
+                }
+            }
+            Command::Response(Response::RPL_WHOSPCRPL, ref args, _) => {
+                // <client> <token> <channel> <user> <host> <nick> <flags> <account>
+                if args.len() >= 8 && args[1] == WHOX_TOKEN {
+                    // Users who are not logged in have an account of "0".
+                    let account = if args[7] == "0" { None } else { Some(&args[7][..]) };
+                    self.handle_who_reply(
+                        &args[2], &args[5], &args[3], &args[4], &args[6], Some(account)
+                    )
+                }
+            }
             Command::Response(Response::RPL_NAMREPLY, ref args, ref suffix) => {
+                if let Some((chan, vis)) = msg.as_channel_visibility() {
+                    self.set_channel_visibility(chan, vis)
+                }
                 self.handle_namreply(args, suffix)
             }
+            Command::Response(Response::RPL_ENDOFNAMES, ref args, _) => {
+                if let Some(chan) = args.get(1) {
+                    self.set_join_reply(chan, Ok(()))
+                }
+            }
+            Command::Response(_, _, _) if msg.as_join_error().is_some() => {
+                let (chan, err) = msg.as_join_error().unwrap();
+                self.pending_channel_keys.lock().unwrap().remove(&fold_nickname(chan));
+                self.set_join_reply(chan, Err(err))
+            }
+            Command::Response(resp, ref args, _) if ListMode::from_response(resp).is_some() => {
+                let (mode, end) = ListMode::from_response(resp).unwrap();
+                self.handle_list_reply(mode, end, args)
+            }
+            Command::Response(Response::RPL_SILELIST, ref args, _) => {
+                // <client> <nick> <mask>
+                if let Some(mask) = args.get(2) {
+                    self.pending_silence_list.lock().unwrap().push(mask.clone());
+                }
+            }
+            Command::Response(Response::RPL_ENDOFSILELIST, _, _) => {
+                let list = self.pending_silence_list.lock().unwrap().drain(..).collect();
+                *self.silence_list.lock().unwrap() = Some(list);
+            }
             Command::Response(Response::RPL_ENDOFMOTD, _, _) |
             Command::Response(Response::ERR_NOMOTD, _, _) => {
+                self.advance_registration(Registration::Complete);
                 self.send_nick_password()?;
                 self.send_umodes()?;
+                self.send_bot_mode()?;
 
                 let config_chans = self.config().channels();
                 for chan in &config_chans {
@@ -365,21 +1295,36 @@ impl ClientState {
                         None => self.send_join(chan)?,
                     }
                 }
-                let joined_chans = self.chanlists.lock().unwrap();
-                for chan in joined_chans.keys().filter(
+                let mut joined_chans: Vec<String> =
+                    self.chanlists.lock().unwrap().keys().cloned().collect();
+                for chan in self.rejoin_channels.lock().unwrap().drain(..) {
+                    if !joined_chans.contains(&chan) {
+                        joined_chans.push(chan);
+                    }
+                }
+                for chan in joined_chans.iter().filter(
                     |x| !config_chans.contains(&x.as_str()),
                 )
                 {
-                    self.send_join(chan)?
+                    // Channels joined at runtime are rejoined with the keys they were joined with.
+                    match self.channel_keys.lock().unwrap().get(&fold_nickname(chan)) {
+                        Some(key) => self.send_join_with_keys::<&str, &str>(chan, key)?,
+                        None => self.send_join(chan)?,
+                    }
                 }
             }
             Command::Response(Response::ERR_NICKNAMEINUSE, _, _) |
             Command::Response(Response::ERR_ERRONEOUSNICKNAME, _, _) => {
+                if let Some(nick) = self.refit_nickname(msg) {
+                    *self.forced_nickname.write().unwrap() = Some(nick.clone());
+                    return self.send(NICK(nick));
+                }
                 let alt_nicks = self.config().alternate_nicknames();
                 let mut index = self.alt_nick_index.write().unwrap();
                 if *index >= alt_nicks.len() {
                     return Err(error::IrcError::NoUsableNick);
                 } else {
+                    *self.forced_nickname.write().unwrap() = None;
                     self.send(NICK(alt_nicks[*index].to_owned()))?;
                     *index += 1;
                 }
@@ -429,34 +1374,331 @@ impl ClientState {
         }
     }
 
-    #[cfg(feature = "nochanlists")]
-    fn handle_join(&self, _: &str, _: &str) {}
-
-    #[cfg(not(feature = "nochanlists"))]
-    fn handle_join(&self, src: &str, chan: &str) {
-        if let Some(vec) = self.chanlists.lock().unwrap().get_mut(&chan.to_owned()) {
-            if !src.is_empty() {
-                vec.push(User::new(src))
-            }
+    /// Starts authenticating again with the configured SASL mechanism after registration, once the
+    /// `sasl` capability has been negotiated.
+    fn reauthenticate(&self) -> error::Result<()> {
+        if !self.capabilities.lock().unwrap().contains(&Capability::Sasl) {
+            return Err(error::IrcError::CapabilityNotEnabled { capability: Capability::Sasl });
         }
+        *self.sasl_pending.lock().unwrap() = true;
+        self.send_sasl(self.config().sasl_mechanism().name())
     }
 
-    #[cfg(feature = "nochanlists")]
-    fn handle_part(&self, _: &str, _: &str) {}
-
-    #[cfg(not(feature = "nochanlists"))]
-    fn handle_part(&self, src: &str, chan: &str) {
-        if let Some(vec) = self.chanlists.lock().unwrap().get_mut(&chan.to_owned()) {
-            if !src.is_empty() {
-                if let Some(n) = vec.iter().position(|x| x.get_nickname() == src) {
-                    vec.swap_remove(n);
-                }
+    /// Sends the credentials for the configured SASL mechanism if `reauthenticate` is waiting to,
+    /// split into chunks as needed. For `PLAIN`, these are the SASL account as both the
+    /// authorization and authentication identity along with the nickname password. For `EXTERNAL`,
+    /// the identity is left empty for the server to take from the client certificate.
+    fn send_sasl_credentials(&self) -> error::Result<()> {
+        if !mem::replace(&mut *self.sasl_pending.lock().unwrap(), false) {
+            return Ok(());
+        }
+        let payload = match self.config().sasl_mechanism() {
+            SaslMechanism::Plain => {
+                let account = self.config().sasl_account()?;
+                let password = self.config().nick_password();
+                base64_encode(format!("{}\0{}\0{}", account, account, password).as_bytes())
+            }
+            SaslMechanism::External => String::new(),
+        };
+        // A payload that fills its last chunk is followed by an empty one, sent as `+`.
+        let mut rest = &payload[..];
+        loop {
+            let len = cmp::min(rest.len(), MAX_SASL_CHUNK_LEN);
+            let chunk = if len == 0 { "+" } else { &rest[..len] };
+            self.send_sasl(chunk)?;
+            if len < MAX_SASL_CHUNK_LEN {
+                return Ok(());
             }
+            rest = &rest[len..];
         }
     }
 
-    #[cfg(feature = "nochanlists")]
-    fn handle_quit(&self, _: &str) {}
+    /// Sets the user mode marking the client as a bot, if enabled and the server advertises one in
+    /// its `BOT` token.
+    fn send_bot_mode(&self) -> error::Result<()> {
+        if !self.config().is_bot() {
+            return Ok(());
+        }
+        // BOT=<mode>
+        let mode = match self.isupport.read().unwrap().get("BOT") {
+            Some(mode) if !mode.is_empty() => format!("+{}", mode),
+            _ => return Ok(()),
+        };
+        match Mode::as_user_modes(&mode) {
+            Ok(modes) => self.send_mode(self.current_nickname(), &modes),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Schedules a rejoin of the given channel after being kicked from it, if enabled. Consecutive
+    /// kicks double the delay, and rejoining stops entirely after `MAX_REJOIN_ATTEMPTS` of them to
+    /// avoid fighting with the channel's operators. The rejoin is sent by the client's stream once
+    /// the delay has passed.
+    fn handle_self_kick(&self, chan: &str) {
+        if !self.config().rejoin_on_kick() {
+            return;
+        }
+        let attempt = {
+            let mut kicks = self.kicks.lock().unwrap();
+            let entry = kicks.entry(chan.to_owned()).or_insert((Instant::now(), 0));
+            if entry.0.elapsed() >= Duration::from_secs(REJOIN_RESET_SECS) {
+                entry.1 = 0;
+            }
+            entry.0 = Instant::now();
+            entry.1 += 1;
+            entry.1
+        };
+        if attempt > MAX_REJOIN_ATTEMPTS {
+            warn!("Kicked from {} too many times, not rejoining.", chan);
+            return;
+        }
+        let delay = u64::from(self.config().rejoin_delay_ms()) << (attempt - 1);
+        let mut rejoins = self.rejoins.lock().unwrap();
+        let sleep = rejoins.timer.get_or_insert_with(|| {
+            // Allow for the longest delay that can be configured.
+            let max = u64::from(u32::MAX) << (MAX_REJOIN_ATTEMPTS - 1);
+            tokio_timer::wheel().max_timeout(Duration::from_millis(max)).build()
+        }).sleep(Duration::from_millis(delay));
+        rejoins.pending.push((sleep, chan.to_owned()));
+    }
+
+    /// Rejoins the channels whose rejoin delay has passed since being kicked from them, using the
+    /// keys they were last joined with.
+    fn send_due_rejoins(&self) -> error::Result<()> {
+        let mut due = vec![];
+        {
+            let mut rejoins = self.rejoins.lock().unwrap();
+            let pending: Vec<_> = rejoins.pending.drain(..).collect();
+            for (mut sleep, chan) in pending {
+                match sleep.poll()? {
+                    Async::Ready(()) => due.push(chan),
+                    Async::NotReady => rejoins.pending.push((sleep, chan)),
+                }
+            }
+        }
+        for chan in due {
+            let key = self.channel_keys.lock().unwrap().get(&fold_nickname(&chan)).cloned();
+            match key.as_ref().map(|key| &key[..]).or_else(|| self.config().channel_key(&chan)) {
+                Some(key) => self.send_join_with_keys::<&str, &str>(&chan, key)?,
+                None => self.send_join(&chan)?,
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "nochanlists")]
+    fn handle_join(&self, _: &str, _: &str, _: Option<&str>) {}
+
+    #[cfg(not(feature = "nochanlists"))]
+    fn handle_join(&self, src: &str, chan: &str, account: Option<&str>) {
+        let mut chanlists = self.chanlists.lock().unwrap();
+        if !chanlists.contains_key(chan) || !self.admit_users(&mut chanlists, chan, 1) {
+            return;
+        }
+        if let Some(vec) = chanlists.get_mut(&chan.to_owned()) {
+            if !src.is_empty() {
+                let mut user = User::new(src);
+                user.set_account(account);
+                vec.push(user)
+            }
+        }
+    }
+
+    /// Forgets every channel the client is in along with the keys used to join them, as when
+    /// parting all of them with `JOIN 0`.
+    fn leave_all_channels(&self) {
+        self.chanlists.lock().unwrap().clear();
+        self.untracked_channels.lock().unwrap().clear();
+        self.channel_keys.lock().unwrap().clear();
+        #[cfg(not(feature = "nochanlists"))]
+        self.visibilities.lock().unwrap().clear();
+    }
+
+    /// Takes on the channels joined by the state of a previous connection and the keys used to
+    /// join them, so that they are rejoined once registration completes.
+    fn inherit_channels(&self, previous: &ClientState) {
+        let chans = previous.chanlists.lock().unwrap().keys().cloned().collect();
+        *self.rejoin_channels.lock().unwrap() = chans;
+        let keys = previous.channel_keys.lock().unwrap().clone();
+        *self.channel_keys.lock().unwrap() = keys;
+    }
+
+    /// Remembers the key sent when joining the given channel, if any, now that the server has
+    /// confirmed the join.
+    fn confirm_channel_key(&self, chan: &str) {
+        let chan = fold_nickname(chan);
+        if let Some(key) = self.pending_channel_keys.lock().unwrap().remove(&chan) {
+            self.channel_keys.lock().unwrap().insert(chan, key);
+        }
+    }
+
+    /// Sends a `WHOIS` for the given nickname unless one was sent within the configured
+    /// `whois_throttle_secs`, returning whether it was sent.
+    fn whois_throttled(&self, nick: &str) -> error::Result<bool> {
+        let window = Duration::from_secs(u64::from(self.config().whois_throttle_secs()));
+        let mut requests = self.whois_requests.lock().unwrap();
+        // Forget requests outside of the window, so that the map doesn't grow without bound.
+        requests.retain(|_, sent| sent.elapsed() < window);
+        let key = fold_nickname(nick);
+        if requests.contains_key(&key) {
+            return Ok(false);
+        }
+        self.send(WHOIS(None, nick.to_owned()))?;
+        requests.insert(key, Instant::now());
+        Ok(true)
+    }
+
+    /// Queues the given message to be sent, subject to the outgoing queue limit.
+    fn send_message(&self, msg: Message) -> error::Result<()> {
+        if !self.backlog.reserve(&msg, self.config())? {
+            debug!("Outgoing queue is full, dropping {}", msg.to_string().trim_end());
+            return Ok(());
+        }
+        self.handle_sent_message(&msg)?;
+        self.backlog.push(msg);
+        Ok(())
+    }
+
+    /// Records that the given message is being sent, first marking the client as back if it had
+    /// marked itself as away automatically.
+    fn record_sent(&self, msg: &Message) -> error::Result<()> {
+        *self.last_sent.lock().unwrap() = Instant::now();
+        {
+            let mut away = self.away.lock().unwrap();
+            match msg.command {
+                // An explicit AWAY takes over from the automatic one.
+                AWAY(ref reason) => {
+                    *away = if reason.is_some() { AwayState::Away } else { AwayState::Present };
+                    return Ok(());
+                }
+                _ if *away == AwayState::AutoAway => *away = AwayState::Present,
+                _ => return Ok(()),
+            }
+        }
+        // The away guard is released first, as sending may block on a full outgoing queue.
+        self.send_message(AWAY(None).into())
+    }
+
+    /// Marks the client as away if it has not sent a message within the configured
+    /// `auto_away_after_ms` as of the given time.
+    fn update_auto_away(&self, now: Instant) -> error::Result<()> {
+        let after = match self.config().auto_away_after_ms() {
+            Some(ms) => Duration::from_millis(u64::from(ms)),
+            None => return Ok(()),
+        };
+        let last_sent = *self.last_sent.lock().unwrap();
+        {
+            let mut away = self.away.lock().unwrap();
+            if *away != AwayState::Present || last_sent + after > now {
+                return Ok(());
+            }
+            *away = AwayState::AutoAway;
+        }
+        let msg = self.config().auto_away_message().to_owned();
+        self.send_message(AWAY(Some(msg)).into())
+    }
+
+    /// Checks whether the given message is a `PRIVMSG` with the same target and text as one sent
+    /// within the configured `dedupe_window_ms`, recording it as sent if not.
+    fn is_duplicate_privmsg(&self, msg: &Message) -> bool {
+        let window = match self.config().dedupe_window_ms() {
+            Some(ms) => Duration::from_millis(u64::from(ms)),
+            None => return false,
+        };
+        let (target, text) = match msg.command {
+            PRIVMSG(ref target, ref text) => (target, text),
+            _ => return false,
+        };
+        let mut recent = self.recent_privmsgs.lock().unwrap();
+        // Forget messages outside of the window, so that the map doesn't grow without bound.
+        recent.retain(|_, sent| sent.elapsed() < window);
+        let key = (fold_nickname(target), text.clone());
+        if recent.contains_key(&key) {
+            return true;
+        }
+        recent.insert(key, Instant::now());
+        false
+    }
+
+    /// Requests the hosts and accounts of the users in a newly joined channel.
+    fn send_who_on_join(&self, chan: &str) -> error::Result<()> {
+        if self.isupport.read().unwrap().contains_key("WHOX") {
+            // Only replies carrying our token are used to update the tracked users.
+            let fields = format!("%tcuhnfa,{}", WHOX_TOKEN);
+            self.send(Command::Raw("WHO".to_owned(), vec![chan.to_owned(), fields], None))
+        } else {
+            self.send(Command::WHO(Some(chan.to_owned()), None))
+        }
+    }
+
+    #[cfg(feature = "nochanlists")]
+    fn handle_who_reply(
+        &self, _: &str, _: &str, _: &str, _: &str, _: &str, _: Option<Option<&str>>,
+    ) {}
+
+    #[cfg(not(feature = "nochanlists"))]
+    fn handle_who_reply(
+        &self, chan: &str, nick: &str, username: &str, hostname: &str, flags: &str,
+        account: Option<Option<&str>>,
+    ) {
+        if let Some(vec) = self.chanlists.lock().unwrap().get_mut(chan) {
+            if let Some(user) = vec.iter_mut().find(|user| user.is_nickname(nick)) {
+                user.set_userhost(username, hostname);
+                // The flags begin with "G" for users who are away, and "H" otherwise.
+                user.set_away(flags.starts_with('G'));
+                if let Some(account) = account {
+                    user.set_account(account);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "nochanlists")]
+    fn handle_away(&self, _: &str, _: bool) {}
+
+    #[cfg(not(feature = "nochanlists"))]
+    fn handle_away(&self, src: &str, away: bool) {
+        if src.is_empty() {
+            return;
+        }
+        for vec in self.chanlists.lock().unwrap().values_mut() {
+            if let Some(n) = vec.iter().position(|x| x.is_nickname(src)) {
+                vec[n].set_away(away);
+            }
+        }
+    }
+
+    #[cfg(feature = "nochanlists")]
+    fn handle_account(&self, _: &str, _: Option<&String>) {}
+
+    #[cfg(not(feature = "nochanlists"))]
+    fn handle_account(&self, src: &str, account: Option<&String>) {
+        if src.is_empty() {
+            return;
+        }
+        for vec in self.chanlists.lock().unwrap().values_mut() {
+            if let Some(n) = vec.iter().position(|x| x.is_nickname(src)) {
+                vec[n].set_account(account.map(|s| &s[..]));
+            }
+        }
+    }
+
+    #[cfg(feature = "nochanlists")]
+    fn handle_part(&self, _: &str, _: &str) {}
+
+    #[cfg(not(feature = "nochanlists"))]
+    fn handle_part(&self, src: &str, chan: &str) {
+        if let Some(vec) = self.chanlists.lock().unwrap().get_mut(&chan.to_owned()) {
+            if !src.is_empty() {
+                if let Some(n) = vec.iter().position(|x| x.is_nickname(src)) {
+                    vec.swap_remove(n);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "nochanlists")]
+    fn handle_quit(&self, _: &str) {}
 
     #[cfg(not(feature = "nochanlists"))]
     fn handle_quit(&self, src: &str) {
@@ -466,13 +1708,73 @@ impl ClientState {
         let mut chanlists = self.chanlists.lock().unwrap();
         for channel in chanlists.clone().keys() {
             if let Some(vec) = chanlists.get_mut(&channel.to_owned()) {
-                if let Some(p) = vec.iter().position(|x| x.get_nickname() == src) {
+                if let Some(p) = vec.iter().position(|x| x.is_nickname(src)) {
                     vec.swap_remove(p);
                 }
             }
         }
     }
 
+    /// Tracks a change of the client's own nickname, which is a forced rename if the client did
+    /// not request the new nickname, e.g. when services enforce the registration of a nickname.
+    fn handle_own_nick_change(&self, old_nick: &str, new_nick: &str) {
+        let index = if nicknames_eq(new_nick, self.config().nickname().unwrap_or("")) {
+            Some(0)
+        } else {
+            self.config().alternate_nicknames().iter().position(|nick| {
+                nicknames_eq(new_nick, nick)
+            }).map(|i| i + 1)
+        };
+        match index {
+            Some(index) => {
+                *self.alt_nick_index.write().unwrap() = index;
+                *self.forced_nickname.write().unwrap() = None;
+            }
+            None => {
+                *self.forced_nickname.write().unwrap() = Some(new_nick.to_owned());
+            }
+        }
+        let requested = self.requested_nickname.lock().unwrap().take();
+        let forced = match requested {
+            Some(nick) => !nicknames_eq(&nick, new_nick),
+            None => true,
+        };
+        if forced {
+            info!("Server changed our nickname from {} to {}.", old_nick, new_nick);
+            *self.forced_rename.lock().unwrap() = Some((old_nick.to_owned(), new_nick.to_owned()));
+        }
+    }
+
+    /// Runs the rename callback, if any, for a forced rename seen while handling the most recent
+    /// message.
+    fn run_rename_callback(state: &Arc<ClientState>) {
+        let rename = state.forced_rename.lock().unwrap().take();
+        if let Some((old_nick, new_nick)) = rename {
+            // The lock is released before running the callback, so that it can use the client.
+            let callback = state.rename_callback.lock().unwrap().clone();
+            if let Some(callback) = callback {
+                let client = IrcClient { state: Arc::clone(state), view: None };
+                (callback.0)(&client, &old_nick, &new_nick);
+            }
+        }
+    }
+
+    /// Runs the limit callback, if any, for each channel that exceeded a limit on tracking while
+    /// handling the most recent message.
+    fn run_limit_callback(&self) {
+        let exceeded: Vec<_> = self.exceeded_limits.lock().unwrap().drain(..).collect();
+        if exceeded.is_empty() {
+            return;
+        }
+        // The lock is released before running the callback, so that it can use the client.
+        let callback = self.limit_callback.lock().unwrap().clone();
+        if let Some(LimitCallback(callback)) = callback {
+            for (chan, limit) in exceeded {
+                callback(&chan, limit);
+            }
+        }
+    }
+
     #[cfg(feature = "nochanlists")]
     fn handle_nick_change(&self, _: &str, _: &str) {}
 
@@ -484,9 +1786,8 @@ impl ClientState {
         let mut chanlists = self.chanlists.lock().unwrap();
         for channel in chanlists.clone().keys() {
             if let Some(vec) = chanlists.get_mut(&channel.to_owned()) {
-                if let Some(n) = vec.iter().position(|x| x.get_nickname() == old_nick) {
-                    let new_entry = User::new(new_nick);
-                    vec[n] = new_entry;
+                if let Some(n) = vec.iter().position(|x| x.is_nickname(old_nick)) {
+                    vec[n].set_nickname(new_nick);
                 }
             }
         }
@@ -501,7 +1802,7 @@ impl ClientState {
             match *mode {
                 Mode::Plus(_, Some(ref user)) | Mode::Minus(_, Some(ref user)) => {
                     if let Some(vec) = self.chanlists.lock().unwrap().get_mut(chan) {
-                        if let Some(n) = vec.iter().position(|x| x.get_nickname() == user) {
+                        if let Some(n) = vec.iter().position(|x| x.is_nickname(user)) {
                             vec[n].update_access_level(mode)
                         }
                     }
@@ -519,8 +1820,12 @@ impl ClientState {
         if let Some(ref users) = *suffix {
             if args.len() == 3 {
                 let chan = &args[2];
-                for user in users.split(' ') {
-                    let mut chanlists = self.chanlists.lock().unwrap();
+                let users: Vec<_> = users.split(' ').collect();
+                let mut chanlists = self.chanlists.lock().unwrap();
+                if !self.admit_users(&mut chanlists, chan, users.len()) {
+                    return;
+                }
+                for user in users {
                     chanlists
                         .entry(chan.clone())
                         .or_insert_with(Vec::new)
@@ -530,146 +1835,710 @@ impl ClientState {
         }
     }
 
-    #[cfg(feature = "ctcp")]
-    fn handle_ctcp(&self, resp: &str, tokens: &[&str]) -> error::Result<()> {
-        if tokens.is_empty() {
-            return Ok(());
+    /// Determines whether the given number of users can be added to the tracked users of a
+    /// channel without exceeding the configured limits on tracking. When they cannot, tracking is
+    /// disabled for the channel, which is still listed among the joined channels.
+    #[cfg(not(feature = "nochanlists"))]
+    fn admit_users(
+        &self,
+        chanlists: &mut HashMap<String, Vec<User>>,
+        chan: &str,
+        count: usize,
+    ) -> bool {
+        let mut untracked = self.untracked_channels.lock().unwrap();
+        if untracked.contains(chan) {
+            return false;
         }
-        if tokens[0].eq_ignore_ascii_case("FINGER") {
-            self.send_ctcp_internal(
-                resp,
-                &format!(
-                    "FINGER :{} ({})",
-                    self.config().real_name(),
-                    self.config().username()
-                ),
-            )
-        } else if tokens[0].eq_ignore_ascii_case("VERSION") {
-            self.send_ctcp_internal(resp, &format!("VERSION {}", self.config().version()))
-        } else if tokens[0].eq_ignore_ascii_case("SOURCE") {
-            self.send_ctcp_internal(
-                resp,
-                &format!("SOURCE {}", self.config().source()),
-            )?;
-            self.send_ctcp_internal(resp, "SOURCE")
-        } else if tokens[0].eq_ignore_ascii_case("PING") && tokens.len() > 1 {
-            self.send_ctcp_internal(resp, &format!("PING {}", tokens[1]))
-        } else if tokens[0].eq_ignore_ascii_case("TIME") {
-            self.send_ctcp_internal(resp, &format!("TIME :{}", Local::now().to_rfc2822()))
-        } else if tokens[0].eq_ignore_ascii_case("USERINFO") {
-            self.send_ctcp_internal(resp, &format!("USERINFO :{}", self.config().user_info()))
+        let over_channels = !chanlists.contains_key(chan) && {
+            let tracked = chanlists.keys().filter(|c| !untracked.contains(*c)).count();
+            match self.config().max_tracked_channels() {
+                Some(max) => tracked >= max,
+                None => false,
+            }
+        };
+        let users = chanlists.get(chan).map_or(0, |users| users.len()) + count;
+        let over_users = match self.config().max_tracked_users_per_channel() {
+            Some(max) => users > max,
+            None => false,
+        };
+        let limit = if over_channels {
+            warn!("Tracking too many channels, not tracking users in {}.", chan);
+            TrackingLimit::Channels
+        } else if over_users {
+            warn!("Too many users in {}, no longer tracking them.", chan);
+            TrackingLimit::UsersPerChannel
         } else {
-            Ok(())
-        }
+            return true;
+        };
+        self.exceeded_limits.lock().unwrap().push((chan.to_owned(), limit));
+        untracked.insert(chan.to_owned());
+        chanlists.insert(chan.to_owned(), vec![]);
+        false
     }
 
-    #[cfg(feature = "ctcp")]
-    fn send_ctcp_internal(&self, target: &str, msg: &str) -> error::Result<()> {
-        self.send_notice(target, &format!("\u{001}{}\u{001}", msg))
+    #[cfg(feature = "nochanlists")]
+    fn set_channel_visibility(&self, _: &str, _: ChannelVisibility) {}
+
+    #[cfg(not(feature = "nochanlists"))]
+    fn set_channel_visibility(&self, chan: &str, vis: ChannelVisibility) {
+        self.visibilities.lock().unwrap().insert(fold_nickname(chan), vis);
     }
 
-    #[cfg(not(feature = "ctcp"))]
-    fn handle_ctcp(&self, _: &str, _: &[&str]) -> error::Result<()> {
-        Ok(())
+    #[cfg(feature = "nochanlists")]
+    fn forget_channel_visibility(&self, _: &str) {}
+
+    #[cfg(not(feature = "nochanlists"))]
+    fn forget_channel_visibility(&self, chan: &str) {
+        self.visibilities.lock().unwrap().remove(&fold_nickname(chan));
     }
-}
 
-/// The canonical implementation of a connection to an IRC server.
-///
-/// The type itself provides a number of methods to create new connections, but most of the API
-/// surface is in the form of the [`Client`](trait.Client.html) and
-/// [`ClientExt`](./ext/trait.ClientExt.html) traits that provide methods of communicating with
-/// the server after connection. Cloning an `IrcClient` is relatively cheap, as it's equivalent to
-/// cloning a single `Arc`. This may be useful for setting up multiple threads with access to one
-/// connection.
-///
-/// For a full example usage, see [`irc::client`](./index.html).
-#[derive(Clone, Debug)]
-pub struct IrcClient {
-    /// The internal, thread-safe server state.
-    state: Arc<ClientState>,
-    /// A view of the logs for a mock connection.
-    view: Option<LogView>,
-}
+    #[cfg(not(feature = "nochanlists"))]
+    fn channel_visibility(&self, chan: &str) -> Option<ChannelVisibility> {
+        self.visibilities.lock().unwrap().get(&fold_nickname(chan)).cloned()
+    }
 
-impl Client for IrcClient {
-    fn config(&self) -> &Config {
-        &self.state.config
+    #[cfg(feature = "nochanlists")]
+    fn channel_visibility(&self, _: &str) -> Option<ChannelVisibility> {
+        None
     }
 
-    fn send<M: Into<Message>>(&self, msg: M) -> error::Result<()>
-    where
-        Self: Sized,
-    {
-        self.state.send(msg)
+    fn handle_isupport(&self, args: &[String]) {
+        // <client> <1-13 tokens> :are supported by this server
+        let mut isupport = self.isupport.write().unwrap();
+        for token in args.iter().skip(1) {
+            if token.starts_with('-') {
+                isupport.remove(&token[1..]);
+            } else {
+                let mut kv = token.splitn(2, '=');
+                let key = kv.next().unwrap_or("").to_owned();
+                isupport.insert(key, kv.next().unwrap_or("").to_owned());
+            }
+        }
     }
 
-    fn stream(&self) -> ClientStream {
-        ClientStream {
-            state: Arc::clone(&self.state),
-            stream: self.state.incoming.lock().unwrap().take().expect(
-                "Stream was already obtained once, and cannot be reobtained."
-            ),
+    fn handle_cap_ls(&self, caps: &str) -> error::Result<()> {
+        let policy = match StsPolicy::from_cap_ls(caps) {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        match (self.tls_port, policy.port(), policy.duration()) {
+            // Over plaintext, the policy demands a reconnect with TLS on the advertised port.
+            (None, Some(port), _) if !self.config.use_starttls() => {
+                Err(error::IrcError::StsUpgrade { port: port })
+            }
+            // Over TLS, the policy is persisted for the advertised duration.
+            (Some(tls_port), _, Some(duration)) => {
+                if let Some(path) = self.config.sts_cache() {
+                    sts::store(path, self.config.server()?, tls_port, duration)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
         }
     }
 
-    #[cfg(not(feature = "nochanlists"))]
-    fn list_channels(&self) -> Option<Vec<String>> {
-        Some(
-            self.state
-                .chanlists
-                .lock()
-                .unwrap()
-                .keys()
-                .map(|k| k.to_owned())
-                .collect(),
-        )
+    fn handle_cap_list(&self, more: bool, caps: &str) {
+        let mut pending = self.pending_capabilities.lock().unwrap();
+        pending.extend(ValuedCapability::parse_list(caps).into_iter().map(|c| c.capability));
+        if !more {
+            *self.capabilities.lock().unwrap() = mem::replace(&mut *pending, Vec::new());
+        }
     }
 
-    #[cfg(feature = "nochanlists")]
-    fn list_channels(&self) -> Option<Vec<String>> {
-        None
+    fn handle_cap_ack(&self, caps: &str) {
+        let mut acked = vec![];
+        {
+            let mut enabled = self.capabilities.lock().unwrap();
+            for cap in caps.split_whitespace() {
+                // Capabilities prefixed with "-" have been disabled.
+                if cap.starts_with('-') {
+                    enabled.retain(|c| c.as_ref() != &cap[1..]);
+                    continue;
+                }
+                let cap = ValuedCapability::from(cap).capability;
+                if !enabled.iter().any(|c| c.as_ref() == cap.as_ref()) {
+                    enabled.push(cap.clone());
+                }
+                acked.push(cap);
+            }
+        }
+        self.set_cap_reply(Ok(acked))
     }
 
-    #[cfg(not(feature = "nochanlists"))]
-    fn list_users(&self, chan: &str) -> Option<Vec<User>> {
-        self.state
-            .chanlists
-            .lock()
-            .unwrap()
-            .get(&chan.to_owned())
+    /// Records the reply to a capability request, waking all threads waiting on one.
+    fn set_cap_reply(&self, reply: CapReply) {
+        let (ref lock, ref cvar) = self.cap_reply;
+        *lock.lock().unwrap() = Some(reply);
+        cvar.notify_all();
+    }
+
+    /// Requests the given capabilities and blocks until the server acknowledges or rejects them,
+    /// returning the requested capabilities that were acknowledged, or failing if they were
+    /// rejected.
+    fn request_capabilities(&self, caps: &[Capability]) -> error::Result<Vec<Capability>> {
+        let timeout = Duration::from_secs(u64::from(self.config().registration_timeout()));
+        let start = Instant::now();
+        let (ref lock, ref cvar) = self.cap_reply;
+        let mut reply = lock.lock().unwrap();
+        *reply = None;
+        self.send_cap_req(caps)?;
+        while reply.is_none() {
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Err(error::IrcError::CapabilityTimeout);
+            }
+            reply = cvar.wait_timeout(reply, timeout - elapsed).unwrap().0;
+        }
+        let acked = reply.take().unwrap().map_err(|nak| {
+            error::IrcError::CapNegotiation(nak.into())
+        })?;
+        Ok(caps.iter()
+            .filter(|c| acked.iter().any(|a| a.as_ref() == c.as_ref()))
             .cloned()
+            .collect())
     }
 
-    #[cfg(feature = "nochanlists")]
-    fn list_users(&self, _: &str) -> Option<Vec<User>> {
-        None
+    /// Records the outcome of joining the given channel, if `join_and_wait` is waiting on it.
+    fn set_join_reply(&self, chan: &str, outcome: JoinOutcome) {
+        let (ref lock, ref cvar) = self.join_replies;
+        if let Some(reply) = lock.lock().unwrap().get_mut(&fold_nickname(chan)) {
+            if reply.is_none() {
+                *reply = Some(outcome);
+                cvar.notify_all();
+            }
+        }
     }
-}
 
-impl IrcClient {
-    /// Creates a new `IrcClient` from the configuration at the specified path, connecting
-    /// immediately. This function is short-hand for loading the configuration and then calling
-    /// `IrcClient::from_config` and consequently inherits its behaviors.
-    ///
-    /// # Example
-    /// ```no_run
-    /// # extern crate irc;
-    /// # use irc::client::prelude::*;
-    /// # fn main() {
-    /// let client = IrcClient::new("config.toml").unwrap();
-    /// # }
-    /// ```
-    pub fn new<P: AsRef<Path>>(config: P) -> error::Result<IrcClient> {
-        IrcClient::from_config(Config::load(config)?)
+    fn join_and_wait(&self, chan: &str, timeout: Duration) -> error::Result<()> {
+        let start = Instant::now();
+        let key = fold_nickname(chan);
+        let (ref lock, ref cvar) = self.join_replies;
+        lock.lock().unwrap().insert(key.clone(), None);
+        if let Err(e) = self.send_join(chan) {
+            lock.lock().unwrap().remove(&key);
+            return Err(e);
+        }
+        let mut replies = lock.lock().unwrap();
+        loop {
+            if let Some(outcome) = replies.get(&key).cloned().and_then(|r| r) {
+                replies.remove(&key);
+                return outcome.map_err(|err| error::IrcError::JoinFailed {
+                    channel: chan.to_owned(),
+                    error: err,
+                });
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                replies.remove(&key);
+                return Err(error::IrcError::JoinTimeout { channel: chan.to_owned() });
+            }
+            replies = cvar.wait_timeout(replies, timeout - elapsed).unwrap().0;
+        }
     }
 
-    /// Creates a new `IrcClient` from the specified configuration, connecting immediately. Due to
-    /// current design limitations, error handling here is somewhat limited. In particular, failed
-    /// connections will cause the program to panic because the connection attempt is made on a
-    /// freshly created thread. If you need to avoid this behavior and handle errors more
-    /// gracefully, it is recommended that you use an
-    /// [`IrcReactor`](./reactor/struct.IrcReactor.html) instead.
+    /// Records the given reply for every `send_and_await` call awaiting its response.
+    fn set_awaited_reply(&self, resp: Response, msg: &Message) {
+        let (ref lock, ref cvar) = self.awaited_replies;
+        let mut awaited = lock.lock().unwrap();
+        let mut matched = false;
+        for &mut (ref responses, ref mut reply) in awaited.waiting.values_mut() {
+            if reply.is_none() && responses.contains(&resp) {
+                *reply = Some(msg.clone());
+                matched = true;
+            }
+        }
+        if matched {
+            cvar.notify_all();
+        }
+    }
+
+    fn send_and_await(
+        &self,
+        command: Command,
+        success: &[Response],
+        failure: &[Response],
+        timeout: Duration,
+    ) -> error::Result<Message> {
+        let start = Instant::now();
+        let (ref lock, ref cvar) = self.awaited_replies;
+        let id = {
+            let mut awaited = lock.lock().unwrap();
+            let id = awaited.next_id;
+            awaited.next_id = id.wrapping_add(1);
+            let mut responses: Vec<_> = success.iter().chain(failure).cloned().collect();
+            responses.push(Response::RPL_TRYAGAIN);
+            awaited.waiting.insert(id, (responses, None));
+            id
+        };
+        if let Err(e) = self.send(command) {
+            lock.lock().unwrap().waiting.remove(&id);
+            return Err(e);
+        }
+        let mut awaited = lock.lock().unwrap();
+        loop {
+            if let Some(reply) = awaited.waiting.get_mut(&id).and_then(|w| w.1.take()) {
+                awaited.waiting.remove(&id);
+                return match reply.command {
+                    Command::Response(Response::RPL_TRYAGAIN, _, _) => {
+                        Err(error::IrcError::RateLimited(reply.into()))
+                    }
+                    Command::Response(resp, _, _) if failure.contains(&resp) => match resp {
+                        Response::ERR_SASLFAIL | Response::ERR_SASLTOOLONG |
+                        Response::ERR_SASLABORT | Response::ERR_SASLALREADY => {
+                            Err(error::IrcError::Sasl(reply.into()))
+                        }
+                        _ => Err(error::IrcError::CommandFailed { response: resp, reply: reply }),
+                    },
+                    _ => Ok(reply),
+                };
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                awaited.waiting.remove(&id);
+                return Err(error::IrcError::ReplyTimeout);
+            }
+            awaited = cvar.wait_timeout(awaited, timeout - elapsed).unwrap().0;
+        }
+    }
+
+    /// Reassembles `chathistory` batches, returning whether the message was part of one. Messages
+    /// in a batch are collected rather than handled, as they were already seen when first sent.
+    fn handle_history(&self, msg: &Message) -> bool {
+        let mut pending = self.pending_history.lock().unwrap();
+        if let BATCH(ref reference, ref sub, ref params) = msg.command {
+            if reference.is_empty() {
+                return false;
+            }
+            let (sign, name) = reference.split_at(1);
+            if sign == "+" {
+                let is_history = match *sub {
+                    Some(BatchSubCommand::CUSTOM(ref kind)) => {
+                        kind == "CHATHISTORY" || kind == "DRAFT/CHATHISTORY"
+                    }
+                    _ => false,
+                };
+                let target = params.as_ref().and_then(|params| params.first());
+                if let (true, Some(target)) = (is_history, target) {
+                    pending.insert(name.to_owned(), (target.clone(), Vec::new()));
+                    return true;
+                }
+            } else if sign == "-" {
+                if let Some((target, messages)) = pending.remove(name) {
+                    self.chat_history.lock().unwrap().insert(fold_nickname(&target), messages);
+                    return true;
+                }
+            }
+            return false;
+        }
+        let batch = msg.tags.as_ref().and_then(|tags| {
+            tags.iter().find(|tag| tag.0 == "batch").and_then(|tag| tag.1.as_ref())
+        });
+        match batch.and_then(|batch| pending.get_mut(batch)) {
+            Some(&mut (_, ref mut messages)) => {
+                messages.push(msg.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Records the reaction the given message represents, if any, against the message it reacts
+    /// to. Repeats of the same reaction from the same user are ignored. Once more messages than
+    /// `max_tracked_reactions` have been reacted to, the oldest are forgotten.
+    fn handle_reaction(&self, msg: &Message) {
+        let reaction = match msg.as_reaction() {
+            Some(reaction) => reaction,
+            None => return,
+        };
+        let mut guard = self.reactions.lock().unwrap();
+        let (ref mut reactions, ref mut order) = *guard;
+        if !reactions.contains_key(&reaction.target_msgid) {
+            while reactions.len() >= self.config().max_tracked_reactions() {
+                match order.pop_front() {
+                    Some(oldest) => reactions.remove(&oldest),
+                    None => break,
+                };
+            }
+            order.push_back(reaction.target_msgid.clone());
+        }
+        let entry = reactions.entry(reaction.target_msgid.clone()).or_default();
+        let repeated = entry.iter().any(|r| {
+            r.emoji == reaction.emoji && nicknames_eq(&r.from, &reaction.from)
+        });
+        if !repeated {
+            entry.push(reaction);
+        }
+    }
+
+    fn reactions(&self, msgid: &str) -> Vec<Reaction> {
+        self.reactions.lock().unwrap().0.get(msgid).cloned().unwrap_or_default()
+    }
+
+    fn chat_history(&self, target: &str) -> Option<Vec<Message>> {
+        self.chat_history.lock().unwrap().get(&fold_nickname(target)).cloned()
+    }
+
+    /// Collects the numerics of `WHOIS` replies, completing the reply for any `whois` call
+    /// awaiting it at `RPL_ENDOFWHOIS`, or failing it at `ERR_NOSUCHNICK`.
+    fn handle_whois_reply(
+        &self,
+        resp: Response,
+        args: &[String],
+        suffix: Option<&str>,
+        msg: &Message,
+    ) {
+        if !WhoisReply::is_whois_response(resp) && resp != Response::ERR_NOSUCHNICK {
+            return;
+        }
+        let nick = match args.get(1) {
+            Some(nick) => nick,
+            None => return,
+        };
+        let key = fold_nickname(nick);
+        let (ref lock, ref cvar) = self.whois_replies;
+        let mut replies = lock.lock().unwrap();
+        let outcome = match resp {
+            Response::RPL_ENDOFWHOIS => {
+                Ok(replies.pending.remove(&key).unwrap_or_else(|| WhoisReply::new(nick)))
+            }
+            Response::ERR_NOSUCHNICK => Err(msg.clone()),
+            // RPL_AWAY is also sent in reply to messages, so it only adds to a reply under way.
+            Response::RPL_AWAY => {
+                if let Some(reply) = replies.pending.get_mut(&key) {
+                    reply.update(resp, args, suffix);
+                }
+                return;
+            }
+            _ => {
+                replies.pending.entry(key).or_insert_with(|| WhoisReply::new(nick))
+                    .update(resp, args, suffix);
+                return;
+            }
+        };
+        if let Some(waiting) = replies.waiting.get_mut(&key) {
+            if waiting.is_none() {
+                *waiting = Some(outcome);
+                cvar.notify_all();
+            }
+        }
+    }
+
+    fn whois(&self, nick: &str, timeout: Duration) -> error::Result<WhoisReply> {
+        let start = Instant::now();
+        let key = fold_nickname(nick);
+        let (ref lock, ref cvar) = self.whois_replies;
+        lock.lock().unwrap().waiting.insert(key.clone(), None);
+        if let Err(e) = self.send(WHOIS(None, nick.to_owned())) {
+            lock.lock().unwrap().waiting.remove(&key);
+            return Err(e);
+        }
+        let mut replies = lock.lock().unwrap();
+        loop {
+            if let Some(outcome) = replies.waiting.get_mut(&key).and_then(|w| w.take()) {
+                replies.waiting.remove(&key);
+                return outcome.map_err(|reply| error::IrcError::CommandFailed {
+                    response: Response::ERR_NOSUCHNICK,
+                    reply: reply,
+                });
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                replies.waiting.remove(&key);
+                return Err(error::IrcError::ReplyTimeout);
+            }
+            replies = cvar.wait_timeout(replies, timeout - elapsed).unwrap().0;
+        }
+    }
+
+    /// Collects the members listed in `RPL_NAMREPLY` for channels awaited by `names` calls,
+    /// completing the list at `RPL_ENDOFNAMES`.
+    fn handle_names_reply(&self, resp: Response, args: &[String], suffix: Option<&str>) {
+        let (chan, end) = match resp {
+            // <client> <symbol> <channel> :[prefix]<nick>{ [prefix]<nick>}
+            Response::RPL_NAMREPLY => (args.get(2), false),
+            // <client> <channel> :End of /NAMES list
+            Response::RPL_ENDOFNAMES => (args.get(1), true),
+            _ => return,
+        };
+        let key = match chan {
+            Some(chan) => fold_nickname(chan),
+            None => return,
+        };
+        let (ref lock, ref cvar) = self.names_replies;
+        let mut replies = lock.lock().unwrap();
+        // Replies are only collected for awaited channels, as they also follow every join.
+        match replies.waiting.get(&key) {
+            Some(&None) => (),
+            _ => return,
+        }
+        if end {
+            let members = replies.pending.remove(&key).unwrap_or_default();
+            replies.waiting.insert(key, Some(members));
+            cvar.notify_all();
+        } else {
+            let names = suffix.unwrap_or("").split_whitespace().map(User::new);
+            replies.pending.entry(key).or_default().extend(names);
+        }
+    }
+
+    fn names(&self, chan: &str, timeout: Duration) -> error::Result<Vec<User>> {
+        let start = Instant::now();
+        let key = fold_nickname(chan);
+        let (ref lock, ref cvar) = self.names_replies;
+        {
+            let mut replies = lock.lock().unwrap();
+            replies.pending.remove(&key);
+            replies.waiting.insert(key.clone(), None);
+        }
+        if let Err(e) = self.send(NAMES(Some(chan.to_owned()), None)) {
+            lock.lock().unwrap().waiting.remove(&key);
+            return Err(e);
+        }
+        let mut replies = lock.lock().unwrap();
+        loop {
+            if let Some(members) = replies.waiting.get_mut(&key).and_then(|w| w.take()) {
+                replies.waiting.remove(&key);
+                return Ok(members);
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                replies.waiting.remove(&key);
+                replies.pending.remove(&key);
+                return Err(error::IrcError::ReplyTimeout);
+            }
+            replies = cvar.wait_timeout(replies, timeout - elapsed).unwrap().0;
+        }
+    }
+
+    #[cfg(feature = "nochanlists")]
+    fn handle_list_reply(&self, _: ListMode, _: bool, _: &[String]) {}
+
+    #[cfg(not(feature = "nochanlists"))]
+    fn handle_list_reply(&self, mode: ListMode, end: bool, args: &[String]) {
+        let key = match args.get(1) {
+            Some(chan) => (chan.clone(), mode),
+            None => return,
+        };
+        let mut pending = self.pending_lists.lock().unwrap();
+        if end {
+            let list = pending.remove(&key).unwrap_or_else(Vec::new);
+            self.lists.lock().unwrap().insert(key, list);
+        } else if let Some(entry) = ListEntry::from_args(args) {
+            pending.entry(key).or_insert_with(Vec::new).push(entry);
+        }
+    }
+
+    #[cfg(not(feature = "nochanlists"))]
+    fn channel_list(&self, chan: &str, mode: ListMode) -> Option<Vec<ListEntry>> {
+        self.lists.lock().unwrap().get(&(chan.to_owned(), mode)).cloned()
+    }
+
+    #[cfg(feature = "nochanlists")]
+    fn channel_list(&self, _: &str, _: ListMode) -> Option<Vec<ListEntry>> {
+        None
+    }
+
+    fn handle_ctcp(&self, resp: &str, tokens: &[&str]) -> error::Result<()> {
+        if tokens.is_empty() {
+            return Ok(());
+        }
+        let tag = tokens[0].to_ascii_uppercase();
+        // The lock is released before running the handler, so that it can use the client.
+        let handler = self.ctcp_handlers.lock().unwrap().get(&tag).cloned();
+        let reply = match handler {
+            Some(handler) => (handler.0)(&tokens[1..].join(" ")),
+            None => return self.handle_builtin_ctcp(resp, tokens),
+        };
+        // The reply is built as by `send_ctcp_reply`, which needs the `ctcp` feature.
+        let payload = match reply {
+            Some(ref body) if body.is_empty() => tag,
+            Some(body) => format!("{} {}", tag, body),
+            None => return Ok(()),
+        };
+        self.send_notice(resp, format!("\u{001}{}\u{001}", payload))
+    }
+
+    #[cfg(feature = "ctcp")]
+    fn handle_builtin_ctcp(&self, resp: &str, tokens: &[&str]) -> error::Result<()> {
+        if tokens[0].eq_ignore_ascii_case("FINGER") {
+            self.send_ctcp_reply(
+                resp,
+                "FINGER",
+                format!(":{} ({})", self.config().real_name(), self.config().username()),
+            )
+        } else if tokens[0].eq_ignore_ascii_case("VERSION") {
+            self.send_ctcp_reply(resp, "VERSION", self.config().version())
+        } else if tokens[0].eq_ignore_ascii_case("SOURCE") {
+            self.send_ctcp_reply(resp, "SOURCE", self.config().source())?;
+            self.send_ctcp_reply(resp, "SOURCE", "")
+        } else if tokens[0].eq_ignore_ascii_case("PING") && tokens.len() > 1 {
+            self.send_ctcp_reply(resp, "PING", tokens[1])
+        } else if tokens[0].eq_ignore_ascii_case("TIME") {
+            self.send_ctcp_reply(resp, "TIME", format!(":{}", Local::now().to_rfc2822()))
+        } else if tokens[0].eq_ignore_ascii_case("USERINFO") {
+            self.send_ctcp_reply(resp, "USERINFO", format!(":{}", self.config().user_info()))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(not(feature = "ctcp"))]
+    fn handle_builtin_ctcp(&self, _: &str, _: &[&str]) -> error::Result<()> {
+        Ok(())
+    }
+}
+
+/// The replies awaited by `send_and_await` calls, keyed by an ID unique to each call.
+#[derive(Debug, Default)]
+struct AwaitedReplies {
+    /// The ID to give the next call.
+    next_id: usize,
+    /// The responses awaited by each call, and the first reply received with one of them.
+    waiting: HashMap<usize, (Vec<Response>, Option<Message>)>,
+}
+
+/// The `WHOIS` replies being received, and those awaited by `whois` calls, keyed by nickname
+/// under RFC 1459 casemapping.
+#[derive(Debug, Default)]
+struct WhoisReplies {
+    /// The replies still being received.
+    pending: HashMap<String, WhoisReply>,
+    /// The outcomes of the replies awaited by `whois` calls, once known.
+    waiting: HashMap<String, Option<WhoisOutcome>>,
+}
+
+/// The `NAMES` replies awaited by `names` calls, keyed by channel under RFC 1459 casemapping.
+#[derive(Debug, Default)]
+struct NamesReplies {
+    /// The members listed so far in replies still being received.
+    pending: HashMap<String, Vec<User>>,
+    /// The members of each awaited channel, once the end of its list is received.
+    waiting: HashMap<String, Option<Vec<User>>>,
+}
+
+/// Whether and how the client has marked itself as away.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AwayState {
+    /// The client has not marked itself as away.
+    Present,
+    /// The client marked itself as away automatically after `auto_away_after_ms`.
+    AutoAway,
+    /// The client was explicitly marked as away.
+    Away,
+}
+
+/// The reply to a capability request: either the acknowledged capabilities, or the `CAP NAK`
+/// rejecting them.
+type CapReply = Result<Vec<Capability>, Message>;
+
+/// The stages of connection registration with the server.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+enum Registration {
+    /// The server has not yet welcomed us.
+    Pending,
+    /// The server has welcomed us with `RPL_WELCOME`.
+    Registered,
+    /// The server has finished the MOTD with `RPL_ENDOFMOTD` or `ERR_NOMOTD`.
+    Complete,
+}
+
+/// The canonical implementation of a connection to an IRC server.
+///
+/// The type itself provides a number of methods to create new connections, but most of the API
+/// surface is in the form of the [`Client`](trait.Client.html) and
+/// [`ClientExt`](./ext/trait.ClientExt.html) traits that provide methods of communicating with
+/// the server after connection. Cloning an `IrcClient` is relatively cheap, as it's equivalent to
+/// cloning a single `Arc`. This may be useful for setting up multiple threads with access to one
+/// connection.
+///
+/// For a full example usage, see [`irc::client`](./index.html).
+#[derive(Clone, Debug)]
+pub struct IrcClient {
+    /// The internal, thread-safe server state.
+    state: Arc<ClientState>,
+    /// A view of the logs for a mock connection.
+    view: Option<LogView>,
+}
+
+impl Client for IrcClient {
+    fn config(&self) -> &Config {
+        &self.state.config
+    }
+
+    fn send<M: Into<Message>>(&self, msg: M) -> error::Result<()>
+    where
+        Self: Sized,
+    {
+        self.state.send(msg)
+    }
+
+    fn stream(&self) -> ClientStream {
+        ClientStream {
+            state: Arc::clone(&self.state),
+            stream: self.state.incoming.lock().unwrap().take().expect(
+                "Stream was already obtained once, and cannot be reobtained."
+            ),
+        }
+    }
+
+    #[cfg(not(feature = "nochanlists"))]
+    fn list_channels(&self) -> Option<Vec<String>> {
+        Some(
+            self.state
+                .chanlists
+                .lock()
+                .unwrap()
+                .keys()
+                .map(|k| k.to_owned())
+                .collect(),
+        )
+    }
+
+    #[cfg(feature = "nochanlists")]
+    fn list_channels(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    fn list_users(&self, chan: &str) -> Option<Vec<User>> {
+        self.state.list_users(chan)
+    }
+
+    /// Gets the maximum number of mode changes per `MODE` command from the `MODES` token. This
+    /// defaults to 3, as specified by
+    /// [RFC 2812](https://tools.ietf.org/html/rfc2812#section-3.2.3), which is also assumed when
+    /// the server advertises `MODES` without a limit.
+    fn max_modes(&self) -> i64 {
+        self.state.max_modes()
+    }
+
+    fn has_capability(&self, cap: &str) -> bool {
+        self.state.has_capability(cap)
+    }
+}
+
+impl IrcClient {
+    /// Creates a new `IrcClient` from the configuration at the specified path, connecting
+    /// immediately. This function is short-hand for loading the configuration and then calling
+    /// `IrcClient::from_config` and consequently inherits its behaviors.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let client = IrcClient::new("config.toml").unwrap();
+    /// # }
+    /// ```
+    pub fn new<P: AsRef<Path>>(config: P) -> error::Result<IrcClient> {
+        IrcClient::from_config(Config::load(config)?)
+    }
+
+    /// Creates a new `IrcClient` from the specified configuration, connecting immediately. Due to
+    /// current design limitations, error handling here is somewhat limited. In particular, failed
+    /// connections will cause the program to panic because the connection attempt is made on a
+    /// freshly created thread. If you need to avoid this behavior and handle errors more
+    /// gracefully, it is recommended that you use an
+    /// [`IrcReactor`](./reactor/struct.IrcReactor.html) instead.
     ///
     /// # Example
     /// ```no_run
@@ -686,25 +2555,35 @@ impl IrcClient {
     /// # }
     /// ```
     pub fn from_config(config: Config) -> error::Result<IrcClient> {
+        IrcClient::from_config_with_last_server(config, LastServer::default())
+    }
+
+    /// Creates a new `IrcClient` from the specified configuration, trying the server that last
+    /// accepted a connection first and recording the one that accepts this connection.
+    fn from_config_with_last_server(config: Config, last: LastServer) -> error::Result<IrcClient> {
+        config.validate()?;
         // Setting up a remote reactor running for the length of the connection.
-        let (tx_outgoing, rx_outgoing) = mpsc::unbounded();
         let (tx_incoming, rx_incoming) = oneshot::channel();
         let (tx_view, rx_view) = oneshot::channel();
+        // The backlog is drained on its own event loop, so sending may wait for it.
+        let backlog = Arc::new(Backlog::new(true));
 
         let cfg = config.clone();
+        let sink_backlog = backlog.clone();
+        let conn_last = last.clone();
 
         let _ = thread::spawn(move || {
             let mut reactor = Core::new().unwrap();
             // Setting up internal processing stuffs.
             let handle = reactor.handle();
-            let conn = reactor.run(Connection::new(&cfg, &handle).unwrap()).unwrap();
+            let conn = Connection::with_last_server(&cfg, &handle, &conn_last).unwrap();
+            let conn = reactor.run(conn).unwrap();
 
-            tx_view.send(conn.log_view()).unwrap();
+            tx_view.send((conn.log_view(), conn.metrics())).unwrap();
             let (sink, stream) = conn.split();
 
-            let outgoing_future = sink.send_all(rx_outgoing.map_err::<error::IrcError, _>(|_| {
-                unreachable!("futures::sync::mpsc::Receiver should never return Err");
-            })).map(|_| ()).map_err(|e| panic!("{}", e));
+            let outgoing_future = SendBacklog::new(sink, sink_backlog, &cfg)
+                .map_err(|e| panic!("{}", e));
 
             // Send the stream half back to the original thread.
             tx_incoming.send(stream).unwrap();
@@ -712,9 +2591,12 @@ impl IrcClient {
             reactor.run(outgoing_future).unwrap();
         });
 
+        let (view, metrics) = rx_view.wait()?;
         Ok(IrcClient {
-            state: Arc::new(ClientState::new(rx_incoming.wait()?, tx_outgoing, config)),
-            view: rx_view.wait()?,
+            state: Arc::new(ClientState::new(
+                rx_incoming.wait()?, backlog, metrics, last, config
+            )),
+            view: view,
         })
     }
 
@@ -758,35 +2640,627 @@ impl IrcClient {
     /// # fn process_msg(server: &IrcClient, message: Message) -> error::Result<()> { Ok(()) }
     /// ```
     pub fn new_future(handle: Handle, config: &Config) -> error::Result<IrcClientFuture> {
-        let (tx_outgoing, rx_outgoing) = mpsc::unbounded();
+        config.validate()?;
 
         Ok(IrcClientFuture {
             conn: Connection::new(config, &handle)?,
             _handle: handle,
             config: config,
-            tx_outgoing: Some(tx_outgoing),
-            rx_outgoing: Some(rx_outgoing),
         })
     }
 
+    /// Creates a `Future` connecting an `IrcClient` with the specified configuration on the event
+    /// loop corresponding to the given handle, which resolves to the client once it is connected.
+    /// Unlike [`new_future`](#method.new_future), the future owns the configuration and the
+    /// client is ready to use as is, as the future sending its messages is spawned on the event
+    /// loop. This makes it a better fit for applications that are already asynchronous, as
+    /// nothing blocks on connecting. Errors in sending messages end the connection, and are
+    /// reported by the client's stream.
+    ///
+    /// Connecting follows the configuration just like the other constructors, including the
+    /// failover to alternative servers and TLS settings. Hostnames are resolved by the configured
+    /// [`Resolver`](./data/resolver/struct.Resolver.html) if one is set, and by the system
+    /// resolver otherwise, which may briefly block the event loop.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # extern crate tokio_core;
+    /// # use std::default::Default;
+    /// # use irc::client::prelude::*;
+    /// # use tokio_core::reactor::Core;
+    /// # fn main() {
+    /// # let config = Config {
+    /// #  nickname: Some("example".to_owned()),
+    /// #  server: Some("irc.example.com".to_owned()),
+    /// #  .. Default::default()
+    /// # };
+    /// let mut reactor = Core::new().unwrap();
+    /// let client = reactor.run(IrcClient::connect_async(&reactor.handle(), config)).unwrap();
+    /// client.identify().unwrap();
+    /// reactor.run(client.stream().for_each(|message| {
+    ///     print!("{}", message);
+    ///     Ok(())
+    /// })).unwrap();
+    /// # }
+    /// ```
+    pub fn connect_async(
+        handle: &Handle,
+        config: Config,
+    ) -> Box<Future<Item = IrcClient, Error = error::IrcError>> {
+        let conn = config.validate().and_then(|()| Connection::new_owned(config.clone(), handle));
+        let handle = handle.clone();
+        Box::new(future::result(conn).flatten().map(move |conn| {
+            let PackedIrcClient(client, outgoing) = PackedIrcClient::new(conn, config);
+            handle.spawn(outgoing.map_err(|e| warn!("Failed to send messages: {}", e)));
+            client
+        }))
+    }
+
     /// Gets the current nickname in use. This may be the primary username set in the configuration,
     /// or it could be any of the alternative nicknames listed as well. As a result, this is the
     /// preferred way to refer to the client's nickname.
-    pub fn current_nickname(&self) -> &str {
+    pub fn current_nickname(&self) -> String {
         self.state.current_nickname()
     }
 
-    /// Gets the log view from the internal transport. Only used for unit testing.
-    #[cfg(test)]
-    fn log_view(&self) -> &LogView {
-        self.view.as_ref().unwrap()
+    /// Sets a callback to run whenever the server forcibly changes the client's nickname, i.e.
+    /// changes it to one that the client did not request, as when services enforce the
+    /// registration of a nickname. The callback is passed the client along with the old and new
+    /// nicknames, and runs on the thread handling incoming messages once the new nickname is
+    /// reflected in [`current_nickname`](#method.current_nickname). This replaces any callback
+    /// set previously.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// # let client = IrcClient::new("config.toml").unwrap();
+    /// client.on_forced_rename(|client, _, _| {
+    ///     // Identify for the configured nickname, and then take it back.
+    ///     let nick = client.config().nickname().unwrap();
+    ///     let password = client.config().nick_password();
+    ///     client.send(Command::NICKSERV(format!("IDENTIFY {} {}", nick, password))).unwrap();
+    ///     client.send(Command::NICK(nick.to_owned())).unwrap();
+    /// });
+    /// # }
+    /// ```
+    pub fn on_forced_rename<F>(&self, callback: F)
+    where
+        F: Fn(&IrcClient, &str, &str) + Send + Sync + 'static,
+    {
+        *self.state.rename_callback.lock().unwrap() = Some(RenameCallback(Arc::new(callback)));
     }
-}
 
-/// A future representing the eventual creation of an `IrcClient`.
-///
-/// Interaction with this future relies on the `futures` API, but is only expected for more advanced
-/// use cases. To learn more, you can view the documentation for the
+    /// Sets a callback to run whenever a channel exceeds `max_tracked_channels` or
+    /// `max_tracked_users_per_channel`, passed the channel and the limit it exceeded. The users
+    /// of the channel are no longer tracked from then on, until it is left. The callback runs on
+    /// the thread handling incoming messages, and replaces any callback set previously.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// # let client = IrcClient::new("config.toml").unwrap();
+    /// client.on_tracking_limit(|chan, limit| {
+    ///     println!("Not tracking the users in {}: {:?}", chan, limit);
+    /// });
+    /// # }
+    /// ```
+    pub fn on_tracking_limit<F>(&self, callback: F)
+    where
+        F: Fn(&str, TrackingLimit) + Send + Sync + 'static,
+    {
+        *self.state.limit_callback.lock().unwrap() = Some(LimitCallback(Arc::new(callback)));
+    }
+
+    /// Registers a handler for CTCP requests with the given tag (e.g. `WEATHER` for
+    /// `\u{001}WEATHER London\u{001}`), matched case-insensitively. The handler is passed the
+    /// arguments of each request, and the body it returns is sent back in a `NOTICE`, or nothing
+    /// is sent if it returns `None`. This replaces any handler previously registered for the tag,
+    /// and takes precedence over the built-in replies enabled by the `ctcp` feature.
+    ///
+    /// The handler is run while the incoming message is being processed, so it should not block.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// # let client = IrcClient::new("config.toml").unwrap();
+    /// client.on_ctcp("WEATHER", |place| match place {
+    ///     "" => None,
+    ///     place => Some(format!("It is sunny in {}.", place)),
+    /// });
+    /// # }
+    /// ```
+    pub fn on_ctcp<F>(&self, tag: &str, handler: F)
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.state.ctcp_handlers.lock().unwrap().insert(
+            tag.to_ascii_uppercase(), CtcpHandler(Arc::new(handler))
+        );
+    }
+
+    /// Blocks until the server has welcomed the client (`RPL_WELCOME`), meaning that registration
+    /// is complete and commands like `JOIN` can be sent safely. If the server does not welcome the
+    /// client within the configured `registration_timeout`, this will fail with
+    /// `IrcError::RegistrationTimeout`, and if the server refuses the client first, e.g. with
+    /// `ERR_PASSWDMISMATCH`, this will fail with `IrcError::Registration`.
+    ///
+    /// Registration is only observed while incoming messages are being processed, so the stream
+    /// must be driven elsewhere (e.g. by `for_each_incoming` on another thread) while waiting.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use std::thread;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let client = IrcClient::new("config.toml").unwrap();
+    /// client.identify().unwrap();
+    /// let reader = client.clone();
+    /// thread::spawn(move || reader.for_each_incoming(|_| ()).unwrap());
+    /// client.wait_until_registered().unwrap();
+    /// client.send_join("#example").unwrap();
+    /// # }
+    /// ```
+    pub fn wait_until_registered(&self) -> error::Result<()> {
+        self.state.wait_for_registration(Registration::Registered)
+    }
+
+    /// Blocks until registration is fully complete, as signaled by the end of the MOTD
+    /// (`RPL_ENDOFMOTD`) or its absence (`ERR_NOMOTD`). This is the point at which the client
+    /// performs its own post-registration actions (identifying with NickServ, setting user modes,
+    /// and joining channels). Like `wait_until_registered`, this is bounded by the configured
+    /// `registration_timeout` and requires the stream to be driven elsewhere while waiting.
+    pub fn wait_until_ready(&self) -> error::Result<()> {
+        self.state.wait_for_registration(Registration::Complete)
+    }
+
+    /// Gets the current stage in the lifecycle of the connection, e.g. for displaying in a user
+    /// interface or for health checks. The stage is updated as registration commands are sent,
+    /// as `RPL_WELCOME` is received, and as the connection ends, so it relies on incoming messages
+    /// being processed like `wait_until_registered`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// use irc::client::ConnectionState;
+    ///
+    /// # fn main() {
+    /// # let client = IrcClient::new("config.toml").unwrap();
+    /// if client.state() != ConnectionState::Registered {
+    ///     println!("not ready yet: {:?}", client.state());
+    /// }
+    /// # }
+    /// ```
+    pub fn state(&self) -> ConnectionState {
+        *self.state.connection_state.read().unwrap()
+    }
+
+    /// Registers a callback to run on every transition between stages of the connection's
+    /// lifecycle (see [`state`](#method.state)), passed the old and new stages. This replaces any
+    /// callback set previously, and is carried over to the new client when a
+    /// [`ReconnectingIter`](./struct.ReconnectingIter.html) reconnects.
+    ///
+    /// The callback is run on whichever thread causes the transition, e.g. the one sending
+    /// `identify` or the one processing incoming messages, so it should not block.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// use irc::client::ConnectionState;
+    ///
+    /// # fn main() {
+    /// # let client = IrcClient::new("config.toml").unwrap();
+    /// client.on_state_change(|_, new| if new == ConnectionState::Reconnecting {
+    ///     println!("Reconnecting...");
+    /// });
+    /// # }
+    /// ```
+    pub fn on_state_change<F>(&self, callback: F)
+    where
+        F: Fn(ConnectionState, ConnectionState) + Send + Sync + 'static,
+    {
+        *self.state.state_callback.lock().unwrap() = Some(StateCallback(Arc::new(callback)));
+    }
+
+    /// Gets the name that the server announced for itself upon registration. This may differ from
+    /// the configured server, e.g. when connecting through round-robin DNS. This is `None` until
+    /// `RPL_WELCOME` or `RPL_MYINFO` is received.
+    pub fn server_name(&self) -> Option<String> {
+        self.state.server_name.read().unwrap().clone()
+    }
+
+    /// Gets the server software version announced in `RPL_MYINFO`, if it has been received.
+    pub fn server_version(&self) -> Option<String> {
+        self.state.server_version.read().unwrap().clone()
+    }
+
+    /// Gets when the most recent message was received from the server, or when the client was
+    /// created if no message has been received yet. This can be used to detect an idle connection
+    /// with a custom policy, independently of the ping timeout.
+    pub fn last_activity(&self) -> Instant {
+        *self.state.last_activity.lock().unwrap()
+    }
+
+    /// Gets the number of messages that have been sent through this client, but are still queued
+    /// waiting to be written to the connection. A steadily growing count means that messages are
+    /// being produced faster than the configured rate-limiting (`max_messages_in_burst` per
+    /// `burst_window_length` seconds) allows them to be sent.
+    pub fn pending_outbound(&self) -> usize {
+        self.state.backlog.len()
+    }
+
+    /// Gets a snapshot of the counters for the traffic of this client, e.g. for exporting to a
+    /// monitoring system. When reconnecting with
+    /// [`iter_reconnecting`](#method.iter_reconnecting), the counters carry over to the new
+    /// connection.
+    pub fn metrics(&self) -> ConnectionMetrics {
+        self.state.metrics.snapshot()
+    }
+
+    /// Gets the number of messages dropped so far for duplicating a `PRIVMSG` sent within the
+    /// configured `dedupe_window_ms`.
+    pub fn suppressed_duplicates(&self) -> usize {
+        *self.state.suppressed_privmsgs.lock().unwrap()
+    }
+
+    /// Sends a `WHOIS` for the given nickname, unless one was already sent for it (under RFC 1459
+    /// casemapping) within the configured `whois_throttle_secs`. This returns whether the `WHOIS`
+    /// was actually sent. This is useful for bots that look up unknown users on sight, as it keeps
+    /// bursts of activity from the same users from turning into floods of `WHOIS` requests. The
+    /// requests that are sent are still subject to the usual message rate-limiting.
+    pub fn whois_throttled(&self, nick: &str) -> error::Result<bool> {
+        self.state.whois_throttled(nick)
+    }
+
+    /// Gets the value of the given `RPL_ISUPPORT` token, if the server advertised it. Tokens
+    /// advertised without a value have an empty value.
+    pub fn isupport(&self, key: &str) -> Option<String> {
+        self.state.isupport.read().unwrap().get(key).cloned()
+    }
+
+    /// Gets the value of the given `RPL_ISUPPORT` token as an integer, if the server advertised
+    /// it with an integer value.
+    pub fn isupport_int(&self, key: &str) -> Option<i64> {
+        self.isupport(key).and_then(|value| value.parse().ok())
+    }
+
+    /// Starts tracking whether the user with the given nickname is online, using `MONITOR` if the
+    /// server advertises it and `WATCH` otherwise. Changes are reported by messages for which
+    /// [`Message::as_presence`](../proto/message/struct.Message.html#method.as_presence) is not
+    /// empty.
+    pub fn track_presence(&self, nick: &str) -> error::Result<()> {
+        if self.isupport("MONITOR").is_some() {
+            self.send(Command::MONITOR("+".to_owned(), Some(nick.to_owned())))
+        } else {
+            self.send_watch_add(nick)
+        }
+    }
+
+    /// Stops tracking whether the user with the given nickname is online, using `MONITOR` if the
+    /// server advertises it and `WATCH` otherwise.
+    pub fn untrack_presence(&self, nick: &str) -> error::Result<()> {
+        if self.isupport("MONITOR").is_some() {
+            self.send(Command::MONITOR("-".to_owned(), Some(nick.to_owned())))
+        } else {
+            self.send_watch_del(nick)
+        }
+    }
+
+    /// Determines whether the given target is a channel, using the channel prefixes the server
+    /// advertised in `CHANTYPES`. Until the server does so, this falls back to the prefixes in
+    /// [`DEFAULT_CHANTYPES`](../proto/chan/constant.DEFAULT_CHANTYPES.html).
+    pub fn is_channel(&self, target: &str) -> bool {
+        self.state.is_channel(target)
+    }
+
+    /// Gets the maximum number of channels the client may join at once, from the `CHANLIMIT`
+    /// limit for `#` channels or the older `MAXCHANNELS` token. This defaults to 10, as
+    /// recommended by [RFC 2811](https://tools.ietf.org/html/rfc2811#section-3.1).
+    pub fn max_channels(&self) -> i64 {
+        self.isupport("CHANLIMIT").and_then(|limits| {
+            // CHANLIMIT=<prefixes>:<limit>[,<prefixes>:<limit>]*
+            limits.split(',').find(|limit| limit.contains('#')).and_then(|limit| {
+                limit.splitn(2, ':').nth(1).and_then(|n| n.parse().ok())
+            })
+        }).or_else(|| self.isupport_int("MAXCHANNELS")).unwrap_or(10)
+    }
+
+    /// Gets the nickname of the given user in the specified channel prefixed with the symbol of
+    /// their highest-ranking membership prefix (e.g. `@ada` for an operator who also has voice),
+    /// ranked by the order in the `PREFIX` token. Until the server advertises `PREFIX`, this
+    /// falls back to `(qaohv)~&@%+`. This will return `None` if the user is not known to be in the
+    /// channel or if the `nochanlists` feature is enabled.
+    pub fn display_nick(&self, chan: &str, nick: &str) -> Option<String> {
+        let prefix = self.isupport("PREFIX").unwrap_or_else(|| DEFAULT_PREFIX.to_owned());
+        self.list_users(chan)?.into_iter().find(|user| user.is_nickname(nick)).map(|user| {
+            user.display_nickname(&prefix)
+        })
+    }
+
+    /// Gets the maximum nickname length from the `NICKLEN` token. This defaults to 9, as specified
+    /// by [RFC 2812](https://tools.ietf.org/html/rfc2812#section-1.2.1).
+    pub fn max_nick_length(&self) -> i64 {
+        self.isupport_int("NICKLEN").unwrap_or(DEFAULT_NICK_LEN as i64)
+    }
+
+    /// Gets the maximum topic length from the `TOPICLEN` token. This defaults to 390, which fits
+    /// any topic within the 512 byte line length limit.
+    pub fn max_topic_length(&self) -> i64 {
+        self.isupport_int("TOPICLEN").unwrap_or(390)
+    }
+
+    /// Gets a snapshot of every user in any tracked channel, with users that share several
+    /// channels merged into a single entry. Account names are known for users seen via the
+    /// `extended-join` capability, and away status is kept up to date via `away-notify`. The
+    /// snapshot is a copy and does not change as further messages are processed. This will always
+    /// return `None` when the `nochanlists` feature is enabled.
+    pub fn users(&self) -> Option<Vec<UserInfo>> {
+        self.state.users()
+    }
+
+    /// Gets the capabilities that the server most recently reported as enabled for this
+    /// connection. This is updated by replies to
+    /// [`send_cap_list`](./ext/trait.ClientExt.html#method.send_cap_list), including multiline
+    /// IRCv3.2 replies, as well as by any `CAP ACK`.
+    pub fn negotiated_capabilities(&self) -> Vec<Capability> {
+        self.state.capabilities.lock().unwrap().clone()
+    }
+
+    /// Authenticates again with the configured `sasl_mechanism` without reconnecting, e.g. after
+    /// services expired the session. With `PLAIN`, the client authenticates as the configured
+    /// `sasl_account` (or its nickname) using the nickname password. This requires the `sasl`
+    /// capability, which can be requested once the server advertises it with `CAP NEW`. The
+    /// outcome is reported by the server with `RPL_SASLSUCCESS` or one of the SASL errors, such
+    /// as `ERR_SASLFAIL`, which are passed on like any other message.
+    pub fn reauthenticate(&self) -> error::Result<()> {
+        self.state.reauthenticate()
+    }
+
+    /// Requests the given capabilities and blocks until the server acknowledges (`CAP ACK`) or
+    /// rejects (`CAP NAK`) the request, returning the requested capabilities that were actually
+    /// acknowledged. A rejected request fails with `IrcError::CapNegotiation`, and if the server
+    /// does not reply within the configured `registration_timeout`, this will fail with
+    /// `IrcError::CapabilityTimeout`.
+    ///
+    /// Like `wait_until_registered`, replies are only observed while incoming messages are being
+    /// processed, so the stream must be driven elsewhere while waiting.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use std::thread;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let client = IrcClient::new("config.toml").unwrap();
+    /// let reader = client.clone();
+    /// thread::spawn(move || reader.for_each_incoming(|_| ()).unwrap());
+    /// let caps = [Capability::MultiPrefix, Capability::AwayNotify];
+    /// let acked = client.request_capabilities(&caps).unwrap();
+    /// if acked.contains(&Capability::AwayNotify) {
+    ///     // away status will be tracked
+    /// }
+    /// client.identify().unwrap();
+    /// # }
+    /// ```
+    pub fn request_capabilities(&self, caps: &[Capability]) -> error::Result<Vec<Capability>> {
+        self.state.request_capabilities(caps)
+    }
+
+    /// Joins the given channel and blocks until the join is confirmed, either by the server echoing
+    /// our `JOIN` or by the end of the channel's `NAMES` list. This fails if the server refuses
+    /// the join with any of the errors described by `ChannelJoinError`, or if neither happens
+    /// within the given timeout. As this blocks until the server replies, it must be called from a
+    /// different thread than the one processing incoming messages.
+    pub fn join_and_wait(&self, chan: &str, timeout: Duration) -> error::Result<()> {
+        self.state.join_and_wait(chan, timeout)
+    }
+
+    /// Sends the given command and blocks until the server replies with one of the given
+    /// responses, returning the first such reply. Replies with a response in `failure` are
+    /// returned as an `IrcError::CommandFailed` error instead, or as `IrcError::Sasl` for the
+    /// SASL errors, and a `RPL_TRYAGAIN` dropping the command is returned as
+    /// `IrcError::RateLimited`. This fails if no such reply arrives within the given timeout. As
+    /// numeric replies do not identify the command they answer, any matching reply is accepted,
+    /// so responses should be chosen to be specific to the command. As this blocks until the
+    /// server replies, it must be called from a different thread than the one processing
+    /// incoming messages.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use std::time::Duration;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// # let client = IrcClient::new("config.toml").unwrap();
+    /// let reply = client.send_and_await(
+    ///     Command::ChannelMODE("#rust".to_owned(), vec![]),
+    ///     &[Response::RPL_CHANNELMODEIS],
+    ///     &[Response::ERR_NOSUCHCHANNEL],
+    ///     Duration::from_secs(10),
+    /// ).unwrap();
+    /// # }
+    /// ```
+    pub fn send_and_await(
+        &self,
+        command: Command,
+        success: &[Response],
+        failure: &[Response],
+        timeout: Duration,
+    ) -> error::Result<Message> {
+        self.state.send_and_await(command, success, failure, timeout)
+    }
+
+    /// Sends a `WHOIS` for the given nickname and blocks until the server has sent the full reply,
+    /// returning the information it contained. This fails with an `IrcError::CommandFailed` error
+    /// if the server replies that there is no such nickname, and with an `IrcError::ReplyTimeout`
+    /// error if the reply is not complete within the given timeout. As this blocks until the
+    /// server replies, it must be called from a different thread than the one processing incoming
+    /// messages.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use std::time::Duration;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// # let client = IrcClient::new("config.toml").unwrap();
+    /// let reply = client.whois("ada", Duration::from_secs(10)).unwrap();
+    /// println!("ada is logged in as {:?}", reply.account());
+    /// # }
+    /// ```
+    pub fn whois(&self, nick: &str, timeout: Duration) -> error::Result<WhoisReply> {
+        self.state.whois(nick, timeout)
+    }
+
+    /// Sends a `NAMES` for the given channel and blocks until the server has sent the full list
+    /// of its members, returning them in the order they were listed along with their membership
+    /// prefixes (e.g. `@` for operators). Unlike
+    /// [`list_users`](./trait.Client.html#tymethod.list_users), this takes a fresh snapshot and
+    /// works for channels the client is not in, as well as with the `nochanlists` feature enabled.
+    /// The list is empty if the channel does not exist or its members are not visible to the
+    /// client.
+    ///
+    /// This fails with an `IrcError::ReplyTimeout` error if the list is not complete within the
+    /// given timeout. As this blocks until the server replies, it must be called from a different
+    /// thread than the one processing incoming messages.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use std::time::Duration;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// # let client = IrcClient::new("config.toml").unwrap();
+    /// for user in client.names("#rust", Duration::from_secs(10)).unwrap() {
+    ///     println!("{} ({:?})", user.get_nickname(), user.highest_access_level());
+    /// }
+    /// # }
+    /// ```
+    pub fn names(&self, chan: &str, timeout: Duration) -> error::Result<Vec<User>> {
+        self.state.names(chan, timeout)
+    }
+
+    /// Requests the modes of the specified channel and blocks until the server replies with them in
+    /// `RPL_CHANNELMODEIS`, returning them along with their parameters, e.g. the channel key for
+    /// `+k`. This fails with `IrcError::CommandFailed` if the channel does not exist, or with
+    /// `IrcError::ReplyTimeout` if the server does not reply within the given timeout. As this
+    /// blocks until the server replies, it must be called from a different thread than the one
+    /// processing incoming messages.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # extern crate irc;
+    /// # use std::time::Duration;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// # let client = IrcClient::new("config.toml").unwrap();
+    /// let modes = client.channel_modes("#rust", Duration::from_secs(10)).unwrap();
+    /// if modes.contains(&Mode::Plus(ChannelMode::Moderated, None)) {
+    ///     println!("#rust is moderated");
+    /// }
+    /// # }
+    /// ```
+    pub fn channel_modes(
+        &self,
+        chan: &str,
+        timeout: Duration,
+    ) -> error::Result<Vec<Mode<ChannelMode>>> {
+        let reply = self.send_and_await(
+            ChannelMODE(chan.to_owned(), vec![]),
+            &[Response::RPL_CHANNELMODEIS],
+            &[Response::ERR_NOSUCHCHANNEL],
+            timeout,
+        )?;
+        // <client> <channel> <modestring> <mode arguments>...
+        let modes = match reply.command {
+            Command::Response(_, ref args, ref suffix) => {
+                args.iter().skip(2).chain(suffix).map(|s| &s[..]).collect::<Vec<_>>().join(" ")
+            }
+            _ => String::new(),
+        };
+        Mode::as_channel_modes(&modes).map_err(|e| error::IrcError::InvalidMessage {
+            string: reply.to_string(),
+            cause: e,
+        })
+    }
+
+    /// Gets the most recently received list of the given mode (bans, ban exceptions, or invite
+    /// exceptions) for the specified channel. Lists can be requested with
+    /// [`send_ban_list`](./ext/trait.ClientExt.html#method.send_ban_list) and similar methods, and
+    /// are only available once the server has sent the end of the list. This will return `None`
+    /// if no such list has been received or if the `nochanlists` feature is enabled.
+    pub fn channel_list(&self, chan: &str, mode: ListMode) -> Option<Vec<ListEntry>> {
+        self.state.channel_list(chan, mode)
+    }
+
+    /// Gets the masks on the most recently received `SILENCE` list of the client. The list can be
+    /// requested with
+    /// [`send_silence_list`](./ext/trait.ClientExt.html#method.send_silence_list), and is only
+    /// available once the server has sent the end of the list. This will return `None` if no such
+    /// list has been received.
+    pub fn silence_list(&self) -> Option<Vec<String>> {
+        self.state.silence_list.lock().unwrap().clone()
+    }
+
+    /// Gets the visibility (public, private, or secret) of the specified channel, as reported in
+    /// the most recent `NAMES` reply for it. This will return `None` if no such reply has been
+    /// received or if the `nochanlists` feature is enabled.
+    pub fn channel_visibility(&self, chan: &str) -> Option<ChannelVisibility> {
+        self.state.channel_visibility(chan)
+    }
+
+    /// Gets the messages in the most recently received `chathistory` batch for the given target,
+    /// in the order the server sent them. History can be requested with
+    /// [`send_chathistory_latest`](./ext/trait.ClientExt.html#method.send_chathistory_latest)
+    /// and similar methods, and is only available once the server has ended the batch. This will
+    /// return `None` if no such batch has been received.
+    pub fn chat_history(&self, target: &str) -> Option<Vec<Message>> {
+        self.state.chat_history(target)
+    }
+
+    /// Gets the reactions received to the message with the given `msgid`, in the order they
+    /// arrived. Reactions are only collected when `track_reactions` is enabled in the
+    /// configuration, and are read from each message with
+    /// [`Message::as_reaction`](../proto/message/struct.Message.html#method.as_reaction).
+    pub fn reactions(&self, msgid: &str) -> Vec<Reaction> {
+        self.state.reactions(msgid)
+    }
+
+    /// Consumes the client, returning a blocking iterator over incoming messages that reconnects
+    /// whenever the connection ends or fails. See
+    /// [`ReconnectingIter`](./struct.ReconnectingIter.html) for details.
+    ///
+    /// **Note**: This takes the client's stream, and so will panic if `stream` was already called.
+    pub fn iter_reconnecting(self) -> ReconnectingIter {
+        let messages = self.stream().wait();
+        ReconnectingIter {
+            client: self,
+            messages: Some(messages),
+            failures: 0,
+            sts_port: None,
+        }
+    }
+
+    /// Gets the log view from the internal transport. Only used for unit testing.
+    #[cfg(test)]
+    fn log_view(&self) -> &LogView {
+        self.view.as_ref().unwrap()
+    }
+}
+
+/// A future representing the eventual creation of an `IrcClient`.
+///
+/// Interaction with this future relies on the `futures` API, but is only expected for more advanced
+/// use cases. To learn more, you can view the documentation for the
 /// [`futures`](https://docs.rs/futures/) crate, or the tutorials for
 /// [`tokio`](https://tokio.rs/docs/getting-started/futures/). An easy to use abstraction that does
 /// not require this knowledge is available via [`IrcReactors`](./reactor/struct.IrcReactor.html).
@@ -795,8 +3269,6 @@ pub struct IrcClientFuture<'a> {
     conn: ConnectionFuture<'a>,
     _handle: Handle,
     config: &'a Config,
-    tx_outgoing: Option<UnboundedSender<Message>>,
-    rx_outgoing: Option<UnboundedReceiver<Message>>,
 }
 
 impl<'a> Future for IrcClientFuture<'a> {
@@ -805,68 +3277,285 @@ impl<'a> Future for IrcClientFuture<'a> {
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let conn = try_ready!(self.conn.poll());
+        Ok(Async::Ready(PackedIrcClient::new(conn, self.config.clone())))
+    }
+}
+
+/// An `IrcClient` packaged with a future that drives its message sending. In order for the client
+/// to actually work properly, this future _must_ be running.
+///
+/// This type should only be used by advanced users who are familiar with the implementation of this
+/// crate. An easy to use abstraction that does not require this knowledge is available via
+/// [`IrcReactors`](./reactor/struct.IrcReactor.html).
+pub struct PackedIrcClient(pub IrcClient, pub Box<Future<Item = (), Error = error::IrcError>>);
 
+impl PackedIrcClient {
+    /// Creates a client on the given connection, along with the future sending the messages
+    /// queued by the client through the connection.
+    fn new(conn: Connection, config: Config) -> PackedIrcClient {
         let view = conn.log_view();
+        let metrics = conn.metrics();
         let (sink, stream) = conn.split();
+        // The backlog is drained on the same event loop that handles the client's messages, so
+        // sending must never wait for it.
+        let backlog = Arc::new(Backlog::new(false));
 
-        let outgoing_future = sink.send_all(
-            self.rx_outgoing.take().unwrap().map_err::<error::IrcError, _>(|()| {
-                unreachable!("futures::sync::mpsc::Receiver should never return Err");
-            })
-        ).map(|_| ());
+        let outgoing_future = SendBacklog::new(sink, backlog.clone(), &config);
 
         let server = IrcClient {
             state: Arc::new(ClientState::new(
-                stream, self.tx_outgoing.take().unwrap(), self.config.clone()
+                stream, backlog, metrics, LastServer::default(), config
             )),
             view: view,
         };
-        Ok(Async::Ready(PackedIrcClient(server, Box::new(outgoing_future))))
+        PackedIrcClient(server, Box::new(outgoing_future))
     }
 }
 
-/// An `IrcClient` packaged with a future that drives its message sending. In order for the client
-/// to actually work properly, this future _must_ be running.
-///
-/// This type should only be used by advanced users who are familiar with the implementation of this
-/// crate. An easy to use abstraction that does not require this knowledge is available via
-/// [`IrcReactors`](./reactor/struct.IrcReactor.html).
-pub struct PackedIrcClient(pub IrcClient, pub Box<Future<Item = (), Error = error::IrcError>>);
+/// The queue of messages waiting to be sent by a client, shared between the `ClientState` that
+/// queues them and the `BacklogStream` that hands them to the transport.
+#[derive(Debug)]
+struct Backlog {
+    queue: Mutex<BacklogQueue>,
+    /// Notified whenever a message leaves the backlog.
+    drained: Condvar,
+    /// Whether the `Block` policy can wait for room, which is only possible when the backlog is
+    /// drained on an event loop other than the one handling the client's messages.
+    can_block: bool,
+}
 
-#[cfg(test)]
-mod test {
-    use std::collections::HashMap;
-    use std::default::Default;
-    use std::thread;
-    use std::time::Duration;
+#[derive(Debug, Default)]
+struct BacklogQueue {
+    /// The messages queued that the transport has yet to accept, oldest first.
+    messages: VecDeque<Message>,
+    /// The task to notify once a message is queued.
+    task: Option<Task>,
+    /// Whether the client has gone away, so that no more messages will be queued.
+    closed: bool,
+}
 
-    use super::{IrcClient, Client};
-    use error::IrcError;
-    use client::data::Config;
-    #[cfg(not(feature = "nochanlists"))]
-    use client::data::User;
-    use proto::{ChannelMode, IrcCodec, Mode};
-    use proto::command::Command::{PART, PRIVMSG, Raw};
+impl Backlog {
+    /// Creates an empty backlog, which waits for room under the `Block` policy if `can_block`.
+    fn new(can_block: bool) -> Backlog {
+        Backlog {
+            queue: Mutex::new(BacklogQueue::default()),
+            drained: Condvar::new(),
+            can_block: can_block,
+        }
+    }
 
-    pub fn test_config() -> Config {
-        Config {
-            owners: Some(vec![format!("test")]),
-            nickname: Some(format!("test")),
-            alt_nicks: Some(vec![format!("test2")]),
-            server: Some(format!("irc.test.net")),
-            channels: Some(vec![format!("#test"), format!("#test2")]),
-            user_info: Some(format!("Testing.")),
-            use_mock_connection: Some(true),
-            ..Default::default()
+    /// Gets the number of messages waiting to be sent.
+    fn len(&self) -> usize {
+        self.queue.lock().unwrap().messages.len()
+    }
+
+    /// Makes room for the given message in the backlog according to the configured overflow
+    /// policy. This returns whether the message should be queued at all, and fails if the policy
+    /// is `Error`. `PONG`, `QUIT`, `CAP`, and `AUTHENTICATE` are never held back, since dropping
+    /// or delaying them would break the connection.
+    fn reserve(&self, msg: &Message, config: &Config) -> error::Result<bool> {
+        let max = match queue_limit(msg, config) {
+            Some(max) => max,
+            None => return Ok(true),
+        };
+        let mut queue = self.queue.lock().unwrap();
+        if queue.messages.len() < max {
+            return Ok(true);
+        }
+        match config.outgoing_overflow_policy() {
+            OverflowPolicy::DropOldest => {
+                let oldest = queue.messages.iter().position(|m| queue_limit(m, config).is_some());
+                if let Some(old) = oldest.and_then(|i| queue.messages.remove(i)) {
+                    debug!("Outgoing queue is full, dropping {}", old.to_string().trim_end());
+                }
+            }
+            OverflowPolicy::Error => return Err(error::IrcError::OutgoingQueueFull { max: max }),
+            OverflowPolicy::Block if self.can_block => {
+                while queue.messages.len() >= max {
+                    queue = self.drained.wait(queue).unwrap();
+                }
+            }
+            OverflowPolicy::Block | OverflowPolicy::DropNewest => return Ok(false),
         }
+        Ok(true)
     }
 
-    pub fn get_client_value(client: IrcClient) -> String {
-        // We sleep here because of synchronization issues.
-        // We can't guarantee that everything will have been sent by the time of this call.
-        thread::sleep(Duration::from_millis(100));
-        client.log_view().sent().unwrap().iter().fold(String::new(), |mut acc, msg| {
-            // NOTE: we have to sanitize here because sanitization happens in IrcCodec after the
+    /// Adds the given message to the back of the backlog.
+    fn push(&self, msg: Message) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.messages.push_back(msg);
+        if let Some(task) = queue.task.take() {
+            task.notify();
+        }
+    }
+
+    /// Takes the oldest message from the backlog, if any.
+    fn pop(&self) -> Option<Message> {
+        self.queue.lock().unwrap().messages.pop_front()
+    }
+
+    /// Takes the oldest message from the backlog that is not a `PRIVMSG` or `NOTICE` to one of the
+    /// given held targets, along with its position. If there is none, the current task will be
+    /// notified once another message is queued, and `None` is returned if the backlog has been
+    /// closed and emptied instead.
+    fn poll_pop(&self, held: &HashSet<String>) -> Async<Option<(usize, Message)>> {
+        let mut queue = self.queue.lock().unwrap();
+        let index = if held.is_empty() {
+            Some(0)
+        } else {
+            queue.messages.iter().position(|msg| match throttle_key(msg) {
+                Some(target) => !held.contains(&target),
+                None => true,
+            })
+        };
+        if let Some(msg) = index.and_then(|i| queue.messages.remove(i)) {
+            return Async::Ready(Some((index.unwrap(), msg)));
+        }
+        if queue.closed && queue.messages.is_empty() {
+            return Async::Ready(None);
+        }
+        queue.task = Some(task::current());
+        Async::NotReady
+    }
+
+    /// Puts a message taken from the backlog back where it was, as the transport was not ready
+    /// for it.
+    fn unpop(&self, index: usize, msg: Message) {
+        let mut queue = self.queue.lock().unwrap();
+        let index = index.min(queue.messages.len());
+        queue.messages.insert(index, msg);
+    }
+
+    /// Records that a message taken from the backlog was accepted by the transport, making room
+    /// for another.
+    fn sent(&self) {
+        self.drained.notify_all();
+    }
+
+    /// Marks the backlog as closed, so that its `SendBacklog` finishes once it is empty.
+    fn close(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.closed = true;
+        if let Some(task) = queue.task.take() {
+            task.notify();
+        }
+    }
+}
+
+/// Gets the configured limit on the outgoing queue that applies to the given message, if any.
+fn queue_limit(msg: &Message, config: &Config) -> Option<usize> {
+    match msg.command {
+        Command::PONG(..) | QUIT(_) | Command::CAP(..) | Command::AUTHENTICATE(_) => None,
+        _ => config.outgoing_queue_max(),
+    }
+}
+
+/// A future sending the messages queued in a `Backlog` through a sink, which finishes once the
+/// backlog is closed and empty. Messages are only taken off the backlog as the sink accepts them,
+/// so that those held back by rate limiting still count towards the outgoing queue's limit.
+struct SendBacklog<S> {
+    sink: S,
+    backlog: Arc<Backlog>,
+    /// Whether the sink limits the rate of messages to each target, so that it may refuse a
+    /// message to one target while accepting messages to others.
+    per_target: bool,
+}
+
+impl<S> SendBacklog<S> {
+    fn new(sink: S, backlog: Arc<Backlog>, config: &Config) -> SendBacklog<S> {
+        SendBacklog {
+            sink: sink,
+            backlog: backlog,
+            per_target: config.max_messages_per_target_burst().is_some(),
+        }
+    }
+}
+
+impl<S> Future for SendBacklog<S> where S: Sink<SinkItem = Message> {
+    type Item = ();
+    type Error = S::SinkError;
+
+    fn poll(&mut self) -> Poll<(), S::SinkError> {
+        // The targets that the sink refused messages to, whose later messages wait behind them to
+        // keep their order while messages to other targets go ahead.
+        let mut held = HashSet::new();
+        while let Async::Ready(next) = self.backlog.poll_pop(&held) {
+            let (index, msg) = match next {
+                Some(next) => next,
+                None => return self.sink.close(),
+            };
+            let target = if self.per_target { throttle_key(&msg) } else { None };
+            if let AsyncSink::NotReady(msg) = self.sink.start_send(msg)? {
+                self.backlog.unpop(index, msg);
+                match target {
+                    Some(target) => held.insert(target),
+                    None => break,
+                };
+                continue;
+            }
+            self.backlog.sent();
+        }
+        try_ready!(self.sink.poll_complete());
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::default::Default;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::process;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use failure::Fail;
+    use futures::{future, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+    use native_tls::{Identity, TlsAcceptor};
+    use tokio_core::reactor::Core;
+
+    use super::{line_len, reconnect_delay, Backlog, ClientEvent, ConnectionState, IrcClient};
+    use super::SendBacklog;
+    use super::{Client, TrackingLimit, MAX_LINE_LEN};
+    use super::MAX_TAGS_LEN;
+    use error::{IrcError, Refusal};
+    use client::test_util::MockServer;
+    use client::data::Config;
+    use client::data::sts;
+    use client::ext::ClientExt;
+    use client::data::{AccessLevel, ConnectionMetrics, OverflowPolicy, SaslMechanism};
+    #[cfg(not(feature = "nochanlists"))]
+    use client::data::{ListEntry, ListMode, User};
+    use proto::{Capability, ChannelJoinError, ChannelMode, Command, IrcCodec, Message, Mode};
+    use proto::message::Tag;
+    use proto::Response;
+    #[cfg(not(feature = "nochanlists"))]
+    use proto::ChannelVisibility;
+    use proto::command::Command::{ChannelMODE, NICK, PART, PRIVMSG, Raw};
+
+    pub fn test_config() -> Config {
+        Config {
+            owners: Some(vec![format!("test")]),
+            nickname: Some(format!("test")),
+            alt_nicks: Some(vec![format!("test2")]),
+            server: Some(format!("irc.test.net")),
+            channels: Some(vec![format!("#test"), format!("#test2")]),
+            user_info: Some(format!("Testing.")),
+            use_mock_connection: Some(true),
+            ..Default::default()
+        }
+    }
+
+    pub fn get_client_value(client: IrcClient) -> String {
+        // We sleep here because of synchronization issues.
+        // We can't guarantee that everything will have been sent by the time of this call.
+        thread::sleep(Duration::from_millis(100));
+        client.log_view().sent().unwrap().iter().fold(String::new(), |mut acc, msg| {
+            // NOTE: we have to sanitize here because sanitization happens in IrcCodec after the
             // messages are converted into strings, but our transport logger catches messages before
             // they ever reach that point.
             acc.push_str(&IrcCodec::sanitize(msg.to_string()));
@@ -875,232 +3564,2117 @@ mod test {
     }
 
     #[test]
-    fn stream() {
-        let exp = "PRIVMSG test :Hi!\r\nPRIVMSG test :This is a test!\r\n\
-                   :test!test@test JOIN #test\r\n";
+    fn stream() {
+        let exp = "PRIVMSG test :Hi!\r\nPRIVMSG test :This is a test!\r\n\
+                   :test!test@test JOIN #test\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(exp.to_owned()),
+            ..test_config()
+        }).unwrap();
+        let mut messages = String::new();
+        client.for_each_incoming(|message| {
+            messages.push_str(&message.to_string());
+        }).unwrap();
+        assert_eq!(&messages[..], exp);
+    }
+
+    #[test]
+    fn handle_message() {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "JOIN #test\r\nJOIN #test2\r\n"
+        );
+    }
+
+    #[test]
+    fn handle_no_motd() {
+        let value = ":irc.test.net 422 test :MOTD File is missing\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "JOIN #test\r\nJOIN #test2\r\n"
+        );
+    }
+
+    #[test]
+    fn handle_end_motd_with_nick_password() {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nick_password: Some(format!("password")),
+            channels: Some(vec![format!("#test"), format!("#test2")]),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NICKSERV IDENTIFY password\r\nJOIN #test\r\n\
+                   JOIN #test2\r\n"
+        );
+    }
+
+    #[test]
+    fn handle_end_motd_with_chan_keys() {
+        let value = ":irc.test.net 376 test :End of /MOTD command\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nickname: Some(format!("test")),
+            channels: Some(vec![format!("#test"), format!("#test2")]),
+            channel_keys: {
+                let mut map = HashMap::new();
+                map.insert(format!("#test2"), format!("password"));
+                Some(map)
+            },
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "JOIN #test\r\nJOIN #test2 password\r\n"
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn handle_end_motd_rejoins_with_runtime_keys() {
+        let value = ":test!test@test JOIN #keyed\r\n\
+                     :irc.test.net 353 test = #keyed :test\r\n\
+                     :irc.test.net 376 test :End of /MOTD command\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.send_join_with_keys::<&str, &str>("#keyed", "secret").unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "JOIN #keyed secret\r\nJOIN #test\r\nJOIN #test2\r\nJOIN #keyed secret\r\n"
+        );
+    }
+
+    #[test]
+    fn handle_welcome_sequence_with_chan_keys() {
+        let value = ":irc.test.net 001 test :Welcome to the Test IRC Network test!test@test\r\n\
+                     :irc.test.net 002 test :Your host is irc.test.net\r\n\
+                     :irc.test.net 375 test :- irc.test.net Message of the day -\r\n\
+                     :irc.test.net 372 test :- Welcome!\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            channels: Some(vec![format!("#test"), format!("#test2")]),
+            channel_keys: {
+                let mut map = HashMap::new();
+                map.insert(format!("#test2"), format!("password"));
+                Some(map)
+            },
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "JOIN #test\r\nJOIN #test2 password\r\n"
+        );
+    }
+
+    #[test]
+    fn no_join_before_end_of_motd() {
+        let value = ":irc.test.net 001 test :Welcome to the Test IRC Network test!test@test\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(&get_client_value(client)[..], "");
+    }
+
+    #[test]
+    fn handle_end_motd_with_ghost() {
+        let value = ":irc.pdgn.co 433 * test :Nickname is already in use.\r\n\
+                     :irc.test.net 376 test2 :End of /MOTD command.\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nickname: Some(format!("test")),
+            alt_nicks: Some(vec![format!("test2")]),
+            nick_password: Some(format!("password")),
+            channels: Some(vec![format!("#test"), format!("#test2")]),
+            should_ghost: Some(true),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NICK :test2\r\nNICKSERV GHOST test password\r\n\
+                   NICK :test\r\nNICKSERV IDENTIFY password\r\nJOIN #test\r\nJOIN #test2\r\n"
+        );
+    }
+
+    #[test]
+    fn handle_end_motd_with_ghost_seq() {
+        let value = ":irc.pdgn.co 433 * test :Nickname is already in use.\r\n\
+                     :irc.test.net 376 test2 :End of /MOTD command.\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nickname: Some(format!("test")),
+            alt_nicks: Some(vec![format!("test2")]),
+            nick_password: Some(format!("password")),
+            channels: Some(vec![format!("#test"), format!("#test2")]),
+            should_ghost: Some(true),
+            ghost_sequence: Some(vec![format!("RECOVER"), format!("RELEASE")]),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NICK :test2\r\nNICKSERV RECOVER test password\
+                   \r\nNICKSERV RELEASE test password\r\nNICK :test\r\nNICKSERV IDENTIFY password\
+                   \r\nJOIN #test\r\nJOIN #test2\r\n"
+        );
+    }
+
+    #[test]
+    fn handle_end_motd_with_umodes() {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nickname: Some(format!("test")),
+            umodes: Some(format!("+B")),
+            channels: Some(vec![format!("#test"), format!("#test2")]),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "MODE test +B\r\nJOIN #test\r\nJOIN #test2\r\n"
+        );
+    }
+
+    #[test]
+    fn handle_end_motd_with_bot_mode() {
+        let value = ":irc.test.net 005 test BOT=B :are supported by this server\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            is_bot: Some(true),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "MODE test +B\r\nJOIN #test\r\nJOIN #test2\r\n"
+        );
+    }
+
+    #[test]
+    fn handle_end_motd_without_bot_token() {
+        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            is_bot: Some(true),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(&get_client_value(client)[..], "JOIN #test\r\nJOIN #test2\r\n");
+    }
+
+    #[test]
+    fn nickname_in_use() {
+        let value = ":irc.pdgn.co 433 * test :Nickname is already in use.\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(&get_client_value(client)[..], "NICK :test2\r\n");
+    }
+
+    #[test]
+    fn ran_out_of_nicknames() {
+        let value = ":irc.pdgn.co 433 * test :Nickname is already in use.\r\n\
+                     :irc.pdgn.co 433 * test2 :Nickname is already in use.\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        let res = client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        });
+
+        if let Err(IrcError::NoUsableNick) = res {
+            ()
+        } else {
+            panic!("expected error when no valid nicks were specified")
+        }
+    }
+
+    #[test]
+    fn wait_until_registered() {
+        let value = ":irc.test.net 001 test :Welcome to the Test IRC Network test!test@test\r\n\
+                     :irc.test.net 376 test :End of /MOTD command.\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        let reader = client.clone();
+        let handle = thread::spawn(move || {
+            reader.for_each_incoming(|message| {
+                println!("{:?}", message);
+            })
+        });
+        client.wait_until_registered().unwrap();
+        handle.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn wait_until_registered_refused() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        let wait = thread::spawn(move || client.wait_until_registered());
+        server.push_incoming(":irc.test.net 464 test :Password incorrect\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        match wait.join().unwrap() {
+            Err(IrcError::Registration(ref refusal)) => {
+                assert_eq!(refusal.reason, "Password incorrect");
+                assert_eq!(refusal.reply.command_name(), "464");
+            }
+            res => panic!("expected Registration, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn server_name_and_version() {
+        let value = ":irc.test.net 001 test :Welcome to the Test IRC Network test!test@test\r\n\
+                     :irc.test.net 004 test node1.test.net ircd-1.2.3 iow biklmnopstv\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        assert_eq!(client.server_name(), None);
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(client.server_name(), Some("node1.test.net".to_owned()));
+        assert_eq!(client.server_version(), Some("ircd-1.2.3".to_owned()));
+    }
+
+    #[test]
+    fn isupport_limits() {
+        let value = ":irc.test.net 005 test NICKLEN=30 CHANLIMIT=#:120,&: PREFIX=(ov)@+ \
+                     :are supported by this server\r\n\
+                     :irc.test.net 005 test EXCEPTS -PREFIX :are supported by this server\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(client.isupport_int("NICKLEN"), Some(30));
+        assert_eq!(client.max_nick_length(), 30);
+        assert_eq!(client.max_channels(), 120);
+        assert_eq!(client.isupport("EXCEPTS"), Some(String::new()));
+        assert_eq!(client.isupport("PREFIX"), None);
+        // TOPICLEN was never advertised, so the default is used.
+        assert_eq!(client.isupport_int("TOPICLEN"), None);
+        assert_eq!(client.max_topic_length(), 390);
+    }
+
+    #[test]
+    fn is_channel_uses_chantypes() {
+        let value = ":irc.test.net 005 test CHANTYPES=# :are supported by this server\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        assert!(client.is_channel("&test"));
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert!(client.is_channel("#test"));
+        assert!(!client.is_channel("&test"));
+        assert!(!client.is_channel("test"));
+    }
+
+    #[test]
+    fn track_presence_prefers_monitor() {
+        let value = ":irc.test.net 005 test MONITOR=100 WATCH=128 \
+                     :are supported by this server\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        client.track_presence("ada").unwrap();
+        client.untrack_presence("ada").unwrap();
+        assert_eq!(&get_client_value(client)[..], "MONITOR + ada\r\nMONITOR - ada\r\n");
+    }
+
+    #[test]
+    fn track_presence_falls_back_to_watch() {
+        let value = ":irc.test.net 005 test WATCH=128 :are supported by this server\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        client.track_presence("ada").unwrap();
+        client.untrack_presence("ada").unwrap();
+        assert_eq!(&get_client_value(client)[..], "WATCH +ada\r\nWATCH -ada\r\n");
+    }
+
+    #[test]
+    fn truncate_nick_to_nicklen() {
+        let value = ":irc.test.net 005 test NICKLEN=9 :are supported by this server\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nickname: Some("fifteencharnick".to_owned()),
+            truncate_nick: Some(true),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        client.send(NICK("fifteencharnick".to_owned())).unwrap();
+        client.send(NICK("ñññññ".to_owned())).unwrap();
+        assert_eq!(client.current_nickname(), "fifteench");
+        assert_eq!(&get_client_value(client)[..], "NICK :fifteench\r\nNICK :ññññ\r\n");
+    }
+
+    #[test]
+    fn truncate_nick_before_nicklen() {
+        let server = MockServer::new(Config {
+            nickname: Some("fifteencharnick".to_owned()),
+            alt_nicks: Some(vec!["sixteencharsnick".to_owned()]),
+            truncate_nick: Some(true),
+            ..test_config()
+        }).unwrap();
+        server.client().send(NICK("fifteencharnick".to_owned())).unwrap();
+        assert_eq!(server.take_outgoing(), vec!["NICK :fifteencharnick"]);
+        // The nickname is only truncated once the server refuses it.
+        server.push_incoming(":irc.test.net 432 * fifteencharnick :Erroneous nickname\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(server.take_outgoing(), vec!["NICK :fifteench"]);
+        assert_eq!(server.client().current_nickname(), "fifteench");
+        server.push_incoming(":irc.test.net 433 * fifteench :Nickname is already in use.\r\n\
+                              :irc.test.net 432 * sixteencharsnick :Erroneous nickname\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(server.take_outgoing(), vec!["NICK :sixteencharsnick", "NICK :sixteench"]);
+        assert_eq!(server.client().current_nickname(), "sixteench");
+    }
+
+    #[test]
+    fn truncate_nick_after_nicklen() {
+        let server = MockServer::new(Config {
+            nickname: Some("fifteencharnick".to_owned()),
+            truncate_nick: Some(true),
+            ..test_config()
+        }).unwrap();
+        server.push_incoming(":irc.test.net 001 fifteencharnick :Welcome\r\n\
+                              :irc.test.net 005 fifteencharnick NICKLEN=12 :are supported\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(server.client().current_nickname(), "fifteencharn");
+    }
+
+    #[test]
+    fn no_truncate_nick_by_default() {
+        let value = ":irc.test.net 005 test NICKLEN=9 :are supported by this server\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            nickname: Some("fifteencharnick".to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        client.send(NICK("fifteencharnick".to_owned())).unwrap();
+        assert_eq!(&get_client_value(client)[..], "NICK :fifteencharnick\r\n");
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn display_nick() {
+        let value = ":irc.test.net 005 test PREFIX=(ohv)@%+ :are supported by this server\r\n\
+                     :irc.test.net 353 test = #test :+@ada %betsy test\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|_| ()).unwrap();
+        assert_eq!(client.display_nick("#test", "ada"), Some("@ada".to_owned()));
+        assert_eq!(client.display_nick("#test", "Betsy"), Some("%betsy".to_owned()));
+        assert_eq!(client.display_nick("#test", "test"), Some("test".to_owned()));
+        assert_eq!(client.display_nick("#test", "carl"), None);
+    }
+
+    #[test]
+    fn isupport_defaults() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(client.max_channels(), 10);
+        assert_eq!(client.max_nick_length(), 9);
+    }
+
+    #[test]
+    fn server_name_from_welcome() {
+        let value = ":node2.test.net 001 test :Welcome to the Test IRC Network test!test@test\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(client.server_name(), Some("node2.test.net".to_owned()));
+        assert_eq!(client.server_version(), None);
+    }
+
+    #[test]
+    fn wait_until_ready_no_motd() {
+        let value = ":irc.test.net 001 test :Welcome to the Test IRC Network test!test@test\r\n\
+                     :irc.test.net 422 test :MOTD File is missing\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        let reader = client.clone();
+        let handle = thread::spawn(move || {
+            reader.for_each_incoming(|message| {
+                println!("{:?}", message);
+            })
+        });
+        client.wait_until_ready().unwrap();
+        client.wait_until_registered().unwrap();
+        handle.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn wait_until_ready_without_motd_end() {
+        let value = ":irc.test.net 001 test :Welcome to the Test IRC Network test!test@test\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            registration_timeout: Some(1),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        client.wait_until_registered().unwrap();
+
+        if let Err(IrcError::RegistrationTimeout) = client.wait_until_ready() {
+            ()
+        } else {
+            panic!("expected readiness to time out without the end of the MOTD")
+        }
+    }
+
+    #[test]
+    fn registration_timeout() {
+        let value = ":irc.test.net NOTICE * :*** Looking up your hostname...\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            registration_timeout: Some(1),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+
+        if let Err(IrcError::RegistrationTimeout) = client.wait_until_registered() {
+            ()
+        } else {
+            panic!("expected registration to time out without a welcome")
+        }
+    }
+
+    fn rejoin_server(delay_ms: u32) -> MockServer {
+        MockServer::new(Config {
+            rejoin_on_kick: Some(true),
+            rejoin_delay_ms: Some(delay_ms),
+            ..test_config()
+        }).unwrap()
+    }
+
+    /// Processes the given lines on a client that rejoins channels when kicked, and returns what it
+    /// sent once the rejoin delays have passed.
+    fn rejoins_after(lines: &str) -> Vec<String> {
+        let server = rejoin_server(1);
+        server.push_incoming(lines);
+        server.process_incoming(|_| ()).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        server.process_incoming(|_| ()).unwrap();
+        server.take_outgoing()
+    }
+
+    #[test]
+    fn rejoin_on_kick() {
+        let server = rejoin_server(300);
+        server.push_incoming(":owner!test@test KICK #test test :Go away.\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(server.take_outgoing(), Vec::<String>::new());
+        thread::sleep(Duration::from_millis(500));
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(server.take_outgoing(), vec!["JOIN #test"]);
+    }
+
+    #[test]
+    fn rejoin_on_multi_target_kick() {
+        let value = ":owner!test@test KICK #test ada,TEST :Go away.\r\n\
+                     :owner!test@test KICK #rust ada,betsy :Go away.\r\n";
+        assert_eq!(rejoins_after(value), vec!["JOIN #test"]);
+    }
+
+    #[test]
+    fn rejoin_on_kick_with_runtime_key() {
+        let server = rejoin_server(1);
+        server.client().send_join_with_keys::<&str, &str>("#secret", "hunter2").unwrap();
+        server.push_incoming(":test!test@test JOIN #secret\r\n\
+                              :owner!test@test KICK #secret test :Go away.\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(server.take_outgoing(), vec!["JOIN #secret hunter2", "JOIN #secret hunter2"]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn multi_target_kick_tracking() {
+        let server = MockServer::new(test_config()).unwrap();
+        server.push_incoming(":irc.test.net 353 test = #test :test ada betsy carol\r\n\
+                              :owner!test@test KICK #test ada,betsy :Go away.\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(
+            server.client().list_users("#test"),
+            Some(vec![User::new("test"), User::new("carol")])
+        );
+    }
+
+    #[test]
+    fn no_rejoin_on_kick_by_default() {
+        let value = ":owner!test@test KICK #test test :Go away.\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(&get_client_value(client)[..], "");
+    }
+
+    #[test]
+    fn rejoin_on_kick_gives_up() {
+        let value = ":owner!test@test KICK #test test :Go away.\r\n\
+                     :owner!test@test KICK #test test :Go away.\r\n\
+                     :owner!test@test KICK #test test :Go away.\r\n\
+                     :owner!test@test KICK #test test :Go away.\r\n";
+        assert_eq!(rejoins_after(value), vec!["JOIN #test", "JOIN #test", "JOIN #test"]);
+    }
+
+    #[test]
+    fn send() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        assert!(
+            client
+                .send(PRIVMSG(format!("#test"), format!("Hi there!")))
+                .is_ok()
+        );
+        assert_eq!(
+            &get_client_value(client)[..],
+            "PRIVMSG #test :Hi there!\r\n"
+        );
+    }
+
+    #[test]
+    fn send_no_newline_injection() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        assert!(
+            client
+                .send(PRIVMSG(format!("#test"), format!("Hi there!\r\nJOIN #bad")))
+                .is_ok()
+        );
+        assert_eq!(&get_client_value(client)[..], "PRIVMSG #test :Hi there!\r\n");
+    }
+
+    #[test]
+    fn send_raw_is_really_raw() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        assert!(
+            client.send(Raw("PASS".to_owned(), vec!["password".to_owned()], None)).is_ok()
+        );
+        assert!(
+            client.send(Raw("NICK".to_owned(), vec!["test".to_owned()], None)).is_ok()
+        );
+        assert_eq!(&get_client_value(client)[..], "PASS password\r\nNICK test\r\n");
+    }
+
+    #[test]
+    fn request_capabilities_partial_ack() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        let request = thread::spawn(move || {
+            client.request_capabilities(&[Capability::MultiPrefix, Capability::Sasl])
+        });
+        // Wait for the request to be sent before replying to it.
+        let mut sent = vec![];
+        while sent.is_empty() {
+            thread::sleep(Duration::from_millis(10));
+            sent = server.take_outgoing();
+        }
+        assert_eq!(sent, vec!["CAP REQ :multi-prefix sasl".to_owned()]);
+        server.push_incoming(":irc.test.net CAP test ACK :multi-prefix\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(request.join().unwrap().unwrap(), vec![Capability::MultiPrefix]);
+        assert_eq!(server.client().negotiated_capabilities(), vec![Capability::MultiPrefix]);
+    }
+
+    #[test]
+    fn reauthenticate() {
+        let server = MockServer::new(Config {
+            nick_password: Some("password".to_owned()),
+            ..test_config()
+        }).unwrap();
+        match server.client().reauthenticate() {
+            Err(IrcError::CapabilityNotEnabled { capability: Capability::Sasl }) => (),
+            res => panic!("expected CapabilityNotEnabled, got {:?}", res),
+        }
+        server.push_incoming(":irc.test.net CAP test NEW :sasl=PLAIN\r\n\
+                              :irc.test.net CAP test ACK :sasl\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        server.client().reauthenticate().unwrap();
+        assert_eq!(server.take_outgoing(), vec!["AUTHENTICATE PLAIN".to_owned()]);
+        server.push_incoming("AUTHENTICATE +\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        // base64("test\0test\0password")
+        assert_eq!(
+            server.take_outgoing(), vec!["AUTHENTICATE dGVzdAB0ZXN0AHBhc3N3b3Jk".to_owned()]
+        );
+        server.push_incoming(":irc.test.net 903 test :SASL authentication successful\r\n\
+                              AUTHENTICATE +\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(server.take_outgoing(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reauthenticate_as_sasl_account() {
+        let server = MockServer::new(Config {
+            nick_password: Some("password".to_owned()),
+            sasl_account: Some("account".to_owned()),
+            ..test_config()
+        }).unwrap();
+        server.push_incoming(":irc.test.net CAP test ACK :sasl\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        server.client().reauthenticate().unwrap();
+        assert_eq!(server.take_outgoing(), vec!["AUTHENTICATE PLAIN".to_owned()]);
+        server.push_incoming("AUTHENTICATE +\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        // base64("account\0account\0password")
+        assert_eq!(
+            server.take_outgoing(),
+            vec!["AUTHENTICATE YWNjb3VudABhY2NvdW50AHBhc3N3b3Jk".to_owned()]
+        );
+    }
+
+    #[test]
+    fn reauthenticate_external() {
+        let server = MockServer::new(Config {
+            sasl_mechanism: Some(SaslMechanism::External),
+            ..test_config()
+        }).unwrap();
+        server.push_incoming(":irc.test.net CAP test ACK :sasl\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        server.client().reauthenticate().unwrap();
+        assert_eq!(server.take_outgoing(), vec!["AUTHENTICATE EXTERNAL".to_owned()]);
+        server.push_incoming("AUTHENTICATE +\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(server.take_outgoing(), vec!["AUTHENTICATE +".to_owned()]);
+    }
+
+    #[test]
+    fn has_capability() {
+        let server = MockServer::new(test_config()).unwrap();
+        server.push_incoming(":irc.test.net CAP test ACK :multi-prefix example.org/custom\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert!(server.client().has_capability("multi-prefix"));
+        assert!(server.client().has_capability("MULTI-PREFIX"));
+        assert!(server.client().has_capability("Example.org/Custom"));
+        assert!(!server.client().has_capability("server-time"));
+        server.push_incoming(":irc.test.net CAP test ACK :-multi-prefix\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert!(!server.client().has_capability("multi-prefix"));
+    }
+
+    #[test]
+    fn request_capabilities_nak() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        let request = thread::spawn(move || client.request_capabilities(&[Capability::Sasl]));
+        while server.take_outgoing().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        server.push_incoming(":irc.test.net CAP test NAK :sasl\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        match request.join().unwrap() {
+            Err(IrcError::CapNegotiation(ref refusal)) if refusal.reason == "sasl" => (),
+            res => panic!("expected CapNegotiation, got {:?}", res),
+        }
+    }
+
+    fn overflow_server(policy: OverflowPolicy) -> MockServer {
+        MockServer::new(Config {
+            outgoing_queue_max: Some(2),
+            outgoing_overflow_policy: Some(policy),
+            ..test_config()
+        }).unwrap()
+    }
+
+    fn send_numbered(client: &IrcClient, n: u32) -> ::error::Result<()> {
+        client.send(PRIVMSG("#test".to_owned(), format!("{}", n)))
+    }
+
+    #[test]
+    fn outgoing_overflow_drop_newest() {
+        let server = overflow_server(OverflowPolicy::DropNewest);
+        for i in 0..3 {
+            send_numbered(server.client(), i).unwrap();
+        }
+        assert_eq!(server.client().pending_outbound(), 2);
+        assert_eq!(server.take_outgoing(), vec!["PRIVMSG #test :0", "PRIVMSG #test :1"]);
+    }
+
+    #[test]
+    fn outgoing_overflow_drop_oldest() {
+        let server = overflow_server(OverflowPolicy::DropOldest);
+        for i in 0..3 {
+            send_numbered(server.client(), i).unwrap();
+        }
+        assert_eq!(server.client().pending_outbound(), 2);
+        assert_eq!(server.take_outgoing(), vec!["PRIVMSG #test :1", "PRIVMSG #test :2"]);
+        assert_eq!(server.client().pending_outbound(), 0);
+    }
+
+    #[test]
+    fn send_too_long() {
+        let client = IrcClient::from_config(Config {
+            reject_long_messages: Some(true),
+            ..test_config()
+        }).unwrap();
+        // PRIVMSG #test :<message>\r\n
+        let overhead = 17;
+        client.send_privmsg("#test", "a".repeat(MAX_LINE_LEN - overhead)).unwrap();
+        match client.send_privmsg("#test", "a".repeat(MAX_LINE_LEN - overhead + 1)) {
+            Err(IrcError::MessageTooLong { length: 513, max: 512 }) => (),
+            res => panic!("expected MessageTooLong, got {:?}", res),
+        }
+        let sent = get_client_value(client);
+        assert_eq!(sent.len(), MAX_LINE_LEN);
+    }
+
+    #[test]
+    fn send_too_long_by_default() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        // PRIVMSG #test :<message>\r\n
+        let overhead = 17;
+        client.send_privmsg("#test", "a".repeat(MAX_LINE_LEN - overhead + 1)).unwrap();
+        let sent = get_client_value(client);
+        assert_eq!(sent.len(), MAX_LINE_LEN + 1);
+    }
+
+    #[test]
+    fn send_tags_too_long() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        let message = |value: String| Message {
+            tags: Some(vec![
+                Tag("+draft/reply".to_owned(), Some("abc".to_owned())),
+                Tag("+example.com/data".to_owned(), Some(value)),
+            ]),
+            prefix: None,
+            command: PRIVMSG("#test".to_owned(), "Hi!".to_owned()),
+        };
+        // +draft/reply=abc;+example.com/data=<value>
+        let overhead = 35;
+        client.send(message("a".repeat(MAX_TAGS_LEN - overhead))).unwrap();
+        match client.send(message(" ".repeat(MAX_TAGS_LEN / 2))) {
+            Err(IrcError::TagsTooLong { length: 4129, max: 4094 }) => (),
+            res => panic!("expected TagsTooLong, got {:?}", res),
+        }
+        let sent = get_client_value(client);
+        assert_eq!(sent.len(), 1 + MAX_TAGS_LEN + " PRIVMSG #test :Hi!\r\n".len());
+    }
+
+    #[test]
+    fn forced_rename() {
+        let value = ":test!test@test NICK Guest123\r\n:Guest123!test@test NICK test2\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        let renames = Arc::new(Mutex::new(vec![]));
+        let seen = Arc::clone(&renames);
+        client.on_forced_rename(move |client, old, new| {
+            assert_eq!(client.current_nickname(), new);
+            seen.lock().unwrap().push((old.to_owned(), new.to_owned()));
+        });
+        let mut nicks = vec![];
+        client.for_each_incoming(|_| nicks.push(client.current_nickname().to_owned())).unwrap();
+        assert_eq!(nicks, vec!["Guest123", "test2"]);
+        // Neither rename was requested, and so both were forced.
+        assert_eq!(*renames.lock().unwrap(), vec![
+            ("test".to_owned(), "Guest123".to_owned()),
+            ("Guest123".to_owned(), "test2".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn forced_rename_callback_can_reregister() {
+        let value = ":test!test@test NICK Guest123\r\n:Guest123!test@test NICK Guest456\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        let renames = Arc::new(Mutex::new(vec![]));
+        let seen = Arc::clone(&renames);
+        client.on_forced_rename(move |client, _, new| {
+            seen.lock().unwrap().push(new.to_owned());
+            let seen = Arc::clone(&seen);
+            client.on_forced_rename(move |_, _, new| {
+                seen.lock().unwrap().push(format!("again {}", new));
+            });
+        });
+        client.for_each_incoming(|_| ()).unwrap();
+        assert_eq!(*renames.lock().unwrap(), vec!["Guest123", "again Guest456"]);
+    }
+
+    #[test]
+    fn requested_rename() {
+        let server = MockServer::new(test_config()).unwrap();
+        let renames = Arc::new(Mutex::new(0));
+        let seen = Arc::clone(&renames);
+        server.client().on_forced_rename(move |_, _, _| *seen.lock().unwrap() += 1);
+        server.client().send(NICK("ada".to_owned())).unwrap();
+        server.push_incoming(":test!test@test NICK ada\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(server.client().current_nickname(), "ada");
+        assert_eq!(*renames.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn reactions() {
+        let value = "@msgid=abc123 :ada!ada@host PRIVMSG #test :Hi!\r\n\
+                     @+draft/reply=abc123;+draft/react=👍 :betsy!betsy@host TAGMSG #test\r\n\
+                     @+draft/reply=abc123;+draft/react=👍 :Betsy!betsy@host TAGMSG #test\r\n\
+                     @+draft/reply=abc123;+draft/react=🎉 :ada!ada@host TAGMSG #test\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            track_reactions: Some(true),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|_| ()).unwrap();
+        let reactions: Vec<_> = client.reactions("abc123").into_iter().map(|r| {
+            (r.from, r.emoji)
+        }).collect();
+        assert_eq!(reactions, vec![
+            ("betsy".to_owned(), "👍".to_owned()),
+            ("ada".to_owned(), "🎉".to_owned()),
+        ]);
+        assert!(client.reactions("def456").is_empty());
+    }
+
+    #[test]
+    fn reactions_bounded() {
+        let value = "@+draft/reply=a;+draft/react=👍 :ada!ada@host TAGMSG #test\r\n\
+                     @+draft/reply=b;+draft/react=👍 :ada!ada@host TAGMSG #test\r\n\
+                     @+draft/reply=a;+draft/react=🎉 :ada!ada@host TAGMSG #test\r\n\
+                     @+draft/reply=c;+draft/react=👍 :ada!ada@host TAGMSG #test\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            track_reactions: Some(true),
+            max_tracked_reactions: Some(2),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|_| ()).unwrap();
+        // The message first reacted to is forgotten, even though it was reacted to again.
+        assert!(client.reactions("a").is_empty());
+        assert_eq!(client.reactions("b").len(), 1);
+        assert_eq!(client.reactions("c").len(), 1);
+    }
+
+    #[test]
+    fn reactions_untracked_by_default() {
+        let value = "@+draft/reply=abc123;+draft/react=👍 :betsy!betsy@host TAGMSG #test\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|_| ()).unwrap();
+        assert!(client.reactions("abc123").is_empty());
+    }
+
+    #[test]
+    fn sanitize_incoming() {
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some("PRIVMSG #test :hi\rthere\r\n".to_owned()),
+            sanitize_incoming: Some(true),
+            ..test_config()
+        }).unwrap();
+        let mut messages = Vec::new();
+        client.for_each_incoming(|message| messages.push(message.command)).unwrap();
+        assert_eq!(messages, vec![PRIVMSG("#test".to_owned(), "hithere".to_owned())]);
+    }
+
+    #[test]
+    fn incoming_line_breaks_preserved_by_default() {
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some("PRIVMSG #test :hi\rthere\r\n".to_owned()),
+            ..test_config()
+        }).unwrap();
+        let mut messages = Vec::new();
+        client.for_each_incoming(|message| messages.push(message.command)).unwrap();
+        assert_eq!(messages, vec![PRIVMSG("#test".to_owned(), "hi\rthere".to_owned())]);
+    }
+
+    #[test]
+    fn line_len_matches_serialization() {
+        let messages = [
+            "PRIVMSG #test :Hi, everybody!\r\n",
+            ":ada!ada@host NOTICE ada :\r\n",
+            "@time=2019-01-01T00:00:00.000Z PRIVMSG #test ::)\r\n",
+            "JOIN #test,#rust key\r\n",
+        ];
+        for line in &messages {
+            let message: Message = line.parse().unwrap();
+            let tags = line.find(' ').filter(|_| line.starts_with('@')).map_or(0, |i| i + 1);
+            assert_eq!(line_len(&message), line.len() - tags, "{}", line);
+        }
+    }
+
+    #[test]
+    fn outgoing_overflow_error() {
+        let server = overflow_server(OverflowPolicy::Error);
+        send_numbered(server.client(), 0).unwrap();
+        send_numbered(server.client(), 1).unwrap();
+        match send_numbered(server.client(), 2) {
+            Err(IrcError::OutgoingQueueFull { max: 2 }) => (),
+            res => panic!("expected OutgoingQueueFull, got {:?}", res),
+        }
+        assert_eq!(server.take_outgoing(), vec!["PRIVMSG #test :0", "PRIVMSG #test :1"]);
+    }
+
+    #[test]
+    fn outgoing_overflow_block() {
+        let server = overflow_server(OverflowPolicy::Block);
+        send_numbered(server.client(), 0).unwrap();
+        send_numbered(server.client(), 1).unwrap();
+        let client = server.client().clone();
+        let blocked = thread::spawn(move || send_numbered(&client, 2));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(server.client().pending_outbound(), 2);
+        let mut sent = server.take_outgoing();
+        blocked.join().unwrap().unwrap();
+        sent.extend(server.take_outgoing());
+        assert_eq!(sent, vec!["PRIVMSG #test :0", "PRIVMSG #test :1", "PRIVMSG #test :2"]);
+    }
+
+    #[test]
+    fn outgoing_overflow_block_on_shared_event_loop() {
+        let config = Config {
+            outgoing_queue_max: Some(1),
+            outgoing_overflow_policy: Some(OverflowPolicy::Block),
+            ..test_config()
+        };
+        let msg: Message = PRIVMSG("#test".to_owned(), "hi".to_owned()).into();
+        let backlog = Backlog::new(false);
+        assert!(backlog.reserve(&msg, &config).unwrap());
+        backlog.push(msg.clone());
+        // Waiting for room on the event loop that drains the backlog would wait forever.
+        assert!(!backlog.reserve(&msg, &config).unwrap());
+        assert_eq!(backlog.len(), 1);
+    }
+
+    #[test]
+    fn outgoing_overflow_exempts_connection_upkeep() {
+        let server = overflow_server(OverflowPolicy::DropOldest);
+        server.client().send_pong("irc.test.net").unwrap();
+        for i in 0..3 {
+            send_numbered(server.client(), i).unwrap();
+        }
+        server.client().send_quit("bye").unwrap();
+        assert_eq!(server.client().pending_outbound(), 3);
+        assert_eq!(
+            server.take_outgoing(), vec!["PONG :irc.test.net", "PRIVMSG #test :2", "QUIT :bye"]
+        );
+    }
+
+    /// A sink that refuses every message to one target, as when it is throttled.
+    struct RefusingSink {
+        refused: &'static str,
+        sent: Vec<String>,
+    }
+
+    impl Sink for RefusingSink {
+        type SinkItem = Message;
+        type SinkError = IrcError;
+
+        fn start_send(&mut self, msg: Message) -> StartSend<Message, IrcError> {
+            if msg.response_target() == Some(self.refused) {
+                return Ok(AsyncSink::NotReady(msg));
+            }
+            self.sent.push(msg.to_string().trim_end().to_owned());
+            Ok(AsyncSink::Ready)
+        }
+
+        fn poll_complete(&mut self) -> Poll<(), IrcError> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    #[test]
+    fn send_backlog_passes_throttled_targets() {
+        let config = Config { max_messages_per_target_burst: Some(1), ..test_config() };
+        let backlog = Arc::new(Backlog::new(false));
+        for (i, target) in ["#busy", "#quiet", "#busy", "#quiet"].iter().enumerate() {
+            backlog.push(PRIVMSG(target.to_string(), format!("{}", i)).into());
+        }
+        let sink = RefusingSink { refused: "#busy", sent: vec![] };
+        let mut send = SendBacklog::new(sink, backlog.clone(), &config);
+        future::lazy(|| Ok::<_, IrcError>(send.poll())).wait().unwrap().unwrap();
+        assert_eq!(send.sink.sent, vec!["PRIVMSG #quiet :1", "PRIVMSG #quiet :3"]);
+        // The refused messages stay queued in order.
+        assert_eq!(backlog.pop().and_then(|msg| msg.suffix()), Some("0".to_owned()));
+        assert_eq!(backlog.pop().and_then(|msg| msg.suffix()), Some("2".to_owned()));
+    }
+
+    #[test]
+    fn join_and_wait() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        let join = thread::spawn(move || client.join_and_wait("#test", Duration::from_secs(5)));
+        while server.take_outgoing().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        server.push_incoming(":test!test@test JOIN #test\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        join.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn join_and_wait_invite_only() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        let join = thread::spawn(move || client.join_and_wait("#test", Duration::from_secs(5)));
+        let mut sent = vec![];
+        while sent.is_empty() {
+            thread::sleep(Duration::from_millis(10));
+            sent = server.take_outgoing();
+        }
+        assert_eq!(sent, vec!["JOIN #test".to_owned()]);
+        server.push_incoming(":irc.test.net 473 test #test :Cannot join channel (+i)\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        match join.join().unwrap() {
+            Err(IrcError::JoinFailed { ref channel, error: ChannelJoinError::InviteOnly }) => {
+                assert_eq!(channel, "#test")
+            }
+            res => panic!("expected JoinFailed, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn names() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        let names = thread::spawn(move || client.names("#rust", Duration::from_secs(5)));
+        let mut sent = vec![];
+        while sent.is_empty() {
+            thread::sleep(Duration::from_millis(10));
+            sent = server.take_outgoing();
+        }
+        assert_eq!(sent, vec!["NAMES #rust".to_owned()]);
+        server.push_incoming(
+            ":irc.test.net 353 test = #irc :bob\r\n\
+             :irc.test.net 353 test = #Rust :@ada +betsy\r\n\
+             :irc.test.net 353 test = #Rust :test\r\n\
+             :irc.test.net 366 test #Rust :End of /NAMES list.\r\n"
+        );
+        server.process_incoming(|_| ()).unwrap();
+        let members: Vec<_> = names.join().unwrap().unwrap().iter().map(|user| {
+            (user.get_nickname().to_owned(), user.highest_access_level())
+        }).collect();
+        assert_eq!(members, vec![
+            ("ada".to_owned(), AccessLevel::Oper),
+            ("betsy".to_owned(), AccessLevel::Voice),
+            ("test".to_owned(), AccessLevel::Member),
+        ]);
+    }
+
+    #[test]
+    fn names_timeout() {
+        let server = MockServer::new(test_config()).unwrap();
+        server.push_incoming(":irc.test.net 353 test = #rust :@ada\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        match server.client().names("#rust", Duration::from_millis(50)) {
+            Err(IrcError::ReplyTimeout) => (),
+            res => panic!("expected the names to time out, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn whois() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        let whois = thread::spawn(move || client.whois("ada", Duration::from_secs(5)));
+        let mut sent = vec![];
+        while sent.is_empty() {
+            thread::sleep(Duration::from_millis(10));
+            sent = server.take_outgoing();
+        }
+        assert_eq!(sent, vec!["WHOIS ada".to_owned()]);
+        server.push_incoming(
+            ":irc.test.net 311 test Ada ada host.example * :Ada Lovelace\r\n\
+             :irc.test.net 319 test Ada :@#rust #irc\r\n\
+             :irc.test.net 312 test Ada irc.test.net :Test Server\r\n\
+             :irc.test.net 301 test Ada :Computing\r\n\
+             :irc.test.net 313 test Ada :is an IRC operator\r\n\
+             :irc.test.net 330 test Ada adaacct :is logged in as\r\n\
+             :irc.test.net 317 test Ada 42 1500000000 :seconds idle, signon time\r\n\
+             :irc.test.net 318 test Ada :End of /WHOIS list.\r\n"
+        );
+        server.process_incoming(|_| ()).unwrap();
+        let reply = whois.join().unwrap().unwrap();
+        assert_eq!(reply.nickname(), "Ada");
+        assert_eq!(reply.username(), Some("ada"));
+        assert_eq!(reply.hostname(), Some("host.example"));
+        assert_eq!(reply.realname(), Some("Ada Lovelace"));
+        assert_eq!(reply.server(), Some("irc.test.net"));
+        assert_eq!(reply.server_info(), Some("Test Server"));
+        assert!(reply.is_operator());
+        assert_eq!(reply.idle_secs(), Some(42));
+        assert_eq!(reply.signon(), Some(1500000000));
+        assert_eq!(reply.channels(), &["@#rust".to_owned(), "#irc".to_owned()][..]);
+        assert_eq!(reply.account(), Some("adaacct"));
+        assert_eq!(reply.away_message(), Some("Computing"));
+    }
+
+    #[test]
+    fn whois_no_such_nick() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        let whois = thread::spawn(move || client.whois("ada", Duration::from_secs(5)));
+        while server.take_outgoing().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        server.push_incoming(
+            ":irc.test.net 401 test ada :No such nick/channel\r\n\
+             :irc.test.net 318 test ada :End of /WHOIS list.\r\n"
+        );
+        server.process_incoming(|_| ()).unwrap();
+        match whois.join().unwrap() {
+            Err(IrcError::CommandFailed { response: Response::ERR_NOSUCHNICK, .. }) => (),
+            res => panic!("expected CommandFailed, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn send_and_await_mode_query() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        let query = thread::spawn(move || client.send_and_await(
+            ChannelMODE("#test".to_owned(), vec![]),
+            &[Response::RPL_CHANNELMODEIS],
+            &[Response::ERR_NOSUCHCHANNEL],
+            Duration::from_secs(5),
+        ));
+        let mut sent = vec![];
+        while sent.is_empty() {
+            thread::sleep(Duration::from_millis(10));
+            sent = server.take_outgoing();
+        }
+        assert_eq!(sent, vec!["MODE #test".to_owned()]);
+        server.push_incoming(":irc.test.net 332 test #test :Unrelated topic\r\n\
+                              :irc.test.net 324 test #test +nt\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        let reply = query.join().unwrap().unwrap();
+        assert_eq!(reply.command, Command::Response(
+            Response::RPL_CHANNELMODEIS,
+            vec!["test".to_owned(), "#test".to_owned(), "+nt".to_owned()],
+            None,
+        ));
+    }
+
+    #[test]
+    fn channel_modes() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        let query = thread::spawn(move || client.channel_modes("#test", Duration::from_secs(5)));
+        let mut sent = vec![];
+        while sent.is_empty() {
+            thread::sleep(Duration::from_millis(10));
+            sent = server.take_outgoing();
+        }
+        assert_eq!(sent, vec!["MODE #test".to_owned()]);
+        server.push_incoming(":irc.test.net 324 test #test +ntk :key\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(query.join().unwrap().unwrap(), vec![
+            Mode::Plus(ChannelMode::NoExternalMessages, None),
+            Mode::Plus(ChannelMode::ProtectedTopic, None),
+            Mode::Plus(ChannelMode::Key, Some("key".to_owned())),
+        ]);
+    }
+
+    #[test]
+    fn send_and_await_failure() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        let query = thread::spawn(move || client.send_and_await(
+            ChannelMODE("#nope".to_owned(), vec![]),
+            &[Response::RPL_CHANNELMODEIS],
+            &[Response::ERR_NOSUCHCHANNEL],
+            Duration::from_secs(5),
+        ));
+        while server.take_outgoing().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        server.push_incoming(":irc.test.net 403 test #nope :No such channel\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        match query.join().unwrap() {
+            Err(IrcError::CommandFailed { response: Response::ERR_NOSUCHCHANNEL, .. }) => (),
+            res => panic!("expected CommandFailed, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn send_and_await_sasl_failure() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        let auth = thread::spawn(move || client.send_and_await(
+            Command::AUTHENTICATE("PLAIN".to_owned()),
+            &[Response::RPL_SASLSUCCESS],
+            &[Response::ERR_SASLFAIL],
+            Duration::from_secs(5),
+        ));
+        while server.take_outgoing().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        server.push_incoming(":irc.test.net 904 test :SASL authentication failed\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        let err = auth.join().unwrap().unwrap_err();
+        match err {
+            IrcError::Sasl(ref refusal) => {
+                assert_eq!(refusal.reason, "SASL authentication failed");
+            }
+            ref res => panic!("expected Sasl, got {:?}", res),
+        }
+        let cause = err.cause().and_then(|cause| cause.downcast_ref::<Refusal>());
+        assert_eq!(cause.map(|refusal| &refusal.reason[..]), Some("SASL authentication failed"));
+    }
+
+    #[test]
+    fn send_and_await_rate_limited() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        let query = thread::spawn(move || client.send_and_await(
+            Command::LIST(None, None),
+            &[Response::RPL_LISTEND],
+            &[],
+            Duration::from_secs(5),
+        ));
+        while server.take_outgoing().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        server.push_incoming(":irc.test.net 263 test LIST :Please wait a while and try again.\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        match query.join().unwrap() {
+            Err(IrcError::RateLimited(ref refusal)) => {
+                assert_eq!(refusal.reason, "Please wait a while and try again.");
+            }
+            res => panic!("expected RateLimited, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn sts_upgrade_over_plaintext() {
+        let value = ":irc.test.net CAP * LS :multi-prefix sts=port=6697,duration=300\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        match client.for_each_incoming(|_| ()) {
+            Err(IrcError::StsUpgrade { port }) => assert_eq!(port, 6697),
+            res => panic!("expected StsUpgrade, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn sts_policy_persisted_over_tls() {
+        let path = env::temp_dir().join(format!("irc-sts-client-test-{}", process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let value = ":irc.test.net CAP * LS :sts=duration=300\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            use_ssl: Some(true),
+            port: Some(6697),
+            sts_cache: Some(path.clone()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|_| ()).unwrap();
+        let plaintext = Config { sts_cache: Some(path.clone()), ..test_config() };
+        assert_eq!(sts::upgrade_port(&plaintext), Some(6697));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cap_list_multiline() {
+        let value = ":irc.test.net CAP * LIST :sasl\r\n\
+                     :irc.test.net CAP test LIST * :multi-prefix away-notify\r\n\
+                     :irc.test.net CAP test LIST :draft/example\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(
+            client.negotiated_capabilities(),
+            vec![
+                Capability::MultiPrefix,
+                Capability::AwayNotify,
+                Capability::Unknown("draft/example".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn silence_list() {
+        let value = ":irc.test.net 271 test test *!*@spam.host\r\n\
+                     :irc.test.net 271 test test troll!*@*\r\n";
+        let server = MockServer::new(test_config()).unwrap();
+        server.push_incoming(value);
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(server.client().silence_list(), None);
+        server.push_incoming(":irc.test.net 272 test :End of Silence List\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(
+            server.client().silence_list(),
+            Some(vec!["*!*@spam.host".to_owned(), "troll!*@*".to_owned()])
+        );
+        server.push_incoming(":irc.test.net 272 test :End of Silence List\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(server.client().silence_list(), Some(vec![]));
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn invite_list_tracking() {
+        let value = ":irc.test.net 346 test #test *!*@friend.host op!op@host 1500000000\r\n\
+                     :irc.test.net 346 test #test *!*@other.host\r\n\
+                     :irc.test.net 347 test #test :End of Channel Invite List\r\n\
+                     :irc.test.net 349 test #test :End of Channel Exception List\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(
+            client.channel_list("#test", ListMode::InviteException),
+            Some(vec![
+                ListEntry::new("*!*@friend.host", Some("op!op@host"), Some(1500000000)),
+                ListEntry::new("*!*@other.host", None, None),
+            ])
+        );
+        assert_eq!(client.channel_list("#test", ListMode::Exception), Some(vec![]));
+        assert_eq!(client.channel_list("#test", ListMode::Ban), None);
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn users_snapshot() {
+        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n\
+                     :irc.test.net 353 test = #test2 :test Owner\r\n\
+                     :acct!user@host JOIN #test2 acct :Real Name\r\n\
+                     :anon!user@host JOIN #test * :Real Name\r\n\
+                     :admin!user@host AWAY :Gone\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        let users = client.users().unwrap();
+        let nicks: Vec<_> = users.iter().map(|u| u.get_nickname()).collect();
+        assert_eq!(nicks, vec!["acct", "admin", "anon", "owner", "test"]);
+        assert_eq!(users[0].get_account(), Some("acct"));
+        assert_eq!(users[0].channels(), &["#test2".to_owned()][..]);
+        assert!(users[1].is_away());
+        assert_eq!(users[2].get_account(), None);
+        assert!(!users[3].is_away());
+        assert_eq!(users[3].channels(), &["#test".to_owned(), "#test2".to_owned()][..]);
+        assert_eq!(users[4].channels(), &["#test".to_owned(), "#test2".to_owned()][..]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn channel_tracking_names() {
+        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(client.list_channels().unwrap(), vec!["#test".to_owned()])
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn channel_visibility() {
+        let value = ":irc.test.net 353 test @ #test :test ~owner &admin\r\n\
+                     :irc.test.net 353 test = #test2 :test\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(client.channel_visibility("#test"), Some(ChannelVisibility::Secret));
+        assert_eq!(client.channel_visibility("#test2"), Some(ChannelVisibility::Public));
+        assert_eq!(client.channel_visibility("#test3"), None);
+        assert_eq!(client.channel_visibility("#TEST"), Some(ChannelVisibility::Secret));
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn channel_visibility_forgotten_on_leaving() {
+        let server = MockServer::new(test_config()).unwrap();
+        server.push_incoming(":irc.test.net 353 test @ #Test :test\r\n\
+                              :irc.test.net 353 test = #test2 :test\r\n\
+                              :owner!test@test KICK #test2 test :Go away.\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        let client = server.client();
+        assert_eq!(client.channel_visibility("#test2"), None);
+        assert_eq!(client.channel_visibility("#test"), Some(ChannelVisibility::Secret));
+        client.send_part("#TEST").unwrap();
+        assert_eq!(client.channel_visibility("#test"), None);
+    }
+
+    #[test]
+    fn connection_state() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client();
+        assert_eq!(client.state(), ConnectionState::Connecting);
+        client.identify().unwrap();
+        assert_eq!(client.state(), ConnectionState::Registering);
+        server.push_incoming(":irc.test.net 001 test :Welcome to the Test IRC Network\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(client.state(), ConnectionState::Registered);
+        server.close();
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(client.state(), ConnectionState::Disconnected);
+    }
+
+    #[test]
+    fn state_change_callback() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client();
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let seen = transitions.clone();
+        client.on_state_change(move |old, new| seen.lock().unwrap().push((old, new)));
+        client.identify().unwrap();
+        server.push_incoming(":irc.test.net 001 test :Welcome to the Test IRC Network\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        server.close();
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(*transitions.lock().unwrap(), vec![
+            (ConnectionState::Connecting, ConnectionState::Registering),
+            (ConnectionState::Registering, ConnectionState::Registered),
+            (ConnectionState::Registered, ConnectionState::Disconnected),
+        ]);
+    }
+
+    #[test]
+    fn connect_async() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(socket);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            reader.get_mut().write_all(b":irc.test.net 001 test :Welcome\r\n").unwrap();
+            line
+        });
+
+        let config = Config {
+            server: Some("127.0.0.1".to_owned()),
+            port: Some(port),
+            use_mock_connection: None,
+            ..test_config()
+        };
+        let mut reactor = Core::new().unwrap();
+        let client = reactor.run(IrcClient::connect_async(&reactor.handle(), config)).unwrap();
+        client.identify().unwrap();
+        let (msg, _) = reactor.run(client.stream().into_future().map_err(|(e, _)| e)).unwrap();
+
+        assert_eq!(server.join().unwrap(), "CAP END\r\n");
+        assert_eq!(msg.unwrap().to_string(), ":irc.test.net 001 test :Welcome\r\n");
+        assert_eq!(client.state(), ConnectionState::Registered);
+    }
+
+    #[test]
+    fn send_join_zero() {
+        let client = IrcClient::from_config(test_config()).unwrap();
+        client.send_join("0").unwrap();
+        assert_eq!(&get_client_value(client)[..], "JOIN 0\r\n");
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn join_zero() {
+        let server = MockServer::new(test_config()).unwrap();
+        server.push_incoming(":irc.test.net 353 test = #test :test ada\r\n\
+                              :irc.test.net 353 test = #rust :test\r\n\
+                              :ada!ada@host JOIN 0\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        let mut chans = server.client().list_channels().unwrap();
+        chans.sort();
+        assert_eq!(chans, vec!["#rust".to_owned(), "#test".to_owned()]);
+        server.push_incoming(":test!test@host JOIN 0\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(server.client().list_channels(), Some(vec![]));
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn send_join_zero_leaves_channels() {
+        let server = MockServer::new(test_config()).unwrap();
+        server.push_incoming(":irc.test.net 353 test = #test :test ada\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        server.client().send_join("0").unwrap();
+        assert_eq!(server.take_outgoing(), vec!["JOIN 0".to_owned()]);
+        assert_eq!(server.client().list_channels(), Some(vec![]));
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn max_tracked_channels() {
+        let server = MockServer::new(Config {
+            max_tracked_channels: Some(1),
+            ..test_config()
+        }).unwrap();
+        let exceeded = Arc::new(Mutex::new(vec![]));
+        let seen = exceeded.clone();
+        server.client().on_tracking_limit(move |chan, limit| {
+            seen.lock().unwrap().push((chan.to_owned(), limit));
+        });
+        server.push_incoming(":irc.test.net 353 test = #a :test ada\r\n\
+                              :irc.test.net 353 test = #b :test ada\r\n\
+                              :ada!ada@host JOIN #b\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        let mut chans = server.client().list_channels().unwrap();
+        chans.sort();
+        assert_eq!(chans, vec!["#a".to_owned(), "#b".to_owned()]);
+        assert_eq!(
+            server.client().list_users("#a"),
+            Some(vec![User::new("test"), User::new("ada")])
+        );
+        assert_eq!(server.client().list_users("#b"), None);
+        assert_eq!(*exceeded.lock().unwrap(), vec![("#b".to_owned(), TrackingLimit::Channels)]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn max_tracked_users_per_channel() {
+        let server = MockServer::new(Config {
+            max_tracked_users_per_channel: Some(2),
+            ..test_config()
+        }).unwrap();
+        let exceeded = Arc::new(Mutex::new(vec![]));
+        let seen = exceeded.clone();
+        server.client().on_tracking_limit(move |chan, limit| {
+            seen.lock().unwrap().push((chan.to_owned(), limit));
+        });
+        server.push_incoming(":irc.test.net 353 test = #test :test ada\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(
+            server.client().list_users("#test"),
+            Some(vec![User::new("test"), User::new("ada")])
+        );
+        server.push_incoming(":grace!grace@host JOIN #test\r\n\
+                              :ada!ada@host PART #test\r\n\
+                              :alan!alan@host JOIN #test\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        // Once untracked, the channel stays untracked until it is left.
+        assert_eq!(server.client().list_users("#test"), None);
+        assert_eq!(
+            *exceeded.lock().unwrap(),
+            vec![("#test".to_owned(), TrackingLimit::UsersPerChannel)]
+        );
+        assert_eq!(server.client().list_channels(), Some(vec!["#test".to_owned()]));
+        server.client().send_part("#test").unwrap();
+        server.push_incoming(":irc.test.net 353 test = #test :test\r\n");
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(server.client().list_users("#test"), Some(vec![User::new("test")]));
+    }
+
+    #[test]
+    fn server_error() {
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some("ERROR :Closing Link: banned\r\n".to_owned()),
+            ..test_config()
+        }).unwrap();
+        let mut messages = vec![];
+        match client.for_each_incoming(|message| messages.push(message.command)) {
+            Err(IrcError::ServerError { ref reason }) if reason == "Closing Link: banned" => (),
+            res => panic!("expected the server error to be surfaced, got {:?}", res),
+        }
+        // The ERROR is still received as a message first.
+        assert_eq!(messages, vec![Command::ERROR("Closing Link: banned".to_owned())]);
+        assert_eq!(client.state(), ConnectionState::Disconnected);
+    }
+
+    #[test]
+    fn server_error_after_quit() {
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some("ERROR :Closing Link: (Quit: bye)\r\n".to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.send_quit("bye").unwrap();
+        client.for_each_incoming(|_| ()).unwrap();
+    }
+
+    #[test]
+    fn connection_state_after_quit() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client();
+        client.identify().unwrap();
+        client.send_quit("bye").unwrap();
+        assert_eq!(client.state(), ConnectionState::Closed);
+        // The connection ending afterwards is expected.
+        server.close();
+        server.process_incoming(|_| ()).unwrap();
+        assert_eq!(client.state(), ConnectionState::Closed);
+    }
+
+    #[test]
+    fn iter_reconnecting() {
+        let value = ":test!test@test PRIVMSG #test :Hi!\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        let mut events = client.iter_reconnecting();
+        let message = ClientEvent::Message(value.parse().unwrap());
+        assert_eq!(events.next().unwrap().unwrap(), message);
+        // The mock connection ends once its initial value is read.
+        assert_eq!(events.next().unwrap().unwrap(), ClientEvent::Reconnected);
+        assert_eq!(events.client().state(), ConnectionState::Registering);
+        assert_eq!(events.next().unwrap().unwrap(), message);
+        // The new client identifies itself upon reconnecting.
+        assert_eq!(
+            &get_client_value(events.client)[..],
+            "CAP END\r\nNICK :test\r\nUSER test 0 * :test\r\n"
+        );
+    }
+
+    #[test]
+    fn iter_reconnecting_backs_off() {
         let client = IrcClient::from_config(Config {
-            mock_initial_value: Some(exp.to_owned()),
+            reconnect_delay_ms: Some(300),
             ..test_config()
         }).unwrap();
-        let mut messages = String::new();
-        client.for_each_incoming(|message| {
-            messages.push_str(&message.to_string());
-        }).unwrap();
-        assert_eq!(&messages[..], exp);
+        let mut events = client.iter_reconnecting();
+        // The mock connection ends immediately without registering, so only the first reconnect
+        // happens right away.
+        let start = Instant::now();
+        assert_eq!(events.next().unwrap().unwrap(), ClientEvent::Reconnected);
+        assert!(start.elapsed() < Duration::from_millis(150));
+        let start = Instant::now();
+        assert_eq!(events.next().unwrap().unwrap(), ClientEvent::Reconnected);
+        assert!(start.elapsed() >= Duration::from_millis(150));
     }
 
     #[test]
-    fn handle_message() {
-        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+    fn reconnect_delay_backoff() {
+        assert_eq!(reconnect_delay(1000, 60_000, 0, 0), Duration::from_millis(0));
+        assert_eq!(reconnect_delay(1000, 60_000, 1, 0), Duration::from_millis(1000));
+        assert_eq!(reconnect_delay(1000, 60_000, 3, 0), Duration::from_millis(4000));
+        assert_eq!(reconnect_delay(1000, 60_000, 10, 0), Duration::from_millis(60_000));
+        assert_eq!(reconnect_delay(1000, 60_000, 100, 0), Duration::from_millis(60_000));
+        // The jitter takes off at most half of the delay.
+        assert_eq!(reconnect_delay(1000, 60_000, 3, 2000), Duration::from_millis(2000));
+        assert_eq!(reconnect_delay(1000, 60_000, 3, 2001), Duration::from_millis(4000));
+        assert_eq!(reconnect_delay(1000, 60_000, 3, 7), Duration::from_millis(3993));
+    }
+
+    #[test]
+    fn iter_reconnecting_sts_upgrade() {
+        let plaintext = TcpListener::bind("127.0.0.1:0").unwrap();
+        let plaintext_port = plaintext.local_addr().unwrap().port();
+        let tls = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tls_port = tls.local_addr().unwrap().port();
+        let policy = format!(":irc.test.net CAP * LS :sts=port={}\r\n", tls_port);
+        let _ = thread::spawn(move || {
+            let (mut socket, _) = plaintext.accept().unwrap();
+            socket.write_all(policy.as_bytes()).unwrap();
+        });
+        let server = thread::spawn(move || {
+            let (socket, _) = tls.accept().unwrap();
+            let mut p12 = vec![];
+            File::open("tests/fixtures/localhost.p12").unwrap().read_to_end(&mut p12).unwrap();
+            let acceptor = TlsAcceptor::new(Identity::from_pkcs12(&p12, "password").unwrap());
+            let mut tls = BufReader::new(acceptor.unwrap().accept(socket).unwrap());
+            tls.get_mut().write_all(b":irc.test.net CAP * LS :sts=duration=300\r\n").unwrap();
+            let mut line = String::new();
+            tls.read_line(&mut line).unwrap();
+            line
+        });
+
+        let path = env::temp_dir().join(format!("irc-sts-reconnect-test-{}", process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let config = Config {
+            server: Some("127.0.0.1".to_owned()),
+            port: Some(plaintext_port),
+            cert_path: Some("tests/fixtures/localhost.der".to_owned()),
+            sts_cache: Some(path.clone()),
+            use_mock_connection: None,
+            ..test_config()
+        };
+        let mut events = IrcClient::from_config(config.clone()).unwrap().iter_reconnecting();
+        match events.next().unwrap() {
+            Err(IrcError::StsUpgrade { port }) => assert_eq!(port, tls_port),
+            res => panic!("expected StsUpgrade, got {:?}", res),
+        }
+        assert_eq!(events.next().unwrap().unwrap(), ClientEvent::Reconnected);
+        assert!(events.client().config().use_ssl());
+        assert_eq!(events.client().config().port(), tls_port);
+        events.next().unwrap().unwrap();
+        // The new client identifies itself over TLS, and the policy is then persisted.
+        assert_eq!(server.join().unwrap(), "CAP END\r\n");
+        assert_eq!(sts::upgrade_port(&config), Some(tls_port));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn iter_reconnecting_rejoins() {
+        let value = ":irc.test.net 376 test :End of /MOTD command\r\n\
+                     :test!test@test JOIN #keyed\r\n\
+                     :irc.test.net 353 test = #keyed :test\r\n";
         let client = IrcClient::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
         }).unwrap();
-        client.for_each_incoming(|message| {
-            println!("{:?}", message);
-        }).unwrap();
+        client.send_join_with_keys::<&str, &str>("#keyed", "secret").unwrap();
+        let mut events = client.iter_reconnecting();
+        for _ in 0..3 {
+            events.next().unwrap().unwrap();
+        }
+        assert_eq!(events.next().unwrap().unwrap(), ClientEvent::Reconnected);
+        // The end of the MOTD on the new connection rejoins the channel joined at runtime.
+        events.next().unwrap().unwrap();
         assert_eq!(
-            &get_client_value(client)[..],
-            "JOIN #test\r\nJOIN #test2\r\n"
+            &get_client_value(events.client)[..],
+            "CAP END\r\nNICK :test\r\nUSER test 0 * :test\r\n\
+             JOIN #test\r\nJOIN #test2\r\nJOIN #keyed secret\r\n"
         );
     }
 
     #[test]
-    fn handle_end_motd_with_nick_password() {
-        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+    fn chat_history() {
+        let value = ":irc.test.net BATCH +hist chathistory #test\r\n\
+                     @batch=hist;msgid=1 :ada!ada@host PRIVMSG #test :first\r\n\
+                     @batch=hist;msgid=2 :bob!bob@host PRIVMSG #test :second\r\n\
+                     :irc.test.net BATCH -hist\r\n\
+                     :irc.test.net BATCH +open chathistory #other\r\n\
+                     @batch=open :ada!ada@host PRIVMSG #other :unfinished\r\n";
         let client = IrcClient::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
-            nick_password: Some(format!("password")),
-            channels: Some(vec![format!("#test"), format!("#test2")]),
             ..test_config()
         }).unwrap();
         client.for_each_incoming(|message| {
             println!("{:?}", message);
         }).unwrap();
-        assert_eq!(
-            &get_client_value(client)[..],
-            "NICKSERV IDENTIFY password\r\nJOIN #test\r\n\
-                   JOIN #test2\r\n"
-        );
+        let history = client.chat_history("#TEST").unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].source_nickname(), Some("ada"));
+        assert_eq!(history[0].command, PRIVMSG("#test".to_owned(), "first".to_owned()));
+        assert_eq!(history[1].command, PRIVMSG("#test".to_owned(), "second".to_owned()));
+        assert_eq!(client.chat_history("#other"), None);
     }
 
     #[test]
-    fn handle_end_motd_with_chan_keys() {
-        let value = ":irc.test.net 376 test :End of /MOTD command\r\n";
+    #[cfg(not(feature = "nochanlists"))]
+    fn channel_tracking_names_part() {
+        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
         let client = IrcClient::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
-            nickname: Some(format!("test")),
-            channels: Some(vec![format!("#test"), format!("#test2")]),
-            channel_keys: {
-                let mut map = HashMap::new();
-                map.insert(format!("#test2"), format!("password"));
-                Some(map)
-            },
             ..test_config()
         }).unwrap();
         client.for_each_incoming(|message| {
             println!("{:?}", message);
         }).unwrap();
-        assert_eq!(
-            &get_client_value(client)[..],
-            "JOIN #test\r\nJOIN #test2 password\r\n"
-        );
+        assert!(client.send(PART(format!("#test"), None)).is_ok());
+        assert!(client.list_channels().unwrap().is_empty())
     }
 
     #[test]
-    fn handle_end_motd_with_ghost() {
-        let value = ":irc.pdgn.co 433 * test :Nickname is already in use.\r\n\
-                     :irc.test.net 376 test2 :End of /MOTD command.\r\n";
+    #[cfg(not(feature = "nochanlists"))]
+    fn user_tracking_names() {
+        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
         let client = IrcClient::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
-            nickname: Some(format!("test")),
-            alt_nicks: Some(vec![format!("test2")]),
-            nick_password: Some(format!("password")),
-            channels: Some(vec![format!("#test"), format!("#test2")]),
-            should_ghost: Some(true),
             ..test_config()
         }).unwrap();
         client.for_each_incoming(|message| {
             println!("{:?}", message);
         }).unwrap();
         assert_eq!(
-            &get_client_value(client)[..],
-            "NICK :test2\r\nNICKSERV GHOST test password\r\n\
-                   NICK :test\r\nNICKSERV IDENTIFY password\r\nJOIN #test\r\nJOIN #test2\r\n"
-        );
+            client.list_users("#test").unwrap(),
+            vec![User::new("test"), User::new("~owner"), User::new("&admin")]
+        )
     }
 
     #[test]
-    fn handle_end_motd_with_ghost_seq() {
-        let value = ":irc.pdgn.co 433 * test :Nickname is already in use.\r\n\
-                     :irc.test.net 376 test2 :End of /MOTD command.\r\n";
+    fn last_activity() {
         let client = IrcClient::from_config(Config {
-            mock_initial_value: Some(value.to_owned()),
-            nickname: Some(format!("test")),
-            alt_nicks: Some(vec![format!("test2")]),
-            nick_password: Some(format!("password")),
-            channels: Some(vec![format!("#test"), format!("#test2")]),
-            should_ghost: Some(true),
-            ghost_sequence: Some(vec![format!("RECOVER"), format!("RELEASE")]),
+            mock_initial_value: Some(":irc.test.net NOTICE test :Hello!\r\n".to_owned()),
             ..test_config()
         }).unwrap();
+        let created = client.last_activity();
+        thread::sleep(Duration::from_millis(10));
         client.for_each_incoming(|message| {
             println!("{:?}", message);
         }).unwrap();
-        assert_eq!(
-            &get_client_value(client)[..],
-            "NICK :test2\r\nNICKSERV RECOVER test password\
-                   \r\nNICKSERV RELEASE test password\r\nNICK :test\r\nNICKSERV IDENTIFY password\
-                   \r\nJOIN #test\r\nJOIN #test2\r\n"
-        );
+        assert!(client.last_activity() > created);
     }
 
     #[test]
-    fn handle_end_motd_with_umodes() {
-        let value = ":irc.test.net 376 test :End of /MOTD command.\r\n";
+    fn pending_outbound() {
         let client = IrcClient::from_config(Config {
-            mock_initial_value: Some(value.to_owned()),
-            nickname: Some(format!("test")),
-            umodes: Some(format!("+B")),
-            channels: Some(vec![format!("#test"), format!("#test2")]),
+            mock_initial_value: Some(String::new()),
+            max_messages_in_burst: Some(2),
+            burst_window_length: Some(60),
             ..test_config()
         }).unwrap();
-        client.for_each_incoming(|message| {
-            println!("{:?}", message);
-        }).unwrap();
-        assert_eq!(
-            &get_client_value(client)[..],
-            "MODE test +B\r\nJOIN #test\r\nJOIN #test2\r\n"
-        );
+        for i in 0..5 {
+            client.send(PRIVMSG("#test".to_owned(), format!("{}", i))).unwrap();
+        }
+        let start = Instant::now();
+        while client.pending_outbound() > 3 && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(client.pending_outbound(), 3);
     }
 
     #[test]
-    fn nickname_in_use() {
-        let value = ":irc.pdgn.co 433 * test :Nickname is already in use.\r\n";
+    fn metrics() {
+        let lines = [
+            ":irc.test.net NOTICE test :Hello!\r\n",
+            "@time=2019-01-01T00:00:00.000Z :irc.test.net NOTICE test :Bye!\r\n",
+        ];
         let client = IrcClient::from_config(Config {
-            mock_initial_value: Some(value.to_owned()),
+            mock_initial_value: Some(lines.concat()),
+            max_messages_in_burst: Some(2),
+            burst_window_length: Some(60),
             ..test_config()
         }).unwrap();
         client.for_each_incoming(|message| {
             println!("{:?}", message);
         }).unwrap();
-        assert_eq!(&get_client_value(client)[..], "NICK :test2\r\n");
+        for i in 0..3 {
+            client.send_privmsg("#test", i).unwrap();
+        }
+        // The third message is only held back once the sink gets to it after the first two.
+        let start = Instant::now();
+        while client.metrics().rate_limit_deferrals < 1 &&
+            start.elapsed() < Duration::from_secs(5)
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(client.metrics(), ConnectionMetrics {
+            messages_sent: 2,
+            messages_received: 2,
+            bytes_sent: 2 * "PRIVMSG #test :0\r\n".len(),
+            bytes_received: lines[0].len() + lines[1].len(),
+            reconnects: 0,
+            rate_limit_deferrals: 1,
+        });
     }
 
     #[test]
-    fn ran_out_of_nicknames() {
-        let value = ":irc.pdgn.co 433 * test :Nickname is already in use.\r\n\
-                     :irc.pdgn.co 433 * test2 :Nickname is already in use.\r\n";
+    fn dedupe_window() {
         let client = IrcClient::from_config(Config {
-            mock_initial_value: Some(value.to_owned()),
+            dedupe_window_ms: Some(60000),
             ..test_config()
         }).unwrap();
-        let res = client.for_each_incoming(|message| {
-            println!("{:?}", message);
-        });
-
-        if let Err(IrcError::NoUsableNick) = res {
-            ()
-        } else {
-            panic!("expected error when no valid nicks were specified")
-        }
+        client.send_privmsg("#test", "hi").unwrap();
+        client.send_privmsg("#TEST", "hi").unwrap();
+        client.send_privmsg("#test", "hi again").unwrap();
+        client.send_privmsg("#test2", "hi").unwrap();
+        assert_eq!(client.suppressed_duplicates(), 1);
+        assert_eq!(
+            &get_client_value(client)[..],
+            "PRIVMSG #test :hi\r\nPRIVMSG #test :hi again\r\nPRIVMSG #test2 :hi\r\n"
+        );
     }
 
     #[test]
-    fn send() {
-        let client = IrcClient::from_config(test_config()).unwrap();
-        assert!(
-            client
-                .send(PRIVMSG(format!("#test"), format!("Hi there!")))
-                .is_ok()
-        );
+    fn auto_away() {
+        let client = IrcClient::from_config(Config {
+            auto_away_after_ms: Some(60000),
+            auto_away_message: Some("Gone fishing".to_owned()),
+            ..test_config()
+        }).unwrap();
+        let start = Instant::now();
+        client.state.update_auto_away(start + Duration::from_secs(30)).unwrap();
+        client.state.update_auto_away(start + Duration::from_secs(61)).unwrap();
+        client.state.update_auto_away(start + Duration::from_secs(120)).unwrap();
+        client.send_privmsg("#test", "back").unwrap();
+        client.send_privmsg("#test", "still here").unwrap();
         assert_eq!(
             &get_client_value(client)[..],
-            "PRIVMSG #test :Hi there!\r\n"
+            "AWAY :Gone fishing\r\nAWAY\r\nPRIVMSG #test :back\r\n\
+             PRIVMSG #test :still here\r\n"
         );
     }
 
     #[test]
-    fn send_no_newline_injection() {
-        let client = IrcClient::from_config(test_config()).unwrap();
-        assert!(
-            client
-                .send(PRIVMSG(format!("#test"), format!("Hi there!\r\nJOIN #bad")))
-                .is_ok()
-        );
-        assert_eq!(&get_client_value(client)[..], "PRIVMSG #test :Hi there!\r\n");
+    fn auto_away_keeps_explicit_away() {
+        let client = IrcClient::from_config(Config {
+            auto_away_after_ms: Some(60000),
+            ..test_config()
+        }).unwrap();
+        client.send(Command::AWAY(Some("Lunch".to_owned()))).unwrap();
+        let later = Instant::now() + Duration::from_secs(120);
+        client.state.update_auto_away(later).unwrap();
+        client.send_privmsg("#test", "hi").unwrap();
+        assert_eq!(&get_client_value(client)[..], "AWAY :Lunch\r\nPRIVMSG #test :hi\r\n");
     }
 
     #[test]
-    fn send_raw_is_really_raw() {
+    fn whois_throttled() {
         let client = IrcClient::from_config(test_config()).unwrap();
-        assert!(
-            client.send(Raw("PASS".to_owned(), vec!["password".to_owned()], None)).is_ok()
-        );
-        assert!(
-            client.send(Raw("NICK".to_owned(), vec!["test".to_owned()], None)).is_ok()
-        );
-        assert_eq!(&get_client_value(client)[..], "PASS password\r\nNICK test\r\n");
+        assert!(client.whois_throttled("ada").unwrap());
+        assert!(!client.whois_throttled("ADA").unwrap());
+        assert!(client.whois_throttled("betsy").unwrap());
+        assert_eq!(&get_client_value(client)[..], "WHOIS ada\r\nWHOIS betsy\r\n");
+    }
+
+    #[test]
+    fn whois_throttle_expires() {
+        let client = IrcClient::from_config(Config {
+            whois_throttle_secs: Some(0),
+            ..test_config()
+        }).unwrap();
+        assert!(client.whois_throttled("ada").unwrap());
+        assert!(client.whois_throttled("ada").unwrap());
+        assert_eq!(&get_client_value(client)[..], "WHOIS ada\r\nWHOIS ada\r\n");
+    }
+
+    #[test]
+    fn who_on_join() {
+        let value = ":test!test@test JOIN #test\r\n\
+                     :test2!test@test JOIN #test\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            who_on_join: Some(true),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(&get_client_value(client)[..], "WHO #test\r\n");
+    }
+
+    #[test]
+    fn no_who_on_join_by_default() {
+        let value = ":test!test@test JOIN #test\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(&get_client_value(client)[..], "");
     }
 
     #[test]
     #[cfg(not(feature = "nochanlists"))]
-    fn channel_tracking_names() {
-        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
+    fn who_on_join_whox() {
+        let value = ":irc.test.net 005 test WHOX :are supported by this server\r\n\
+                     :test!test@test JOIN #test\r\n\
+                     :irc.test.net 353 test = #test :test ada\r\n\
+                     :irc.test.net 354 test 152 #test ~ada ada.host ada G adaacct\r\n\
+                     :irc.test.net 354 test 152 #test ~test test.host test H 0\r\n";
         let client = IrcClient::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
+            who_on_join: Some(true),
             ..test_config()
         }).unwrap();
         client.for_each_incoming(|message| {
             println!("{:?}", message);
         }).unwrap();
-        assert_eq!(client.list_channels().unwrap(), vec!["#test".to_owned()])
+        let users = client.list_users("#test").unwrap();
+        assert_eq!(users[0].get_hostname(), Some("test.host"));
+        assert_eq!(users[0].get_account(), None);
+        assert!(!users[0].is_away());
+        assert_eq!(users[1].get_username(), Some("~ada"));
+        assert_eq!(users[1].get_hostname(), Some("ada.host"));
+        assert_eq!(users[1].get_account(), Some("adaacct"));
+        assert!(users[1].is_away());
+        assert_eq!(&get_client_value(client)[..], "WHO #test %tcuhnfa,152\r\n");
     }
 
     #[test]
     #[cfg(not(feature = "nochanlists"))]
-    fn channel_tracking_names_part() {
-        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
+    fn account_notify() {
+        let value = ":irc.test.net 353 test = #test :test ada\r\n\
+                     :ada!ada@test ACCOUNT adaacct\r\n";
         let client = IrcClient::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
@@ -1108,14 +5682,17 @@ mod test {
         client.for_each_incoming(|message| {
             println!("{:?}", message);
         }).unwrap();
-        assert!(client.send(PART(format!("#test"), None)).is_ok());
-        assert!(client.list_channels().unwrap().is_empty())
+        let users = client.list_users("#test").unwrap();
+        assert_eq!(users[1].get_account(), Some("adaacct"));
+        assert_eq!(users[0].get_account(), None);
     }
 
     #[test]
     #[cfg(not(feature = "nochanlists"))]
-    fn user_tracking_names() {
-        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n";
+    fn account_notify_logout() {
+        let value = ":irc.test.net 353 test = #test :test\r\n\
+                     :ada!ada@test JOIN #test adaacct :Ada\r\n\
+                     :ada!ada@test ACCOUNT *\r\n";
         let client = IrcClient::from_config(Config {
             mock_initial_value: Some(value.to_owned()),
             ..test_config()
@@ -1123,10 +5700,9 @@ mod test {
         client.for_each_incoming(|message| {
             println!("{:?}", message);
         }).unwrap();
-        assert_eq!(
-            client.list_users("#test").unwrap(),
-            vec![User::new("test"), User::new("~owner"), User::new("&admin")]
-        )
+        let users = client.list_users("#test").unwrap();
+        assert_eq!(users[1].get_nickname(), "ada");
+        assert_eq!(users[1].get_account(), None);
     }
 
     #[test]
@@ -1191,6 +5767,47 @@ mod test {
         )
     }
 
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn user_tracking_quit_all_channels() {
+        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n\
+                     :irc.test.net 353 test = #test2 :test +admin\r\n\
+                     :Admin!test@test QUIT :Leaving.\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        assert_eq!(
+            client.list_users("#test").unwrap(),
+            vec![User::new("test"), User::new("~owner")]
+        );
+        assert_eq!(client.list_users("#test2").unwrap(), vec![User::new("test")]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "nochanlists"))]
+    fn user_tracking_nick_all_channels() {
+        let value = ":irc.test.net 353 test = #test :test ~owner &admin\r\n\
+                     :irc.test.net 353 test = #test2 :test +admin\r\n\
+                     :ADMIN!test@test NICK :root\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.for_each_incoming(|message| {
+            println!("{:?}", message);
+        }).unwrap();
+        let users = client.list_users("#test").unwrap();
+        assert_eq!(users, vec![User::new("test"), User::new("~owner"), User::new("&root")]);
+        assert_eq!(users[2].highest_access_level(), AccessLevel::Admin);
+        let users = client.list_users("#test2").unwrap();
+        assert_eq!(users, vec![User::new("test"), User::new("+root")]);
+        assert_eq!(users[1].highest_access_level(), AccessLevel::Voice);
+    }
+
     #[test]
     #[cfg(not(feature = "nochanlists"))]
     fn user_tracking_names_mode() {
@@ -1355,6 +5972,49 @@ mod test {
         );
     }
 
+    #[test]
+    fn custom_ctcp() {
+        let value = ":test!test@test PRIVMSG test :\u{001}WEATHER London\u{001}\r\n\
+                     :test!test@test PRIVMSG test :\u{001}weather\u{001}\r\n\
+                     :test!test@test PRIVMSG test :\u{001}VERSION\u{001}\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        client.on_ctcp("weather", |place| match place {
+            "" => None,
+            place => Some(format!("It is sunny in {}.", place)),
+        });
+        client.on_ctcp("VERSION", |_| Some("custom".to_owned()));
+        client.for_each_incoming(|_| ()).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NOTICE test :\u{001}WEATHER It is sunny in London.\u{001}\r\n\
+             NOTICE test :\u{001}VERSION custom\u{001}\r\n"
+        );
+    }
+
+    #[test]
+    fn custom_ctcp_can_use_client() {
+        let value = ":test!test@test PRIVMSG test :\u{001}COUNT\u{001}\r\n\
+                     :test!test@test PRIVMSG test :\u{001}COUNT\u{001}\r\n";
+        let client = IrcClient::from_config(Config {
+            mock_initial_value: Some(value.to_owned()),
+            ..test_config()
+        }).unwrap();
+        let inner = client.clone();
+        client.on_ctcp("COUNT", move |_| {
+            // Replacing the handler from within it must not deadlock.
+            inner.on_ctcp("COUNT", |_| Some("2".to_owned()));
+            Some("1".to_owned())
+        });
+        client.for_each_incoming(|_| ()).unwrap();
+        assert_eq!(
+            &get_client_value(client)[..],
+            "NOTICE test :\u{001}COUNT 1\u{001}\r\nNOTICE test :\u{001}COUNT 2\u{001}\r\n"
+        );
+    }
+
     #[test]
     #[cfg(feature = "ctcp")]
     fn ctcp_ping_no_timestamp() {
@@ -24,8 +24,9 @@
 pub use client::data::Config;
 pub use client::reactor::IrcReactor;
 pub use client::{EachIncomingExt, IrcClient, Client};
-pub use client::ext::ClientExt;
+pub use client::ext::{ClientExt, TypingState};
 pub use proto::{Capability, ChannelExt, Command, Message, NegotiationVersion, Response};
+pub use proto::{ChannelJoinError, ChannelVisibility, ValuedCapability};
 pub use proto::{ChannelMode, Mode, UserMode};
 
 pub use futures::{Future, Stream};
@@ -0,0 +1,78 @@
+//! Forwarding of messages between connections, e.g. for building bouncers.
+use client::{Client, IrcClient};
+use error;
+use proto::Message;
+
+/// Blocks on the stream of incoming messages of one client, forwarding each of them through
+/// another client until the first connection ends. Each message is passed through the given
+/// filter first, which can transform it or return `None` to drop it. Messages are forwarded as
+/// is otherwise, including their tags and prefix.
+///
+/// Forwarded messages are queued on the other client like any other message, so each side is
+/// rate limited independently according to its own configuration. As this blocks, it is usually
+/// run on its own thread, once for each direction.
+///
+/// **Note**: This takes the stream of the first client, and so will panic if it was already
+/// taken.
+///
+/// # Example
+/// ```no_run
+/// # extern crate irc;
+/// # use std::thread;
+/// # use irc::client::prelude::*;
+/// use irc::client::relay;
+///
+/// # fn main() {
+/// let upstream = IrcClient::new("upstream.toml").unwrap();
+/// let downstream = IrcClient::new("downstream.toml").unwrap();
+/// let (from, to) = (upstream.clone(), downstream.clone());
+/// thread::spawn(move || {
+///     // Only chatter is relayed.
+///     relay(&from, &to, |message| match message.command {
+///         Command::PRIVMSG(..) | Command::NOTICE(..) => Some(message),
+///         _ => None,
+///     })
+/// });
+/// # }
+/// ```
+pub fn relay<F>(from: &IrcClient, to: &IrcClient, mut filter: F) -> error::Result<()>
+where
+    F: FnMut(Message) -> Option<Message>,
+{
+    let mut result = Ok(());
+    from.for_each_incoming(|message| {
+        if result.is_err() {
+            return;
+        }
+        if let Some(message) = filter(message) {
+            result = to.send(message);
+        }
+    })?;
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::relay;
+    use client::test::test_config;
+    use client::test_util::MockServer;
+    use proto::Command;
+
+    #[test]
+    fn relay_with_tags() {
+        let from = MockServer::new(test_config()).unwrap();
+        let to = MockServer::new(test_config()).unwrap();
+        from.push_incoming("@+draft/reply=abc;time=2019-01-01T00:00:00.000Z \
+                            :ada!ada@host PRIVMSG #test :hi there\r\n");
+        from.push_incoming(":irc.test.net PING :irc.test.net\r\n");
+        from.close();
+        relay(from.client(), to.client(), |message| match message.command {
+            Command::PRIVMSG(..) => Some(message),
+            _ => None,
+        }).unwrap();
+        assert_eq!(to.take_outgoing(), vec![
+            "@+draft/reply=abc;time=2019-01-01T00:00:00.000Z \
+             :ada!ada@host PRIVMSG #test :hi there".to_owned(),
+        ]);
+    }
+}
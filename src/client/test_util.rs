@@ -0,0 +1,510 @@
+//! Utilities for unit-testing code built on top of an `IrcClient` without a real server.
+//!
+//! A [`MockServer`](struct.MockServer.html) provides an `IrcClient` backed by an in-memory
+//! [`MockTransport`](struct.MockTransport.html). Tests can script inbound lines from the server
+//! with `push_incoming` and inspect what the client sent back with `take_outgoing`.
+//!
+//! # Example
+//!
+//! ```
+//! # extern crate irc;
+//! # use std::default::Default;
+//! use irc::client::prelude::*;
+//! use irc::client::test_util::MockServer;
+//!
+//! // The handler under test replies to anyone who says hello.
+//! fn handle(client: &IrcClient, message: Message) {
+//!     let target = message.response_target().map(|s| s.to_owned());
+//!     if let (Command::PRIVMSG(_, ref text), Some(target)) = (&message.command, target) {
+//!         if text == "hello" {
+//!             client.send_privmsg(&target, "hi there").unwrap();
+//!         }
+//!     }
+//! }
+//!
+//! # fn main() {
+//! let server = MockServer::new(Config {
+//!     nickname: Some("bot".to_owned()),
+//!     ..Default::default()
+//! }).unwrap();
+//! server.push_incoming(":friend!f@host PRIVMSG #chan :hello\r\n");
+//! server.push_incoming(":friend!f@host PRIVMSG #chan :goodbye\r\n");
+//! server.close();
+//!
+//! let client = server.client();
+//! client.for_each_incoming(|message| handle(client, message)).unwrap();
+//! assert_eq!(server.take_outgoing(), vec!["PRIVMSG #chan :hi there".to_owned()]);
+//! # }
+//! ```
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use bytes::BytesMut;
+use futures::{Async, Poll, Stream};
+use futures::executor::{self, Notify, NotifyHandle, Spawn};
+use futures::task::{self, Task};
+use tokio_codec::{Decoder, Encoder};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use error;
+use client::{Backlog, Client, ClientState, ClientStream, IrcClient};
+use client::conn::{Connection, LastServer};
+use client::data::Config;
+use client::transport::IrcTransport;
+use proto::{IrcCodec, Message};
+use proto::line::LineCodec;
+
+/// An in-memory, bidirectional byte stream standing in for a connection to an IRC server.
+///
+/// Reads are served from bytes pushed with `push_incoming`, and block (without blocking the
+/// thread) until more bytes arrive or the transport is closed. Writes are buffered until they are
+/// taken with `take_outgoing`. Clones share the same underlying buffers.
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    shared: Arc<Mutex<Shared>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    closed: bool,
+    reader: Option<Task>,
+}
+
+impl fmt::Debug for MockTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MockTransport(...)")
+    }
+}
+
+impl MockTransport {
+    /// Creates a new, empty `MockTransport`.
+    pub fn new() -> MockTransport {
+        MockTransport::default()
+    }
+
+    /// Makes the given bytes available to be read from the transport.
+    pub fn push_incoming(&self, data: &[u8]) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.incoming.extend_from_slice(data);
+        if let Some(task) = shared.reader.take() {
+            task.notify();
+        }
+    }
+
+    /// Closes the incoming half of the transport, so that reads end once the pushed bytes have
+    /// been consumed.
+    pub fn close(&self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.closed = true;
+        if let Some(task) = shared.reader.take() {
+            task.notify();
+        }
+    }
+
+    /// Takes all the bytes written to the transport so far.
+    pub fn take_outgoing(&self) -> Vec<u8> {
+        let mut shared = self.shared.lock().unwrap();
+        ::std::mem::replace(&mut shared.outgoing, Vec::new())
+    }
+}
+
+impl Read for MockTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.incoming.is_empty() {
+            if shared.closed {
+                return Ok(0);
+            }
+            shared.reader = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let len = buf.len().min(shared.incoming.len());
+        buf[..len].copy_from_slice(&shared.incoming[..len]);
+        shared.incoming.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for MockTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.shared.lock().unwrap().outgoing.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for MockTransport {}
+
+impl AsyncWrite for MockTransport {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// A scripted IRC server for unit-testing handlers built on an `IrcClient`.
+///
+/// Messages sent by the client are collected synchronously by `take_outgoing`, so tests do not
+/// need to wait for a background thread to write them. Incoming messages are only processed when
+/// the client's stream is driven, either step-by-step with `process_incoming` or with
+/// `for_each_incoming` after calling `close`.
+pub struct MockServer {
+    client: IrcClient,
+    transport: MockTransport,
+    codec: Mutex<IrcCodec>,
+    incoming: Mutex<Option<Spawn<ClientStream>>>,
+    encoding: String,
+}
+
+impl fmt::Debug for MockServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MockServer({:?}, ...)", self.client)
+    }
+}
+
+impl MockServer {
+    /// Creates a new `MockServer` along with a client connected to it using the given
+    /// configuration. No network connection is made, so the server settings are ignored.
+    pub fn new(config: Config) -> error::Result<MockServer> {
+        let transport = MockTransport::new();
+        let framed = Connection::codec(&config)?.framed(transport.clone());
+        let conn = Connection::Scripted(IrcTransport::new(&config, framed));
+        let metrics = conn.metrics();
+        let (_, stream) = conn.split();
+        // Other threads may wait for room in the backlog while it is flushed by this one.
+        let backlog = Arc::new(Backlog::new(true));
+
+        Ok(MockServer {
+            codec: Mutex::new(IrcCodec::new(config.encoding())?),
+            encoding: config.encoding().to_owned(),
+            client: IrcClient {
+                state: Arc::new(ClientState::new(
+                    stream, backlog, metrics, LastServer::default(), config,
+                )),
+                view: None,
+            },
+            transport: transport,
+            incoming: Mutex::new(None),
+        })
+    }
+
+    /// Gets the client connected to this server.
+    pub fn client(&self) -> &IrcClient {
+        &self.client
+    }
+
+    /// Gets the transport underlying the client's connection.
+    pub fn transport(&self) -> &MockTransport {
+        &self.transport
+    }
+
+    /// Sends the given raw data from the server to the client. Lines should end with `\r\n`.
+    pub fn push_incoming(&self, data: &str) {
+        self.transport.push_incoming(data.as_bytes());
+    }
+
+    /// Closes the connection from the server's side. The client's stream will end once all the
+    /// incoming data has been processed.
+    pub fn close(&self) {
+        self.transport.close();
+    }
+
+    /// Processes all the incoming data pushed so far on the current thread, running the given
+    /// function on each message after the client has handled it. Unlike `for_each_incoming`, this
+    /// returns as soon as no more complete messages are available, so the server need not be
+    /// closed first. This takes the client's stream, so the two cannot be mixed.
+    pub fn process_incoming<F>(&self, mut f: F) -> error::Result<()>
+    where
+        F: FnMut(Message),
+    {
+        let mut incoming = self.incoming.lock().unwrap();
+        if incoming.is_none() {
+            *incoming = Some(executor::spawn(self.client.stream()));
+        }
+        let stream = incoming.as_mut().unwrap();
+        let notify = NotifyHandle::from(&NOOP_NOTIFY);
+        self.flush_outgoing();
+        while let Async::Ready(Some(msg)) = stream.poll_stream_notify(&notify, 0)? {
+            f(msg);
+            // Flush after each message so that replies stay in order with automatic responses.
+            self.flush_outgoing();
+        }
+        Ok(())
+    }
+
+    /// Takes every line sent by the client so far, in order and without line terminators. This
+    /// includes automatic replies made by the client (such as `PONG`s) as well as messages sent
+    /// through the `Client` API.
+    pub fn take_outgoing(&self) -> Vec<String> {
+        self.flush_outgoing();
+        let mut data = BytesMut::from(self.transport.take_outgoing());
+        let mut lines = LineCodec::new(&self.encoding).expect("unknown codec");
+        let mut res = vec![];
+        while let Ok(Some(line)) = lines.decode(&mut data) {
+            res.push(line.trim_end_matches(|c| c == '\r' || c == '\n').to_owned());
+        }
+        res
+    }
+
+    /// Writes anything sent through the client into the transport's outgoing buffer.
+    fn flush_outgoing(&self) {
+        let mut buf = BytesMut::new();
+        {
+            let backlog = &self.client.state.backlog;
+            let mut codec = self.codec.lock().unwrap();
+            while let Some(msg) = backlog.pop() {
+                codec.encode(msg, &mut buf).expect("failed to encode outgoing message");
+                backlog.sent();
+            }
+        }
+        if !buf.is_empty() {
+            let mut transport = self.transport.clone();
+            transport.write_all(&buf).unwrap();
+        }
+    }
+}
+
+/// The direction in which a line of a [`Transcript`](struct.Transcript.html) travels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// A line sent from the server to the client.
+    Incoming,
+    /// A line the client is expected to send to the server.
+    Outgoing,
+}
+
+/// A scripted conversation between a `MockServer` and its client.
+///
+/// Running a transcript pushes each incoming line to the client in turn, and checks that the
+/// client sends exactly the expected outgoing lines in the expected order. Any mismatch, missing
+/// line, or unexpected line causes a panic that shows the conversation up to that point.
+///
+/// # Example
+///
+/// ```
+/// # extern crate irc;
+/// # use std::default::Default;
+/// use irc::client::prelude::*;
+/// use irc::client::test_util::{MockServer, Transcript};
+/// use irc::client::test_util::Direction::{Incoming, Outgoing};
+///
+/// # fn main() {
+/// let server = MockServer::new(Config {
+///     nickname: Some("bot".to_owned()),
+///     ..Default::default()
+/// }).unwrap();
+/// server.client().send_privmsg("#chan", "hello").unwrap();
+/// Transcript::new(vec![
+///     (Outgoing, "PRIVMSG #chan :hello"),
+///     (Incoming, "PING :irc.example.com"),
+///     (Outgoing, "PONG :irc.example.com"),
+/// ]).run(&server);
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transcript {
+    lines: Vec<(Direction, String)>,
+}
+
+impl Transcript {
+    /// Creates a new transcript from the given lines, without line terminators, in order.
+    pub fn new<S: Into<String>>(lines: Vec<(Direction, S)>) -> Transcript {
+        Transcript {
+            lines: lines.into_iter().map(|(dir, line)| (dir, line.into())).collect(),
+        }
+    }
+
+    /// Runs the transcript against the given server, panicking if the client deviates from it.
+    pub fn run(&self, server: &MockServer) {
+        self.run_with(server, |_| ())
+    }
+
+    /// Runs the transcript against the given server, calling the given function (e.g. the handler
+    /// under test) on each incoming message after the client has handled it. This panics if the
+    /// client deviates from the transcript.
+    pub fn run_with<F>(&self, server: &MockServer, mut f: F)
+    where
+        F: FnMut(Message),
+    {
+        let mut sent = VecDeque::new();
+        for (i, &(dir, ref line)) in self.lines.iter().enumerate() {
+            sent.extend(server.take_outgoing());
+            match dir {
+                Direction::Incoming => {
+                    if let Some(actual) = sent.pop_front() {
+                        self.fail(i, &format!("unexpected outgoing line: {}", actual));
+                    }
+                    server.push_incoming(&format!("{}\r\n", line));
+                    if let Err(e) = server.process_incoming(&mut f) {
+                        self.fail(i, &format!("failed to process incoming line: {}", e));
+                    }
+                }
+                Direction::Outgoing => match sent.pop_front() {
+                    Some(ref actual) if actual == line => (),
+                    Some(actual) => self.fail(i, &format!(
+                        "outgoing line mismatch\nexpected: {}\n  actual: {}", line, actual
+                    )),
+                    None => self.fail(i, &format!("missing outgoing line: {}", line)),
+                },
+            }
+        }
+        sent.extend(server.take_outgoing());
+        if let Some(actual) = sent.pop_front() {
+            self.fail(self.lines.len(), &format!("unexpected outgoing line: {}", actual));
+        }
+    }
+
+    /// Panics with the given reason, showing the transcript up to and including the given step.
+    fn fail(&self, step: usize, reason: &str) -> ! {
+        let mut transcript = String::new();
+        for (i, &(dir, ref line)) in self.lines.iter().enumerate().take(step + 1) {
+            let marker = if i == step { "=>" } else { "  " };
+            let prefix = match dir {
+                Direction::Incoming => "S:",
+                Direction::Outgoing => "C:",
+            };
+            transcript.push_str(&format!("{} {} {}\n", marker, prefix, line));
+        }
+        panic!("transcript failed at step {}: {}\n{}", step, reason, transcript)
+    }
+}
+
+/// A `Notify` that does nothing, used to poll streams outside of any task.
+struct NoopNotify;
+
+impl Notify for NoopNotify {
+    fn notify(&self, _: usize) {}
+}
+
+static NOOP_NOTIFY: NoopNotify = NoopNotify;
+
+#[cfg(test)]
+mod test {
+    use std::default::Default;
+    use std::thread;
+
+    use super::{MockServer, Transcript};
+    use super::Direction::{Incoming, Outgoing};
+    use client::{Client, EachIncomingExt};
+    use client::data::Config;
+    use client::ext::ClientExt;
+    use proto::Command::PRIVMSG;
+
+    fn test_config() -> Config {
+        Config {
+            nickname: Some("test".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn take_outgoing_in_order() {
+        let server = MockServer::new(test_config()).unwrap();
+        server.client().send_privmsg("#test", "one").unwrap();
+        server.client().send_privmsg("#test", "two").unwrap();
+        assert_eq!(
+            server.take_outgoing(),
+            vec!["PRIVMSG #test :one".to_owned(), "PRIVMSG #test :two".to_owned()]
+        );
+        assert!(server.take_outgoing().is_empty());
+    }
+
+    #[test]
+    fn push_incoming_then_close() {
+        let server = MockServer::new(test_config()).unwrap();
+        server.push_incoming(":a!a@a PRIVMSG test :hi\r\nPING :irc.test.net\r\n");
+        server.close();
+        let mut received = vec![];
+        server.client().for_each_incoming(|msg| received.push(msg)).unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].command, PRIVMSG("test".to_owned(), "hi".to_owned()));
+        assert_eq!(server.take_outgoing(), vec!["PONG :irc.test.net".to_owned()]);
+    }
+
+    #[test]
+    fn push_incoming_wakes_reader() {
+        let server = MockServer::new(test_config()).unwrap();
+        let stream = server.client().stream();
+        let reader = thread::spawn(move || {
+            let mut count = 0;
+            stream.for_each_incoming(|_| count += 1).unwrap();
+            count
+        });
+        server.push_incoming(":a!a@a PRIVMSG test :one\r\n");
+        server.push_incoming(":a!a@a PRIVMSG test :two\r\n");
+        server.close();
+        assert_eq!(reader.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn transcript_identify() {
+        let server = MockServer::new(Config {
+            nick_password: Some("password".to_owned()),
+            channels: Some(vec!["#test".to_owned(), "#test2".to_owned()]),
+            ..test_config()
+        }).unwrap();
+        server.client().identify().unwrap();
+        Transcript::new(vec![
+            (Outgoing, "CAP END"),
+            (Outgoing, "NICK :test"),
+            (Outgoing, "USER test 0 * :test"),
+            (Incoming, ":irc.test.net 001 test :Welcome to the test network"),
+            (Incoming, ":irc.test.net 376 test :End of /MOTD command."),
+            (Outgoing, "NICKSERV IDENTIFY password"),
+            (Outgoing, "JOIN #test"),
+            (Outgoing, "JOIN #test2"),
+            (Incoming, "PING :irc.test.net"),
+            (Outgoing, "PONG :irc.test.net"),
+        ]).run(&server);
+    }
+
+    #[test]
+    fn transcript_with_handler() {
+        let server = MockServer::new(test_config()).unwrap();
+        let client = server.client().clone();
+        Transcript::new(vec![
+            (Incoming, ":a!a@a PRIVMSG #test :ping me"),
+            (Outgoing, "PRIVMSG #test :pong"),
+            (Incoming, ":a!a@a PRIVMSG #test :ignore me"),
+        ]).run_with(&server, |msg| {
+            if let PRIVMSG(ref chan, ref text) = msg.command {
+                if text == "ping me" {
+                    client.send_privmsg(chan, "pong").unwrap();
+                }
+            }
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "outgoing line mismatch")]
+    fn transcript_mismatch() {
+        let server = MockServer::new(test_config()).unwrap();
+        server.client().send_privmsg("#test", "hi").unwrap();
+        Transcript::new(vec![(Outgoing, "PRIVMSG #test :bye")]).run(&server);
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected outgoing line: PRIVMSG #test :hi")]
+    fn transcript_out_of_order() {
+        let server = MockServer::new(test_config()).unwrap();
+        server.client().send_privmsg("#test", "hi").unwrap();
+        Transcript::new(vec![
+            (Incoming, "PING :irc.test.net"),
+            (Outgoing, "PRIVMSG #test :hi"),
+            (Outgoing, "PONG :irc.test.net"),
+        ]).run(&server);
+    }
+
+    #[test]
+    #[should_panic(expected = "missing outgoing line: JOIN #test")]
+    fn transcript_missing() {
+        let server = MockServer::new(test_config()).unwrap();
+        Transcript::new(vec![(Outgoing, "JOIN #test")]).run(&server);
+    }
+}
@@ -1,11 +1,10 @@
 //! An IRC transport that wraps an IRC-framed stream to provide a number of features including
 //! automatic PING replies, automatic sending of PINGs, and message rate-limiting.
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, RwLock, RwLockReadGuard};
 use std::time::{Duration, Instant};
 
 use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
-use chrono::prelude::*;
 use tokio_codec::Framed;
 use tokio_io::{AsyncRead, AsyncWrite};
 use tokio_timer;
@@ -13,8 +12,40 @@ use tokio_timer::{Interval, Sleep, Timer};
 
 use error;
 use client::data::Config;
+use client::data::metrics::Metrics;
+use client::data::user::fold_nickname;
 use proto::{Command, IrcCodec, Message};
 
+/// The maximum number of messages to any one target held back by per-target throttling at once.
+/// Further messages to that target are refused until some of them have been sent, leaving them in
+/// the client's outgoing queue where they count towards `outgoing_queue_max`, while messages to
+/// other targets are still accepted.
+const MAX_DEFERRED_PER_TARGET: usize = 64;
+
+/// Gets the folded target of the given message if it is a `PRIVMSG` or `NOTICE`, which are the
+/// messages subject to per-target throttling.
+pub(crate) fn throttle_key(msg: &Message) -> Option<String> {
+    match msg.command {
+        Command::PRIVMSG(ref target, _) | Command::NOTICE(ref target, _) => {
+            Some(fold_nickname(target))
+        }
+        _ => None,
+    }
+}
+
+/// A generator for the tokens sent with client-initiated PINGs. Each token should be unique, so
+/// that a PONG can only be matched to the PING that caused it.
+pub type PingNonce = Box<FnMut() -> String + Send>;
+
+/// Creates the default PING token generator, which counts up from one.
+pub fn counter_ping_nonce() -> PingNonce {
+    let mut counter: u64 = 0;
+    Box::new(move || {
+        counter += 1;
+        counter.to_string()
+    })
+}
+
 /// An IRC transport that handles core functionality.
 pub struct IrcTransport<T>
 where
@@ -26,8 +57,15 @@ where
     burst_window_length: u64,
     max_burst_messages: u64,
     current_burst_messages: u64,
+    max_target_burst_messages: Option<usize>,
+    target_burst_windows: HashMap<String, VecDeque<Instant>>,
+    deferred: VecDeque<Message>,
+    deferred_timer: Option<Sleep>,
+    throttled: bool,
+    metrics: Arc<Metrics>,
     ping_timer: Interval,
     ping_timeout: u64,
+    ping_nonce: PingNonce,
     last_ping_data: String,
     last_ping_sent: Instant,
     last_pong_received: Instant,
@@ -47,19 +85,42 @@ where
             burst_window_length: u64::from(config.burst_window_length()),
             max_burst_messages: u64::from(config.max_messages_in_burst()),
             current_burst_messages: 0,
+            max_target_burst_messages: config.max_messages_per_target_burst().map(|max| {
+                max as usize
+            }),
+            target_burst_windows: HashMap::new(),
+            deferred: VecDeque::new(),
+            deferred_timer: None,
+            throttled: false,
+            metrics: Arc::new(Metrics::default()),
             ping_timer: timer.interval(Duration::from_secs(u64::from(config.ping_time()))),
             ping_timeout: u64::from(config.ping_timeout()),
+            ping_nonce: counter_ping_nonce(),
             last_ping_data: String::new(),
             last_ping_sent: Instant::now(),
             last_pong_received: Instant::now(),
         }
     }
 
+    /// Replaces the generator used for the tokens of client-initiated PINGs.
+    pub fn with_ping_nonce<F>(mut self, nonce: F) -> IrcTransport<T>
+    where
+        F: FnMut() -> String + Send + 'static,
+    {
+        self.ping_nonce = Box::new(nonce);
+        self
+    }
+
     /// Gets the inner stream underlying the `IrcTransport`.
     pub fn into_inner(self) -> Framed<T, IrcCodec> {
         self.inner
     }
 
+    /// Gets the counters for the traffic over this transport.
+    pub(crate) fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
     fn ping_timed_out(&self) -> bool {
         if self.last_pong_received < self.last_ping_sent {
             self.last_ping_sent.elapsed().as_secs() >= self.ping_timeout
@@ -69,7 +130,7 @@ where
     }
 
     fn send_ping(&mut self) -> error::Result<()> {
-        let last_ping_data = format!("{}", Local::now().timestamp());
+        let last_ping_data = (self.ping_nonce)();
         let data = last_ping_data.clone();
         let result = self.start_send(Command::PING(data, None).into())?;
         if let AsyncSink::Ready = result {
@@ -83,6 +144,111 @@ where
     fn rolling_burst_window_front(&mut self) -> Result<Async<()>, tokio_timer::TimerError> {
         self.rolling_burst_window.front_mut().map(|w| w.poll()).unwrap_or(Ok(Async::NotReady))
     }
+
+    /// Checks whether another message can be sent without exceeding the global rate limit.
+    fn burst_ready(&mut self) -> Result<bool, tokio_timer::TimerError> {
+        // Check if the oldest message in the rolling window is discounted.
+        if let Async::Ready(()) = self.rolling_burst_window_front()? {
+            self.current_burst_messages -= 1;
+            self.rolling_burst_window.pop_front();
+        }
+        Ok(self.current_burst_messages < self.max_burst_messages)
+    }
+
+    /// Records that a message to the given target (if any) was sent, counting it against the
+    /// global and per-target rate limits.
+    fn record_sent(&mut self, target: Option<String>) {
+        self.current_burst_messages += 1;
+        self.rolling_burst_window.push_back(self.burst_timer.sleep(Duration::from_secs(
+            self.burst_window_length
+        )));
+        if let (Some(target), Some(_)) = (target, self.max_target_burst_messages) {
+            self.target_burst_windows.entry(target).or_default()
+                .push_back(Instant::now());
+        }
+    }
+
+    /// Gets the folded target of the given message if it is subject to per-target throttling,
+    /// i.e. if per-target throttling is enabled and the message is a `PRIVMSG` or `NOTICE`.
+    fn throttled_target(&self, msg: &Message) -> Option<String> {
+        self.max_target_burst_messages?;
+        throttle_key(msg)
+    }
+
+    /// Checks whether another message can be sent to the given target without exceeding the
+    /// per-target rate limit, returning how long until it can be otherwise.
+    fn target_ready(&mut self, target: &str) -> Result<(), Duration> {
+        let window = Duration::from_secs(self.burst_window_length);
+        let max = match self.max_target_burst_messages {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        let sent = match self.target_burst_windows.get_mut(target) {
+            Some(sent) => sent,
+            None => return Ok(()),
+        };
+        while let Some(at) = sent.front().cloned() {
+            if at.elapsed() < window {
+                break;
+            }
+            sent.pop_front();
+        }
+        match sent.front() {
+            Some(oldest) if sent.len() >= max => {
+                Err(window.checked_sub(oldest.elapsed()).unwrap_or_default())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends as many of the messages deferred by per-target throttling as the rate limits allow,
+    /// in order for each target, and arranges to be woken when more of them can be sent.
+    fn flush_deferred(&mut self) -> error::Result<()> {
+        loop {
+            let mut blocked = HashSet::new();
+            let mut wait: Option<Duration> = None;
+            let mut i = 0;
+            while i < self.deferred.len() {
+                let target = self.throttled_target(&self.deferred[i]).unwrap_or_default();
+                if !blocked.contains(&target) {
+                    if let Err(delay) = self.target_ready(&target) {
+                        wait = Some(wait.map_or(delay, |wait| wait.min(delay)));
+                    } else if !self.burst_ready()? {
+                        return Ok(());
+                    } else {
+                        let msg = self.deferred.remove(i).unwrap();
+                        let len = super::wire_len(&msg);
+                        match self.inner.start_send(msg)? {
+                            AsyncSink::NotReady(msg) => {
+                                self.deferred.insert(i, msg);
+                                return Ok(());
+                            }
+                            AsyncSink::Ready => {
+                                self.metrics.message_sent(len);
+                                self.record_sent(Some(target));
+                                continue;
+                            }
+                        }
+                    }
+                }
+                // Later messages to the same target must wait to preserve their order.
+                blocked.insert(target);
+                i += 1;
+            }
+            let mut timer = match wait {
+                Some(wait) => self.burst_timer.sleep(wait),
+                None => {
+                    self.deferred_timer = None;
+                    return Ok(());
+                }
+            };
+            // If the wait has already elapsed, try again rather than waiting to be woken.
+            if let Async::NotReady = timer.poll()? {
+                self.deferred_timer = Some(timer);
+                return Ok(());
+            }
+        }
+    }
 }
 
 impl<T> Stream for IrcTransport<T>
@@ -110,6 +276,7 @@ where
             }
             (Async::Ready(None), _) => Ok(Async::Ready(None)),
             (Async::Ready(Some(msg)), _) => {
+                self.metrics.message_received(super::wire_len(&msg));
                 if let Async::Ready(msg) = timer_poll {
                     assert!(msg.is_some());
                     self.send_ping()?;
@@ -150,26 +317,46 @@ where
             self.close()?;
             Err(error::IrcError::PingTimeout)
         } else {
-            // Check if the oldest message in the rolling window is discounted.
-            if let Async::Ready(()) = self.rolling_burst_window_front()? {
-                self.current_burst_messages -= 1;
-                self.rolling_burst_window.pop_front();
+            // Messages deferred by per-target throttling go out before newer ones.
+            self.flush_deferred()?;
+
+            // Defer messages to targets that have been sent too many messages recently, so that
+            // they don't hold up messages to other targets.
+            let target = self.throttled_target(&item);
+            if let Some(target) = target.clone() {
+                let queued = self.deferred.iter().filter(|msg| {
+                    self.throttled_target(msg).as_ref() == Some(&target)
+                }).count();
+                if queued > 0 || self.target_ready(&target).is_err() {
+                    if queued >= MAX_DEFERRED_PER_TARGET {
+                        return Ok(AsyncSink::NotReady(item))
+                    }
+                    self.metrics.message_deferred();
+                    self.deferred.push_back(item);
+                    self.flush_deferred()?;
+                    return Ok(AsyncSink::Ready)
+                }
             }
 
             // Throttling if too many messages have been sent recently.
-            if self.current_burst_messages >= self.max_burst_messages {
+            if !self.burst_ready()? {
+                // A message that is retried is only counted the first time it is held back.
+                if !self.throttled {
+                    self.throttled = true;
+                    self.metrics.message_deferred();
+                }
                 // When throttled, we know we need to finish sending what's already queued up.
                 self.poll_complete()?;
                 return Ok(AsyncSink::NotReady(item))
             }
 
+            let len = super::wire_len(&item);
             match self.inner.start_send(item)? {
                 AsyncSink::NotReady(item) => Ok(AsyncSink::NotReady(item)),
                 AsyncSink::Ready => {
-                    self.current_burst_messages += 1;
-                    self.rolling_burst_window.push_back(self.burst_timer.sleep(Duration::from_secs(
-                        self.burst_window_length
-                    )));
+                    self.throttled = false;
+                    self.metrics.message_sent(len);
+                    self.record_sent(target);
                     Ok(AsyncSink::Ready)
                 }
             }
@@ -188,7 +375,13 @@ where
                 self.send_ping()?;
             }
 
-            Ok(self.inner.poll_complete()?)
+            self.flush_deferred()?;
+            try_ready!(self.inner.poll_complete());
+            if self.deferred.is_empty() {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
         }
     }
 
@@ -245,6 +438,11 @@ where
     pub fn view(&self) -> LogView {
         self.view.clone()
     }
+
+    /// Gets the counters for the traffic over the wrapped transport.
+    pub(crate) fn metrics(&self) -> Arc<Metrics> {
+        self.inner.metrics()
+    }
 }
 
 impl<T> Stream for Logged<T>
@@ -288,3 +486,105 @@ where
         Ok(self.inner.poll_complete()?)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::default::Default;
+
+    use futures::future;
+    use futures::{AsyncSink, Future, Sink};
+    use tokio_codec::Decoder;
+
+    use super::{IrcTransport, MAX_DEFERRED_PER_TARGET};
+    use client::data::Config;
+    use client::test_util::MockTransport;
+    use proto::Command::PRIVMSG;
+    use proto::IrcCodec;
+
+    fn transport() -> (MockTransport, IrcTransport<MockTransport>) {
+        transport_with_config(&Config::default())
+    }
+
+    fn transport_with_config(config: &Config) -> (MockTransport, IrcTransport<MockTransport>) {
+        let mock = MockTransport::new();
+        let framed = IrcCodec::new("UTF-8").unwrap().framed(mock.clone());
+        (mock, IrcTransport::new(config, framed))
+    }
+
+    #[test]
+    fn ping_tokens_are_distinct() {
+        let (mock, mut transport) = transport();
+        future::lazy(|| {
+            transport.send_ping()?;
+            transport.send_ping()
+        }).wait().unwrap();
+        assert_eq!(String::from_utf8(mock.take_outgoing()).unwrap(), "PING :1\r\nPING :2\r\n");
+    }
+
+    #[test]
+    fn injected_ping_nonce() {
+        let (mock, transport) = transport();
+        let mut transport = transport.with_ping_nonce(|| "token".to_owned());
+        future::lazy(|| transport.send_ping()).wait().unwrap();
+        assert_eq!(String::from_utf8(mock.take_outgoing()).unwrap(), "PING :token\r\n");
+    }
+
+    #[test]
+    fn per_target_throttling() {
+        let (mock, mut transport) = transport_with_config(&Config {
+            burst_window_length: Some(1),
+            max_messages_per_target_burst: Some(2),
+            ..Config::default()
+        });
+        future::lazy(|| {
+            for i in 0..5 {
+                transport.start_send(PRIVMSG("#busy".to_owned(), format!("{}", i)).into())?;
+            }
+            transport.start_send(PRIVMSG("#quiet".to_owned(), "hi".to_owned()).into())?;
+            transport.start_send(PRIVMSG("#BUSY".to_owned(), "5".to_owned()).into())?;
+            transport.poll_complete()
+        }).wait().unwrap();
+        assert_eq!(
+            String::from_utf8(mock.take_outgoing()).unwrap(),
+            "PRIVMSG #busy :0\r\nPRIVMSG #busy :1\r\nPRIVMSG #quiet :hi\r\n"
+        );
+
+        // The held back messages follow in order as the window rolls over.
+        future::poll_fn(|| transport.poll_complete()).wait().unwrap();
+        assert_eq!(
+            String::from_utf8(mock.take_outgoing()).unwrap(),
+            "PRIVMSG #busy :2\r\nPRIVMSG #busy :3\r\nPRIVMSG #busy :4\r\nPRIVMSG #BUSY :5\r\n"
+        );
+    }
+    #[test]
+    fn deferred_messages_are_bounded() {
+        let (mock, mut transport) = transport_with_config(&Config {
+            burst_window_length: Some(1),
+            max_messages_per_target_burst: Some(1),
+            ..Config::default()
+        });
+        let refused = future::lazy(|| {
+            for i in 0..MAX_DEFERRED_PER_TARGET + 1 {
+                let msg = PRIVMSG("#busy".to_owned(), format!("{}", i)).into();
+                assert!(transport.start_send(msg)?.is_ready());
+            }
+            let msg = PRIVMSG("#busy".to_owned(), "overflow".to_owned()).into();
+            let refused = transport.start_send(msg)?;
+            // Other targets are unaffected by the busy one.
+            let msg = PRIVMSG("#quiet".to_owned(), "hi".to_owned()).into();
+            assert!(transport.start_send(msg)?.is_ready());
+            transport.poll_complete()?;
+            Ok::<_, ::error::IrcError>(refused)
+        }).wait().unwrap();
+        match refused {
+            AsyncSink::NotReady(msg) => assert_eq!(msg.suffix(), Some("overflow".to_owned())),
+            AsyncSink::Ready => panic!("expected the message to be refused"),
+        }
+        let deferrals = transport.metrics().snapshot().rate_limit_deferrals;
+        assert_eq!(deferrals, MAX_DEFERRED_PER_TARGET);
+        assert_eq!(
+            String::from_utf8(mock.take_outgoing()).unwrap(),
+            "PRIVMSG #busy :0\r\nPRIVMSG #quiet :hi\r\n"
+        );
+    }
+}
@@ -1,4 +1,8 @@
 //! Errors for `irc` crate using `failure`.
+//!
+//! Errors wrapping a lower-level error expose it through `Fail::cause`, and the whole chain can
+//! be walked with `Fail::iter_causes`. Where a `std::error::Error` is needed instead, an error can
+//! be converted with `Fail::compat`.
 
 use std::io::Error as IoError;
 use std::sync::mpsc::RecvError;
@@ -17,7 +21,7 @@ use toml::de::Error as TomlReadError;
 #[cfg(feature = "toml")]
 use toml::ser::Error as TomlWriteError;
 
-use proto::Message;
+use proto::{Capability, ChannelJoinError, Message, Response};
 
 /// A specialized `Result` type for the `irc` crate.
 pub type Result<T> = ::std::result::Result<T, IrcError>;
@@ -33,6 +37,19 @@ pub enum IrcError {
     #[fail(display = "a TLS error occurred")]
     Tls(#[cause] TlsError),
 
+    /// Failed to connect to any of the configured servers. This holds the last server that was
+    /// tried along with the error that occurred connecting to it.
+    #[fail(display = "failed to connect to {}:{}", server, port)]
+    ConnectionFailed {
+        /// The hostname of the last server that was tried.
+        server: String,
+        /// The port of the last server that was tried.
+        port: u16,
+        /// The error that occurred connecting to the server.
+        #[cause]
+        cause: IoError,
+    },
+
     /// An internal synchronous channel closed.
     #[fail(display = "a sync channel closed")]
     SyncChannelClosed(#[cause] RecvError),
@@ -77,6 +94,106 @@ pub enum IrcError {
     #[fail(display = "connection reset: no ping response")]
     PingTimeout,
 
+    /// Registration timed out because the server never welcomed us.
+    #[fail(display = "registration timed out: no welcome from server")]
+    RegistrationTimeout,
+
+    /// The server refused to register the connection, e.g. with `ERR_PASSWDMISMATCH` or an
+    /// `ERROR` before welcoming us.
+    #[fail(display = "registration refused")]
+    Registration(#[cause] Refusal),
+
+    /// The server refused SASL authentication with one of the SASL errors, e.g. `ERR_SASLFAIL`.
+    #[fail(display = "SASL authentication failed")]
+    Sasl(#[cause] Refusal),
+
+    /// The server advertised a Strict Transport Security policy over a plaintext connection. The
+    /// client must reconnect with TLS on the given port, which `ReconnectingIter` does on its own.
+    #[fail(display = "server requires a TLS connection on port {}", port)]
+    StsUpgrade {
+        /// The port on which the server accepts TLS connections.
+        port: u16,
+    },
+
+    /// Capability negotiation timed out because the server never acknowledged or rejected a
+    /// request.
+    #[fail(display = "capability request timed out: no ACK or NAK from server")]
+    CapabilityTimeout,
+
+    /// The server rejected a capability request with `CAP NAK`.
+    #[fail(display = "capability negotiation failed")]
+    CapNegotiation(#[cause] Refusal),
+
+    /// A message could not be sent because it requires a capability that has not been enabled.
+    #[fail(display = "capability not enabled: {:?}", capability)]
+    CapabilityNotEnabled {
+        /// The capability that the message requires.
+        capability: Capability,
+    },
+
+    /// The server refused to let us join a channel.
+    #[fail(display = "failed to join {}: {:?}", channel, error)]
+    JoinFailed {
+        /// The channel that could not be joined.
+        channel: String,
+        /// The reason the server gave for refusing the join.
+        error: ChannelJoinError,
+    },
+
+    /// Joining a channel timed out because the server never confirmed or refused the join.
+    #[fail(display = "timed out joining {}", channel)]
+    JoinTimeout {
+        /// The channel that was being joined.
+        channel: String,
+    },
+
+    /// The server replied to a command with one of the responses marking it as failed.
+    #[fail(display = "command failed with {:?}: {}", response, reply)]
+    CommandFailed {
+        /// The failure response sent by the server.
+        response: Response,
+        /// The full reply sent by the server.
+        reply: Message,
+    },
+
+    /// The server did not reply to a command with any of the awaited responses in time.
+    #[fail(display = "timed out awaiting a reply to a command")]
+    ReplyTimeout,
+
+    /// The server dropped a command with `RPL_TRYAGAIN` because it was sent too soon or the server
+    /// was under load. The command may be sent again later.
+    #[fail(display = "rate limited by server")]
+    RateLimited(#[cause] Refusal),
+
+    /// A message could not be sent because the outgoing queue was full, and the configured
+    /// `outgoing_overflow_policy` is `error`.
+    #[fail(display = "outgoing queue is full: {} messages pending", max)]
+    OutgoingQueueFull {
+        /// The capacity of the outgoing queue.
+        max: usize,
+    },
+
+    /// A message could not be sent because it would exceed the line length limit of the protocol,
+    /// and would otherwise be truncated or rejected by the server. Tags do not count towards the
+    /// limit. This is only checked if `reject_long_messages` is enabled.
+    #[fail(display = "message too long: {} bytes exceeds the limit of {}", length, max)]
+    MessageTooLong {
+        /// The length of the message in bytes, including the trailing `\r\n`.
+        length: usize,
+        /// The maximum length of a message in bytes.
+        max: usize,
+    },
+
+    /// A message could not be sent because its tags would exceed the length limit for the tags
+    /// of messages sent by clients, and the server would otherwise reject the whole message.
+    #[fail(display = "message tags too long: {} bytes exceeds the limit of {}", length, max)]
+    TagsTooLong {
+        /// The length of the tags in bytes, excluding the leading `@` and the trailing space.
+        length: usize,
+        /// The maximum length of the tags in bytes.
+        max: usize,
+    },
+
     /// Failed to lookup an unknown codec.
     #[fail(display = "unknown codec: {}", codec)]
     UnknownCodec {
@@ -93,6 +210,31 @@ pub enum IrcError {
         data: String,
     },
 
+    /// Failed to decode a received line because it contained invalid byte sequences. This is only
+    /// produced when lossy decoding is disabled.
+    #[fail(display = "invalid data for codec {}: {:?}", codec, data)]
+    InvalidUtf8 {
+        /// The canonical codec name.
+        codec: &'static str,
+        /// The raw bytes of the offending line.
+        data: Vec<u8>,
+    },
+
+    /// The server refused or failed to upgrade the connection with STARTTLS.
+    #[fail(display = "STARTTLS failed: {}", reason)]
+    StartTlsFailed {
+        /// The reason given by the server.
+        reason: String,
+    },
+
+    /// The server closed the connection after sending an `ERROR`, e.g. because the client was
+    /// banned or timed out. This is not produced when the client quit itself.
+    #[fail(display = "server closed the connection: {}", reason)]
+    ServerError {
+        /// The reason given by the server.
+        reason: String,
+    },
+
     /// All specified nicknames were in use or unusable.
     #[fail(display = "none of the specified nicknames were usable")]
     NoUsableNick,
@@ -107,6 +249,26 @@ pub enum IrcError {
     },
 }
 
+/// A reply with which the server refused a request, given as the cause of errors such as
+/// `IrcError::Registration` and `IrcError::Sasl`.
+#[derive(Clone, Debug, Fail)]
+#[fail(display = "{}", reason)]
+pub struct Refusal {
+    /// The reason given by the server.
+    pub reason: String,
+    /// The full reply sent by the server.
+    pub reply: Message,
+}
+
+impl From<Message> for Refusal {
+    fn from(reply: Message) -> Refusal {
+        Refusal {
+            reason: reply.suffix().unwrap_or_default(),
+            reply: reply,
+        }
+    }
+}
+
 /// Errors that occur when parsing messages.
 #[derive(Debug, Fail)]
 pub enum MessageParseError {
@@ -196,6 +358,22 @@ pub enum ConfigError {
     /// Configuration does not specify a server.
     #[fail(display = "server not specified")]
     ServerNotSpecified,
+
+    /// Configuration has a field with an invalid value, or one that conflicts with other fields.
+    #[fail(display = "invalid {}: {}", field, reason)]
+    InvalidField {
+        /// The name of the offending field.
+        field: &'static str,
+        /// Why the field is invalid.
+        reason: &'static str,
+    },
+
+    /// Configuration refers to an environment variable that is not set.
+    #[fail(display = "environment variable not set: {}", var)]
+    UnsetEnvVar {
+        /// The name of the environment variable.
+        var: String,
+    },
 }
 
 /// A wrapper that combines toml's serialization and deserialization errors.
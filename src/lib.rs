@@ -29,7 +29,7 @@
 //! client.for_each_incoming(|irc_msg| {
 //!     // irc_msg is a Message
 //!     if let Command::PRIVMSG(channel, message) = irc_msg.command {
-//!         if message.contains(client.current_nickname()) {
+//!         if message.contains(&client.current_nickname()) {
 //!             // send_privmsg comes from ClientExt
 //!             client.send_privmsg(&channel, "beep boop").unwrap();
 //!         }
@@ -64,6 +64,8 @@ extern crate tokio_io;
 extern crate tokio_mockstream;
 extern crate tokio_timer;
 extern crate tokio_tls;
+#[cfg(unix)]
+extern crate tokio_uds;
 #[cfg(feature = "toml")]
 extern crate toml;
 
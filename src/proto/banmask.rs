@@ -0,0 +1,71 @@
+//! Construction of ban masks from the hostmasks of users.
+
+/// A common form of ban mask, each matching users by a different part of their hostmask.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BanMaskType {
+    /// Matches any user connecting from the same host (`*!*@host`).
+    Host,
+    /// Matches any user with the same username (`*!user@*`).
+    User,
+    /// Matches any user with the same nickname (`nick!*@*`).
+    Nick,
+}
+
+/// Constructs a ban mask of the given type from a hostmask of the form `nick!user@host`, such as
+/// the prefix of a message. Missing parts of the hostmask are tolerated, but this returns `None`
+/// if the part that the mask is based on is missing, as the mask would otherwise match everyone.
+///
+/// # Example
+/// ```
+/// # extern crate irc;
+/// # use irc::proto::{banmask, BanMaskType};
+/// # fn main() {
+/// assert_eq!(banmask("ada!ada@host.example", BanMaskType::Host), Some("*!*@host.example".into()));
+/// assert_eq!(banmask("ada", BanMaskType::Host), None);
+/// # }
+/// ```
+pub fn banmask(hostmask: &str, kind: BanMaskType) -> Option<String> {
+    let (rest, host) = match hostmask.find('@') {
+        Some(i) => (&hostmask[..i], &hostmask[i + 1..]),
+        None => (hostmask, ""),
+    };
+    let (nick, user) = match rest.find('!') {
+        Some(i) => (&rest[..i], &rest[i + 1..]),
+        None => (rest, ""),
+    };
+    let (part, mask) = match kind {
+        BanMaskType::Host => (host, format!("*!*@{}", host)),
+        BanMaskType::User => (user, format!("*!{}@*", user)),
+        BanMaskType::Nick => (nick, format!("{}!*@*", nick)),
+    };
+    if part.is_empty() {
+        None
+    } else {
+        Some(mask)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{banmask, BanMaskType};
+
+    #[test]
+    fn mask_types() {
+        let hostmask = "ada!~ada@host.example";
+        assert_eq!(banmask(hostmask, BanMaskType::Host), Some("*!*@host.example".to_owned()));
+        assert_eq!(banmask(hostmask, BanMaskType::User), Some("*!~ada@*".to_owned()));
+        assert_eq!(banmask(hostmask, BanMaskType::Nick), Some("ada!*@*".to_owned()));
+    }
+
+    #[test]
+    fn missing_parts() {
+        assert_eq!(banmask("ada", BanMaskType::Nick), Some("ada!*@*".to_owned()));
+        assert_eq!(banmask("ada", BanMaskType::User), None);
+        assert_eq!(banmask("ada", BanMaskType::Host), None);
+        assert_eq!(banmask("ada@host.example", BanMaskType::Host),
+                   Some("*!*@host.example".to_owned()));
+        assert_eq!(banmask("ada@host.example", BanMaskType::User), None);
+        assert_eq!(banmask("!ada@", BanMaskType::Nick), None);
+        assert_eq!(banmask("!ada@", BanMaskType::User), Some("*!ada@*".to_owned()));
+    }
+}
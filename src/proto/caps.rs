@@ -1,8 +1,10 @@
 //! Enumeration of all supported IRCv3 capability extensions.
+use std::fmt;
+use std::str::FromStr;
 
 /// List of all supported IRCv3 capability extensions from the
 /// [IRCv3 specifications](http://ircv3.net/irc/).
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Capability {
     /// [multi-prefix](http://ircv3.net/specs/extensions/multi-prefix-3.1.html)
     MultiPrefix,
@@ -38,6 +40,66 @@ pub enum Capability {
     UserhostInNames,
     /// Custom IRCv3 capability extensions
     Custom(&'static str),
+    /// Capability extensions unknown to the crate (e.g. `draft/*` or vendor capabilities), as
+    /// advertised by the server.
+    Unknown(String),
+}
+
+/// An IRCv3 capability along with its value, if any (e.g. `sasl=PLAIN,EXTERNAL`). Values are
+/// advertised by servers in `CAP LS 302` replies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValuedCapability {
+    /// The capability itself.
+    pub capability: Capability,
+    /// The value of the capability, if it has one.
+    pub value: Option<String>,
+}
+
+impl ValuedCapability {
+    /// Creates a new capability with the given value.
+    pub fn new(capability: Capability, value: Option<&str>) -> ValuedCapability {
+        ValuedCapability {
+            capability: capability,
+            value: value.map(|s| s.to_owned()),
+        }
+    }
+
+    /// Parses a space-separated list of capabilities with optional values, such as the one given
+    /// in the suffix of a `CAP LS` reply.
+    pub fn parse_list(s: &str) -> Vec<ValuedCapability> {
+        s.split_whitespace().map(|cap| cap.into()).collect()
+    }
+}
+
+impl From<Capability> for ValuedCapability {
+    fn from(capability: Capability) -> ValuedCapability {
+        ValuedCapability::new(capability, None)
+    }
+}
+
+impl<'a> From<&'a str> for ValuedCapability {
+    fn from(s: &'a str) -> ValuedCapability {
+        let mut split = s.splitn(2, '=');
+        let name = split.next().unwrap_or("");
+        ValuedCapability::new(name.into(), split.next())
+    }
+}
+
+impl FromStr for ValuedCapability {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ValuedCapability, Self::Err> {
+        Ok(s.into())
+    }
+}
+
+impl fmt::Display for ValuedCapability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.value {
+            Some(ref value) => write!(f, "{}={}", self.capability.as_ref(), value),
+            None => write!(f, "{}", self.capability.as_ref()),
+        }
+    }
 }
 
 /// List of IRCv3 capability negotiation versions.
@@ -68,12 +130,48 @@ impl AsRef<str> for Capability {
             Capability::ServerTime => "server-time",
             Capability::UserhostInNames => "userhost-in-names",
             Capability::Custom(s) => s,
+            Capability::Unknown(ref s) => s,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Capability {
+    fn from(s: &'a str) -> Capability {
+        match s {
+            "multi-prefix" => Capability::MultiPrefix,
+            "sasl" => Capability::Sasl,
+            "account-notify" => Capability::AccountNotify,
+            "away-notify" => Capability::AwayNotify,
+            "extended-join" => Capability::ExtendedJoin,
+            "metadata" => Capability::Metadata,
+            "metadata-notify" => Capability::MetadataNotify,
+            "monitor" => Capability::Monitor,
+            "account-tag" => Capability::AccountTag,
+            "batch" => Capability::Batch,
+            "cap-notify" => Capability::CapNotify,
+            "chghost" => Capability::ChgHost,
+            "echo-message" => Capability::EchoMessage,
+            "invite-notify" => Capability::InviteNotify,
+            "server-time" => Capability::ServerTime,
+            "userhost-in-names" => Capability::UserhostInNames,
+            s => Capability::Unknown(s.to_owned()),
         }
     }
 }
 
+impl FromStr for Capability {
+    type Err = ();
+
+    /// Parses a capability name, preserving names unknown to the crate as
+    /// `Capability::Unknown`. This never fails.
+    fn from_str(s: &str) -> Result<Capability, Self::Err> {
+        Ok(s.into())
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use super::{Capability, ValuedCapability};
     use super::Capability::*;
 
     #[test]
@@ -95,5 +193,44 @@ mod test {
         assert_eq!(ServerTime.as_ref(), "server-time");
         assert_eq!(UserhostInNames.as_ref(), "userhost-in-names");
         assert_eq!(Custom("example").as_ref(), "example");
+        assert_eq!(Unknown("example".to_owned()).as_ref(), "example");
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!("multi-prefix".parse(), Ok(MultiPrefix));
+        assert_eq!("sasl".parse(), Ok(Sasl));
+        assert_eq!("userhost-in-names".parse(), Ok(UserhostInNames));
+        assert_eq!(Capability::from("server-time"), ServerTime);
+    }
+
+    #[test]
+    fn unknown_round_trip() {
+        let cap: Capability = "draft/chathistory".parse().unwrap();
+        assert_eq!(cap, Unknown("draft/chathistory".to_owned()));
+        assert_eq!(cap.as_ref(), "draft/chathistory");
+        assert_eq!(Capability::from(cap.as_ref()), cap);
+    }
+
+    #[test]
+    fn valued_round_trip() {
+        let cap: ValuedCapability = "sasl=PLAIN".parse().unwrap();
+        assert_eq!(cap, ValuedCapability::new(Sasl, Some("PLAIN")));
+        assert_eq!(cap.to_string(), "sasl=PLAIN");
+        let cap: ValuedCapability = "multi-prefix".parse().unwrap();
+        assert_eq!(cap, ValuedCapability::new(MultiPrefix, None));
+        assert_eq!(cap.to_string(), "multi-prefix");
+    }
+
+    #[test]
+    fn parse_valued_list() {
+        assert_eq!(
+            ValuedCapability::parse_list("multi-prefix sasl=PLAIN,EXTERNAL draft/foo=bar=baz"),
+            vec![
+                MultiPrefix.into(),
+                ValuedCapability::new(Sasl, Some("PLAIN,EXTERNAL")),
+                ValuedCapability::new(Unknown("draft/foo".to_owned()), Some("bar=baz")),
+            ]
+        );
     }
 }
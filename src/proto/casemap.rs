@@ -0,0 +1,39 @@
+//! Comparison of nicknames and channel names under RFC 1459 casemapping.
+
+/// Compares two nicknames according to RFC 1459 casemapping, under which `[]\~` are the
+/// lowercase equivalents of `{}|^` in addition to the usual ASCII letters.
+pub(crate) fn nicknames_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.chars().map(fold_char).eq(b.chars().map(fold_char))
+}
+
+/// Folds a nickname to its canonical lowercase form under RFC 1459 casemapping.
+pub(crate) fn fold_nickname(nickname: &str) -> String {
+    nickname.chars().map(fold_char).collect()
+}
+
+fn fold_char(c: char) -> char {
+    match c {
+        '[' => '{',
+        ']' => '}',
+        '\\' => '|',
+        '~' => '^',
+        c => c.to_ascii_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fold_nickname, nicknames_eq};
+
+    #[test]
+    fn nickname_casemapping() {
+        assert!(nicknames_eq("test", "TEST"));
+        assert!(nicknames_eq("te[s]t\\~", "TE{S}T|^"));
+        assert!(!nicknames_eq("test", "test2"));
+    }
+
+    #[test]
+    fn fold() {
+        assert_eq!(fold_nickname("TE[S]T\\~"), "te{s}t|^");
+    }
+}
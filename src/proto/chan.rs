@@ -1,17 +1,68 @@
 //! An extension trait that provides the ability to check if a string is a channel name.
 
+/// The channel prefixes assumed when a server has not advertised its own in `CHANTYPES`.
+pub const DEFAULT_CHANTYPES: &str = "#&+!";
+
+/// Returns true if the given target begins with one of the given channel prefixes, e.g. those
+/// advertised by a server in its `CHANTYPES` token.
+///
+/// # Example
+/// ```
+/// # extern crate irc;
+/// # use irc::proto::is_channel;
+/// # fn main() {
+/// assert!(is_channel("#rust", "#&"));
+/// assert!(!is_channel("+rust", "#&"));
+/// # }
+/// ```
+pub fn is_channel(target: &str, chantypes: &str) -> bool {
+    match target.chars().next() {
+        Some(prefix) => chantypes.contains(prefix),
+        None => false,
+    }
+}
+
+/// The visibility of a channel, as given by the symbol before its name in `RPL_NAMREPLY`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChannelVisibility {
+    /// A public channel (`=`).
+    Public,
+    /// A private channel (`*`, mode `+p`).
+    Private,
+    /// A secret channel (`@`, mode `+s`).
+    Secret,
+}
+
+impl ChannelVisibility {
+    /// Gets the visibility denoted by the given `RPL_NAMREPLY` symbol, if it is a known symbol.
+    pub fn from_symbol(symbol: &str) -> Option<ChannelVisibility> {
+        match symbol {
+            "=" => Some(ChannelVisibility::Public),
+            "*" => Some(ChannelVisibility::Private),
+            "@" => Some(ChannelVisibility::Secret),
+            _ => None,
+        }
+    }
+
+    /// Gets the `RPL_NAMREPLY` symbol denoting this visibility.
+    pub fn symbol(&self) -> &'static str {
+        match *self {
+            ChannelVisibility::Public => "=",
+            ChannelVisibility::Private => "*",
+            ChannelVisibility::Secret => "@",
+        }
+    }
+}
+
 /// An extension trait giving strings a function to check if they are a channel.
 pub trait ChannelExt {
-    /// Returns true if the specified name is a channel name.
+    /// Returns true if the specified name is a channel name under the `DEFAULT_CHANTYPES`.
     fn is_channel_name(&self) -> bool;
 }
 
 impl<'a> ChannelExt for &'a str {
     fn is_channel_name(&self) -> bool {
-        self.starts_with('#') ||
-            self.starts_with('&') ||
-            self.starts_with('+') ||
-            self.starts_with('!')
+        is_channel(self, DEFAULT_CHANTYPES)
     }
 }
 
@@ -20,3 +71,40 @@ impl ChannelExt for String {
         (&self[..]).is_channel_name()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{is_channel, ChannelExt, ChannelVisibility, DEFAULT_CHANTYPES};
+
+    #[test]
+    fn channel_prefixes() {
+        for chan in &["#rust", "&local", "+modeless", "!12345safe"] {
+            assert!(is_channel(chan, DEFAULT_CHANTYPES));
+            assert!(chan.is_channel_name());
+        }
+    }
+
+    #[test]
+    fn non_channels() {
+        for target in &["rust", "ada", "", "*.example.com", "$*.example.com"] {
+            assert!(!is_channel(target, DEFAULT_CHANTYPES));
+            assert!(!target.is_channel_name());
+        }
+    }
+
+    #[test]
+    fn advertised_chantypes() {
+        assert!(is_channel("#rust", "#"));
+        assert!(!is_channel("&local", "#"));
+        assert!(!is_channel("#rust", ""));
+    }
+
+    #[test]
+    fn visibility_symbols() {
+        for &vis in &[ChannelVisibility::Public, ChannelVisibility::Private,
+                      ChannelVisibility::Secret] {
+            assert_eq!(ChannelVisibility::from_symbol(vis.symbol()), Some(vis));
+        }
+        assert_eq!(ChannelVisibility::from_symbol("#"), None);
+    }
+}
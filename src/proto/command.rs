@@ -190,14 +190,28 @@ pub enum Command {
     /// CHGHOST user host
     CHGHOST(String, String),
 
+    // IRCv3 message-tags extension
+    /// TAGMSG msgtarget
+    ///
+    /// A message consisting only of its tags, which are typically client-only tags.
+    TAGMSG(String),
+
     // Default option.
     /// An IRC response code with arguments and optional suffix.
     Response(Response, Vec<String>, Option<String>),
-    /// A raw IRC command unknown to the crate.
+    /// A raw IRC command unknown to the crate, with its arguments and optional suffix. Without a
+    /// suffix, the last argument is sent as a trailing parameter if it is empty, contains spaces,
+    /// or begins with a colon.
     Raw(String, Vec<String>, Option<String>),
 }
 
 fn stringify(cmd: &str, args: &[&str], suffix: Option<&str>) -> String {
+    // Without a suffix, the last argument must still be sent as one if it can't stand as a middle
+    // parameter, or else it would be split or misread by the server.
+    let (args, suffix) = match (args.split_last(), suffix) {
+        (Some((last, init)), None) if needs_suffix(last) => (init, Some(*last)),
+        _ => (args, suffix),
+    };
     let args = args.join(" ");
     let sp = if args.is_empty() { "" } else { " " };
     match suffix {
@@ -207,6 +221,24 @@ fn stringify(cmd: &str, args: &[&str], suffix: Option<&str>) -> String {
 
 }
 
+/// Checks whether a parameter can only be sent as a suffix, i.e. if it is empty, contains spaces,
+/// or begins with a colon.
+fn needs_suffix(arg: &str) -> bool {
+    arg.is_empty() || arg.contains(' ') || arg.starts_with(':')
+}
+
+/// Formats a message to a service. The text is sent as is, as services expect their commands
+/// split into parameters, unless it could not be parsed back the same way.
+fn stringify_service(cmd: &str, text: &str) -> String {
+    if text.is_empty() || text.starts_with(':') || text.starts_with(' ') || text.ends_with(' ') ||
+        text.contains("  ") || text.contains(" :")
+    {
+        stringify(cmd, &[], Some(text))
+    } else {
+        format!("{} {}", cmd, text)
+    }
+}
+
 impl<'a> From<&'a Command> for String {
     fn from(cmd: &'a Command) -> String {
         match *cmd {
@@ -327,12 +359,13 @@ impl<'a> From<&'a Command> for String {
             Command::SAPART(ref c, ref r) => stringify("SAPART", &[c], Some(r)),
             Command::SAQUIT(ref c, ref r) => stringify("SAQUIT", &[c], Some(r)),
 
-            Command::NICKSERV(ref m) => stringify("NICKSERV", &[m], None),
-            Command::CHANSERV(ref m) => stringify("CHANSERV", &[m], None),
-            Command::OPERSERV(ref m) => stringify("OPERSERV", &[m], None),
-            Command::BOTSERV(ref m) => stringify("BOTSERV", &[m], None),
-            Command::HOSTSERV(ref m) => stringify("HOSTSERV", &[m], None),
-            Command::MEMOSERV(ref m) => stringify("MEMOSERV", &[m], None),
+            // Services commands are sent as space-separated words rather than a single suffix.
+            Command::NICKSERV(ref m) => stringify_service("NICKSERV", m),
+            Command::CHANSERV(ref m) => stringify_service("CHANSERV", m),
+            Command::OPERSERV(ref m) => stringify_service("OPERSERV", m),
+            Command::BOTSERV(ref m) => stringify_service("BOTSERV", m),
+            Command::HOSTSERV(ref m) => stringify_service("HOSTSERV", m),
+            Command::MEMOSERV(ref m) => stringify_service("MEMOSERV", m),
 
             Command::CAP(None, ref s, None, Some(ref p)) => {
                 stringify("CAP", &[s.to_str()], Some(p))
@@ -442,14 +475,15 @@ impl<'a> From<&'a Command> for String {
             }
             Command::BATCH(ref t, None, None) => stringify("BATCH", &[t], None),
             Command::CHGHOST(ref u, ref h) => stringify("CHGHOST", &[u, h], None),
+            Command::TAGMSG(ref t) => stringify("TAGMSG", &[t], None),
 
             Command::Response(ref resp, ref a, Some(ref s)) => {
-                stringify(&format!("{:03}", *resp as u16),
+                stringify(resp.code(),
                           &a.iter().map(|s| &s[..]).collect::<Vec<_>>(),
                           Some(s))
             }
             Command::Response(ref resp, ref a, None) => {
-                stringify(&format!("{:03}", *resp as u16),
+                stringify(resp.code(),
                           &a.iter().map(|s| &s[..]).collect::<Vec<_>>(),
                           None)
             }
@@ -537,6 +571,7 @@ impl Command {
         } else if cmd.eq_ignore_ascii_case("MODE") {
             match suffix {
                 Some(suffix) => raw(cmd, args, Some(suffix)),
+                None if args.is_empty() => raw(cmd, args, suffix),
                 None => if args[0].is_channel_name() {
                     let arg = args[1..].join(" ");
                     Command::ChannelMODE(args[0].to_owned(), Mode::as_channel_modes(&arg)?)
@@ -968,8 +1003,8 @@ impl Command {
                 Some(suffix) => {
                     if args.is_empty() {
                         Command::WHO(Some(suffix.to_owned()), None)
-                    } else if args.len() == 1 {
-                        Command::WHO(Some(args[0].to_owned()), Some(&suffix[..] == "o"))
+                    } else if args.len() == 1 && suffix == "o" {
+                        Command::WHO(Some(args[0].to_owned()), Some(true))
                     } else {
                         raw(cmd, args, Some(suffix))
                     }
@@ -979,8 +1014,8 @@ impl Command {
                         Command::WHO(None, None)
                     } else if args.len() == 1 {
                         Command::WHO(Some(args[0].to_owned()), None)
-                    } else if args.len() == 2 {
-                        Command::WHO(Some(args[0].to_owned()), Some(&args[1][..] == "o"))
+                    } else if args.len() == 2 && args[1] == "o" {
+                        Command::WHO(Some(args[0].to_owned()), Some(true))
                     } else {
                         raw(cmd, args, suffix)
                     }
@@ -1013,7 +1048,7 @@ impl Command {
                     if args.is_empty() {
                         Command::WHOWAS(suffix.to_owned(), None, None)
                     } else if args.len() == 1 {
-                        Command::WHOWAS(args[0].to_owned(), None, Some(suffix.to_owned()))
+                        Command::WHOWAS(args[0].to_owned(), Some(suffix.to_owned()), None)
                     } else if args.len() == 2 {
                         Command::WHOWAS(
                             args[0].to_owned(),
@@ -1028,7 +1063,7 @@ impl Command {
                     if args.len() == 1 {
                         Command::WHOWAS(args[0].to_owned(), None, None)
                     } else if args.len() == 2 {
-                        Command::WHOWAS(args[0].to_owned(), None, Some(args[1].to_owned()))
+                        Command::WHOWAS(args[0].to_owned(), Some(args[1].to_owned()), None)
                     } else if args.len() == 3 {
                         Command::WHOWAS(
                             args[0].to_owned(),
@@ -1316,106 +1351,34 @@ impl Command {
                 }
             }
         } else if cmd.eq_ignore_ascii_case("NICKSERV") {
-            match suffix {
-                Some(suffix) => {
-                    if !args.is_empty() {
-                        raw(cmd, args, Some(suffix))
-                    } else {
-                        Command::NICKSERV(suffix.to_owned())
-                    }
-                }
-                None => {
-                    if args.len() != 1 {
-                        raw(cmd, args, suffix)
-                    } else {
-                        Command::NICKSERV(args[0].to_owned())
-                    }
-                }
+            match service_text(&args, suffix) {
+                Some(text) => Command::NICKSERV(text),
+                None => raw(cmd, args, suffix),
             }
         } else if cmd.eq_ignore_ascii_case("CHANSERV") {
-            match suffix {
-                Some(suffix) => {
-                    if !args.is_empty() {
-                        raw(cmd, args, Some(suffix))
-                    } else {
-                        Command::CHANSERV(suffix.to_owned())
-                    }
-                }
-                None => {
-                    if args.len() != 1 {
-                        raw(cmd, args, suffix)
-                    } else {
-                        Command::CHANSERV(args[0].to_owned())
-                    }
-                }
+            match service_text(&args, suffix) {
+                Some(text) => Command::CHANSERV(text),
+                None => raw(cmd, args, suffix),
             }
         } else if cmd.eq_ignore_ascii_case("OPERSERV") {
-            match suffix {
-                Some(suffix) => {
-                    if !args.is_empty() {
-                        raw(cmd, args, Some(suffix))
-                    } else {
-                        Command::OPERSERV(suffix.to_owned())
-                    }
-                }
-                None => {
-                    if args.len() != 1 {
-                        raw(cmd, args, suffix)
-                    } else {
-                        Command::OPERSERV(args[0].to_owned())
-                    }
-                }
+            match service_text(&args, suffix) {
+                Some(text) => Command::OPERSERV(text),
+                None => raw(cmd, args, suffix),
             }
         } else if cmd.eq_ignore_ascii_case("BOTSERV") {
-            match suffix {
-                Some(suffix) => {
-                    if !args.is_empty() {
-                        raw(cmd, args, Some(suffix))
-                    } else {
-                        Command::BOTSERV(suffix.to_owned())
-                    }
-                }
-                None => {
-                    if args.len() != 1 {
-                        raw(cmd, args, suffix)
-                    } else {
-                        Command::BOTSERV(args[0].to_owned())
-                    }
-                }
+            match service_text(&args, suffix) {
+                Some(text) => Command::BOTSERV(text),
+                None => raw(cmd, args, suffix),
             }
         } else if cmd.eq_ignore_ascii_case("HOSTSERV") {
-            match suffix {
-                Some(suffix) => {
-                    if !args.is_empty() {
-                        raw(cmd, args, Some(suffix))
-                    } else {
-                        Command::HOSTSERV(suffix.to_owned())
-                    }
-                }
-                None => {
-                    if args.len() != 1 {
-                        raw(cmd, args, suffix)
-                    } else {
-                        Command::HOSTSERV(args[0].to_owned())
-                    }
-                }
+            match service_text(&args, suffix) {
+                Some(text) => Command::HOSTSERV(text),
+                None => raw(cmd, args, suffix),
             }
         } else if cmd.eq_ignore_ascii_case("MEMOSERV") {
-            match suffix {
-                Some(suffix) => {
-                    if !args.is_empty() {
-                        raw(cmd, args, Some(suffix))
-                    } else {
-                        Command::MEMOSERV(suffix.to_owned())
-                    }
-                }
-                None => {
-                    if args.len() != 1 {
-                        raw(cmd, args, suffix)
-                    } else {
-                        Command::MEMOSERV(args[0].to_owned())
-                    }
-                }
+            match service_text(&args, suffix) {
+                Some(text) => Command::MEMOSERV(text),
+                None => raw(cmd, args, suffix),
             }
         } else if cmd.eq_ignore_ascii_case("CAP") {
             if args.len() == 1 {
@@ -1532,7 +1495,7 @@ impl Command {
                         Command::METADATA(
                             args[0].to_owned(),
                             Some(c),
-                            Some(args.into_iter().skip(1).map(|s| s.to_owned()).collect()),
+                            Some(args.into_iter().skip(2).map(|s| s.to_owned()).collect()),
                             suffix.map(|s| s.to_owned()),
                         )
                     }
@@ -1555,59 +1518,55 @@ impl Command {
         } else if cmd.eq_ignore_ascii_case("MONITOR") {
             if args.len() == 1 {
                 Command::MONITOR(args[0].to_owned(), suffix.map(|s| s.to_owned()))
+            } else if args.len() == 2 && suffix.is_none() {
+                Command::MONITOR(args[0].to_owned(), Some(args[1].to_owned()))
             } else {
                 raw(cmd, args, suffix)
             }
         } else if cmd.eq_ignore_ascii_case("BATCH") {
+            // The suffix is only ever the last of the parameters.
+            let params: Vec<&str> = args.iter().cloned().chain(suffix).collect();
+            match params.len() {
+                1 => Command::BATCH(params[0].to_owned(), None, None),
+                2 => Command::BATCH(params[0].to_owned(), Some(params[1].parse().unwrap()), None),
+                n if n > 2 => {
+                    Command::BATCH(
+                        params[0].to_owned(),
+                        Some(params[1].parse().unwrap()),
+                        Some(params[2..].iter().map(|&s| s.to_owned()).collect()),
+                    )
+                }
+                _ => raw(cmd, args, suffix),
+            }
+        } else if cmd.eq_ignore_ascii_case("CHGHOST") {
             match suffix {
                 Some(suffix) => {
-                    if args.is_empty() {
-                        Command::BATCH(suffix.to_owned(), None, None)
-                    } else if args.len() == 1 {
-                        Command::BATCH(args[0].to_owned(), Some(suffix.parse().unwrap()), None)
-                    } else if args.len() > 1 {
-                        Command::BATCH(
-                            args[0].to_owned(),
-                            Some(args[1].parse().unwrap()),
-                            Some(
-                                vec![suffix.to_owned()]
-                                    .into_iter()
-                                    .chain(args.into_iter().skip(2).map(|s| s.to_owned()))
-                                    .collect(),
-                            ),
-                        )
+                    if args.len() == 1 {
+                        Command::CHGHOST(args[0].to_owned(), suffix.to_owned())
                     } else {
                         raw(cmd, args, Some(suffix))
                     }
                 }
                 None => {
-                    if args.len() == 1 {
-                        Command::BATCH(args[0].to_owned(), None, None)
-                    } else if args.len() == 2 {
-                        Command::BATCH(args[0].to_owned(), Some(args[1].parse().unwrap()), None)
-                    } else if args.len() > 2 {
-                        Command::BATCH(
-                            args[0].to_owned(),
-                            Some(args[1].parse().unwrap()),
-                            Some(args.iter().skip(2).map(|&s| s.to_owned()).collect()),
-                        )
+                    if args.len() == 2 {
+                        Command::CHGHOST(args[0].to_owned(), args[1].to_owned())
                     } else {
                         raw(cmd, args, suffix)
                     }
                 }
             }
-        } else if cmd.eq_ignore_ascii_case("CHGHOST") {
+        } else if cmd.eq_ignore_ascii_case("TAGMSG") {
             match suffix {
                 Some(suffix) => {
-                    if args.len() == 1 {
-                        Command::CHGHOST(args[0].to_owned(), suffix.to_owned())
+                    if args.is_empty() {
+                        Command::TAGMSG(suffix.to_owned())
                     } else {
                         raw(cmd, args, Some(suffix))
                     }
                 }
                 None => {
-                    if args.len() == 2 {
-                        Command::CHGHOST(args[0].to_owned(), args[1].to_owned())
+                    if args.len() == 1 {
+                        Command::TAGMSG(args[0].to_owned())
                     } else {
                         raw(cmd, args, suffix)
                     }
@@ -1623,6 +1582,131 @@ impl Command {
             raw(cmd, args, suffix)
         })
     }
+
+    /// Constructs a new Command from a command name and a flat list of its parameters, as they
+    /// would appear on the wire without distinguishing the trailing parameter. Known commands are
+    /// mapped to their typed variants, and anything else becomes a `Command::Raw` holding the
+    /// parameters verbatim. This is the inverse of serialization, and is useful for building
+    /// commands dynamically (e.g. in scripting layers).
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::proto::Command;
+    /// # fn main() {
+    /// let cmd = Command::from_args("JOIN", vec!["#rust".to_owned()]).unwrap();
+    /// assert_eq!(cmd, Command::JOIN("#rust".to_owned(), None, None));
+    /// # }
+    /// ```
+    pub fn from_args(cmd: &str, args: Vec<String>) -> Result<Command, MessageParseError> {
+        let args: Vec<&str> = args.iter().map(|s| &s[..]).collect();
+        // Most commands expect their final parameter as a suffix, so try that first and fall back
+        // to treating every parameter as a middle argument (e.g. for MODE).
+        if let Some((last, init)) = args.split_last() {
+            match Command::new(cmd, init.to_vec(), Some(last))? {
+                Command::Raw(..) => (),
+                cmd => return Ok(cmd),
+            }
+        }
+        match Command::new(cmd, args.clone(), None)? {
+            Command::Raw(..) => Ok(raw(cmd, args, None)),
+            cmd => Ok(cmd),
+        }
+    }
+
+    /// Gets the name of the command as sent, e.g. `MODE` for both `UserMODE` and `ChannelMODE`,
+    /// or the three digit code of a response.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::proto::{Command, Response};
+    /// # fn main() {
+    /// assert_eq!(Command::PRIVMSG("#rust".to_owned(), "Hi!".to_owned()).name(), "PRIVMSG");
+    /// assert_eq!(Command::Response(Response::RPL_WELCOME, vec![], None).name(), "001");
+    /// # }
+    /// ```
+    pub fn name(&self) -> &str {
+        match *self {
+            Command::PASS(..) => "PASS",
+            Command::NICK(..) => "NICK",
+            Command::USER(..) => "USER",
+            Command::OPER(..) => "OPER",
+            Command::UserMODE(..) | Command::ChannelMODE(..) => "MODE",
+            Command::SERVICE(..) => "SERVICE",
+            Command::QUIT(..) => "QUIT",
+            Command::SQUIT(..) => "SQUIT",
+            Command::JOIN(..) => "JOIN",
+            Command::PART(..) => "PART",
+            Command::TOPIC(..) => "TOPIC",
+            Command::NAMES(..) => "NAMES",
+            Command::LIST(..) => "LIST",
+            Command::INVITE(..) => "INVITE",
+            Command::KICK(..) => "KICK",
+            Command::PRIVMSG(..) => "PRIVMSG",
+            Command::NOTICE(..) => "NOTICE",
+            Command::MOTD(..) => "MOTD",
+            Command::LUSERS(..) => "LUSERS",
+            Command::VERSION(..) => "VERSION",
+            Command::STATS(..) => "STATS",
+            Command::LINKS(..) => "LINKS",
+            Command::TIME(..) => "TIME",
+            Command::CONNECT(..) => "CONNECT",
+            Command::TRACE(..) => "TRACE",
+            Command::ADMIN(..) => "ADMIN",
+            Command::INFO(..) => "INFO",
+            Command::SERVLIST(..) => "SERVLIST",
+            Command::SQUERY(..) => "SQUERY",
+            Command::WHO(..) => "WHO",
+            Command::WHOIS(..) => "WHOIS",
+            Command::WHOWAS(..) => "WHOWAS",
+            Command::KILL(..) => "KILL",
+            Command::PING(..) => "PING",
+            Command::PONG(..) => "PONG",
+            Command::ERROR(..) => "ERROR",
+            Command::AWAY(..) => "AWAY",
+            Command::REHASH => "REHASH",
+            Command::DIE => "DIE",
+            Command::RESTART => "RESTART",
+            Command::SUMMON(..) => "SUMMON",
+            Command::USERS(..) => "USERS",
+            Command::WALLOPS(..) => "WALLOPS",
+            Command::USERHOST(..) => "USERHOST",
+            Command::ISON(..) => "ISON",
+            Command::SAJOIN(..) => "SAJOIN",
+            Command::SAMODE(..) => "SAMODE",
+            Command::SANICK(..) => "SANICK",
+            Command::SAPART(..) => "SAPART",
+            Command::SAQUIT(..) => "SAQUIT",
+            Command::NICKSERV(..) => "NICKSERV",
+            Command::CHANSERV(..) => "CHANSERV",
+            Command::OPERSERV(..) => "OPERSERV",
+            Command::BOTSERV(..) => "BOTSERV",
+            Command::HOSTSERV(..) => "HOSTSERV",
+            Command::MEMOSERV(..) => "MEMOSERV",
+            Command::CAP(..) => "CAP",
+            Command::AUTHENTICATE(..) => "AUTHENTICATE",
+            Command::ACCOUNT(..) => "ACCOUNT",
+            Command::METADATA(..) => "METADATA",
+            Command::MONITOR(..) => "MONITOR",
+            Command::BATCH(..) => "BATCH",
+            Command::CHGHOST(..) => "CHGHOST",
+            Command::TAGMSG(..) => "TAGMSG",
+            Command::Response(ref resp, _, _) => resp.code(),
+            Command::Raw(ref cmd, _, _) => cmd,
+        }
+    }
+}
+
+/// Reassembles the text of a message to a service from its parameters, or returns `None` if there
+/// are none.
+fn service_text(args: &[&str], suffix: Option<&str>) -> Option<String> {
+    match (args.is_empty(), suffix) {
+        (true, None) => None,
+        (true, Some(suffix)) => Some(suffix.to_owned()),
+        (false, None) => Some(args.join(" ")),
+        (false, Some(suffix)) => Some(format!("{} :{}", args.join(" "), suffix)),
+    }
 }
 
 /// Makes a raw message from the specified command, arguments, and suffix.
@@ -1804,9 +1888,154 @@ mod test {
         assert_eq!(cmd, returned_cmd);
     }
 
+    #[test]
+    fn from_args_known_command() {
+        let args = vec!["#test".to_owned(), "key".to_owned()];
+        assert_eq!(
+            Command::from_args("JOIN", args).unwrap(),
+            Command::JOIN("#test".to_owned(), Some("key".to_owned()), None)
+        );
+        let args = vec!["#test".to_owned(), "hello there".to_owned()];
+        assert_eq!(
+            Command::from_args("PRIVMSG", args).unwrap(),
+            Command::PRIVMSG("#test".to_owned(), "hello there".to_owned())
+        );
+    }
+
+    #[test]
+    fn from_args_middle_params() {
+        let args = vec!["#test".to_owned(), "+o".to_owned(), "test".to_owned()];
+        let cmd = Command::from_args("MODE", args).unwrap();
+        assert_eq!(String::from(&cmd), "MODE #test +o test");
+    }
+
+    #[test]
+    fn from_args_unknown_command() {
+        let args = vec!["a".to_owned(), "b c".to_owned()];
+        assert_eq!(
+            Command::from_args("FOOBAR", args).unwrap(),
+            Command::Raw("FOOBAR".to_owned(), vec!["a".to_owned(), "b c".to_owned()], None)
+        );
+    }
+
+    #[test]
+    fn format_raw() {
+        let cmd = Command::Raw("FOO".to_owned(), vec!["a".to_owned(), "b".to_owned()], None);
+        assert_eq!(String::from(&cmd), "FOO a b");
+        let cmd = Command::Raw("FOO".to_owned(), vec!["a".to_owned(), "b c".to_owned()], None);
+        assert_eq!(String::from(&cmd), "FOO a :b c");
+        let cmd = Command::Raw("FOO".to_owned(), vec!["a".to_owned(), String::new()], None);
+        assert_eq!(String::from(&cmd), "FOO a :");
+        let cmd = Command::Raw("FOO".to_owned(), vec!["a".to_owned()], Some("b".to_owned()));
+        assert_eq!(String::from(&cmd), "FOO a :b");
+    }
+
+    #[test]
+    fn format_topic_with_colon() {
+        let cmd = Command::TOPIC("#test".to_owned(), Some("rules: be nice".to_owned()));
+        assert_eq!(String::from(&cmd), "TOPIC #test :rules: be nice");
+        let cmd = Command::TOPIC("#test".to_owned(), Some(":)".to_owned()));
+        assert_eq!(String::from(&cmd), "TOPIC #test ::)");
+    }
+
+    #[test]
+    fn format_kick_reason_with_spaces() {
+        let cmd = Command::KICK("#test".to_owned(), "user".to_owned(), Some("go away".to_owned()));
+        assert_eq!(String::from(&cmd), "KICK #test user :go away");
+    }
+
+    #[test]
+    fn format_empty_trailing_param() {
+        let cmd = Command::TOPIC("#test".to_owned(), Some(String::new()));
+        assert_eq!(String::from(&cmd), "TOPIC #test :");
+        let cmd = Command::Response(Response::RPL_TOPIC, vec!["test".into(), "".into()], None);
+        assert_eq!(String::from(&cmd), "332 test :");
+    }
+
+    #[test]
+    fn format_last_param_as_suffix() {
+        let cmd = Command::SQUERY("alis".to_owned(), "list *rust*".to_owned());
+        assert_eq!(String::from(&cmd), "SQUERY alis :list *rust*");
+        let cmd = Command::JOIN("#test".to_owned(), Some(":key".to_owned()), None);
+        assert_eq!(String::from(&cmd), "JOIN #test ::key");
+        let cmd = Command::NICKSERV("IDENTIFY password".to_owned());
+        assert_eq!(String::from(&cmd), "NICKSERV IDENTIFY password");
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let cmd = Command::Raw("FOO".to_owned(), vec!["a".to_owned(), "b c".to_owned()], None);
+        let line = Message::from(cmd).to_string();
+        assert_eq!(
+            line.parse::<Message>().unwrap().command,
+            Command::Raw("FOO".to_owned(), vec!["a".to_owned()], Some("b c".to_owned()))
+        );
+    }
+
     #[test]
     fn parse_user_message() {
         let cmd = "USER a 0 * b".parse::<Message>().unwrap().command;
         assert_eq!(Command::USER("a".to_string(), "0".to_string(), "b".to_string()), cmd);
     }
+
+    fn parse(line: &str) -> Command {
+        line.parse::<Message>().unwrap().command
+    }
+
+    #[test]
+    fn parse_optional_params() {
+        assert_eq!(parse("WHO #test o"), Command::WHO(Some("#test".into()), Some(true)));
+        assert_eq!(
+            parse("WHO #test %tcuhnfa,152"),
+            Command::Raw("WHO".into(), vec!["#test".into(), "%tcuhnfa,152".into()], None)
+        );
+        assert_eq!(parse("WHOWAS ada 5"), Command::WHOWAS("ada".into(), Some("5".into()), None));
+        assert_eq!(
+            parse("MONITOR + ada,betsy"),
+            Command::MONITOR("+".into(), Some("ada,betsy".into()))
+        );
+        assert_eq!(parse("MODE"), Command::Raw("MODE".into(), vec![], None));
+    }
+
+    #[test]
+    fn parse_batch_params_in_order() {
+        assert_eq!(
+            parse("BATCH +ref chathistory #test :with spaces"),
+            Command::BATCH(
+                "+ref".into(),
+                Some(super::BatchSubCommand::CUSTOM("CHATHISTORY".into())),
+                Some(vec!["#test".into(), "with spaces".into()]),
+            )
+        );
+    }
+
+    #[test]
+    fn service_round_trip() {
+        for text in &["IDENTIFY password", "SET :x", "a  b", ""] {
+            let line = String::from(&Command::NICKSERV(text.to_string()));
+            assert_eq!(parse(&line), Command::NICKSERV(text.to_string()));
+        }
+        assert_eq!(parse("NICKSERV a :b c"), Command::NICKSERV("a :b c".into()));
+    }
+
+    #[test]
+    fn pass_round_trip() {
+        assert_eq!(String::from(&Command::PASS("user/net:pass".into())), "PASS :user/net:pass");
+        for pass in &["user/net:pass", "account:password", ":leading", "with space", ""] {
+            let line = String::from(&Command::PASS(pass.to_string()));
+            assert_eq!(parse(&line), Command::PASS(pass.to_string()));
+        }
+    }
+
+    #[test]
+    fn parse_modes_without_args() {
+        use proto::{ChannelMode, Mode};
+        assert_eq!(parse("MODE #test +b -e"), Command::ChannelMODE("#test".into(), vec![
+            Mode::Plus(ChannelMode::Ban, None), Mode::Minus(ChannelMode::Exception, None),
+        ]));
+        assert_eq!(parse("MODE #test +ob ada *!*@host"), Command::ChannelMODE("#test".into(), vec![
+            Mode::Plus(ChannelMode::Oper, Some("ada".into())),
+            Mode::Plus(ChannelMode::Ban, Some("*!*@host".into())),
+        ]));
+    }
 }
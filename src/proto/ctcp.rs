@@ -0,0 +1,162 @@
+//! Quoting and dequoting of CTCP messages, as described in the
+//! [CTCP specification](http://www.irchelp.org/protocol/ctcpspec.html).
+//!
+//! CTCP defines two layers of quoting. Low-level quoting (with `\x10`) protects the characters
+//! that cannot appear in an IRC message at all, i.e. `NUL`, `CR`, and `LF`, and applies to the
+//! whole message body. CTCP-level quoting (with `\`) protects the `\x01` delimiters within a CTCP
+//! payload. Many modern clients implement neither, so the `ClientExt` CTCP helpers send payloads
+//! as is. These functions are intended for payloads that may contain such characters, e.g. for
+//! DCC or custom CTCPs.
+
+/// The CTCP delimiter, which surrounds each CTCP payload in a message.
+pub const DELIMITER: char = '\u{001}';
+
+/// The escape character for low-level quoting.
+const M_QUOTE: char = '\u{010}';
+
+/// The escape character for CTCP-level quoting.
+const X_QUOTE: char = '\\';
+
+/// Applies low-level quoting to the given message body, escaping `NUL`, `CR`, `LF`, and `\x10`.
+pub fn low_level_quote(body: &str) -> String {
+    let mut quoted = String::with_capacity(body.len());
+    for c in body.chars() {
+        match c {
+            '\0' => quoted.push_str("\u{010}0"),
+            '\n' => quoted.push_str("\u{010}n"),
+            '\r' => quoted.push_str("\u{010}r"),
+            M_QUOTE => quoted.push_str("\u{010}\u{010}"),
+            c => quoted.push(c),
+        }
+    }
+    quoted
+}
+
+/// Removes low-level quoting from the given message body. Per the specification, a `\x10`
+/// followed by any other character is dropped, leaving the character.
+pub fn low_level_dequote(body: &str) -> String {
+    let mut dequoted = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != M_QUOTE {
+            dequoted.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('0') => dequoted.push('\0'),
+            Some('n') => dequoted.push('\n'),
+            Some('r') => dequoted.push('\r'),
+            Some(c) => dequoted.push(c),
+            None => (),
+        }
+    }
+    dequoted
+}
+
+/// Applies CTCP-level quoting to the given payload, escaping `\x01` and `\`.
+pub fn ctcp_quote(payload: &str) -> String {
+    let mut quoted = String::with_capacity(payload.len());
+    for c in payload.chars() {
+        match c {
+            DELIMITER => quoted.push_str("\\a"),
+            X_QUOTE => quoted.push_str("\\\\"),
+            c => quoted.push(c),
+        }
+    }
+    quoted
+}
+
+/// Removes CTCP-level quoting from the given payload. Per the specification, a `\` followed by
+/// any other character is dropped, leaving the character.
+pub fn ctcp_dequote(payload: &str) -> String {
+    let mut dequoted = String::with_capacity(payload.len());
+    let mut chars = payload.chars();
+    while let Some(c) = chars.next() {
+        if c != X_QUOTE {
+            dequoted.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('a') => dequoted.push(DELIMITER),
+            Some(c) => dequoted.push(c),
+            None => (),
+        }
+    }
+    dequoted
+}
+
+/// Encodes the given CTCP payload (e.g. `ACTION waves`) as a message body, applying both layers
+/// of quoting and adding the delimiters.
+///
+/// # Example
+/// ```
+/// # extern crate irc;
+/// # use irc::proto::ctcp;
+/// # fn main() {
+/// assert_eq!(ctcp::encode("PING 1\r\n2"), "\u{001}PING 1\u{010}r\u{010}n2\u{001}");
+/// # }
+/// ```
+pub fn encode(payload: &str) -> String {
+    low_level_quote(&format!("{}{}{}", DELIMITER, ctcp_quote(payload), DELIMITER))
+}
+
+/// Decodes the CTCP payload from the given message body, removing the delimiters and both layers
+/// of quoting. This returns `None` if the body is not a CTCP message. As many clients do, this
+/// tolerates a missing closing delimiter.
+pub fn decode(body: &str) -> Option<String> {
+    let body = low_level_dequote(body);
+    if !body.starts_with(DELIMITER) {
+        return None;
+    }
+    let payload = &body[DELIMITER.len_utf8()..];
+    let payload = if payload.ends_with(DELIMITER) {
+        &payload[..payload.len() - DELIMITER.len_utf8()]
+    } else {
+        payload
+    };
+    Some(ctcp_dequote(payload))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ctcp_dequote, ctcp_quote, decode, encode, low_level_dequote, low_level_quote};
+
+    #[test]
+    fn low_level_round_trip() {
+        let body = "a\0b\rc\nd\u{010}e";
+        let quoted = low_level_quote(body);
+        assert_eq!(quoted, "a\u{010}0b\u{010}rc\u{010}nd\u{010}\u{010}e");
+        assert!(!quoted.contains(|c| c == '\0' || c == '\r' || c == '\n'));
+        assert_eq!(low_level_dequote(&quoted), body);
+    }
+
+    #[test]
+    fn ctcp_level_round_trip() {
+        let payload = "DCC SEND \u{001}file\\name\u{001}";
+        let quoted = ctcp_quote(payload);
+        assert_eq!(quoted, "DCC SEND \\afile\\\\name\\a");
+        assert_eq!(ctcp_dequote(&quoted), payload);
+    }
+
+    #[test]
+    fn unknown_escapes() {
+        assert_eq!(low_level_dequote("a\u{010}xb\u{010}"), "axb");
+        assert_eq!(ctcp_dequote("a\\xb\\"), "axb");
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let payload = "CUSTOM \u{001}delimited\u{001} \\ \u{010} line\r\nbreak\0";
+        let encoded = encode(payload);
+        assert!(encoded.starts_with('\u{001}') && encoded.ends_with('\u{001}'));
+        assert_eq!(encoded.matches('\u{001}').count(), 2);
+        assert!(!encoded.contains(|c| c == '\0' || c == '\r' || c == '\n'));
+        assert_eq!(decode(&encoded), Some(payload.to_owned()));
+    }
+
+    #[test]
+    fn decode_non_ctcp() {
+        assert_eq!(decode("hello"), None);
+        assert_eq!(decode("\u{001}VERSION"), Some("VERSION".to_owned()));
+    }
+}
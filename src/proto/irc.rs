@@ -9,12 +9,45 @@ use proto::message::Message;
 /// An IRC codec built around an inner codec.
 pub struct IrcCodec {
     inner: LineCodec,
+    sanitize_incoming: bool,
 }
 
 impl IrcCodec {
     /// Creates a new instance of IrcCodec wrapping a LineCodec with the specific encoding.
     pub fn new(label: &str) -> error::Result<IrcCodec> {
-        LineCodec::new(label).map(|codec| IrcCodec { inner: codec })
+        LineCodec::new(label).map(|codec| IrcCodec { inner: codec, sanitize_incoming: false })
+    }
+
+    /// Creates a new instance of IrcCodec wrapping a LineCodec with the specific encoding, which
+    /// either replaces invalid byte sequences (when `decode_lossy` is true) or fails on them.
+    pub fn with_decode_lossy(label: &str, decode_lossy: bool) -> error::Result<IrcCodec> {
+        LineCodec::with_decode_lossy(label, decode_lossy).map(|codec| {
+            IrcCodec { inner: codec, sanitize_incoming: false }
+        })
+    }
+
+    /// Sets whether carriage returns and line feeds embedded in received messages are stripped
+    /// before they are parsed. Such characters are malformed, but some servers pass them through
+    /// in the text of messages, where they could otherwise be used for injection by anything
+    /// echoing the text. They are preserved unless this is set.
+    pub fn sanitize_incoming(mut self, sanitize_incoming: bool) -> IrcCodec {
+        self.sanitize_incoming = sanitize_incoming;
+        self
+    }
+
+    /// Strips any carriage returns and line feeds from the given line other than its terminator.
+    fn strip_line_breaks(line: String) -> String {
+        let is_break = |c| c == '\r' || c == '\n';
+        let (body, terminator) = {
+            let body = line.trim_end_matches(is_break);
+            (body, &line[body.len()..])
+        };
+        if !body.contains(is_break) {
+            return line;
+        }
+        let mut stripped: String = body.chars().filter(|&c| !is_break(c)).collect();
+        stripped.push_str(terminator);
+        stripped
     }
 
     /// Sanitizes the input string by cutting up to (and including) the first occurence of a line
@@ -39,8 +72,16 @@ impl Decoder for IrcCodec {
     type Error = error::IrcError;
 
     fn decode(&mut self, src: &mut BytesMut) -> error::Result<Option<Message>> {
+        let sanitize_incoming = self.sanitize_incoming;
         self.inner.decode(src).and_then(|res| {
-            res.map_or(Ok(None), |msg| msg.parse::<Message>().map(Some))
+            res.map_or(Ok(None), |line| {
+                let line = if sanitize_incoming {
+                    IrcCodec::strip_line_breaks(line)
+                } else {
+                    line
+                };
+                line.parse::<Message>().map(Some)
+            })
         })
     }
 }
@@ -12,14 +12,21 @@ use error;
 /// A line-based codec parameterized by an encoding.
 pub struct LineCodec {
     encoding: EncodingRef,
+    decode_lossy: bool,
     next_index: usize,
 }
 
 impl LineCodec {
     /// Creates a new instance of LineCodec from the specified encoding.
     pub fn new(label: &str) -> error::Result<LineCodec> {
+        LineCodec::with_decode_lossy(label, true)
+    }
+
+    /// Creates a new instance of LineCodec from the specified encoding, which either replaces
+    /// invalid byte sequences with U+FFFD (when `decode_lossy` is true) or fails on them.
+    pub fn with_decode_lossy(label: &str, decode_lossy: bool) -> error::Result<LineCodec> {
         encoding_from_whatwg_label(label)
-            .map(|enc| LineCodec { encoding: enc, next_index: 0 })
+            .map(|enc| LineCodec { encoding: enc, decode_lossy: decode_lossy, next_index: 0 })
             .ok_or_else(|| io::Error::new(
                 io::ErrorKind::InvalidInput,
                 &format!("Attempted to use unknown codec {}.", label)[..],
@@ -40,8 +47,13 @@ impl Decoder for LineCodec {
             self.next_index = 0;
 
             // Decode the line using the codec's encoding.
-            match self.encoding.decode(line.as_ref(), DecoderTrap::Replace) {
+            let trap = if self.decode_lossy { DecoderTrap::Replace } else { DecoderTrap::Strict };
+            match self.encoding.decode(line.as_ref(), trap) {
                 Ok(data) => Ok(Some(data)),
+                Err(_) if !self.decode_lossy => Err(error::IrcError::InvalidUtf8 {
+                    codec: self.encoding.name(),
+                    data: line.to_vec(),
+                }),
                 Err(data) => Err(
                     io::Error::new(
                         io::ErrorKind::InvalidInput,
@@ -79,3 +91,68 @@ impl Encoder for LineCodec {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use bytes::BytesMut;
+    use tokio_io::codec::{Decoder, Encoder};
+
+    use error::IrcError;
+    use super::LineCodec;
+
+    #[test]
+    fn decode_latin1() {
+        let mut codec = LineCodec::new("ISO-8859-1").unwrap();
+        let mut buf = BytesMut::from(&b"PRIVMSG #test :caf\xe9\r\n"[..]);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some("PRIVMSG #test :café\r\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn encode_latin1() {
+        let mut codec = LineCodec::new("ISO-8859-1").unwrap();
+        let mut buf = BytesMut::new();
+        codec.encode("PRIVMSG #test :café\r\n".to_owned(), &mut buf).unwrap();
+        assert_eq!(&buf[..], &b"PRIVMSG #test :caf\xe9\r\n"[..]);
+    }
+
+    #[test]
+    fn round_trip_latin1() {
+        let mut codec = LineCodec::new("ISO-8859-1").unwrap();
+        let mut buf = BytesMut::new();
+        codec.encode("é\n".to_owned(), &mut buf).unwrap();
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some("é\n".to_owned()));
+    }
+
+    #[test]
+    fn decode_invalid_lossy() {
+        let mut codec = LineCodec::with_decode_lossy("UTF-8", true).unwrap();
+        let mut buf = BytesMut::from(&b"PRIVMSG #test :caf\xe9\r\nPING :test\r\n"[..]);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some("PRIVMSG #test :caf\u{FFFD}\r\n".to_owned())
+        );
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some("PING :test\r\n".to_owned()));
+    }
+
+    #[test]
+    fn decode_invalid_strict() {
+        let mut codec = LineCodec::with_decode_lossy("UTF-8", false).unwrap();
+        let mut buf = BytesMut::from(&b"PRIVMSG #test :caf\xe9\r\nPING :test\r\n"[..]);
+        match codec.decode(&mut buf) {
+            Err(IrcError::InvalidUtf8 { data, .. }) => {
+                assert_eq!(&data[..], &b"PRIVMSG #test :caf\xe9\r\n"[..])
+            }
+            res => panic!("expected InvalidUtf8, got {:?}", res),
+        }
+        // The offending line is consumed, so later lines still decode.
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some("PING :test\r\n".to_owned()));
+    }
+
+    #[test]
+    fn unknown_encoding() {
+        assert!(LineCodec::new("not-an-encoding").is_err());
+    }
+}
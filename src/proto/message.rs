@@ -1,11 +1,15 @@
 //! A module providing a data structure for messages to and from IRC servers.
-use std::borrow::ToOwned;
+use std::borrow::{Cow, ToOwned};
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use std::str::FromStr;
+use std::str::{FromStr, SplitN};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use proto::casemap::nicknames_eq;
 use error;
 use error::{IrcError, MessageParseError};
-use proto::{Command, ChannelExt};
+use proto::{Command, ChannelExt, ChannelJoinError, ChannelVisibility, Response};
+use proto::ctcp::DELIMITER;
+use proto::reply::{Severity, StandardReply};
 
 /// A data structure representing an IRC message according to the protocol specification. It
 /// consists of a collection of IRCv3 tags, a prefix (describing the source of the message), and
@@ -119,6 +123,417 @@ impl Message {
         }
     }
 
+    /// Gets the trailing parameter of this message, i.e. the part after the ` :` in its wire form,
+    /// whatever the command. This is the text of a `PRIVMSG` or `NOTICE`, and the reason given in
+    /// a `KICK`, `PART` or `QUIT`. This is `None` if the command has no trailing parameter. As with
+    /// [`params`](#method.params), the suffix is taken from the command as serialized, and so is
+    /// owned.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let msg: Message = ":op!op@host KICK #rust ada :Go away.".parse().unwrap();
+    /// assert_eq!(msg.suffix(), Some("Go away.".to_owned()));
+    /// # }
+    /// ```
+    pub fn suffix(&self) -> Option<String> {
+        let line = String::from(&self.command);
+        MessageRef::parse(&line).ok()?.suffix.map(|s| s.to_owned())
+    }
+
+    /// Gets the text of the action this message represents, if it is a `PRIVMSG` with a CTCP
+    /// `ACTION` (as sent by `/me`) as its body. As with other CTCP messages, a missing closing
+    /// delimiter is tolerated.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let msg: Message = ":ada PRIVMSG #channel :\u{001}ACTION waves\u{001}".parse().unwrap();
+    /// assert_eq!(msg.as_action(), Some("waves"));
+    /// # }
+    /// ```
+    pub fn as_action(&self) -> Option<&str> {
+        let payload = match self.command {
+            Command::PRIVMSG(_, ref body) => ctcp_payload(body)?,
+            _ => return None,
+        };
+        match payload.find(' ') {
+            Some(i) if &payload[..i] == "ACTION" => Some(&payload[i + 1..]),
+            None if payload == "ACTION" => Some(""),
+            _ => None,
+        }
+    }
+
+    /// Gets the timestamp echoed by this message as the time since the Unix epoch, if it is a
+    /// `NOTICE` replying to a CTCP `PING` sent with
+    /// [`send_ctcp_ping`](../../client/ext/trait.ClientExt.html#method.send_ctcp_ping) (in
+    /// seconds) or
+    /// [`send_ctcp_ping_ms`](../../client/ext/trait.ClientExt.html#method.send_ctcp_ping_ms) (in
+    /// milliseconds). The two are told apart by magnitude, as a timestamp in seconds would not
+    /// reach that of one in milliseconds for thousands of years.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use std::time::Duration;
+    /// # use irc::client::prelude::Message;
+    /// # fn main() {
+    /// let msg: Message = ":ada NOTICE test :\u{001}PING 1500000000\u{001}".parse().unwrap();
+    /// assert_eq!(msg.as_ctcp_ping_reply(), Some(Duration::from_secs(1500000000)));
+    /// # }
+    /// ```
+    pub fn as_ctcp_ping_reply(&self) -> Option<Duration> {
+        let payload = match self.command {
+            Command::NOTICE(_, ref body) => ctcp_payload(body)?,
+            _ => return None,
+        };
+        let mut tokens = payload.split(' ');
+        match (tokens.next(), tokens.next(), tokens.next()) {
+            (Some(tag), Some(time), None) if tag.eq_ignore_ascii_case("PING") => {
+                match time.parse().ok()? {
+                    time if time < MIN_CTCP_PING_MILLIS => Some(Duration::from_secs(time)),
+                    time => Some(Duration::from_millis(time)),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Gets the round-trip time of the CTCP `PING` that this message replies to, i.e. the time
+    /// elapsed since the timestamp it echoes. This returns `None` if this is not a reply to a CTCP
+    /// `PING` or if the timestamp is in the future.
+    pub fn ctcp_ping_latency(&self) -> Option<Duration> {
+        let sent = self.as_ctcp_ping_reply()?;
+        SystemTime::now().duration_since(UNIX_EPOCH).ok()?.checked_sub(sent)
+    }
+
+    /// Gets the standard reply in this message, if it is a `FAIL`, `WARN`, or `NOTE` message with
+    /// at least a command, a code, and a description.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::Message;
+    /// # use irc::proto::Severity;
+    /// # fn main() {
+    /// let msg: Message = "FAIL JOIN CHANNEL_FULL #rust :Channel is full".parse().unwrap();
+    /// let reply = msg.as_standard_reply().unwrap();
+    /// assert_eq!(reply.severity, Severity::Fail);
+    /// assert_eq!(reply.code, "CHANNEL_FULL");
+    /// # }
+    /// ```
+    pub fn as_standard_reply(&self) -> Option<StandardReply> {
+        let severity = Severity::from_command(self.command_name())?;
+        StandardReply::from_params(severity, self.params())
+    }
+
+    /// Gets the server-assigned ID of this message from its `msgid` tag, if it has one. This ID can
+    /// be used to refer to the message, e.g. when replying or reacting to it.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let msg: Message = "@msgid=abc123 :ada PRIVMSG #channel :Hi!".parse().unwrap();
+    /// assert_eq!(msg.msgid(), Some("abc123"));
+    /// # }
+    /// ```
+    pub fn msgid(&self) -> Option<&str> {
+        self.tag_value("msgid")
+    }
+
+    /// Determines whether this message was sent by a bot, i.e. whether the server marked it with
+    /// the `bot` tag (or `draft/bot`, as named by earlier drafts of the specification).
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let msg: Message = "@bot :robo PRIVMSG #channel :Beep!".parse().unwrap();
+    /// assert!(msg.is_from_bot());
+    /// # }
+    /// ```
+    pub fn is_from_bot(&self) -> bool {
+        match self.tags {
+            Some(ref tags) => tags.iter().any(|tag| tag.0 == "bot" || tag.0 == "draft/bot"),
+            None => false,
+        }
+    }
+
+    /// Gets the value of the tag with the given key, if the message has such a tag with a value.
+    fn tag_value(&self, key: &str) -> Option<&str> {
+        self.tags.as_ref().and_then(|tags| {
+            tags.iter().find(|tag| tag.0 == key).and_then(|tag| tag.1.as_ref().map(|s| &s[..]))
+        })
+    }
+
+    /// Gets the details of the reaction this message represents, if it is a `TAGMSG` sent by a
+    /// user with both the `+draft/react` and `+draft/reply` client tags, i.e. the counterpart of
+    /// [`send_react`](../../client/ext/trait.ClientExt.html#method.send_react).
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let msg: Message = "@+draft/reply=abc123;+draft/react=👍 :ada!ada@host TAGMSG #rust"
+    ///     .parse().unwrap();
+    /// let reaction = msg.as_reaction().unwrap();
+    /// assert_eq!(reaction.from, "ada");
+    /// assert_eq!(reaction.target_msgid, "abc123");
+    /// assert_eq!(reaction.emoji, "👍");
+    /// # }
+    /// ```
+    pub fn as_reaction(&self) -> Option<Reaction> {
+        match self.command {
+            Command::TAGMSG(ref target) => Some(Reaction {
+                from: self.source_nickname()?.to_owned(),
+                target: target.clone(),
+                target_msgid: self.tag_value("+draft/reply")?.to_owned(),
+                emoji: self.tag_value("+draft/react")?.to_owned(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Gets the details of the invite this message represents, if it is an `INVITE` sent by a
+    /// user. With the `invite-notify` capability, servers also notify channel members of invites
+    /// sent to other users, which can be told apart using
+    /// [`Invite::is_for`](./struct.Invite.html#method.is_for).
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let msg: Message = ":ada!ada@host INVITE betsy #rust".parse().unwrap();
+    /// let invite = msg.as_invite().unwrap();
+    /// assert_eq!(invite.inviter, "ada");
+    /// assert!(invite.is_for("Betsy"));
+    /// # }
+    /// ```
+    pub fn as_invite(&self) -> Option<Invite> {
+        match self.command {
+            Command::INVITE(ref invitee, ref channel) => self.source_nickname().map(|inviter| {
+                Invite {
+                    inviter: inviter.to_owned(),
+                    invitee: invitee.clone(),
+                    channel: channel.clone(),
+                }
+            }),
+            _ => None,
+        }
+    }
+
+    /// Gets the account that the source of this message logged in to, if it is an `ACCOUNT`
+    /// message sent under the `account-notify` capability. This is `Some(None)` when the user
+    /// logged out, which servers indicate with an account name of `*`.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let login: Message = ":ada!ada@host ACCOUNT ada".parse().unwrap();
+    /// assert_eq!(login.as_account(), Some(Some("ada".to_owned())));
+    /// let logout: Message = ":ada!ada@host ACCOUNT *".parse().unwrap();
+    /// assert_eq!(logout.as_account(), Some(None));
+    /// # }
+    /// ```
+    pub fn as_account(&self) -> Option<Option<String>> {
+        match self.command {
+            Command::ACCOUNT(ref account) if account == "*" => Some(None),
+            Command::ACCOUNT(ref account) => Some(Some(account.clone())),
+            _ => None,
+        }
+    }
+
+    /// Determines whether this message is a `KICK` removing the user with the given nickname, i.e.
+    /// the client itself when given its current nickname, from a channel. Nicknames are compared
+    /// under RFC 1459 casemapping, and each of the targets of a `KICK` of several users is
+    /// checked.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let msg: Message = ":op!op@host KICK #rust ada,Betsy :Go away.".parse().unwrap();
+    /// assert!(msg.is_self_kick("betsy"));
+    /// assert!(!msg.is_self_kick("carol"));
+    /// # }
+    /// ```
+    pub fn is_self_kick(&self, our_nick: &str) -> bool {
+        match self.command {
+            Command::KICK(_, ref users, _) => {
+                users.split(',').any(|user| nicknames_eq(user, our_nick))
+            }
+            _ => false,
+        }
+    }
+
+    /// Gets the changes in presence reported by this message, if it is a `MONITOR` or `WATCH`
+    /// notification that users on the client's list came online or went offline. This is empty
+    /// for any other message.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # use irc::proto::message::Presence;
+    /// # fn main() {
+    /// let msg: Message = ":irc.test.net 730 me :ada!ada@host,betsy".parse().unwrap();
+    /// assert_eq!(msg.as_presence(), vec![
+    ///     Presence::Online("ada".to_owned()),
+    ///     Presence::Online("betsy".to_owned()),
+    /// ]);
+    /// # }
+    /// ```
+    pub fn as_presence(&self) -> Vec<Presence> {
+        let (resp, args, suffix) = match self.command {
+            Command::Response(resp, ref args, ref suffix) => (resp, args, suffix),
+            _ => return vec![],
+        };
+        let presence: fn(String) -> Presence = match resp {
+            Response::RPL_MONONLINE | Response::RPL_LOGON | Response::RPL_NOWON |
+            Response::RPL_NOWISAWAY => Presence::Online,
+            Response::RPL_MONOFFLINE | Response::RPL_LOGOFF | Response::RPL_NOWOFF => {
+                Presence::Offline
+            }
+            _ => return vec![],
+        };
+        match resp {
+            // <client> :target[!user@host][,target[!user@host]]*
+            Response::RPL_MONONLINE | Response::RPL_MONOFFLINE => {
+                let targets = suffix.as_ref().or_else(|| args.get(1));
+                targets.map_or(vec![], |targets| {
+                    targets.split(',')
+                        .filter(|t| !t.is_empty())
+                        .map(|t| presence(t.split('!').next().unwrap().to_owned()))
+                        .collect()
+                })
+            }
+            // <client> <nick> <user> <host> <timestamp> :<text>
+            _ => args.get(1).map_or(vec![], |nick| vec![presence(nick.clone())]),
+        }
+    }
+
+    /// Gets the channel and the reason for a server's refusal to let the client join it, if this
+    /// message is one of the error numerics sent in response to a failed `JOIN`.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let msg: Message = ":irc.test.net 474 ada #rust :Cannot join channel (+b)".parse().unwrap();
+    /// assert_eq!(msg.as_join_error(), Some(("#rust", ChannelJoinError::Banned)));
+    /// # }
+    /// ```
+    pub fn as_join_error(&self) -> Option<(&str, ChannelJoinError)> {
+        match self.command {
+            Command::Response(resp, ref args, _) => {
+                let err = ChannelJoinError::from_response(resp)?;
+                args.get(1).map(|chan| (&chan[..], err))
+            }
+            _ => None,
+        }
+    }
+
+    /// Gets the channel and its visibility, if this message is an `RPL_NAMREPLY`.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let msg: Message = ":irc.test.net 353 ada @ #rust :ada @betsy".parse().unwrap();
+    /// assert_eq!(msg.as_channel_visibility(), Some(("#rust", ChannelVisibility::Secret)));
+    /// # }
+    /// ```
+    pub fn as_channel_visibility(&self) -> Option<(&str, ChannelVisibility)> {
+        match self.command {
+            // <client> <symbol> <channel> :[prefix]<nick>{ [prefix]<nick>}
+            Command::Response(Response::RPL_NAMREPLY, ref args, _) if args.len() == 3 => {
+                ChannelVisibility::from_symbol(&args[1]).map(|vis| (&args[2][..], vis))
+            }
+            _ => None,
+        }
+    }
+
+    /// Gets the metadata this message reports, if it is an `RPL_KEYVALUE` (or
+    /// `RPL_WHOISKEYVALUE`) sent in reply to a `METADATA` command. The value is `None` if the
+    /// server reports the key as having no value.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let msg: Message = ":irc.test.net 761 ada betsy avatar * :https://example.com/betsy.png"
+    ///     .parse()
+    ///     .unwrap();
+    /// let metadata = msg.as_metadata().unwrap();
+    /// assert_eq!(metadata.target, "betsy");
+    /// assert_eq!(metadata.key, "avatar");
+    /// assert_eq!(metadata.value, Some("https://example.com/betsy.png".to_owned()));
+    /// # }
+    /// ```
+    pub fn as_metadata(&self) -> Option<Metadata> {
+        match self.command {
+            // <client> <target> <key> <visibility> :[<value>]
+            Command::Response(Response::RPL_KEYVALUE, ref args, ref value) |
+            Command::Response(Response::RPL_WHOISKEYVALUE, ref args, ref value)
+                if args.len() == 4 => {
+                Some(Metadata {
+                    target: args[1].clone(),
+                    key: args[2].clone(),
+                    value: value.clone(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Gets the name of the command as sent, whether or not it is one of the typed commands. For
+    /// responses, this is the three digit code, e.g. `001` for `RPL_WELCOME`.
+    ///
+    /// # Example
+    /// ```
+    /// # extern crate irc;
+    /// # use irc::client::prelude::*;
+    /// # fn main() {
+    /// let message: Message = "MODE #rust +o ada\r\n".parse().unwrap();
+    /// assert_eq!(message.command_name(), "MODE");
+    /// assert_eq!(message.params(), vec!["#rust", "+o", "ada"]);
+    /// # }
+    /// ```
+    pub fn command_name(&self) -> &str {
+        self.command.name()
+    }
+
+    /// Gets the parameters of the command as sent, with the suffix (if any) as the last
+    /// parameter, whether or not the command is one of the typed commands. Together with
+    /// [`command_name`](#method.command_name), this allows handling any command uniformly, e.g.
+    /// in plugins. As some parameters of typed commands (such as modes) are not stored as
+    /// strings, the parameters are taken from the command as serialized, and so are owned.
+    pub fn params(&self) -> Vec<String> {
+        let line = String::from(&self.command);
+        match MessageRef::parse(&line) {
+            Ok(msg) => {
+                msg.args.split(' ').filter(|s| !s.is_empty()).chain(msg.suffix)
+                    .map(|s| s.to_owned()).collect()
+            }
+            Err(_) => vec![],
+        }
+    }
+
     /// Converts a Message into a String according to the IRC protocol.
     ///
     /// # Example
@@ -140,7 +555,7 @@ impl Message {
                 ret.push_str(&tag.0);
                 if let Some(ref value) = tag.1 {
                     ret.push('=');
-                    ret.push_str(value);
+                    escape_tag_value(&mut ret, value);
                 }
                 ret.push(';');
             }
@@ -173,28 +588,132 @@ impl FromStr for Message {
     type Err = IrcError;
 
     fn from_str(s: &str) -> Result<Message, Self::Err> {
-        if s.is_empty() {
+        MessageRef::parse(s)?.to_message()
+    }
+}
+
+impl<'a> From<&'a str> for Message {
+    fn from(s: &'a str) -> Message {
+        s.parse().unwrap()
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.to_string())
+    }
+}
+
+/// The smallest CTCP `PING` timestamp treated as being in milliseconds rather than seconds, i.e.
+/// early 1973 in milliseconds or the year 5138 in seconds.
+const MIN_CTCP_PING_MILLIS: u64 = 100_000_000_000;
+
+/// Gets the payload of the CTCP message in the given body, if any. As with other CTCP messages, a
+/// missing closing delimiter is tolerated.
+fn ctcp_payload(body: &str) -> Option<&str> {
+    if !body.starts_with(DELIMITER) {
+        return None;
+    }
+    let payload = &body[DELIMITER.len_utf8()..];
+    if payload.ends_with(DELIMITER) {
+        Some(&payload[..payload.len() - DELIMITER.len_utf8()])
+    } else {
+        Some(payload)
+    }
+}
+
+/// Appends the given tag value to the string, escaping the characters that cannot appear in tag
+/// values as described in [IRCv3.2](http://ircv3.net/specs/core/message-tags-3.2.html).
+fn escape_tag_value(ret: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            ';' => ret.push_str("\\:"),
+            ' ' => ret.push_str("\\s"),
+            '\\' => ret.push_str("\\\\"),
+            '\r' => ret.push_str("\\r"),
+            '\n' => ret.push_str("\\n"),
+            c => ret.push(c),
+        }
+    }
+}
+
+/// Removes the escaping from the given tag value. Per the specification, a backslash followed by
+/// any other character is dropped, leaving the character, and a trailing backslash is dropped.
+fn unescape_tag_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => (),
+        }
+    }
+    unescaped
+}
+
+/// A message borrowing each of its components from the line it was parsed from, for consumers
+/// that inspect each message and then discard it, e.g. when processing logs or proxying. Unlike
+/// parsing a [`Message`](./struct.Message.html), parsing a `MessageRef` makes no allocations,
+/// and the command is left as is rather than parsed into a `Command`. It can be converted into an
+/// owned `Message` when needed.
+///
+/// Lines can be read from a connection or a file with
+/// [`LineCodec`](../line/struct.LineCodec.html), and each parsed with `MessageRef::parse`.
+///
+/// # Example
+/// ```
+/// # extern crate irc;
+/// # use irc::proto::MessageRef;
+/// # fn main() {
+/// let line = "@time=2019-01-01T00:00:00.000Z :ada!ada@host PRIVMSG #rust :Hi!\r\n";
+/// let message = MessageRef::parse(line).unwrap();
+/// assert_eq!(message.prefix(), Some("ada!ada@host"));
+/// assert_eq!(message.command(), "PRIVMSG");
+/// assert_eq!(message.args().collect::<Vec<_>>(), vec!["#rust"]);
+/// assert_eq!(message.suffix(), Some("Hi!"));
+/// # }
+/// ```
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MessageRef<'a> {
+    /// The whole line the message was parsed from.
+    line: &'a str,
+    /// The tags of the message without the leading `@`, with their values still escaped.
+    tags: Option<&'a str>,
+    /// The message prefix without the leading colon.
+    prefix: Option<&'a str>,
+    /// The name of the command, or the numeric of a response.
+    command: &'a str,
+    /// The arguments of the command other than the suffix, still separated by spaces.
+    args: &'a str,
+    /// The suffix argument of the command without the leading colon.
+    suffix: Option<&'a str>,
+}
+
+impl<'a> MessageRef<'a> {
+    /// Parses a message from the given line, borrowing each of its components. The line may
+    /// include its line ending.
+    pub fn parse(line: &'a str) -> error::Result<MessageRef<'a>> {
+        if line.is_empty() {
             return Err(IrcError::InvalidMessage {
-                string: s.to_owned(),
+                string: line.to_owned(),
                 cause: MessageParseError::EmptyMessage,
             })
         }
 
-        let mut state = s;
+        let mut state = line;
 
         let tags = if state.starts_with('@') {
             let tags = state.find(' ').map(|i| &state[1..i]);
             state = state.find(' ').map_or("", |i| &state[i + 1..]);
-            tags.map(|ts| {
-                ts.split(';')
-                    .filter(|s| !s.is_empty())
-                    .map(|s: &str| {
-                        let mut iter = s.splitn(2, '=');
-                        let (fst, snd) = (iter.next(), iter.next());
-                        Tag(fst.unwrap_or("").to_owned(), snd.map(|s| s.to_owned()))
-                    })
-                    .collect::<Vec<_>>()
-            })
+            tags
         } else {
             None
         };
@@ -233,7 +752,7 @@ impl FromStr for Message {
             }
             // If there's no arguments but the "command" starts with colon, it's not a command.
             None if state.starts_with(':') => return Err(IrcError::InvalidMessage {
-                string: s.to_owned(),
+                string: line.to_owned(),
                 cause: MessageParseError::InvalidCommand,
             }),
             // If there's no arguments following the command, the rest of the state is the command.
@@ -244,26 +763,97 @@ impl FromStr for Message {
             },
         };
 
-        let args: Vec<_> = state.splitn(14, ' ').filter(|s| !s.is_empty()).collect();
+        Ok(MessageRef {
+            line: line,
+            tags: tags,
+            prefix: prefix,
+            command: command,
+            args: state,
+            suffix: suffix,
+        })
+    }
+
+    /// Gets the whole line the message was parsed from.
+    pub fn line(&self) -> &'a str {
+        self.line
+    }
+
+    /// Gets the tags of the message as sent, without the leading `@`. Tag values are escaped, and
+    /// can be read unescaped with [`tag`](#method.tag).
+    pub fn tags(&self) -> Option<&'a str> {
+        self.tags
+    }
+
+    /// Gets the unescaped value of the tag with the given key, if the message has such a tag. As
+    /// per the specification, a tag without a value has an empty value. The value is only copied
+    /// if it had to be unescaped.
+    pub fn tag(&self, key: &str) -> Option<Cow<'a, str>> {
+        self.tags?.split(';').filter_map(|tag| {
+            let mut iter = tag.splitn(2, '=');
+            match (iter.next(), iter.next()) {
+                (Some(k), value) if k == key => Some(value.unwrap_or("")),
+                _ => None,
+            }
+        }).next().map(|value| if value.contains('\\') {
+            Cow::Owned(unescape_tag_value(value))
+        } else {
+            Cow::Borrowed(value)
+        })
+    }
+
+    /// Gets the message prefix (or source), if any.
+    pub fn prefix(&self) -> Option<&'a str> {
+        self.prefix
+    }
+
+    /// Gets the name of the command, or the numeric of a response, as sent.
+    pub fn command(&self) -> &'a str {
+        self.command
+    }
 
-        Message::with_tags(tags, prefix, command, args, suffix).map_err(|e| {
+    /// Gets an iterator over the arguments of the command, not including the suffix.
+    pub fn args(&self) -> Args<'a> {
+        Args { inner: self.args.splitn(14, ' ') }
+    }
+
+    /// Gets the suffix argument of the command, if any.
+    pub fn suffix(&self) -> Option<&'a str> {
+        self.suffix
+    }
+
+    /// Converts the message into an owned `Message`, parsing its tags and command.
+    pub fn to_message(&self) -> error::Result<Message> {
+        let tags = self.tags.map(|ts| {
+            ts.split(';')
+                .filter(|s| !s.is_empty())
+                .map(|s: &str| {
+                    let mut iter = s.splitn(2, '=');
+                    let (fst, snd) = (iter.next(), iter.next());
+                    Tag(fst.unwrap_or("").to_owned(), snd.map(unescape_tag_value))
+                })
+                .collect::<Vec<_>>()
+        });
+        let args = self.args().collect();
+        Message::with_tags(tags, self.prefix, self.command, args, self.suffix).map_err(|e| {
             IrcError::InvalidMessage {
-                string: s.to_owned(),
+                string: self.line.to_owned(),
                 cause: e,
             }
         })
     }
 }
 
-impl<'a> From<&'a str> for Message {
-    fn from(s: &'a str) -> Message {
-        s.parse().unwrap()
-    }
+/// An iterator over the arguments of a [`MessageRef`](./struct.MessageRef.html).
+#[derive(Clone, Debug)]
+pub struct Args<'a> {
+    inner: SplitN<'a, char>,
 }
 
-impl Display for Message {
-    fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        write!(f, "{}", self.to_string())
+impl<'a> Iterator for Args<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.inner.by_ref().find(|s| !s.is_empty())
     }
 }
 
@@ -274,10 +864,70 @@ impl Display for Message {
 #[derive(Clone, PartialEq, Debug)]
 pub struct Tag(pub String, pub Option<String>);
 
+/// A change in whether a user on the client's `MONITOR` or `WATCH` list is online.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Presence {
+    /// The user with the given nickname is online.
+    Online(String),
+    /// The user with the given nickname is offline.
+    Offline(String),
+}
+
+/// An invitation from one user to another to join a channel.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Invite {
+    /// The nickname of the user who sent the invite.
+    pub inviter: String,
+    /// The nickname of the user who was invited.
+    pub invitee: String,
+    /// The channel the invite is for.
+    pub channel: String,
+}
+
+/// A reaction from a user to a message, sent as a `TAGMSG` with the `+draft/react` and
+/// `+draft/reply` client tags.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Reaction {
+    /// The nickname of the user who reacted.
+    pub from: String,
+    /// The channel or user the reaction was sent to.
+    pub target: String,
+    /// The `msgid` of the message that was reacted to.
+    pub target_msgid: String,
+    /// The reaction itself, typically an emoji.
+    pub emoji: String,
+}
+
+/// A key and its value in the metadata of a user or channel, as reported by the server.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Metadata {
+    /// The user or channel the metadata belongs to.
+    pub target: String,
+    /// The key of the metadata, e.g. `avatar`.
+    pub key: String,
+    /// The value of the key, if it has one.
+    pub value: Option<String>,
+}
+
+impl Invite {
+    /// Determines whether the invite was sent to the user with the given nickname, under RFC 1459
+    /// casemapping. With `invite-notify`, this tells invites for the client itself apart from
+    /// invites that it was merely notified about.
+    pub fn is_for(&self, nickname: &str) -> bool {
+        nicknames_eq(&self.invitee, nickname)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{Message, Tag};
-    use proto::Command::{PRIVMSG, QUIT, Raw};
+    use std::borrow::Cow;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::{Invite, Message, MessageRef, Metadata, Presence, Reaction, Tag};
+    use error::{IrcError, MessageParseError};
+    use proto::{ChannelJoinError, ChannelVisibility};
+    use proto::Command::{PRIVMSG, QUIT, Raw, TAGMSG};
+    use proto::reply::{Severity, StandardReply};
 
     #[test]
     fn new() {
@@ -292,6 +942,187 @@ mod test {
         )
     }
 
+    #[test]
+    fn as_invite_for_us() {
+        let msg: Message = ":ada!ada@test INVITE test #test".parse().unwrap();
+        let invite = msg.as_invite().unwrap();
+        assert_eq!(invite, Invite {
+            inviter: format!("ada"),
+            invitee: format!("test"),
+            channel: format!("#test"),
+        });
+        assert!(invite.is_for("test"));
+    }
+
+    #[test]
+    fn as_invite_notify_for_others() {
+        let msg: Message = ":ada!ada@test INVITE betsy[away] #test".parse().unwrap();
+        let invite = msg.as_invite().unwrap();
+        assert_eq!(invite.invitee, "betsy[away]");
+        assert!(!invite.is_for("test"));
+        assert!(invite.is_for("Betsy{Away}"));
+    }
+
+    #[test]
+    fn is_self_kick() {
+        let msg: Message = ":op!op@host KICK #test test :Go away.".parse().unwrap();
+        assert!(msg.is_self_kick("test"));
+        assert!(msg.is_self_kick("TEST"));
+        assert!(!msg.is_self_kick("ada"));
+        let msg: Message = ":op!op@host KICK #test ada[away],test2".parse().unwrap();
+        assert!(msg.is_self_kick("Ada{Away}"));
+        assert!(msg.is_self_kick("test2"));
+        assert!(!msg.is_self_kick("test"));
+        let msg: Message = ":test!test@host PART #test".parse().unwrap();
+        assert!(!msg.is_self_kick("test"));
+    }
+
+    #[test]
+    fn as_invite_requires_command_and_source() {
+        let msg: Message = ":irc.test.net INVITE test #test".parse().unwrap();
+        assert_eq!(msg.as_invite(), None);
+        let msg: Message = ":ada!ada@test PRIVMSG test #test".parse().unwrap();
+        assert_eq!(msg.as_invite(), None);
+    }
+
+    #[test]
+    fn as_account_login() {
+        let msg: Message = ":ada!ada@test ACCOUNT adaacct".parse().unwrap();
+        assert_eq!(msg.as_account(), Some(Some(format!("adaacct"))));
+    }
+
+    #[test]
+    fn as_account_logout() {
+        let msg: Message = ":ada!ada@test ACCOUNT *".parse().unwrap();
+        assert_eq!(msg.as_account(), Some(None));
+        let msg: Message = ":ada!ada@test AWAY :Gone".parse().unwrap();
+        assert_eq!(msg.as_account(), None);
+    }
+
+    #[test]
+    fn as_presence_watch() {
+        let online = [
+            ":irc.test.net 600 test ada ada host 1500000000 :logged online",
+            ":irc.test.net 604 test ada ada host 1500000000 :is online",
+            ":irc.test.net 609 test ada ada host 1500000000 :is away",
+        ];
+        for line in &online {
+            let msg: Message = line.parse().unwrap();
+            assert_eq!(msg.as_presence(), vec![Presence::Online(format!("ada"))]);
+        }
+        let offline = [
+            ":irc.test.net 601 test ada ada host 1500000000 :logged offline",
+            ":irc.test.net 605 test ada * * 0 :is offline",
+        ];
+        for line in &offline {
+            let msg: Message = line.parse().unwrap();
+            assert_eq!(msg.as_presence(), vec![Presence::Offline(format!("ada"))]);
+        }
+        let msg: Message = ":irc.test.net 607 test :End of WATCH l".parse().unwrap();
+        assert_eq!(msg.as_presence(), vec![]);
+    }
+
+    #[test]
+    fn as_presence_monitor() {
+        let msg: Message = ":irc.test.net 731 test :ada,betsy".parse().unwrap();
+        assert_eq!(
+            msg.as_presence(),
+            vec![Presence::Offline(format!("ada")), Presence::Offline(format!("betsy"))]
+        );
+    }
+
+    #[test]
+    fn as_join_error() {
+        let errors = [
+            ("471", ChannelJoinError::ChannelIsFull),
+            ("473", ChannelJoinError::InviteOnly),
+            ("474", ChannelJoinError::Banned),
+            ("475", ChannelJoinError::BadKey),
+            ("476", ChannelJoinError::BadChannelMask),
+            ("477", ChannelJoinError::RegistrationRequired),
+        ];
+        for &(numeric, err) in &errors {
+            let msg: Message = format!(":irc.test.net {} test #test :Cannot join channel", numeric)
+                .parse()
+                .unwrap();
+            assert_eq!(msg.as_join_error(), Some(("#test", err)));
+        }
+        let msg: Message = ":irc.test.net 366 test #test :End of /NAMES list.".parse().unwrap();
+        assert_eq!(msg.as_join_error(), None);
+        let msg: Message = ":irc.test.net 473 test".parse().unwrap();
+        assert_eq!(msg.as_join_error(), None);
+    }
+
+    #[test]
+    fn as_channel_visibility() {
+        let msg: Message = ":irc.test.net 353 test @ #test :test @ada".parse().unwrap();
+        assert_eq!(msg.as_channel_visibility(), Some(("#test", ChannelVisibility::Secret)));
+        let msg: Message = ":irc.test.net 353 test = #test :test".parse().unwrap();
+        assert_eq!(msg.as_channel_visibility(), Some(("#test", ChannelVisibility::Public)));
+        let msg: Message = ":irc.test.net 366 test #test :End of /NAMES list.".parse().unwrap();
+        assert_eq!(msg.as_channel_visibility(), None);
+    }
+
+    #[test]
+    fn tag_value_escaping() {
+        let msg = Message::with_tags(
+            Some(vec![Tag("note".to_owned(), Some("a; b\\c\r\n".to_owned()))]), None, "TAGMSG",
+            vec!["#test"], None
+        ).unwrap();
+        let line = msg.to_string();
+        assert_eq!(line, "@note=a\\:\\sb\\\\c\\r\\n TAGMSG #test\r\n");
+        assert_eq!(line.parse::<Message>().unwrap(), msg);
+        let msg: Message = "@a=b\\x\\;c=\\ TAGMSG #test".parse().unwrap();
+        assert_eq!(msg.tags, Some(vec![
+            Tag("a".to_owned(), Some("bx".to_owned())), Tag("c".to_owned(), Some("".to_owned())),
+        ]));
+    }
+
+    #[test]
+    fn metadata_round_trip() {
+        let line = "METADATA * SET avatar :https://example.com/ada.png\r\n";
+        let msg: Message = line.parse().unwrap();
+        assert_eq!(msg.to_string(), line);
+    }
+
+    #[test]
+    fn as_reaction() {
+        let msg: Message = "@+draft/reply=abc123;+draft/react=\\:) :ada!ada@host TAGMSG #test"
+            .parse()
+            .unwrap();
+        assert_eq!(msg.as_reaction(), Some(Reaction {
+            from: "ada".to_owned(),
+            target: "#test".to_owned(),
+            target_msgid: "abc123".to_owned(),
+            emoji: ";)".to_owned(),
+        }));
+        // Both tags are required, as is a source.
+        let msg: Message = "@+draft/react=👍 :ada!ada@host TAGMSG #test".parse().unwrap();
+        assert_eq!(msg.as_reaction(), None);
+        let msg: Message = "@+draft/reply=abc123;+draft/react=👍 TAGMSG #test".parse().unwrap();
+        assert_eq!(msg.as_reaction(), None);
+        let msg: Message = "@+draft/reply=abc123;+draft/react=👍 :ada!ada@host PRIVMSG #test :hi"
+            .parse()
+            .unwrap();
+        assert_eq!(msg.as_reaction(), None);
+    }
+
+    #[test]
+    fn as_metadata() {
+        let msg: Message = ":irc.test.net 761 test #test url * :https://example.com"
+            .parse()
+            .unwrap();
+        assert_eq!(msg.as_metadata(), Some(Metadata {
+            target: "#test".to_owned(),
+            key: "url".to_owned(),
+            value: Some("https://example.com".to_owned()),
+        }));
+        let msg: Message = ":irc.test.net 761 test ada avatar *".parse().unwrap();
+        assert_eq!(msg.as_metadata().unwrap().value, None);
+        let msg: Message = ":irc.test.net 762 test :end of metadata".parse().unwrap();
+        assert_eq!(msg.as_metadata(), None);
+    }
+
     #[test]
     fn source_nickname() {
         assert_eq!(
@@ -410,6 +1241,63 @@ mod test {
         )
     }
 
+    #[test]
+    fn from_string_invalid() {
+        match "".parse::<Message>() {
+            Err(IrcError::InvalidMessage { cause: MessageParseError::EmptyMessage, .. }) => (),
+            res => panic!("expected EmptyMessage, got {:?}", res),
+        }
+        match ":test!test@test :hi".parse::<Message>() {
+            Err(IrcError::InvalidMessage { ref string, cause: MessageParseError::InvalidCommand })
+                => assert_eq!(string, ":test!test@test :hi"),
+            res => panic!("expected InvalidCommand, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn command_name_and_params() {
+        let message: Message = ":ada!ada@host PRIVMSG #test :Hi, everybody!\r\n".parse().unwrap();
+        assert_eq!(message.command_name(), "PRIVMSG");
+        assert_eq!(message.params(), vec!["#test", "Hi, everybody!"]);
+
+        let message: Message = "353 test = #test :@ada grace\r\n".parse().unwrap();
+        assert_eq!(message.command_name(), "353");
+        assert_eq!(message.params(), vec!["test", "=", "#test", "@ada grace"]);
+
+        let message: Message = "FOO bar baz :qux quux\r\n".parse().unwrap();
+        assert_eq!(message.command, Raw("FOO".to_owned(), vec!["bar".to_owned(),
+                                        "baz".to_owned()], Some("qux quux".to_owned())));
+        assert_eq!(message.command_name(), "FOO");
+        assert_eq!(message.params(), vec!["bar", "baz", "qux quux"]);
+    }
+
+    #[test]
+    fn message_ref() {
+        let line = "@a=1;b=x\\sy;c :ada!ada@host MODE #test +o  ada :extra\r\n";
+        let message = MessageRef::parse(line).unwrap();
+        assert_eq!(message.line(), line);
+        assert_eq!(message.tags(), Some("a=1;b=x\\sy;c"));
+        assert_eq!(message.tag("a"), Some(Cow::Borrowed("1")));
+        assert_eq!(message.tag("b"), Some(Cow::Owned("x y".to_owned())));
+        assert_eq!(message.tag("c"), Some(Cow::Borrowed("")));
+        assert_eq!(message.tag("d"), None);
+        assert_eq!(message.prefix(), Some("ada!ada@host"));
+        assert_eq!(message.command(), "MODE");
+        assert_eq!(message.args().collect::<Vec<_>>(), vec!["#test", "+o", "ada"]);
+        assert_eq!(message.suffix(), Some("extra"));
+        assert_eq!(message.to_message().unwrap(), line.parse::<Message>().unwrap());
+    }
+
+    #[test]
+    fn message_ref_without_args() {
+        let message = MessageRef::parse("QUIT\r\n").unwrap();
+        assert_eq!((message.tags(), message.prefix()), (None, None));
+        assert_eq!(message.command(), "QUIT");
+        assert_eq!(message.args().next(), None);
+        assert_eq!(message.suffix(), None);
+        assert_eq!(message.to_message().unwrap(), Message::from(QUIT(None)));
+    }
+
     #[test]
     fn from_string_atypical_endings() {
         let message = Message {
@@ -456,6 +1344,140 @@ mod test {
         assert_eq!(msg, message);
     }
 
+    #[test]
+    fn tagmsg_round_trip() {
+        let message = Message {
+            tags: Some(vec![Tag(format!("+typing"), Some(format!("active")))]),
+            prefix: Some(format!("ada!ada@host")),
+            command: TAGMSG(format!("#test")),
+        };
+        let line = "@+typing=active :ada!ada@host TAGMSG #test\r\n";
+        assert_eq!(message.to_string(), line);
+        assert_eq!(line.parse::<Message>().unwrap(), message);
+    }
+
+    #[test]
+    fn msgid() {
+        let msg: Message = "@time=2018-01-01T00:00:00.000Z;msgid=abc123 :ada PRIVMSG #test :Hi!\r\n"
+            .into();
+        assert_eq!(msg.msgid(), Some("abc123"));
+        let msg: Message = "@time=2018-01-01T00:00:00.000Z :ada PRIVMSG #test :Hi!\r\n".into();
+        assert_eq!(msg.msgid(), None);
+        let msg: Message = ":ada PRIVMSG #test :Hi!\r\n".into();
+        assert_eq!(msg.msgid(), None);
+    }
+
+    #[test]
+    fn suffix() {
+        let msg: Message = ":ada!ada@host PRIVMSG #test :Hi there!\r\n".into();
+        assert_eq!(msg.suffix(), Some("Hi there!".to_owned()));
+        let msg: Message = ":ada!ada@host QUIT :Leaving.\r\n".into();
+        assert_eq!(msg.suffix(), Some("Leaving.".to_owned()));
+        let msg: Message = ":ada!ada@host QUIT\r\n".into();
+        assert_eq!(msg.suffix(), None);
+        let msg: Message = ":ada!ada@host PART #test :Bye.\r\n".into();
+        assert_eq!(msg.suffix(), Some("Bye.".to_owned()));
+        let msg: Message = ":irc.test.net 005 test MODES=4 :are supported\r\n".into();
+        assert_eq!(msg.suffix(), Some("are supported".to_owned()));
+        let msg: Message = ":ada!ada@host INVITE test #test\r\n".into();
+        assert_eq!(msg.suffix(), None);
+        let msg: Message = ":ada!ada@host MODE #test +o test\r\n".into();
+        assert_eq!(msg.suffix(), None);
+    }
+
+    #[test]
+    fn as_action() {
+        let msg: Message = ":ada!ada@host PRIVMSG #test :\u{001}ACTION waves.\u{001}\r\n".into();
+        assert_eq!(msg.as_action(), Some("waves."));
+        let msg: Message = ":ada!ada@host PRIVMSG #test :\u{001}ACTION waves.\r\n".into();
+        assert_eq!(msg.as_action(), Some("waves."));
+        let msg: Message = ":ada!ada@host PRIVMSG test :\u{001}ACTION\u{001}\r\n".into();
+        assert_eq!(msg.as_action(), Some(""));
+    }
+
+    #[test]
+    fn as_action_requires_action() {
+        let msg: Message = ":ada!ada@host PRIVMSG #test :ACTION waves.\r\n".into();
+        assert_eq!(msg.as_action(), None);
+        let msg: Message = ":ada!ada@host PRIVMSG #test :\u{001}ACTIONS\u{001}\r\n".into();
+        assert_eq!(msg.as_action(), None);
+        let msg: Message = ":ada!ada@host PRIVMSG test :\u{001}VERSION\u{001}\r\n".into();
+        assert_eq!(msg.as_action(), None);
+        let msg: Message = ":ada!ada@host NOTICE #test :\u{001}ACTION waves.\u{001}\r\n".into();
+        assert_eq!(msg.as_action(), None);
+    }
+
+    #[test]
+    fn as_ctcp_ping_reply() {
+        let msg: Message = ":ada!ada@host NOTICE test :\u{001}PING 1500000000\u{001}\r\n".into();
+        assert_eq!(msg.as_ctcp_ping_reply(), Some(Duration::from_secs(1500000000)));
+        let msg: Message = ":ada!ada@host NOTICE test :\u{001}PING 1500000000123\u{001}\r\n".into();
+        assert_eq!(msg.as_ctcp_ping_reply(), Some(Duration::from_millis(1500000000123)));
+        let msg: Message = ":ada!ada@host NOTICE test :\u{001}PING soon\u{001}\r\n".into();
+        assert_eq!(msg.as_ctcp_ping_reply(), None);
+        let msg: Message = ":ada PRIVMSG test :\u{001}PING 1500000000000\u{001}\r\n".into();
+        assert_eq!(msg.as_ctcp_ping_reply(), None);
+    }
+
+    #[test]
+    fn ctcp_ping_latency() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let sent = now - Duration::from_millis(1500);
+        let sent = sent.as_secs() * 1000 + u64::from(sent.subsec_nanos() / 1_000_000);
+        let line = format!(":ada!ada@host NOTICE test :\u{001}PING {}\u{001}\r\n", sent);
+        let latency = Message::from(&line[..]).ctcp_ping_latency().unwrap();
+        assert!(latency >= Duration::from_millis(1500));
+        assert!(latency < Duration::from_secs(60));
+        let line = format!(":ada!ada@host NOTICE test :\u{001}PING {}\u{001}\r\n", sent + 60000);
+        assert_eq!(Message::from(&line[..]).ctcp_ping_latency(), None);
+        // Timestamps in seconds, as sent by `send_ctcp_ping`, are measured to the second.
+        let line = format!(":ada!ada@host NOTICE test :\u{001}PING {}\u{001}\r\n", sent / 1000);
+        let latency = Message::from(&line[..]).ctcp_ping_latency().unwrap();
+        assert!(latency >= Duration::from_millis(500));
+        assert!(latency < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn as_standard_reply() {
+        let msg: Message = ":irc.test.net FAIL CHATHISTORY INVALID_TARGET LATEST #nope \
+                            :Messages could not be retrieved\r\n".into();
+        assert_eq!(msg.as_standard_reply(), Some(StandardReply {
+            severity: Severity::Fail,
+            command: "CHATHISTORY".to_owned(),
+            code: "INVALID_TARGET".to_owned(),
+            context: vec!["LATEST".to_owned(), "#nope".to_owned()],
+            description: "Messages could not be retrieved".to_owned(),
+        }));
+        let msg: Message = ":irc.test.net NOTE * OPER_MESSAGE :The message\r\n".into();
+        assert_eq!(msg.as_standard_reply(), Some(StandardReply {
+            severity: Severity::Note,
+            command: "*".to_owned(),
+            code: "OPER_MESSAGE".to_owned(),
+            context: vec![],
+            description: "The message".to_owned(),
+        }));
+    }
+
+    #[test]
+    fn as_standard_reply_invalid() {
+        let msg: Message = ":irc.test.net WARN REHASH :Missing code\r\n".into();
+        assert_eq!(msg.as_standard_reply(), None);
+        let msg: Message = ":ada!ada@host PRIVMSG #test :FAIL JOIN X :no\r\n".into();
+        assert_eq!(msg.as_standard_reply(), None);
+    }
+
+    #[test]
+    fn is_from_bot() {
+        let msg: Message = "@bot;msgid=abc123 :robo PRIVMSG #test :Beep!\r\n".into();
+        assert!(msg.is_from_bot());
+        let msg: Message = "@draft/bot= :robo PRIVMSG #test :Beep!\r\n".into();
+        assert!(msg.is_from_bot());
+        let msg: Message = "@msgid=abc123 :ada PRIVMSG #test :Hi!\r\n".into();
+        assert!(!msg.is_from_bot());
+        let msg: Message = ":ada PRIVMSG #test :Hi!\r\n".into();
+        assert!(!msg.is_from_bot());
+    }
+
     #[test]
     fn to_message_with_colon_in_arg() {
         // Apparently, UnrealIRCd (and perhaps some others) send some messages that include
@@ -489,4 +1511,101 @@ mod test {
     fn to_message_invalid_format() {
         let _: Message = ":invalid :message".into();
     }
+
+    /// The commands understood by `Command::new`, and a few that are parsed as raw commands.
+    const COMMANDS: &[&str] = &[
+        "PASS", "NICK", "USER", "OPER", "MODE", "SERVICE", "QUIT", "SQUIT", "JOIN", "PART",
+        "TOPIC", "NAMES", "LIST", "INVITE", "KICK", "PRIVMSG", "NOTICE", "MOTD", "LUSERS",
+        "VERSION", "STATS", "LINKS", "TIME", "CONNECT", "TRACE", "ADMIN", "INFO", "SERVLIST",
+        "SQUERY", "WHO", "WHOIS", "WHOWAS", "KILL", "PING", "PONG", "ERROR", "AWAY", "REHASH",
+        "DIE", "RESTART", "SUMMON", "USERS", "WALLOPS", "USERHOST", "ISON", "SAJOIN", "SAMODE",
+        "SANICK", "SAPART", "SAQUIT", "NICKSERV", "CHANSERV", "OPERSERV", "BOTSERV", "HOSTSERV",
+        "MEMOSERV", "CAP", "AUTHENTICATE", "ACCOUNT", "METADATA", "MONITOR", "BATCH", "CHGHOST",
+        "TAGMSG", "001", "353", "761", "CHATHISTORY", "WATCH",
+    ];
+
+    /// A small xorshift generator, so that the round-trip property can be checked against many
+    /// messages without depending on an external crate for property testing.
+    struct Gen(u64);
+
+    impl Gen {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+
+        fn text(&mut self, alphabet: &str, min: usize, max: usize) -> String {
+            let chars: Vec<char> = alphabet.chars().collect();
+            let len = min + self.below(max - min + 1);
+            (0..len).map(|_| chars[self.below(chars.len())]).collect()
+        }
+
+        /// Generates a parameter that can stand in the middle of a message.
+        fn middle(&mut self) -> String {
+            let first = self.text("abcXYZ019#&+-*,!@.=", 1, 1);
+            first + &self.text("abcXYZ019#&+-*,!@.=:", 0, 6)
+        }
+
+        fn message(&mut self) -> Option<Message> {
+            let tags = if self.below(2) == 0 {
+                Some((0..1 + self.below(3)).map(|_| {
+                    let key = self.text("abcxyz+/.-", 1, 6);
+                    let value = match self.below(3) {
+                        0 => None,
+                        _ => Some(self.text("abc=;: \\\r\nxyz", 0, 8)),
+                    };
+                    Tag(key, value)
+                }).collect())
+            } else {
+                None
+            };
+            let prefix = if self.below(2) == 0 {
+                Some(self.text("abcxyz!@.019", 1, 10))
+            } else {
+                None
+            };
+            let command = self.pick(COMMANDS);
+            let args: Vec<String> = (0..self.below(5)).map(|_| self.middle()).collect();
+            let suffix = match self.below(3) {
+                0 => None,
+                _ => Some(self.text("abc :#+-!@", 0, 10)),
+            };
+            Message::with_tags(
+                tags,
+                prefix.as_ref().map(|s| &s[..]),
+                command,
+                args.iter().map(|s| &s[..]).collect(),
+                suffix.as_ref().map(|s| &s[..]),
+            ).ok()
+        }
+    }
+
+    #[test]
+    fn round_trip_property() {
+        let mut gen = Gen(0x2545_f491_4f6c_dd1d);
+        for _ in 0..20_000 {
+            let msg = match gen.message() {
+                Some(msg) => msg,
+                None => continue,
+            };
+            let line = msg.to_string();
+            let parsed: Message = line.parse().unwrap();
+            assert_eq!(parsed, msg, "{:?} did not round-trip", line);
+            let sent = MessageRef::parse(&line).unwrap();
+            assert_eq!(msg.command_name(), sent.command(), "{:?}", line);
+            assert_eq!(msg.params().len(), sent.args().count() + sent.suffix().iter().count(),
+                       "{:?}", line);
+            assert_eq!(msg.suffix().as_ref().map(|s| &s[..]), sent.suffix(), "{:?}", line);
+        }
+    }
 }
@@ -1,20 +1,26 @@
 //! Support for the IRC protocol using Tokio.
 
+pub mod banmask;
 pub mod caps;
+pub(crate) mod casemap;
 pub mod chan;
 pub mod command;
 pub mod colors;
+pub mod ctcp;
 pub mod irc;
 pub mod line;
 pub mod message;
 pub mod mode;
+pub mod reply;
 pub mod response;
 
-pub use self::caps::{Capability, NegotiationVersion};
-pub use self::chan::ChannelExt;
+pub use self::banmask::{banmask, BanMaskType};
+pub use self::caps::{Capability, NegotiationVersion, ValuedCapability};
+pub use self::chan::{is_channel, ChannelExt, ChannelVisibility};
 pub use self::colors::FormattedStringExt;
 pub use self::command::{BatchSubCommand, CapSubCommand, Command};
 pub use self::irc::IrcCodec;
-pub use self::message::Message;
+pub use self::message::{Message, MessageRef};
 pub use self::mode::{ChannelMode, Mode, UserMode};
-pub use self::response::Response;
+pub use self::reply::{Severity, StandardReply};
+pub use self::response::{ChannelJoinError, Response};
@@ -294,11 +294,9 @@ impl Mode<ChannelMode> {
         use self::PlusMinus::*;
 
         let mut res = vec![];
-        let mut pieces = s.split(' ');
-        for term in pieces.clone() {
+        let mut pieces = s.split(' ').peekable();
+        while let Some(term) = pieces.next() {
             if term.starts_with('+') || term.starts_with('-') {
-                let _ = pieces.next();
-
                 let mut chars = term.chars();
                 let init = match chars.next() {
                     Some('+') => Plus,
@@ -315,7 +313,12 @@ impl Mode<ChannelMode> {
 
                 for c in chars {
                     let mode = ChannelMode::from_char(c);
-                    let arg = if mode.takes_arg() {
+                    // Modes may be sent without their argument (e.g. `+b` to query the ban list),
+                    // so a following mode string is never taken as an argument.
+                    let next_is_modes = pieces.peek().map(|p| {
+                        p.starts_with('+') || p.starts_with('-')
+                    }).unwrap_or(false);
+                    let arg = if mode.takes_arg() && !next_is_modes {
                         pieces.next()
                     } else {
                         None
@@ -0,0 +1,65 @@
+//! Standard replies, as described in the
+//! [IRCv3 specification](https://ircv3.net/specs/extensions/standard-replies).
+
+/// The severity of a standard reply, given by its command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Severity {
+    /// A `FAIL` reply, indicating that a command failed.
+    Fail,
+    /// A `WARN` reply, indicating that a command succeeded but something noteworthy happened.
+    Warn,
+    /// A `NOTE` reply, giving information about a command or the state of the connection.
+    Note,
+}
+
+impl Severity {
+    /// Gets the severity of a standard reply with the given command, if it is one.
+    pub fn from_command(command: &str) -> Option<Severity> {
+        match command {
+            "FAIL" => Some(Severity::Fail),
+            "WARN" => Some(Severity::Warn),
+            "NOTE" => Some(Severity::Note),
+            _ => None,
+        }
+    }
+}
+
+/// A standard reply, which many servers send in place of numerics. It takes the form
+/// `<FAIL|WARN|NOTE> <command> <code> [<context>...] :<description>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StandardReply {
+    /// The severity of the reply.
+    pub severity: Severity,
+    /// The command the reply relates to, or `*` if it relates to none in particular.
+    pub command: String,
+    /// The machine-readable code of the reply, e.g. `ACCOUNT_REQUIRED`.
+    pub code: String,
+    /// Any further parameters giving the context of the reply, depending on its code.
+    pub context: Vec<String>,
+    /// The human-readable description of the reply.
+    pub description: String,
+}
+
+impl StandardReply {
+    /// Creates a standard reply from the given severity and the parameters of its message, or
+    /// returns `None` if there are too few of them.
+    pub(crate) fn from_params(
+        severity: Severity,
+        mut params: Vec<String>,
+    ) -> Option<StandardReply> {
+        if params.len() < 3 {
+            return None;
+        }
+        let description = params.pop().unwrap();
+        let context = params.split_off(2);
+        let code = params.pop().unwrap();
+        let command = params.pop().unwrap();
+        Some(StandardReply {
+            severity: severity,
+            command: command,
+            code: code,
+            context: context,
+            description: description,
+        })
+    }
+}
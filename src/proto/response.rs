@@ -1,6 +1,6 @@
 //! Enumeration of all the possible server responses.
 #![allow(non_camel_case_types)]
-use std::str::FromStr;
+use std::str::{self, FromStr};
 
 macro_rules! make_response {
     ($($(#[$attr:meta])+ $variant:ident = $value:expr),+) => {
@@ -27,6 +27,20 @@ macro_rules! make_response {
     }
 }
 
+/// The three digit codes from `000` to `999` concatenated, from which the code of each response
+/// is taken.
+static CODES: [u8; 3000] = {
+    let mut codes = [0; 3000];
+    let mut i = 0;
+    while i < 1000 {
+        codes[i * 3] = b'0' + (i / 100) as u8;
+        codes[i * 3 + 1] = b'0' + (i / 10 % 10) as u8;
+        codes[i * 3 + 2] = b'0' + (i % 10) as u8;
+        i += 1;
+    }
+    codes
+};
+
 make_response! {
     // Expected replies
     /// `001 Welcome to the Internet Relay Network <nick>!<user>@<host>` (Source: RFC2812)
@@ -120,6 +134,9 @@ make_response! {
     /// `352 <channel> <user> <host> <server> <nick> ( "H" / "G" > ["*"] [ ( "@" / "+" ) ]
     /// :<hopcount> <real name>` (Source: RFC2812)
     RPL_WHOREPLY        = 352,
+    /// `354 [token] [channel] [user] [ip] [host] [server] [nick] [flags] [hopcount] [idle]
+    /// [account] [oplevel] [:realname]`, with only the fields requested by WHOX (Source: Modern)
+    RPL_WHOSPCRPL       = 354,
     /// `315 <name> :End of WHO list` (Source: RFC2812)
     RPL_ENDOFWHO        = 315,
     /// `353 ( "=" / "*" / "@" ) <channel> :[ "@" / "+" ] <nick> *( " " [ "@" / "+" ] <nick> )`
@@ -236,8 +253,36 @@ make_response! {
     RPL_LOCALUSERS      = 265,
     /// `266 <client> [<u> <m>] :Current local users <u>, max <m>` (Source: Modern)
     RPL_GLOBALUSERS     = 266,
+    /// `271 <client> <nick> <mask>` (Source: SILENCE)
+    RPL_SILELIST        = 271,
+    /// `272 <client> :End of Silence List` (Source: SILENCE)
+    RPL_ENDOFSILELIST   = 272,
     /// `276 <client> <nick> :has client certificate fingerprint <fingerprint>` (Source: Modern)
     RPL_WHOISCERTFP     = 276,
+    /// `330 <client> <nick> <account> :is logged in as` (Source: Modern)
+    RPL_WHOISACCOUNT    = 330,
+    /// `600 <client> <nick> <user> <host> <signon> :logged online` (Source: WATCH)
+    RPL_LOGON           = 600,
+    /// `601 <client> <nick> <user> <host> <signoff> :logged offline` (Source: WATCH)
+    RPL_LOGOFF          = 601,
+    /// `602 <client> <nick> <user> <host> <signon> :stopped watching` (Source: WATCH)
+    RPL_WATCHOFF        = 602,
+    /// `603 <client> :You have <n> and are on <m> WATCH entries` (Source: WATCH)
+    RPL_WATCHSTAT       = 603,
+    /// `604 <client> <nick> <user> <host> <signon> :is online` (Source: WATCH)
+    RPL_NOWON           = 604,
+    /// `605 <client> <nick> <user> <host> <signoff> :is offline` (Source: WATCH)
+    RPL_NOWOFF          = 605,
+    /// `606 <client> :<nick>[ <nick>]*` (Source: WATCH)
+    RPL_WATCHLIST       = 606,
+    /// `607 <client> :End of WATCH <l|s>` (Source: WATCH)
+    RPL_ENDOFWATCHLIST  = 607,
+    /// `608 <client> :WATCH list cleared` (Source: WATCH)
+    RPL_CLEARWATCH      = 608,
+    /// `609 <client> <nick> <user> <host> <away since> :is away` (Source: WATCH)
+    RPL_NOWISAWAY       = 609,
+    /// `670 <client> :STARTTLS successful, proceed with TLS handshake` (Source: IRCv3)
+    RPL_STARTTLS        = 670,
     /// `730 <nick> :target[,target2]*` (Source: RFC2812)
     RPL_MONONLINE       = 730,
     /// `731 <nick> :target[,target2]*` (Source: RFC2812)
@@ -375,6 +420,8 @@ make_response! {
     ERR_UMODEUNKNOWNFLAG    = 501,
     /// `502 :Cannot change mode for other users` (Source: RFC2812)
     ERR_USERSDONTMATCH      = 502,
+    /// `691 <client> :STARTTLS failed (Wrong moon phase)` (Source: IRCv3)
+    ERR_STARTTLS            = 691,
     /// `723 <client> <priv> :Insufficient oper privileges.` (Source: Modern)
     ///
     /// Sent to an operator to indicate that they don't have the specific privileges to perform the
@@ -415,6 +462,57 @@ impl Response {
     pub fn is_error(&self) -> bool {
         *self as u16 >= 400
     }
+
+    /// Gets the three digit code of this response as sent, e.g. `001` for `RPL_WELCOME`.
+    pub fn code(&self) -> &'static str {
+        let i = *self as usize * 3;
+        str::from_utf8(&CODES[i..i + 3]).unwrap()
+    }
+}
+
+/// A reason for which the server refused to let the client join a channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelJoinError {
+    /// The channel has reached its user limit, `+l` (`ERR_CHANNELISFULL`).
+    ChannelIsFull,
+    /// The channel is invite-only, `+i` (`ERR_INVITEONLYCHAN`).
+    InviteOnly,
+    /// The client is banned from the channel, `+b` (`ERR_BANNEDFROMCHAN`).
+    Banned,
+    /// The channel key was missing or incorrect, `+k` (`ERR_BADCHANNELKEY`).
+    BadKey,
+    /// The channel name is not valid on this server (`ERR_BADCHANMASK`).
+    BadChannelMask,
+    /// The channel requires users to be logged in to an account. Servers send this as `477`,
+    /// which RFC2812 names `ERR_NOCHANMODES`.
+    RegistrationRequired,
+}
+
+impl ChannelJoinError {
+    /// Gets the join error corresponding to the given response, if it is one.
+    pub fn from_response(resp: Response) -> Option<ChannelJoinError> {
+        match resp {
+            Response::ERR_CHANNELISFULL => Some(ChannelJoinError::ChannelIsFull),
+            Response::ERR_INVITEONLYCHAN => Some(ChannelJoinError::InviteOnly),
+            Response::ERR_BANNEDFROMCHAN => Some(ChannelJoinError::Banned),
+            Response::ERR_BADCHANNELKEY => Some(ChannelJoinError::BadKey),
+            Response::ERR_BADCHANMASK => Some(ChannelJoinError::BadChannelMask),
+            Response::ERR_NOCHANMODES => Some(ChannelJoinError::RegistrationRequired),
+            _ => None,
+        }
+    }
+
+    /// Gets the response that the server sends for this join error.
+    pub fn response(&self) -> Response {
+        match *self {
+            ChannelJoinError::ChannelIsFull => Response::ERR_CHANNELISFULL,
+            ChannelJoinError::InviteOnly => Response::ERR_INVITEONLYCHAN,
+            ChannelJoinError::Banned => Response::ERR_BANNEDFROMCHAN,
+            ChannelJoinError::BadKey => Response::ERR_BADCHANNELKEY,
+            ChannelJoinError::BadChannelMask => Response::ERR_BADCHANMASK,
+            ChannelJoinError::RegistrationRequired => Response::ERR_NOCHANMODES,
+        }
+    }
 }
 
 impl FromStr for Response {
@@ -433,11 +531,31 @@ impl FromStr for Response {
 
 #[cfg(test)]
 mod test {
-    use super::Response;
+    use super::{ChannelJoinError, Response};
 
     #[test]
     fn is_error() {
         assert!(!Response::RPL_NAMREPLY.is_error());
         assert!(Response::ERR_NICKNAMEINUSE.is_error());
     }
+
+    #[test]
+    fn code() {
+        assert_eq!(Response::RPL_WELCOME.code(), "001");
+        assert_eq!(Response::RPL_NAMREPLY.code(), "353");
+        assert_eq!(Response::ERR_NICKNAMEINUSE.code(), "433");
+        assert_eq!(Response::RPL_SASLMECHS.code(), "908");
+    }
+
+    #[test]
+    fn channel_join_error_round_trip() {
+        assert_eq!(ChannelJoinError::from_response(Response::RPL_NAMREPLY), None);
+        for &err in &[
+            ChannelJoinError::ChannelIsFull, ChannelJoinError::InviteOnly,
+            ChannelJoinError::Banned, ChannelJoinError::BadKey,
+            ChannelJoinError::BadChannelMask, ChannelJoinError::RegistrationRequired,
+        ] {
+            assert_eq!(ChannelJoinError::from_response(err.response()), Some(err));
+        }
+    }
 }